@@ -1,31 +1,56 @@
 #!/usr/bin/env python3
 
+import argparse
 import os
+import struct
 import subprocess
 
+parser = argparse.ArgumentParser(description="Compile the shaders in resources/shaders to SPIR-V")
+parser.add_argument("--archive", metavar="NAME",
+                    help="also pack the compiled shaders into resources/shaders/spv/NAME "
+                         "(see global_shaders.archive)")
+args = parser.parse_args()
+
 # Paths
 shader_dir = "resources/shaders"
 output_dir = os.path.join(shader_dir, "spv")
 
 # Supported shader extensions
-shader_extensions = [".rchit", ".rmiss", ".rgen", ".rint"]
+shader_extensions = [".rchit", ".rmiss", ".rgen", ".rint", ".comp"]
 
 # Ensure the output directory exists
 os.makedirs(output_dir, exist_ok=True)
 
+compiled = []
+
 # Iterate through shader files and compile them
-for filename in os.listdir(shader_dir):
+for filename in sorted(os.listdir(shader_dir)):
     # Check for supported shader extensions
     if any(filename.endswith(ext) for ext in shader_extensions):
         shader_path = os.path.join(shader_dir, filename)
         output_path = os.path.join(output_dir, f"{filename}.spv")
 
         try:
-            # Compile the shader using glslc
+            # Compile the shader using glslc, includes are also looked up relative to the shader directory
             subprocess.run(
-                ["glslc", shader_path, "--target-spv=spv1.6", "-o", output_path],
+                ["glslc", shader_path, "-I", shader_dir, "--target-spv=spv1.6", "-o", output_path],
                 check=True
             )
             print(f"Compiled: {shader_path} -> {output_path}")
+            compiled.append((filename, output_path))
         except subprocess.CalledProcessError as e:
             print(f"Error compiling {shader_path}: {e}")
+
+# Same layout ShaderArchive reads, all little endian: magic, count, then length-prefixed names and code
+if args.archive:
+    archive_path = os.path.join(output_dir, args.archive)
+    with open(archive_path, "wb") as archive:
+        archive.write(b"KGSA")
+        archive.write(struct.pack("<I", len(compiled)))
+        for name, path in compiled:
+            with open(path, "rb") as spv:
+                code = spv.read()
+            name = name.encode()
+            archive.write(struct.pack("<I", len(name)) + name)
+            archive.write(struct.pack("<I", len(code)) + code)
+    print(f"Packed {len(compiled)} shaders -> {archive_path}")
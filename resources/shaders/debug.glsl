@@ -0,0 +1,26 @@
+// debug views of the primary hit, selected through debug_mode in the frame parameters
+// include after raygen_common.glsl, the ray_info payload and T_MIN/T_MAX
+
+const float WIREFRAME_WIDTH = 0.02;
+
+// traces a single ray and returns the debug color for whatever it hits
+vec3 trace_debug(vec3 origin, vec3 direction) {
+    // hit shaders that don't know about these leave them alone, so give them recognizable defaults
+    // (no normal shows up as flat gray, no barycentrics means no edges)
+    ray_info.hit_normal = vec3(0);
+    ray_info.hit_bary = vec3(1);
+
+    traceRayEXT(tlas, RAY_FLAGS, VISIBILITY_CAMERA, 0, 0, 0, origin, T_MIN, direction, T_MAX, 0);
+
+    if (!ray_info.is_hit)
+        return vec3(0);
+
+    vec3 normal_color = ray_info.hit_normal * 0.5 + 0.5;
+
+    if (debug_mode == DEBUG_MODE_NORMALS)
+        return normal_color;
+
+    // faces get a dimmed normal color so neighbouring triangles are still distinguishable
+    float edge_dist = min(ray_info.hit_bary.x, min(ray_info.hit_bary.y, ray_info.hit_bary.z));
+    return edge_dist < WIREFRAME_WIDTH ? vec3(1) : 0.2 * normal_color;
+}
@@ -1,3 +1,5 @@
+#include "environment.glsl"
+
 struct EmitterSample {
     vec3 position;
     vec3 direction;
@@ -9,7 +11,32 @@ struct EmitterSample {
 EmitterSample sample_light(vec3 hit_pos, inout uint seed) {
     EmitterSample result;
 
-    uint light_i = uint(rnd(seed) * lights.num_lights);
+    // walk the lights until the running total of samples passes the random pick, scenes only have a handful
+    uint total = lights.total_samples + (environment.width > 0 ? 1 : 0);
+    uint pick = min(uint(rnd(seed) * float(total)), total - 1);
+    uint light_i = 0;
+    for (; light_i < lights.num_lights; light_i++) {
+        uint samples = lights.lights[light_i].samples;
+        if (pick < samples)
+            break;
+        pick -= samples;
+    }
+
+    // the environment is the light after the last one
+    if (light_i == lights.num_lights) {
+        float pdf;
+        vec3 direction = sample_environment(seed, pdf);
+
+        // treat it as an area light facing the hit point at ENVIRONMENT_DISTANCE, so the raygen shader can
+        // handle it like any other: converted back to solid angle there, the distance cancels out
+        result.position = hit_pos + ENVIRONMENT_DISTANCE * direction;
+        result.direction = direction;
+        result.normal = -direction;
+        result.radiance = environment_radiance(direction);
+        result.pdf = light_select_pdf(1) * pdf / (ENVIRONMENT_DISTANCE * ENVIRONMENT_DISTANCE);
+        return result;
+    }
+
     Light light = lights.lights[light_i];
 
     if (light.type == EMITTER_TYPE_POINT) {
@@ -17,7 +44,7 @@ EmitterSample sample_light(vec3 hit_pos, inout uint seed) {
         result.direction = normalize(light.position - hit_pos);
         result.normal = -result.direction;
         result.radiance = light.color;
-        result.pdf = 1.0 / lights.num_lights;
+        result.pdf = light_select_pdf(light.samples);
     } else if (light.type == EMITTER_TYPE_AREA) {
         float s = rnd(seed);
         float t = sqrt(rnd(seed));
@@ -35,7 +62,7 @@ EmitterSample sample_light(vec3 hit_pos, inout uint seed) {
         result.direction = normalize(result.position - hit_pos);
         result.normal = normal;
         result.radiance = light.color;
-        result.pdf = 1.0 / lights.num_lights / area;
+        result.pdf = light_select_pdf(light.samples) / area;
     } else if (light.type == EMITTER_TYPE_DIRECTIONAL) {
         vec3 light_dir = normalize(light.data[0]);
         vec3 dir_to_light = -light_dir;
@@ -54,7 +81,7 @@ EmitterSample sample_light(vec3 hit_pos, inout uint seed) {
         result.direction = dir_to_light;
         result.normal = light_dir;
         result.radiance = in_beam ? light.color * dist_sq : vec3(0);
-        result.pdf = 1.0 / lights.num_lights;
+        result.pdf = light_select_pdf(light.samples);
     }
 
     return result;
@@ -0,0 +1,102 @@
+// lookups and importance sampling for the Environment buffer in hit_common.glsl
+
+// the distance sample_light puts the environment at, shadow rays toward it stop here too
+const float ENVIRONMENT_DISTANCE = 1000.0;
+
+// keeps the pdfs finite at the poles, where the solid angle of a pixel goes to 0
+const float ENVIRONMENT_MIN_SIN_THETA = 1e-6;
+
+uint environment_marginal_offset() {
+    return 3 * environment.width * environment.height;
+}
+
+uint environment_conditional_offset(uint y) {
+    return environment_marginal_offset() + environment.height + 1 + y * (environment.width + 1);
+}
+
+// z is up, same mapping as EnvironmentMap in environment.rs
+vec2 environment_uv(vec3 direction) {
+    float u = atan(direction.y, direction.x) / (2.0 * PI) + 0.5;
+    float v = acos(clamp(direction.z, -1.0, 1.0)) / PI;
+    return vec2(u, v);
+}
+
+vec3 environment_direction(vec2 uv) {
+    float phi = (uv.x - 0.5) * 2.0 * PI;
+    float theta = uv.y * PI;
+    return vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+}
+
+uvec2 environment_pixel(vec2 uv) {
+    uvec2 size = uvec2(environment.width, environment.height);
+    return min(uvec2(uv * vec2(size)), size - 1u);
+}
+
+vec3 environment_radiance(vec3 direction) {
+    if (environment.width == 0)
+        return vec3(0);
+
+    uvec2 pixel = environment_pixel(environment_uv(direction));
+    uint i = 3 * (pixel.y * environment.width + pixel.x);
+    return vec3(environment.data[i], environment.data[i + 1], environment.data[i + 2]);
+}
+
+// probability density of the pixel in uv space, which is constant over the pixel
+float environment_pixel_pdf(uvec2 pixel) {
+    uint m = environment_marginal_offset() + pixel.y;
+    uint c = environment_conditional_offset(pixel.y) + pixel.x;
+    float row_pdf = (environment.data[m + 1] - environment.data[m]) * float(environment.height);
+    float column_pdf = (environment.data[c + 1] - environment.data[c]) * float(environment.width);
+    return row_pdf * column_pdf;
+}
+
+// uv space to solid angle, the map covers 2pi by pi radians
+float environment_solid_angle_pdf(float pixel_pdf, float v) {
+    float sin_theta = max(sin(v * PI), ENVIRONMENT_MIN_SIN_THETA);
+    return pixel_pdf / (2.0 * PI * PI * sin_theta);
+}
+
+// solid angle pdf of sample_environment returning direction, not counting light selection
+float environment_pdf(vec3 direction) {
+    if (environment.width == 0)
+        return 0.0;
+
+    vec2 uv = environment_uv(direction);
+    return environment_solid_angle_pdf(environment_pixel_pdf(environment_pixel(uv)), uv.y);
+}
+
+// last entry of the count + 1 entry cdf at offset that is <= xi
+uint environment_find_interval(uint offset, uint count, float xi) {
+    uint lo = 0;
+    uint hi = count;
+    while (lo + 1 < hi) {
+        uint mid = (lo + hi) / 2;
+        if (environment.data[offset + mid] <= xi)
+            lo = mid;
+        else
+            hi = mid;
+    }
+    return lo;
+}
+
+// direction toward the environment proportional to its luminance, needs rnd from random.glsl
+vec3 sample_environment(inout uint seed, out float pdf) {
+    float xi_v = rnd(seed);
+    float xi_u = rnd(seed);
+
+    uint m = environment_marginal_offset();
+    uint y = environment_find_interval(m, environment.height, xi_v);
+    float v_lo = environment.data[m + y];
+    float v_hi = environment.data[m + y + 1];
+    float v = (float(y) + (xi_v - v_lo) / (v_hi - v_lo)) / float(environment.height);
+
+    uint c = environment_conditional_offset(y);
+    uint x = environment_find_interval(c, environment.width, xi_u);
+    float u_lo = environment.data[c + x];
+    float u_hi = environment.data[c + x + 1];
+    float u = (float(x) + (xi_u - u_lo) / (u_hi - u_lo)) / float(environment.width);
+
+    // straight from the cdfs rather than through environment_pdf, so it's the pixel that was actually picked
+    pdf = environment_solid_angle_pdf(environment_pixel_pdf(uvec2(x, y)), v);
+    return environment_direction(vec2(u, v));
+}
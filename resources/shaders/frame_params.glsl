@@ -0,0 +1,31 @@
+#extension GL_EXT_scalar_block_layout : enable
+
+// per-frame render parameters, a uniform buffer so there's room to grow (push constants only guarantee 128 bytes)
+// has to match frame_data in raytrace.rs, which is bound with a dynamic offset per frame in flight
+layout(scalar, set = 0, binding = 8) uniform FrameParams {
+    mat4 view_inverse;
+    mat4 proj_inverse;
+    // thin lens, both in world units - aperture is the lens diameter (0 is a pinhole)
+    // and focus_distance is how far along the view direction things are in focus
+    float aperture;
+    float focus_distance;
+    // where in the shutter interval this frame was traced, 0 is previous_transform and 1 is transform
+    // the tlas is rebuilt at this time every frame, accumulation over frames is what makes the blur
+    float shutter_time;
+    // anything but DEBUG_MODE_OFF replaces the shaded image with a view of the primary hit, see debug.glsl
+    uint debug_mode;
+    // background for clear_color.rmiss
+    vec3 clear_color;
+    // how raygen shaders that jitter within pixels should pick their subpixel offsets (render.sampler)
+    // SAMPLER_RANDOM draws from the rng like everything else, SAMPLER_HALTON uses sample index
+    // frame * spp + i of the 2-3 halton sequence, rotated per pixel so neighbours don't line up
+    // (not called sampler since that's a type in vulkan glsl)
+    uint pixel_sampler;
+    // whether raygen shaders should fill aov_images (render.aovs), they're 1x1 otherwise
+    uint write_aovs;
+    // render.taa, raygen shaders leave the image to taa.comp then (see store_color in raygen_common.glsl)
+    uint taa;
+    // whether taa.comp has a history to blend with, and the view projection of the frame that's in it
+    uint taa_history;
+    mat4 previous_view_projection;
+};
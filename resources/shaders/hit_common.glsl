@@ -1,6 +1,9 @@
 struct Vertex {
     vec3 position;
     vec3 normal;
+    vec2 uv;
+    // xyz along u, w is the sign of the bitangent, all zero without texture coordinates
+    vec4 tangent;
 };
 
 struct Light {
@@ -8,6 +11,7 @@ struct Light {
     vec3 color;
     vec3 position;
     vec3 data[3]; // area light: data = vertices, directional light: data[0] = direction and data[1].r = radius
+    uint samples; // weight for picking this light in sample_light, see Light in mesh.rs
 };
 
 struct Offsets {
@@ -26,6 +30,8 @@ layout(scalar, set = 0, binding = 3) readonly buffer Vertices {
 
 layout(scalar, set = 0, binding = 4) readonly buffer Lights {
     uint num_lights;
+    // sum of every light's samples
+    uint total_samples;
     Light lights[];
 } lights;
 
@@ -33,4 +39,58 @@ layout(scalar, set = 0, binding = 5) readonly buffer InstanceOffsets {
     Offsets offsets[];
 } offsets;
 
+// Object::custom_index (or ProceduralObject::custom_index) of every instance, index with gl_InstanceID
+layout(scalar, set = 0, binding = 9) readonly buffer InstanceIds {
+    uint ids[];
+} instance_ids;
+
+// equirectangular environment map, width and height are 0 if the scene doesn't have one
+// data is the rgb pixels row by row, then the marginal cdf over rows (height + 1 entries), then the cdf
+// within each row (width + 1 entries each), see environment.glsl
+layout(scalar, set = 0, binding = 7) readonly buffer Environment {
+    uint width;
+    uint height;
+    float data[];
+} environment;
+
+// see NormalMap::buffer: the offset of each brdf's map in data (NO_NORMAL_MAP if it has none), then the maps,
+// each its width, height and rgba8 pixels row by row from the top
+layout(scalar, set = 0, binding = 10) readonly buffer NormalMaps {
+    uint data[];
+} normal_maps;
+
+const uint NO_NORMAL_MAP = 0xffffffffu;
+
+// -1 for a mirroring object to world transform, which turns cross products around. tangent.w has to be
+// multiplied with it when the tangent goes to world space, or mirrored objects get their normal map's green flipped
+float transform_handedness(mat4x3 object_to_world) {
+    return determinant(mat3(object_to_world)) < 0.0 ? -1.0 : 1.0;
+}
+
+// shading normal perturbed by the normal map of brdf_i, everything in the same (world) space
+// without a map or a tangent (the mesh has no texture coordinates) the normal is left alone
+// the map is read from a storage buffer at the nearest texel, with no filtering or mipmaps, so it aliases
+// when it's minified and shows its texels when it's magnified
+vec3 apply_normal_map(uint brdf_i, vec2 uv, vec4 tangent, vec3 normal) {
+    uint map = normal_maps.data[brdf_i];
+    if (map == NO_NORMAL_MAP || dot(tangent.xyz, tangent.xyz) == 0.0)
+        return normal;
+
+    // nearest texel, repeating. obj texture coordinates have v going up the image
+    uvec2 size = uvec2(normal_maps.data[map], normal_maps.data[map + 1]);
+    uvec2 texel = min(uvec2(fract(vec2(uv.x, 1.0 - uv.y)) * vec2(size)), size - 1u);
+    vec3 local = unpackUnorm4x8(normal_maps.data[map + 2 + texel.y * size.x + texel.x]).xyz * 2.0 - 1.0;
+
+    // interpolation bends the tangent away from the normal, so straighten it out again
+    vec3 t = normalize(tangent.xyz - normal * dot(normal, tangent.xyz));
+    vec3 b = (tangent.w < 0.0 ? -1.0 : 1.0) * cross(normal, t);
+    return normalize(local.x * t + local.y * b + local.z * normal);
+}
+
+// sample_light picks between the lights and the environment map in proportion to their samples
+// the environment map always has 1
+float light_select_pdf(uint samples) {
+    return float(samples) / float(lights.total_samples + (environment.width > 0 ? 1 : 0));
+}
+
 #define BRDF_PARAMS_BINDING 6
\ No newline at end of file
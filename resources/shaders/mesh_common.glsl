@@ -2,9 +2,14 @@ struct MeshHitInfo {
     vec3 position;
     vec3 normal;
     vec3 geo_normal;
+    vec3 bary;
     bool is_backface;
 };
 
+vec3 mesh_bary(vec2 bary_coord) {
+    return vec3(1.0 - bary_coord.x - bary_coord.y, bary_coord);
+}
+
 MeshHitInfo compute_mesh_hit(vec2 bary_coord) {
     MeshHitInfo info;
 
@@ -12,7 +17,8 @@ MeshHitInfo compute_mesh_hit(vec2 bary_coord) {
     Vertex b = vertices.vertices[gl_InstanceCustomIndexEXT + 3*gl_PrimitiveID + 1];
     Vertex c = vertices.vertices[gl_InstanceCustomIndexEXT + 3*gl_PrimitiveID + 2];
 
-    vec3 bary = vec3(1.0 - bary_coord.x - bary_coord.y, bary_coord);
+    vec3 bary = mesh_bary(bary_coord);
+    info.bary = bary;
 
     vec3 local_pos = a.position * bary.x + b.position * bary.y + c.position * bary.z;
     info.position = vec3(gl_ObjectToWorldEXT * vec4(local_pos, 1.0));
@@ -20,6 +26,12 @@ MeshHitInfo compute_mesh_hit(vec2 bary_coord) {
     vec3 local_normal = a.normal * bary.x + b.normal * bary.y + c.normal * bary.z;
     info.normal = normalize(vec3(gl_ObjectToWorldEXT * vec4(local_normal, 0.0)));
 
+    vec2 uv = a.uv * bary.x + b.uv * bary.y + c.uv * bary.z;
+    vec4 local_tangent = a.tangent * bary.x + b.tangent * bary.y + c.tangent * bary.z;
+    vec4 tangent = vec4(vec3(gl_ObjectToWorldEXT * vec4(local_tangent.xyz, 0.0)),
+                        local_tangent.w * transform_handedness(gl_ObjectToWorldEXT));
+    info.normal = apply_normal_map(offsets.offsets[gl_InstanceID].brdf_i, uv, tangent, info.normal);
+
     vec3 edge1 = b.position - a.position;
     vec3 edge2 = c.position - a.position;
     vec3 face_normal = normalize(cross(edge1, edge2));
@@ -33,14 +45,3 @@ MeshHitInfo compute_mesh_hit(vec2 bary_coord) {
 
     return info;
 }
-
-vec3 compute_mesh_hit_position(vec2 bary_coord) {
-    Vertex a = vertices.vertices[gl_InstanceCustomIndexEXT + 3*gl_PrimitiveID];
-    Vertex b = vertices.vertices[gl_InstanceCustomIndexEXT + 3*gl_PrimitiveID + 1];
-    Vertex c = vertices.vertices[gl_InstanceCustomIndexEXT + 3*gl_PrimitiveID + 2];
-
-    vec3 bary = vec3(1.0 - bary_coord.x - bary_coord.y, bary_coord);
-    vec3 local_pos = a.position * bary.x + b.position * bary.y + c.position * bary.z;
-    return vec3(gl_ObjectToWorldEXT * vec4(local_pos, 1.0));
-}
-
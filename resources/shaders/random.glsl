@@ -29,6 +29,18 @@ uint lcg(inout uint prev) {
     return prev & 0x00FFFFFF;
 }
 
+// Radical inverse of index in the given base, i.e. the index-th element of a halton sequence
+float halton(uint index, uint base) {
+    float result = 0.0;
+    float f = 1.0;
+    while (index > 0) {
+        f /= float(base);
+        result += f * float(index % base);
+        index /= base;
+    }
+    return result;
+}
+
 // Generate a random float in [0, 1) given the previous RNG state
 float rnd(inout uint prev) {
     return (float(lcg(prev)) / float(0x01000000));
@@ -12,6 +12,10 @@ struct RayPayload {
     vec3 hit_pos;
     vec3 hit_normal;
     vec3 hit_geo_normal;
+    // barycentrics of the hit triangle, only written by triangle hit shaders (used by the wireframe debug mode)
+    vec3 hit_bary;
+    // gl_InstanceID of the hit, for the instance aov (see store_aovs in raygen_common.glsl)
+    uint hit_instance;
 
     vec3 brdf_vals;
     vec3 brdf_d;
@@ -1,9 +1,116 @@
 layout(set = 0, binding = 0) writeonly uniform image2D image;
 layout(set = 0, binding = 1, rgba32f) uniform image2D accum_image;
 layout(set = 0, binding = 2) uniform accelerationStructureEXT tlas;
+// just the random offsets and the render region, everything else is in FrameParams (frame_params.glsl)
+// only the region_size pixels starting at region_offset get traced, which is the whole image by default
 layout(push_constant) uniform Constants {
-    mat4 view_inverse;
-    mat4 proj_inverse;
     uvec2 seed_offset;
     uint frame;
+    uvec2 region_offset;
+    uvec2 region_size;
 };
+
+#include "frame_params.glsl"
+
+// extra outputs from the camera rays' first hits, for compositing and denoising (see Aovs in aovs.rs)
+// 0 holds the albedo and depth, 1 the normal and the instance index
+layout(set = 0, binding = 11, rgba32f) uniform image2D aov_images[2];
+
+// instance index where camera rays hit nothing, stored as -1
+const uint NO_INSTANCE = 0xffffffff;
+
+// launch and invocation ids only cover the render region, this is the image pixel they're for
+uvec2 region_pixel(uvec2 id) {
+    return id + region_offset;
+}
+
+// the whole image, not just the region
+uvec2 image_size() {
+    return uvec2(imageSize(accum_image));
+}
+
+const uint DEBUG_MODE_OFF = 0;
+const uint DEBUG_MODE_NORMALS = 1;
+const uint DEBUG_MODE_WIREFRAME = 2;
+
+const uint SAMPLER_RANDOM = 0;
+const uint SAMPLER_HALTON = 1;
+
+// with taa every pixel gets the same subpixel offset each frame, like jittering the projection
+// it cycles through this many points of the 2-3 halton sequence
+const uint TAA_JITTER_PERIOD = 16;
+
+// cull masks for each kind of ray, objects set which of these can hit them with visibility_mask
+const uint VISIBILITY_CAMERA = 0x01;
+const uint VISIBILITY_SHADOW = 0x02;
+const uint VISIBILITY_INDIRECT = 0x04;
+
+// flags for every ray, back faces only actually get culled on objects that ask for it (see Cull in mesh.rs)
+const uint RAY_FLAGS = gl_RayFlagsOpaqueEXT | gl_RayFlagsCullBackFacingTrianglesEXT;
+
+// how far in front of the camera plane a point is, along the view direction
+float view_depth(vec3 position) {
+    return dot(position - view_inverse[3].xyz, normalize(view_inverse[2].xyz));
+}
+
+// averages this frame's aovs into the images the way the accumulation averages the image
+// except for the instance, which is kept from the first frame - averaging indices would make up new ones
+void store_aovs(uvec2 pixel, vec3 albedo, vec3 normal, float depth, uint instance) {
+    if (write_aovs == 0)
+        return;
+
+    ivec2 p = ivec2(pixel);
+    float weight = 1.0 / (frame + 1.0);
+    vec4 albedo_depth = frame > 0 ? imageLoad(aov_images[0], p) : vec4(0);
+    vec4 normal_instance = frame > 0 ? imageLoad(aov_images[1], p) : vec4(0);
+    imageStore(aov_images[0], p, mix(albedo_depth, vec4(albedo, depth), weight));
+
+    // floats hold every index up to 2^24 exactly
+    float stored_instance = frame > 0 ? normal_instance.w : (instance == NO_INSTANCE ? -1.0 : float(instance));
+    imageStore(aov_images[1], p, vec4(mix(normal_instance.xyz, normal, weight), stored_instance));
+}
+
+// hands this frame's color to taa.comp, which blends it into its history and writes image
+// depth is view_depth of what the pixel shows (0 for nothing), for finding it in the previous frame
+void store_taa_color(uvec2 pixel, vec3 color, float depth) {
+    imageStore(accum_image, ivec2(pixel), vec4(color, depth));
+}
+
+// adds this frame's color to the accumulation and writes the average to image, or leaves it to taa.comp
+void store_color(uvec2 pixel, vec3 color, float depth) {
+    if (taa != 0) {
+        store_taa_color(pixel, color, depth);
+        return;
+    }
+
+    ivec2 p = ivec2(pixel);
+    vec3 rad = frame > 0 ? imageLoad(accum_image, p).rgb : vec3(0);
+    rad += color;
+    imageStore(accum_image, p, vec4(rad, 1.0));
+    imageStore(image, p, vec4(rad / (frame + 1.0), 1.0));
+}
+
+// camera ray through d (pixel position in [-1, 1]) in world space
+// unprojects the near and far plane so this works for both perspective and orthographic projections
+// the origin is moved back onto the camera plane (z = 0 in view space), which is the eye for perspective
+// lens_u is a uniform sample in [0, 1)^2 used to pick a point on the aperture
+void camera_ray(vec2 d, vec2 lens_u, out vec3 origin, out vec3 direction) {
+    vec4 near = proj_inverse * vec4(d, 0, 1);
+    vec4 far = proj_inverse * vec4(d, 1, 1);
+    vec3 ray_near = near.xyz / near.w;
+    vec3 ray_d = far.xyz / far.w - ray_near;
+    vec3 ray_o = ray_near - ray_d * (ray_near.z / ray_d.z);
+
+    if (aperture > 0.0) {
+        // everything on the focal plane stays put, everything else gets blurred
+        vec3 focus = ray_o + ray_d * (focus_distance / ray_d.z);
+        float r = 0.5 * aperture * sqrt(lens_u.x);
+        float phi = 2.0 * PI * lens_u.y;
+
+        ray_o += vec3(r * cos(phi), r * sin(phi), 0);
+        ray_d = focus - ray_o;
+    }
+
+    origin = (view_inverse * vec4(ray_o, 1)).xyz;
+    direction = normalize((view_inverse * vec4(ray_d, 0)).xyz);
+}
@@ -18,21 +18,103 @@ enum Direction {
     Down = 0x20,
 }
 
+// movement for a key given the view direction and the up vector
+type MovementFn = Box<dyn Fn(&Vec3, &Vec3) -> Vec3>;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    // vertical field of view in degrees
+    Perspective { fov: f32 },
+    // height of the view volume in world units
+    Orthographic { height: f32 },
+}
+
+impl Projection {
+    const NEAR: f32 = 0.1f32;
+    const FAR: f32 = 1000f32;
+
+    // flipped for vulkan with flip_y, see vulkan_y_flip
+    fn matrix(&self, aspect: f32, flip_y: bool) -> Mat4 {
+        let projection = match *self {
+            Projection::Perspective { fov } => {
+                let fov_radians = fov * PI / 180f32;
+                Mat4::perspective_lh(fov_radians, aspect, Self::NEAR, Self::FAR)
+            }
+            Projection::Orthographic { height } => {
+                let half_height = height / 2f32;
+                let half_width = half_height * aspect;
+                Mat4::orthographic_lh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    Self::NEAR,
+                    Self::FAR,
+                )
+            }
+        };
+
+        if flip_y {
+            vulkan_y_flip(projection)
+        } else {
+            projection
+        }
+    }
+}
+
+/// Negates clip space y, glam's projections have +y going up but in Vulkan's framebuffer coordinates it goes down
+///
+/// The raygen shader unprojects pixel coordinates with the inverse projection, so without this the image
+/// comes out upside down. Flipping twice gives back the original matrix.
+fn vulkan_y_flip(projection: Mat4) -> Mat4 {
+    Mat4::from_scale(Vec3::new(1f32, -1f32, 1f32)) * projection
+}
+
+// thin lens for depth of field, everything is in world units
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Lens {
+    // diameter of the lens, 0 means a pinhole camera with everything in focus
+    pub aperture: f32,
+    // distance along the view direction of the plane that is in focus
+    pub focus_distance: f32,
+}
+
+impl Default for Lens {
+    fn default() -> Self {
+        Lens {
+            aperture: 0f32,
+            focus_distance: 1f32,
+        }
+    }
+}
+
+/// Fly camera, looking along +z in camera space like glam's `_lh` matrices
+///
+/// World space is z up unless the scene sets another up vector. Mouse look turns around it, the view never
+/// rolls around the view direction, and space and ctrl move along it. This is unrelated to the y flip in
+/// the projection matrix, which only turns glam's +y up clip space into Vulkan's +y down framebuffer.
 pub struct Camera {
     // matrix from world space to camera space
     view: Mat4,
     // matrix from camera to clip space
-    perspective: Mat4,
+    projection_matrix: Mat4,
 
-    fov: f32,
+    projection: Projection,
+    lens: Lens,
+    // whether projection_matrix includes vulkan_y_flip, true unless the target wants y going up
+    flip_y: bool,
 
     position: Vec3,
     direction: Vec3,
+    // unit length
+    up: Vec3,
 
-    key_movements: BTreeMap<KeyCode, (Direction, Box<dyn Fn(&Vec3) -> Vec3>)>,
+    key_movements: BTreeMap<KeyCode, (Direction, MovementFn)>,
     movement_direction: u32,
     updated_view: bool,
 
+    // units per second, before speed_modifier
+    speed: f32,
     speed_modifier: f32,
 }
 
@@ -40,8 +122,9 @@ impl Debug for Camera {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Camera")
             .field("view", &self.view)
-            .field("perspective", &self.perspective)
-            .field("fov", &self.fov)
+            .field("projection_matrix", &self.projection_matrix)
+            .field("projection", &self.projection)
+            .field("lens", &self.lens)
             .field("position", &self.position)
             .field("direction", &self.direction)
             .finish_non_exhaustive()
@@ -49,76 +132,142 @@ impl Debug for Camera {
 }
 
 impl Camera {
-    const SPEED: f32 = 5f32;
+    pub const SPEED: f32 = 5f32;
+    pub const UP: Vec3 = Vec3::Z;
 
-    pub fn new(view: Mat4, fov: f32) -> Camera {
-        let mut key_movements: BTreeMap<KeyCode, (Direction, Box<dyn Fn(&Vec3) -> Vec3>)> =
-            BTreeMap::new();
+    pub fn new(view: Mat4, projection: Projection, lens: Lens) -> Camera {
+        let mut key_movements: BTreeMap<KeyCode, (Direction, MovementFn)> = BTreeMap::new();
 
         key_movements.insert(
             KeyCode::KeyW,
-            (Direction::Forward, Box::new(|dir: &Vec3| *dir)),
+            (Direction::Forward, Box::new(|dir: &Vec3, _: &Vec3| *dir)),
         );
         key_movements.insert(
             KeyCode::KeyS,
-            (Direction::Backward, Box::new(|dir: &Vec3| -*dir)),
+            (Direction::Backward, Box::new(|dir: &Vec3, _: &Vec3| -*dir)),
         );
         key_movements.insert(
             KeyCode::KeyA,
             (
                 Direction::Left,
-                Box::new(|dir: &Vec3| Vec3::new(dir.y, -dir.x, 0f32).normalize()),
+                Box::new(|dir: &Vec3, up: &Vec3| dir.cross(*up).normalize()),
             ),
         );
         key_movements.insert(
             KeyCode::KeyD,
             (
                 Direction::Right,
-                Box::new(|dir: &Vec3| Vec3::new(-dir.y, dir.x, 0f32).normalize()),
+                Box::new(|dir: &Vec3, up: &Vec3| up.cross(*dir).normalize()),
             ),
         );
         key_movements.insert(
             KeyCode::ControlLeft,
             (
                 Direction::Down,
-                Box::new(|dir: &Vec3| dir.cross(Vec3::new(dir.y, -dir.x, 0f32).normalize())),
+                Box::new(|dir: &Vec3, up: &Vec3| dir.cross(dir.cross(*up).normalize())),
             ),
         );
         key_movements.insert(
             KeyCode::Space,
             (
                 Direction::Up,
-                Box::new(|dir: &Vec3| dir.cross(Vec3::new(-dir.y, dir.x, 0f32).normalize())),
+                Box::new(|dir: &Vec3, up: &Vec3| dir.cross(up.cross(*dir).normalize())),
             ),
         );
 
-        let fov_radians = fov * PI / 180f32;
-        let mut perspective = Mat4::perspective_lh(
-            fov_radians,
+        let projection_matrix = projection.matrix(
             WindowData::DEFAULT_WIDTH as f32 / WindowData::DEFAULT_HEIGHT as f32,
-            0.1f32,
-            1000f32,
+            true,
         );
-        perspective.y_axis = -perspective.y_axis;
 
         Camera {
             view,
-            perspective,
-            fov,
+            projection_matrix,
+            projection,
+            lens,
+            flip_y: true,
             position: view.inverse().col(3).truncate(),
             direction: view.inverse().col(2).truncate(),
+            up: Camera::UP,
             key_movements,
             movement_direction: Direction::None as u32,
             updated_view: false,
+            speed: Camera::SPEED,
             speed_modifier: 1f32,
         }
     }
 
+    /// Camera at `eye` looking at `target`, with `up` as the world space up vector
+    pub fn look_at(
+        eye: Vec3,
+        target: Vec3,
+        up: Vec3,
+        projection: Projection,
+        lens: Lens,
+    ) -> Camera {
+        let mut camera = Camera::new(Mat4::look_at_lh(eye, target, up), projection, lens);
+        camera.set_up(up);
+        camera.updated_view = true;
+        camera
+    }
+
+    pub fn set_position(&mut self, position: Vec3) {
+        self.position = position;
+        self.updated_view = true;
+    }
+
+    /// Doesn't need to be normalized, but shouldn't be parallel to the up vector
+    pub fn set_direction(&mut self, direction: Vec3) {
+        self.direction = direction.normalize();
+        self.updated_view = true;
+    }
+
+    /// Turns the camera towards `target`, which has to be somewhere other than the camera's position
+    pub fn set_target(&mut self, target: Vec3) {
+        self.set_direction(target - self.position);
+    }
+
+    /// Turns towards the sphere at `center` and backs up until all of it is in view
+    ///
+    /// Orthographic views can't zoom out by moving, so the sphere is only moved in front of the camera.
+    pub fn frame(&mut self, center: Vec3, radius: f32) {
+        // looking at the center from the center doesn't say which way to look, so keep the direction
+        if center.distance(self.position) > f32::EPSILON {
+            self.set_target(center);
+        }
+        let distance = match self.projection {
+            Projection::Perspective { fov } => radius / (fov * PI / 360f32).sin(),
+            Projection::Orthographic { .. } => 2f32 * radius,
+        };
+        self.set_position(center - self.direction * distance.max(Projection::NEAR));
+    }
+
     pub fn handle_resize(&mut self, width: u32, height: u32) {
-        let fov_radians = self.fov * PI / 180f32;
-        self.perspective =
-            Mat4::perspective_lh(fov_radians, width as f32 / height as f32, 0.1f32, 1000f32);
-        self.perspective.y_axis = -self.perspective.y_axis;
+        self.projection_matrix = self.projection_at(width, height);
+    }
+
+    /// The projection matrix for an image of this size, without resizing the camera
+    pub fn projection_at(&self, width: u32, height: u32) -> Mat4 {
+        self.projection
+            .matrix(width as f32 / height as f32, self.flip_y)
+    }
+
+    /// Whether to flip y for Vulkan (the default), turn it off for targets that expect +y to go up
+    pub fn set_flip_y(&mut self, flip_y: bool) {
+        if flip_y != self.flip_y {
+            self.projection_matrix = vulkan_y_flip(self.projection_matrix);
+            self.flip_y = flip_y;
+        }
+    }
+
+    /// World space up vector, [`Camera::UP`] by default, doesn't need to be normalized
+    pub fn set_up(&mut self, up: Vec3) {
+        self.up = up.normalize();
+    }
+
+    /// Movement speed in units per second, [`Camera::SPEED`] by default
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
     }
 
     pub fn handle_key_input(&mut self, key: KeyCode, pressed: bool) {
@@ -135,15 +284,17 @@ impl Camera {
     }
 
     pub fn handle_mouse_input(&mut self, rx: f32, ry: f32) {
-        let ry_axis = Vec3::new(-self.direction.y, self.direction.x, 0f32);
-        let rx_axis = Vec3::new(0f32, 0f32, 1f32);
+        let ry_axis = self.up.cross(self.direction);
+        let rx_axis = self.up;
 
         let rot_x = Mat3::from_axis_angle(rx_axis, rx);
         let rot_y = Mat3::from_axis_angle(ry_axis.normalize(), ry);
 
         let new_direction = rot_x * rot_y * self.direction;
 
-        if new_direction.truncate().dot(self.direction.truncate()) < 0f32 {
+        // don't tilt over the top, that would flip the view upside down
+        let horizontal = |v: Vec3| v - self.up * v.dot(self.up);
+        if horizontal(new_direction).dot(horizontal(self.direction)) < 0f32 {
             self.direction = (rot_x * self.direction).normalize();
         } else {
             self.direction = new_direction.normalize();
@@ -156,7 +307,7 @@ impl Camera {
         for (d, movement_fn) in self.key_movements.values() {
             if self.movement_direction & (*d as u32) == (*d as u32) {
                 self.position +=
-                    Camera::SPEED * dt * self.speed_modifier * movement_fn(&self.direction);
+                    self.speed * dt * self.speed_modifier * movement_fn(&self.direction, &self.up);
                 self.updated_view = true;
             }
         }
@@ -167,7 +318,7 @@ impl Camera {
             return None;
         }
 
-        self.view = Mat4::look_to_lh(self.position, self.direction, Vec3::new(0f32, 0f32, 1f32));
+        self.view = Mat4::look_to_lh(self.position, self.direction, self.up);
         self.updated_view = false;
 
         Some(self.view)
@@ -177,7 +328,150 @@ impl Camera {
         self.view
     }
 
-    pub fn perspective(&self) -> Mat4 {
-        self.perspective
+    pub fn projection(&self) -> Mat4 {
+        self.projection_matrix
+    }
+
+    pub fn lens(&self) -> Lens {
+        self.lens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec3};
+
+    use super::{vulkan_y_flip, Camera, Lens, Projection};
+
+    #[test]
+    fn set_position_and_direction() {
+        let eye = Vec3::new(4.0, 0.0, 2.0);
+        let mut camera = Camera::new(
+            Mat4::look_at_lh(eye, Vec3::ZERO, Vec3::Z),
+            Projection::Perspective { fov: 70.0 },
+            Lens::default(),
+        );
+        assert_eq!(camera.update_view(), None);
+
+        camera.set_position(Vec3::new(0.0, -3.0, 0.0));
+        camera.set_target(Vec3::new(0.0, 0.0, 0.0));
+        let view = camera.update_view().unwrap();
+        assert!(view
+            .transform_point3(Vec3::ZERO)
+            .abs_diff_eq(Vec3::new(0.0, 0.0, 3.0), 1e-5));
+
+        camera.set_direction(Vec3::new(2.0, 0.0, 0.0));
+        let view = camera.update_view().unwrap();
+        assert!(view
+            .transform_point3(Vec3::new(1.0, -3.0, 0.0))
+            .abs_diff_eq(Vec3::Z, 1e-5));
+    }
+
+    #[test]
+    fn look_at() {
+        let eye = Vec3::new(4.0, 0.0, 2.0);
+        let mut camera = Camera::look_at(
+            eye,
+            Vec3::ZERO,
+            Vec3::Y,
+            Projection::Perspective { fov: 70.0 },
+            Lens::default(),
+        );
+        // same view as the lookat transform, and turning around the given up vector from there
+        let view = camera.update_view().unwrap();
+        assert!(view.abs_diff_eq(Mat4::look_at_lh(eye, Vec3::ZERO, Vec3::Y), 1e-5));
+        assert_eq!(camera.update_view(), None);
+        assert_eq!(camera.position, eye);
+        assert_eq!(camera.up, Vec3::Y);
+    }
+
+    #[test]
+    fn frame_sphere() {
+        let mut camera = Camera::new(
+            Mat4::look_at_lh(Vec3::new(0.0, -10.0, 0.0), Vec3::ZERO, Vec3::Z),
+            Projection::Perspective { fov: 90.0 },
+            Lens::default(),
+        );
+        let center = Vec3::new(5.0, 0.0, 0.0);
+        camera.frame(center, 2.0);
+        let view = camera.update_view().unwrap();
+
+        // straight ahead, just far enough that the sphere touches the top and bottom of the view
+        let center_view = view.transform_point3(center);
+        assert!(center_view.abs_diff_eq(Vec3::new(0.0, 0.0, 2.0 * 2f32.sqrt()), 1e-5));
+
+        // from the center itself it backs up along the current direction
+        let direction = (center - camera.position).normalize();
+        camera.set_position(center);
+        camera.frame(center, 1.0);
+        assert!(camera.direction.abs_diff_eq(direction, 1e-5));
+        assert!((camera.position.distance(center) - 2f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn projection_y_flip() {
+        let mut camera = Camera::new(
+            Mat4::IDENTITY,
+            Projection::Perspective { fov: 90.0 },
+            Lens::default(),
+        );
+        camera.handle_resize(100, 100);
+        let ndc = |camera: &Camera, point: Vec3| camera.projection().project_point3(point);
+        let close = |a: Vec3, b: Vec3| (a - b).abs().max_element() < 1e-5;
+
+        // up in view space ends up at the top of the framebuffer, which is -y in vulkan
+        assert!(close(
+            ndc(&camera, Vec3::new(0.0, 1.0, 1.0)),
+            Vec3::new(0.0, -1.0, ndc(&camera, Vec3::Z).z)
+        ));
+        assert!(close(
+            ndc(&camera, Vec3::new(1.0, 0.0, 1.0))
+                .truncate()
+                .extend(0.0),
+            Vec3::X
+        ));
+        assert!(close(
+            ndc(&camera, Vec3::new(0.0, 0.0, Projection::NEAR)),
+            Vec3::ZERO
+        ));
+
+        // and it stays that way across resizes
+        camera.handle_resize(200, 100);
+        assert!(close(
+            ndc(&camera, Vec3::new(2.0, 1.0, 1.0))
+                .truncate()
+                .extend(0.0),
+            Vec3::new(1.0, -1.0, 0.0)
+        ));
+        let projection = camera.projection();
+        assert_eq!(vulkan_y_flip(vulkan_y_flip(projection)), projection);
+
+        // without the flip up stays +y, also across resizes
+        camera.set_flip_y(false);
+        assert!(close(
+            ndc(&camera, Vec3::new(2.0, 1.0, 1.0))
+                .truncate()
+                .extend(0.0),
+            Vec3::new(1.0, 1.0, 0.0)
+        ));
+        camera.handle_resize(100, 100);
+        assert!(close(
+            ndc(&camera, Vec3::new(0.0, 1.0, 1.0)),
+            Vec3::new(0.0, 1.0, ndc(&camera, Vec3::Z).z)
+        ));
+        camera.set_flip_y(true);
+        assert!(close(
+            ndc(&camera, Vec3::new(0.0, 1.0, 1.0)),
+            Vec3::new(0.0, -1.0, ndc(&camera, Vec3::Z).z)
+        ));
+
+        for flip_y in [true, false] {
+            let orthographic = Projection::Orthographic { height: 2.0 }.matrix(1.0, flip_y);
+            let y = if flip_y { -1.0 } else { 1.0 };
+            assert!(close(
+                orthographic.project_point3(Vec3::new(1.0, 1.0, Projection::NEAR)),
+                Vec3::new(1.0, y, 0.0)
+            ));
+        }
     }
 }
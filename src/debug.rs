@@ -11,6 +11,56 @@ use ash::{
 };
 use log::{debug, error, info, warn};
 
+/// Overrides whether the validation layer is used at all, regardless of the build profile
+///
+/// Like the feature variables below, `0` turns it off and any other value turns it on.
+const VALIDATION_VAR: &str = "KUBGRUPP_VALIDATION";
+
+/// Validation features that can be toggled with environment variables, and whether they're on by default
+///
+/// Setting a variable to `0` turns its feature off, any other value turns it on.
+const VALIDATION_FEATURE_VARS: &[(&str, vk::ValidationFeatureEnableEXT, bool)] = &[
+    (
+        "KUBGRUPP_VK_DEBUG_PRINTF",
+        vk::ValidationFeatureEnableEXT::DEBUG_PRINTF,
+        false,
+    ),
+    (
+        "KUBGRUPP_VK_SYNC_VALIDATION",
+        vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION,
+        true,
+    ),
+    (
+        "KUBGRUPP_VK_BEST_PRACTICES",
+        vk::ValidationFeatureEnableEXT::BEST_PRACTICES,
+        true,
+    ),
+];
+
+/// Whether to enable validation, `default` unless `KUBGRUPP_VALIDATION` is set
+pub fn validation_enabled(default: bool) -> bool {
+    select_validation_enabled(default, |name| std::env::var(name).ok())
+}
+
+fn select_validation_enabled(default: bool, lookup: impl Fn(&str) -> Option<String>) -> bool {
+    lookup(VALIDATION_VAR).map_or(default, |x| x != "0")
+}
+
+/// Returns the validation features to enable, taking the `KUBGRUPP_VK_*` overrides into account
+pub fn validation_features() -> Vec<vk::ValidationFeatureEnableEXT> {
+    select_validation_features(|name| std::env::var(name).ok())
+}
+
+fn select_validation_features(
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Vec<vk::ValidationFeatureEnableEXT> {
+    VALIDATION_FEATURE_VARS
+        .iter()
+        .filter(|(name, _, default)| lookup(name).map_or(*default, |x| x != "0"))
+        .map(|(_, feature, _)| *feature)
+        .collect()
+}
+
 pub struct DebugUtilsData {
     loader: ext::debug_utils::Instance,
     messenger: DebugUtilsMessengerEXT,
@@ -64,3 +114,46 @@ pub unsafe extern "system" fn debug_callback(
 
     vk::FALSE
 }
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::ValidationFeatureEnableEXT;
+
+    use super::{select_validation_enabled, select_validation_features};
+
+    #[test]
+    fn validation_override() {
+        assert!(select_validation_enabled(true, |_| None));
+        assert!(!select_validation_enabled(false, |_| None));
+
+        let set = |value: &'static str| {
+            move |name: &str| (name == "KUBGRUPP_VALIDATION").then(|| value.to_string())
+        };
+        assert!(!select_validation_enabled(true, set("0")));
+        assert!(select_validation_enabled(false, set("1")));
+    }
+
+    #[test]
+    fn validation_feature_overrides() {
+        assert_eq!(
+            select_validation_features(|_| None),
+            [
+                ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION,
+                ValidationFeatureEnableEXT::BEST_PRACTICES,
+            ]
+        );
+
+        let features = select_validation_features(|name| match name {
+            "KUBGRUPP_VK_BEST_PRACTICES" => Some("0".to_string()),
+            "KUBGRUPP_VK_DEBUG_PRINTF" => Some("1".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            features,
+            [
+                ValidationFeatureEnableEXT::DEBUG_PRINTF,
+                ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION,
+            ]
+        );
+    }
+}
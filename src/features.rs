@@ -166,8 +166,12 @@ impl<'a> VkFeatureGuard<'a> {
         unsafe { &*self.head }
     }
 
-    /// Returns `true` if all features are supported, false otherwise
-    pub fn supported(&self, instance: &ash::Instance, device: vk::PhysicalDevice) -> bool {
+    /// Returns the structure types of every feature struct with at least one unsupported feature
+    pub fn unsupported(
+        &self,
+        instance: &ash::Instance,
+        device: vk::PhysicalDevice,
+    ) -> Vec<StructureType> {
         // create copy of features list
         // this copy will be mutated, which breaks the invariant,
         // so we must make sure the user never sees it
@@ -177,17 +181,18 @@ impl<'a> VkFeatureGuard<'a> {
         unsafe { instance.get_physical_device_features2(device, &mut *copy.head) };
 
         // check if all requested features are in the list
+        let mut unsupported = Vec::new();
         let mut curr = copy.head as *mut vk::BaseOutStructure;
         let mut all_features = self.parent.features.iter();
         while !curr.is_null() {
             let features = all_features.next().unwrap();
 
-            for &offset in &features.offsets {
+            let all_supported = features.offsets.iter().all(|&offset| {
                 let feature_ptr = unsafe { curr.byte_add(offset) } as *mut vk::Bool32;
-                let supported = unsafe { feature_ptr.read() };
-                if supported == vk::FALSE {
-                    return false;
-                }
+                (unsafe { feature_ptr.read() }) == vk::TRUE
+            });
+            if !all_supported {
+                unsupported.push(features.s_type);
             }
 
             curr = unsafe { (*curr).p_next };
@@ -195,7 +200,7 @@ impl<'a> VkFeatureGuard<'a> {
 
         // we should have gone through all features while iterating
         assert!(all_features.next().is_none());
-        true
+        unsupported
     }
 }
 
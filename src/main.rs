@@ -1,12 +1,14 @@
 use std::cell::RefCell;
-use std::ffi::{c_char, c_void, CStr};
-use std::fs::File;
-use std::path::Path;
+use std::ffi::{c_char, CStr, CString};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::rc::Rc;
-use std::time::Instant;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use ash::vk::{
     DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT,
     DebugUtilsMessengerCreateInfoEXT, EXT_DEBUG_UTILS_NAME,
@@ -17,25 +19,31 @@ use ash::{
     Entry, Instance,
 };
 
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
 use debug::DebugUtilsData;
 use defer::Defer;
 use env_logger::Builder;
 use gpu_allocator::vulkan::{Allocator, AllocatorCreateDesc};
-use log::{debug, info, warn, LevelFilter};
-use render::renderers::RaytraceRenderer;
+use log::{debug, error, info, warn, LevelFilter};
+use render::denoise::{self, BilateralDenoiser};
+use render::exr::{write_exr, Channel};
+use render::renderers::{preview, RayQueryRenderer, RaytraceRenderer};
+use render::screenshot::{encode_for_surface, write_png};
 use render::Renderer;
-use scene::scenes::mesh::{MeshScene, MeshSceneUpdate};
+use scene::asset_cache::AssetCache;
+use scene::scenes::mesh::{DebugMode, MeshScene, MeshSceneUpdate};
 use scene::Scene;
-use utils::{query_queue_families, QueueFamilyInfo};
-use window::WindowData;
+use utils::{
+    format_api_version, is_device_lost, query_queue_families, QueueFamilyInfo, VULKAN_API_VERSION,
+};
+use window::{SwapchainOptions, WindowData, HDR_SURFACE_FORMATS};
 use winit::application::ApplicationHandler;
 use winit::dpi::PhysicalSize;
 use winit::event::{DeviceEvent, DeviceId, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, EventLoop};
 use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::raw_window_handle::{HasDisplayHandle, HasWindowHandle};
-use winit::window::{CursorGrabMode, WindowAttributes, WindowId};
+use winit::window::{WindowAttributes, WindowId};
 
 mod camera;
 mod debug;
@@ -54,7 +62,11 @@ const DEBUG_MODE: bool = true;
 #[cfg(not(debug_assertions))]
 const DEBUG_MODE: bool = false;
 
-const APPLICATION_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "\0");
+const APPLICATION_NAME: &CStr =
+    match CStr::from_bytes_with_nul(concat!(env!("CARGO_PKG_NAME"), "\0").as_bytes()) {
+        Ok(name) => name,
+        Err(_) => panic!("crate name contains a nul byte"),
+    };
 
 struct MeshApp<R> {
     // WARNING: ORDER MATTERS HERE!!!
@@ -68,16 +80,122 @@ struct MeshApp<R> {
     device: Option<Device>,
     instance: Instance,
     vk_lib: Entry,
-    scene: MeshScene,
+    // every scene keeps its own camera, so switching back picks up where you left off
+    scenes: Vec<MeshScene>,
+    scene_loader: Option<SceneLoader>,
+    current_scene: usize,
     pending_resize: Option<(u32, u32)>,
+    // MeshAppBuilder::debug_mode, passed on to the renderer
+    debug: bool,
+    debug_mode: DebugMode,
+    pending_debug_mode: Option<DebugMode>,
+    pending_invalidate: bool,
+    pending_light_toggles: Vec<usize>,
     prev_instant: Option<Instant>,
+    // set when rendering stopped because the device was lost, so the process can exit with an error
+    device_lost: bool,
+}
+
+/// Loads a scene again by its index, see [`MeshAppBuilder::scene_loader`]
+type SceneLoader = Box<dyn Fn(usize) -> Result<MeshScene>>;
+
+/// Configures a [`MeshApp`] before it's created
+///
+/// Anything that isn't set keeps its default, see [`MeshAppBuilder::new`].
+struct MeshAppBuilder {
+    scenes: Vec<MeshScene>,
+    scene_loader: Option<SceneLoader>,
+    debug_mode: bool,
+    app_info: AppInfo,
+}
+
+/// What the instance tells the driver about the application, some drivers have app-specific behaviour keyed on it
+struct AppInfo {
+    name: CString,
+    version: u32,
+    engine_name: Option<CString>,
+    engine_version: u32,
+}
+
+impl Default for AppInfo {
+    fn default() -> Self {
+        Self {
+            name: APPLICATION_NAME.to_owned(),
+            version: vk::make_api_version(
+                0,
+                env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+                env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+                env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+            ),
+            engine_name: None,
+            engine_version: 0,
+        }
+    }
+}
+
+impl MeshAppBuilder {
+    /// No scenes, with debug mode on in debug builds
+    ///
+    /// The application is named and versioned after this crate, with no engine.
+    pub fn new() -> Self {
+        Self {
+            scenes: Vec::new(),
+            scene_loader: None,
+            debug_mode: DEBUG_MODE,
+            app_info: AppInfo::default(),
+        }
+    }
+
+    /// The scenes to cycle through, the first one is shown at startup
+    pub fn scenes(mut self, scenes: Vec<MeshScene>) -> Self {
+        self.scenes = scenes;
+        self
+    }
+
+    /// Loads scene `i` again for reloading it, without one scenes can't be reloaded
+    pub fn scene_loader(mut self, loader: impl Fn(usize) -> Result<MeshScene> + 'static) -> Self {
+        self.scene_loader = Some(Box::new(loader));
+        self
+    }
+
+    /// Whether to enable the validation layer and debug messenger (if they're available)
+    ///
+    /// The renderer gets it too, the raytrace renderer logs its SBT layout in debug mode.
+    pub fn debug_mode(mut self, debug_mode: bool) -> Self {
+        self.debug_mode = debug_mode;
+        self
+    }
+
+    /// The application and engine names and versions passed to the instance
+    pub fn app_info(mut self, app_info: AppInfo) -> Self {
+        self.app_info = app_info;
+        self
+    }
+
+    pub fn build<R>(self, event_loop: &EventLoop<()>) -> Result<MeshApp<R>>
+    where
+        R: Renderer<MeshScene, WindowData>,
+    {
+        if self.scenes.is_empty() {
+            bail!("at least one scene is required");
+        }
+
+        MeshApp::new(event_loop, self)
+    }
 }
 
 impl<R> MeshApp<R>
 where
     R: Renderer<MeshScene, WindowData>,
 {
-    pub fn new(event_loop: &EventLoop<()>, scene: MeshScene, debug_mode: bool) -> Result<Self> {
+    fn new(event_loop: &EventLoop<()>, options: MeshAppBuilder) -> Result<Self> {
+        let MeshAppBuilder {
+            scenes,
+            scene_loader,
+            debug_mode,
+            app_info,
+        } = options;
+
         let vk_lib = unsafe { Entry::load().expect("failed to load Vulkan library") };
 
         let enable_vk_debug = debug_mode && Self::is_vk_debug_supported(&vk_lib)?;
@@ -98,11 +216,13 @@ where
             ..Default::default()
         });
 
-        let validation_feature_enable = [
-            // vk::ValidationFeatureEnableEXT::DEBUG_PRINTF,
-            vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION,
-            vk::ValidationFeatureEnableEXT::BEST_PRACTICES,
-        ];
+        let validation_feature_enable = debug::validation_features();
+        if enable_vk_debug {
+            debug!(
+                "Enabled validation features: {:?}",
+                validation_feature_enable
+            );
+        }
         let mut validation_features = enable_vk_debug.then(|| vk::ValidationFeaturesEXT {
             enabled_validation_feature_count: validation_feature_enable.len() as u32,
             p_enabled_validation_features: validation_feature_enable.as_ptr(),
@@ -112,6 +232,7 @@ where
         let instance = Self::create_instance(
             &vk_lib,
             event_loop,
+            &app_info,
             debug_utils_info.as_mut(),
             validation_features.as_mut(),
         )?
@@ -133,9 +254,17 @@ where
             physical_device: None,
             instance: instance.undefer(),
             vk_lib,
-            scene,
+            scenes,
+            scene_loader,
+            current_scene: 0,
             pending_resize: None,
+            debug: debug_mode,
+            debug_mode: DebugMode::Off,
+            pending_debug_mode: None,
+            pending_invalidate: false,
+            pending_light_toggles: Vec::new(),
             prev_instant: None,
+            device_lost: false,
         })
     }
 
@@ -156,6 +285,7 @@ where
     }
 
     fn get_layers_and_extensions(
+        vk_lib: &Entry,
         event_loop: &EventLoop<()>,
         use_debug_layers: bool,
     ) -> Result<(Vec<*const c_char>, Vec<*const c_char>)> {
@@ -178,27 +308,47 @@ where
         extensions.extend_from_slice(required_extensions);
         extensions.extend_from_slice(required_renderer_extensions);
 
+        // optional, but without it surfaces don't offer hdr color spaces at all
+        let supported_extensions = unsafe { vk_lib.enumerate_instance_extension_properties(None)? };
+        if supported_extensions.iter().any(|x| unsafe {
+            CStr::from_ptr(x.extension_name.as_ptr()) == ext::swapchain_colorspace::NAME
+        }) {
+            extensions.push(ext::swapchain_colorspace::NAME.as_ptr());
+        }
+
         Ok((layers, extensions))
     }
 
     fn create_instance(
         vk_lib: &Entry,
         event_loop: &EventLoop<()>,
+        app_info: &AppInfo,
         debug_utils_info: Option<&mut DebugUtilsMessengerCreateInfoEXT>,
         validation_features: Option<&mut vk::ValidationFeaturesEXT>,
     ) -> Result<Instance> {
+        // a 1.0 loader doesn't even have vkEnumerateInstanceVersion
+        let instance_version =
+            unsafe { vk_lib.try_enumerate_instance_version()? }.unwrap_or(vk::API_VERSION_1_0);
+        if instance_version < VULKAN_API_VERSION {
+            bail!(
+                "Vulkan {} is required, but the installed loader only supports {}",
+                format_api_version(VULKAN_API_VERSION),
+                format_api_version(instance_version)
+            );
+        }
+
         let (layers, extensions) =
-            Self::get_layers_and_extensions(event_loop, debug_utils_info.is_some())?;
+            Self::get_layers_and_extensions(vk_lib, event_loop, debug_utils_info.is_some())?;
 
         let app_info = vk::ApplicationInfo {
-            p_application_name: APPLICATION_NAME.as_ptr() as *const c_char,
-            application_version: vk::make_api_version(
-                0,
-                env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
-                env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
-                env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
-            ),
-            api_version: vk::make_api_version(0, 1, 3, 0),
+            p_application_name: app_info.name.as_ptr(),
+            application_version: app_info.version,
+            p_engine_name: app_info
+                .engine_name
+                .as_ref()
+                .map_or(ptr::null(), |name| name.as_ptr()),
+            engine_version: app_info.engine_version,
+            api_version: VULKAN_API_VERSION,
             ..Default::default()
         };
 
@@ -227,6 +377,39 @@ where
         device: vk::PhysicalDevice,
         surface: vk::SurfaceKHR,
     ) -> Result<bool> {
+        let problems = Self::device_problems(&self.vk_lib, &self.instance, device, Some(surface))?;
+        if !problems.is_empty() {
+            let properties = unsafe { self.instance.get_physical_device_properties(device) };
+            warn!(
+                "Skipping physical device {:?}: {}",
+                properties.device_name_as_c_str().unwrap(),
+                problems.join(", ")
+            );
+        }
+
+        Ok(problems.is_empty())
+    }
+
+    /// Everything that keeps `device` from being used, empty if it's suitable
+    ///
+    /// Without a surface, only the compute queue family is checked, not the window's requirements.
+    fn device_problems(
+        vk_lib: &Entry,
+        instance: &Instance,
+        device: vk::PhysicalDevice,
+        surface: Option<vk::SurfaceKHR>,
+    ) -> Result<Vec<String>> {
+        let mut problems = Vec::new();
+
+        let properties = unsafe { instance.get_physical_device_properties(device) };
+        if properties.api_version < VULKAN_API_VERSION {
+            problems.push(format!(
+                "it supports Vulkan {}, but {} is required",
+                format_api_version(properties.api_version),
+                format_api_version(VULKAN_API_VERSION)
+            ));
+        }
+
         // check compatibility of device with window and renderer
         let required_renderer_extensions = R::required_device_extensions();
         let required_window_extensions = WindowData::required_device_extensions();
@@ -234,53 +417,123 @@ where
             [required_renderer_extensions, required_window_extensions].concat();
         let required_features = R::required_features();
 
-        let supported_extensions = unsafe {
-            self.instance
-                .enumerate_device_extension_properties(device)?
-        };
+        let supported_extensions =
+            unsafe { instance.enumerate_device_extension_properties(device)? };
 
         // check that all required extensions and features are supported (i.e. required is a subset of supported)
-        for ext in required_extensions {
-            let ext_name = unsafe { CStr::from_ptr(ext) };
-            if !supported_extensions
+        let missing_extensions: Vec<_> = required_extensions
+            .iter()
+            .map(|&ext| unsafe { CStr::from_ptr(ext) })
+            .filter(|&ext_name| {
+                !supported_extensions
+                    .iter()
+                    .any(|x| x.extension_name_as_c_str().unwrap() == ext_name)
+            })
+            .map(|x| x.to_string_lossy())
+            .collect();
+        if !missing_extensions.is_empty() {
+            problems.push(format!(
+                "missing extensions {}",
+                missing_extensions.join(", ")
+            ));
+        }
+
+        let unsupported_features = required_features.unsupported(instance, device);
+        if !unsupported_features.is_empty() {
+            problems.push(format!("missing features in {unsupported_features:?}"));
+        }
+
+        let Some(surface) = surface else {
+            let queue_families =
+                unsafe { instance.get_physical_device_queue_family_properties(device) };
+            if !queue_families
                 .iter()
-                .any(|x| x.extension_name_as_c_str().unwrap() == ext_name)
+                .any(|x| x.queue_flags.contains(vk::QueueFlags::COMPUTE))
             {
-                return Ok(false);
+                problems.push("no compute queue family".to_string());
             }
+            return Ok(problems);
+        };
+
+        if !WindowData::is_device_suitable(vk_lib, instance, device, surface)? {
+            problems.push("no surface formats or present modes for the window".to_string());
         }
 
-        if !required_features.supported(&self.instance, device) {
-            return Ok(false);
+        let queue_family_info = utils::query_queue_families(vk_lib, instance, device, surface)?;
+        if !R::has_required_queue_families(&queue_family_info) {
+            problems.push(format!(
+                "missing queue families (compute: {:?}, present: {:?})",
+                queue_family_info.compute_index, queue_family_info.present_index
+            ));
         }
 
-        if !WindowData::is_device_suitable(&self.vk_lib, &self.instance, device, surface)? {
-            return Ok(false);
+        Ok(problems)
+    }
+
+    /// Prints every physical device, and why it can't be used with this renderer if it can't
+    pub fn list_devices(event_loop: &EventLoop<()>) -> Result<()> {
+        let vk_lib = unsafe { Entry::load()? };
+        let instance = Self::create_instance(&vk_lib, event_loop, &AppInfo::default(), None, None)?
+            .defer(|x| unsafe { x.destroy_instance(None) });
+
+        let devices = unsafe { instance.enumerate_physical_devices()? };
+        if devices.is_empty() {
+            println!("no physical devices found");
         }
+        for device in devices {
+            let properties = unsafe { instance.get_physical_device_properties(device) };
+            println!(
+                "{:?} ({:?}, Vulkan {})",
+                properties.device_name_as_c_str().unwrap(),
+                properties.device_type,
+                format_api_version(properties.api_version)
+            );
 
-        let queue_family_info =
-            utils::query_queue_families(&self.vk_lib, &self.instance, device, surface)?;
-        Ok(R::has_required_queue_families(&queue_family_info))
+            let problems = Self::device_problems(&vk_lib, &instance, device, None)?;
+            if problems.is_empty() {
+                println!("    suitable (present support is only checked once there's a window)");
+            }
+            for problem in problems {
+                println!("    {problem}");
+            }
+        }
+
+        Ok(())
     }
 
     fn pick_physical_device(
         &self,
         devices: impl Iterator<Item = vk::PhysicalDevice>,
+        surface: vk::SurfaceKHR,
     ) -> Option<vk::PhysicalDevice> {
-        // could make a smarter device scoring system, but let's just take either the first discrete GPU device
-        // or the first device that works if there is no discrete GPU
+        // on hybrid graphics the gpu the window isn't on can usually still present, but only by copying the
+        // image over to the other one, which is slow. there's no portable way to ask which gpu drives the
+        // display, but such a device typically can't present from its compute queue family, so prefer
+        // devices that can. after that prefer discrete GPUs, and otherwise keep the enumeration order
         // in the future could expand this to have the renderer score devices based on what would be best for it
-        let mut devices = devices.peekable();
-        let first = devices.peek().cloned();
-
-        for device in devices {
+        let score = |device: vk::PhysicalDevice| {
             let properties = unsafe { self.instance.get_physical_device_properties(device) };
-            if properties.device_type == vk::PhysicalDeviceType::DISCRETE_GPU {
-                return Some(device);
+            let discrete = properties.device_type == vk::PhysicalDeviceType::DISCRETE_GPU;
+
+            let shared_family = query_queue_families(&self.vk_lib, &self.instance, device, surface)
+                .is_ok_and(|info| {
+                    info.compute_index.is_some() && info.compute_index == info.present_index
+                });
+            if !shared_family {
+                debug!(
+                    "Physical device {:?} can't present from its compute queue family",
+                    properties.device_name_as_c_str().unwrap()
+                );
             }
-        }
 
-        first
+            (shared_family, discrete)
+        };
+
+        // max_by_key would take the last of equally good devices
+        devices
+            .enumerate()
+            .max_by_key(|&(i, device)| (score(device), std::cmp::Reverse(i)))
+            .map(|(_, device)| device)
     }
 
     fn create_device(
@@ -297,21 +550,139 @@ where
 
         let queue_info = R::get_queue_info(queue_family_info);
 
-        let create_info = vk::DeviceCreateInfo {
-            p_next: enabled_features.get() as *const _ as *const c_void,
-            queue_create_info_count: queue_info.len() as u32,
-            p_queue_create_infos: queue_info.as_ptr(),
-            enabled_extension_count: enabled_extensions.len() as u32,
-            pp_enabled_extension_names: enabled_extensions.as_ptr(),
-            p_enabled_features: ptr::null(),
-            ..Default::default()
+        utils::create_device(
+            &self.instance,
+            physical_device,
+            &enabled_extensions,
+            enabled_features.get(),
+            &queue_info,
+        )
+    }
+
+    // saves the last frame at the render resolution, which can be smaller than the window with a render scale
+    // scenes with render.aovs also get an exr with the unclamped image and the aovs as layers, and with
+    // render.denoise a denoised png
+    fn save_screenshot(&self) -> Result<Vec<PathBuf>> {
+        let renderer = self.renderer.as_ref().unwrap();
+        let surface_format = self.window.as_ref().unwrap().surface_format();
+        let (size, pixels) = renderer.read_image()?;
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let path = PathBuf::from(format!("screenshot-{timestamp}.png"));
+        write_png(&path, size, &encode_for_surface(&pixels, surface_format)?)?;
+        let mut paths = vec![path];
+
+        if let Some(aovs) = renderer.read_aovs()? {
+            let mut channels = Channel::interleaved(None, &["R", "G", "B", "A"], 4, &pixels);
+            channels.extend(aovs.exr_channels());
+            let path = PathBuf::from(format!("screenshot-{timestamp}.exr"));
+            write_exr(&path, size, &channels)?;
+            paths.push(path);
+        }
+
+        if self.scene().render_options.denoise {
+            let (size, pixels) = denoise::denoise(renderer, &BilateralDenoiser::default())?;
+            let path = PathBuf::from(format!("screenshot-{timestamp}-denoised.png"));
+            write_png(&path, size, &encode_for_surface(&pixels, surface_format)?)?;
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+
+    fn scene(&self) -> &MeshScene {
+        &self.scenes[self.current_scene]
+    }
+
+    // moves the camera so every mesh is in view
+    fn frame_scene(&mut self) {
+        let scene = self.scene_mut();
+        let bounds = scene.world_bounds();
+        if bounds.min.cmpgt(bounds.max).any() {
+            warn!("nothing to frame, the scene has no meshes");
+            return;
+        }
+        scene.camera.frame(
+            (bounds.min + bounds.max) / 2.0,
+            (bounds.max - bounds.min).length() / 2.0,
+        );
+    }
+
+    // the scene that's in now decides whether the cursor is grabbed for mouse look
+    fn update_cursor_grab(&self) {
+        let grab = self.scene().window_options.grab_cursor;
+        if let Err(e) = self.window.as_ref().unwrap().grab_cursor(grab) {
+            warn!(
+                "could not {} cursor: {e}",
+                if grab { "confine" } else { "release" }
+            );
+        }
+    }
+
+    fn scene_mut(&mut self) -> &mut MeshScene {
+        &mut self.scenes[self.current_scene]
+    }
+
+    fn next_scene(&mut self) -> Result<()> {
+        if self.scenes.len() < 2 {
+            return Ok(());
+        }
+
+        let renderer = self.renderer.as_mut().unwrap();
+        renderer.reset()?;
+        self.current_scene = (self.current_scene + 1) % self.scenes.len();
+        renderer.ingest_scene(&self.scenes[self.current_scene])?;
+
+        self.update_cursor_grab();
+        // ingest_scene starts out at the default size, so resize to the window (and this scene's camera)
+        self.pending_resize = Some(self.window.as_ref().unwrap().get_size());
+        info!("Switched to scene {}", self.current_scene);
+
+        Ok(())
+    }
+
+    // loads the current scene again, with its meshes and shaders from the asset cache unless clear_cache
+    // a scene that fails to load leaves the old one in place
+    fn reload_scene(&mut self, clear_cache: bool) -> Result<()> {
+        let Some(loader) = &self.scene_loader else {
+            warn!("scenes can't be reloaded");
+            return Ok(());
+        };
+        if clear_cache {
+            AssetCache::global().clear();
+        }
+        let scene = match loader(self.current_scene) {
+            Ok(scene) => scene,
+            Err(e) => {
+                warn!("failed to reload scene {}: {e}", self.current_scene);
+                return Ok(());
+            }
         };
-        let device = unsafe {
-            self.instance
-                .create_device(physical_device, &create_info, None)
-        }?;
 
-        Ok(device)
+        // ingest_scene validates too, but this way a broken scene doesn't tear down the old one first
+        if let Err(e) = scene.validate() {
+            warn!("failed to reload scene {}: {e}", self.current_scene);
+            return Ok(());
+        }
+
+        let renderer = self.renderer.as_mut().unwrap();
+        renderer.reset()?;
+        let previous = std::mem::replace(&mut self.scenes[self.current_scene], scene);
+        // the renderer can still turn it down (or fail creating its shaders), then the old one goes back in
+        match renderer.ingest_scene(&self.scenes[self.current_scene]) {
+            Ok(()) => info!("Reloaded scene {}", self.current_scene),
+            Err(e) => {
+                warn!("failed to reload scene {}: {e}", self.current_scene);
+                renderer.reset()?;
+                self.scenes[self.current_scene] = previous;
+                renderer.ingest_scene(&self.scenes[self.current_scene])?;
+            }
+        }
+
+        self.update_cursor_grab();
+        self.pending_resize = Some(self.window.as_ref().unwrap().get_size());
+
+        Ok(())
     }
 }
 
@@ -344,17 +715,12 @@ where
                 .create_window(
                     WindowAttributes::default()
                         .with_inner_size(PhysicalSize::new(
-                            WindowData::DEFAULT_WIDTH,
-                            WindowData::DEFAULT_HEIGHT,
+                            self.scene().window_options.width,
+                            self.scene().window_options.height,
                         ))
                         .with_title("kubgrupp"),
                 )
                 .unwrap();
-            window
-                .set_cursor_grab(CursorGrabMode::Confined)
-                .or_else(|_e| window.set_cursor_grab(CursorGrabMode::Locked))
-                .expect("could not confine cursor");
-            window.set_cursor_visible(false);
 
             let display_handle = window.display_handle().unwrap();
             let window_handle = window.window_handle().unwrap();
@@ -390,15 +756,16 @@ where
             });
 
             let physical_device = self
-                .pick_physical_device(valid_devices)
-                .expect("failed to find compatible physical device");
+                .pick_physical_device(valid_devices, *surface)
+                .expect("failed to find compatible physical device (--list-devices shows why)");
             let physical_device_properties = unsafe {
                 self.instance
                     .get_physical_device_properties(physical_device)
             };
             info!(
-                "Using physical device: {:?}",
-                physical_device_properties.device_name_as_c_str().unwrap()
+                "Using physical device: {:?} (Vulkan {})",
+                physical_device_properties.device_name_as_c_str().unwrap(),
+                format_api_version(physical_device_properties.api_version)
             );
             self.physical_device = Some(physical_device);
 
@@ -430,10 +797,35 @@ where
                     physical_device,
                     *surface,
                     window,
+                    SwapchainOptions {
+                        image_count: self.scene().window_options.image_count,
+                        image_usage: R::target_image_usage(),
+                        surface_formats: if self.scene().window_options.hdr {
+                            HDR_SURFACE_FORMATS.to_vec()
+                        } else {
+                            Vec::new()
+                        },
+                    },
                 )
                 .expect("swapchain creation failed"),
             );
             surface.undefer();
+            if self.scene().window_options.grab_cursor {
+                self.window
+                    .as_ref()
+                    .unwrap()
+                    .grab_cursor(true)
+                    .expect("could not confine cursor");
+            }
+            debug!(
+                "Supported present modes: {:?}",
+                self.window.as_ref().unwrap().supported_present_modes()
+            );
+            debug!(
+                "Supported surface formats: {:?}, using {:?}",
+                self.window.as_ref().unwrap().supported_surface_formats(),
+                self.window.as_ref().unwrap().surface_format()
+            );
 
             self.renderer = Some(
                 R::new(
@@ -443,6 +835,7 @@ where
                     physical_device,
                     &queue_family_info,
                     self.allocator.as_mut().unwrap().clone(),
+                    self.debug,
                 )
                 .expect("failed to create renderer"),
             );
@@ -452,8 +845,10 @@ where
             self.renderer
                 .as_mut()
                 .unwrap()
-                .ingest_scene(&self.scene)
+                .ingest_scene(&self.scenes[self.current_scene])
                 .expect("failed to ingest scene");
+            // same as in next_scene, the window usually isn't the default size
+            self.pending_resize = Some(self.window.as_ref().unwrap().get_size());
         }
     }
 
@@ -476,8 +871,58 @@ where
                 if let PhysicalKey::Code(key_code) = input_event.physical_key {
                     match key_code {
                         KeyCode::Escape => event_loop.exit(),
+                        KeyCode::F1 => {
+                            if input_event.state.is_pressed() && !input_event.repeat {
+                                self.debug_mode = self.debug_mode.next();
+                                self.pending_debug_mode = Some(self.debug_mode);
+                                info!("Debug mode: {:?}", self.debug_mode);
+                            }
+                        }
+                        KeyCode::F5 => {
+                            if input_event.state.is_pressed() && !input_event.repeat {
+                                self.pending_invalidate = true;
+                                info!("Restarting accumulation");
+                            }
+                        }
+                        // F7 also rereads meshes and shaders, for when they changed on disk
+                        KeyCode::F6 | KeyCode::F7 => {
+                            if input_event.state.is_pressed() && !input_event.repeat {
+                                self.reload_scene(key_code == KeyCode::F7)
+                                    .expect("failed to reload scene");
+                            }
+                        }
+                        KeyCode::F12 => {
+                            if input_event.state.is_pressed() && !input_event.repeat {
+                                match self.save_screenshot() {
+                                    Ok(paths) => info!(
+                                        "Saved screenshot to {}",
+                                        paths
+                                            .iter()
+                                            .map(|x| x.display().to_string())
+                                            .collect::<Vec<_>>()
+                                            .join(", ")
+                                    ),
+                                    Err(e) => warn!("failed to save screenshot: {e}"),
+                                }
+                            }
+                        }
+                        _ if light_key_index(key_code).is_some() => {
+                            if input_event.state.is_pressed() && !input_event.repeat {
+                                self.pending_light_toggles.extend(light_key_index(key_code));
+                            }
+                        }
+                        KeyCode::KeyF => {
+                            if input_event.state.is_pressed() && !input_event.repeat {
+                                self.frame_scene();
+                            }
+                        }
+                        KeyCode::Tab => {
+                            if input_event.state.is_pressed() && !input_event.repeat {
+                                self.next_scene().expect("failed to switch scene");
+                            }
+                        }
                         _ => self
-                            .scene
+                            .scene_mut()
                             .camera
                             .handle_key_input(key_code, input_event.state.is_pressed()),
                     };
@@ -486,7 +931,20 @@ where
             WindowEvent::Resized(PhysicalSize { width, height }) => {
                 self.pending_resize = Some((width, height));
             }
+            // the event loop can still deliver a redraw or two after exiting
+            WindowEvent::RedrawRequested if self.device_lost => (),
             WindowEvent::RedrawRequested => {
+                // sleep off whatever is left of the frame interval, measured from the start of the last frame
+                if let (Some(max_fps), Some(t)) =
+                    (self.scene().render_options.max_fps, self.prev_instant)
+                {
+                    let interval =
+                        Duration::try_from_secs_f32(1f32 / max_fps).unwrap_or(Duration::MAX);
+                    if let Some(remaining) = interval.checked_sub(t.elapsed()) {
+                        thread::sleep(remaining);
+                    }
+                }
+
                 let dt: f32;
                 if let Some(t) = self.prev_instant.as_ref() {
                     dt = t.elapsed().as_secs_f32()
@@ -495,30 +953,53 @@ where
                 }
                 self.prev_instant = Some(Instant::now());
 
-                self.scene.camera.handle_movement(dt);
+                let scene = &mut self.scenes[self.current_scene];
+                scene.camera.handle_movement(dt);
 
                 let mut updates = Vec::new();
 
-                if let Some(new_view) = self.scene.camera.update_view() {
+                if let Some(new_view) = scene.camera.update_view() {
                     updates.push(MeshSceneUpdate::NewView(new_view));
                 }
 
                 if let Some((w, h)) = self.pending_resize {
-                    self.scene.camera.handle_resize(w, h);
-                    updates.push(MeshSceneUpdate::NewSize((
-                        w,
-                        h,
-                        self.scene.camera.perspective(),
-                    )));
+                    scene.camera.handle_resize(w, h);
+                    updates.push(MeshSceneUpdate::NewSize((w, h, scene.camera.projection())));
 
                     self.pending_resize = None;
                 }
 
-                self.renderer
+                if let Some(mode) = self.pending_debug_mode.take() {
+                    updates.push(MeshSceneUpdate::DebugMode(mode));
+                }
+
+                for index in self.pending_light_toggles.drain(..) {
+                    updates.push(MeshSceneUpdate::ToggleLight(index));
+                }
+
+                if std::mem::take(&mut self.pending_invalidate) {
+                    updates.push(MeshSceneUpdate::Invalidate);
+                }
+
+                let result = self
+                    .renderer
                     .as_mut()
                     .unwrap()
-                    .render_to(&updates, self.window.as_mut().unwrap())
-                    .expect("failed to render to target");
+                    .render_to(&updates, self.window.as_mut().unwrap());
+                if let Err(e) = result {
+                    // nothing on this device works anymore, so stop instead of panicking on the next call
+                    if is_device_lost(&e) {
+                        error!(
+                            "The GPU device was lost while rendering ({e}). This usually means a frame took \
+                            so long that the driver reset the GPU, try a smaller window or \
+                            render.target_frame_time"
+                        );
+                        self.device_lost = true;
+                        event_loop.exit();
+                        return;
+                    }
+                    panic!("failed to render to target: {e:?}");
+                }
 
                 self.window.as_ref().unwrap().request_redraw();
             }
@@ -526,26 +1007,175 @@ where
         }
     }
 
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        // drop would wait as well, but this is the last chance to say something went wrong
+        if let Some(Err(e)) = self.renderer.as_ref().map(R::wait_idle) {
+            error!("failed to wait for the renderer before exiting: {e}");
+        }
+        if let Some(window) = &self.window {
+            if let Err(e) = window.wait_idle() {
+                error!("failed to wait for the gpu before exiting: {e}");
+            }
+        }
+    }
+
     fn device_event(
         &mut self,
         _event_loop: &ActiveEventLoop,
         _device_id: DeviceId,
         event: DeviceEvent,
     ) {
+        // without a grabbed cursor the mouse is just a mouse, so don't look around with it
+        if !self.scene().window_options.grab_cursor {
+            return;
+        }
+
         if let DeviceEvent::MouseMotion { delta: (dx, dy) } = event {
             let (sx, sy) = self.window.as_ref().unwrap().get_size();
-            self.scene
+            self.scene_mut()
                 .camera
                 .handle_mouse_input((dx / sx as f64) as f32, (dy / sy as f64) as f32);
         }
     }
 }
 
+const SCENES_DIR: &str = "resources/scenes/";
+const DEFAULT_SCENE: &str = "cubes.toml";
+// scene argument that reads the scene from stdin instead
+const STDIN_SCENE: &str = "-";
+// --preview renders at the window size divided by this, the cpu is a lot slower than tracing on the gpu
+const PREVIEW_DOWNSCALE: u32 = 4;
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    #[arg(short, long)]
-    scene_file: String,
+    /// Scenes to load, either paths or file names in resources/scenes/, or - to read one from stdin
+    /// [default: cubes.toml] (press tab to cycle through them)
+    scenes: Vec<String>,
+
+    /// Same as a positional scene argument
+    #[arg(short, long, conflicts_with = "scenes")]
+    scene_file: Option<String>,
+
+    /// Fixed seed for reproducible renders (overrides the scene's render.seed)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Trace with ray queries from a compute shader instead of a ray tracing pipeline
+    /// (works without VK_KHR_ray_tracing_pipeline, but ignores the scene's shaders)
+    #[arg(long)]
+    ray_query: bool,
+
+    /// Print the physical devices and whether the renderer can use them, then exit
+    #[arg(long)]
+    list_devices: bool,
+
+    /// Application name passed to the driver instead of the crate name
+    #[arg(long, value_name = "NAME")]
+    app_name: Option<CString>,
+
+    /// Application version passed to the driver instead of the crate version
+    #[arg(long, value_name = "MAJOR.MINOR.PATCH", value_parser = parse_api_version)]
+    app_version: Option<u32>,
+
+    /// Engine name passed to the driver, there's none by default
+    #[arg(long, value_name = "NAME")]
+    engine_name: Option<CString>,
+
+    /// Engine version passed to the driver [default: 0.0.0]
+    #[arg(long, value_name = "MAJOR.MINOR.PATCH", value_parser = parse_api_version)]
+    engine_version: Option<u32>,
+
+    /// Render a rough preview of the first scene on the cpu into a png at this path, then exit
+    /// (for machines without a ray tracing gpu, only shows which way the meshes face)
+    #[arg(long, value_name = "PATH")]
+    preview: Option<PathBuf>,
+}
+
+// drivers get versions packed with vk::make_api_version
+fn parse_api_version(version: &str) -> Result<u32, String> {
+    let parts: Vec<_> = version
+        .split('.')
+        .map(|x| x.parse::<u32>())
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())?;
+    let &[major, minor, patch] = parts.as_slice() else {
+        return Err("expected MAJOR.MINOR.PATCH".to_string());
+    };
+    Ok(vk::make_api_version(0, major, minor, patch))
+}
+
+// the command line --seed overrides the scene's render.seed
+fn load_scene(reader: impl Read, seed: Option<u64>) -> Result<MeshScene> {
+    let mut scene = MeshScene::load_from(reader)?;
+    if seed.is_some() {
+        scene.render_options.seed = seed;
+    }
+    Ok(scene)
+}
+
+// number keys toggle the first ten [[light]] entries, 1 is the first one and 0 the tenth
+fn light_key_index(key_code: KeyCode) -> Option<usize> {
+    const KEYS: [KeyCode; 10] = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+        KeyCode::Digit0,
+    ];
+    KEYS.iter().position(|&key| key == key_code)
+}
+
+// looks for the scene as given first, then in the scenes directory
+fn scene_candidates(scene_name: &str) -> [PathBuf; 2] {
+    [
+        Path::new(scene_name).to_path_buf(),
+        Path::new(SCENES_DIR).join(scene_name),
+    ]
+}
+
+fn find_scene_file(scene_name: &str) -> Option<File> {
+    scene_candidates(scene_name)
+        .iter()
+        .filter(|x| x.is_file())
+        .find_map(|x| File::open(x).ok())
+}
+
+// - is stdin, meshes and shaders still load from resources/ like for any other scene
+// exits with a usage message listing the bundled scenes if the scene can't be found
+fn open_scene_file(scene_name: &str) -> Box<dyn Read> {
+    if scene_name == STDIN_SCENE {
+        return Box::new(io::stdin().lock());
+    }
+
+    let candidates = scene_candidates(scene_name);
+    if let Some(file) = find_scene_file(scene_name) {
+        return Box::new(file);
+    }
+
+    let mut available: Vec<_> = fs::read_dir(SCENES_DIR)
+        .into_iter()
+        .flatten()
+        .filter_map(|x| x.ok()?.file_name().into_string().ok())
+        .filter(|x| x.ends_with(".toml"))
+        .collect();
+    available.sort();
+
+    Args::command()
+        .error(
+            ErrorKind::ValueValidation,
+            format!(
+                "scene file not found: {scene_name} (also tried {})\n\navailable scenes in {SCENES_DIR}: {}",
+                candidates[1].display(),
+                available.join(", ")
+            ),
+        )
+        .exit()
 }
 
 fn main() {
@@ -556,11 +1186,100 @@ fn main() {
 
     let args = Args::parse();
 
-    let event_loop = EventLoop::new().unwrap();
+    if args.list_devices {
+        let event_loop = EventLoop::new().unwrap();
+        let result = if args.ray_query {
+            MeshApp::<RayQueryRenderer>::list_devices(&event_loop)
+        } else {
+            MeshApp::<RaytraceRenderer>::list_devices(&event_loop)
+        };
+        result.expect("failed to list physical devices");
+        return;
+    }
+
+    let mut scene_names = args.scenes;
+    scene_names.extend(args.scene_file);
+    if scene_names.is_empty() {
+        scene_names.push(DEFAULT_SCENE.to_string());
+    }
+    if scene_names.iter().filter(|x| *x == STDIN_SCENE).count() > 1 {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "stdin (-) can only be given as a scene once",
+            )
+            .exit()
+    }
+
+    let seed = args.seed;
+    let scenes: Vec<_> = scene_names
+        .iter()
+        .map(|scene_name| {
+            load_scene(open_scene_file(scene_name), seed)
+                .unwrap_or_else(|e| panic!("scene {scene_name} could not be loaded: {e}"))
+        })
+        .collect();
+    // stdin can only be read once, everything else is looked up again
+    let scene_loader = move |i: usize| {
+        let scene_name = &scene_names[i];
+        if scene_name == STDIN_SCENE {
+            bail!("scenes read from stdin can't be reloaded");
+        }
+        let file = find_scene_file(scene_name)
+            .ok_or_else(|| anyhow!("scene file {scene_name} not found"))?;
+        load_scene(file, seed)
+    };
+
+    if let Some(path) = args.preview {
+        let scene = &scenes[0];
+        let size = (
+            (scene.window_options.width / PREVIEW_DOWNSCALE).max(1),
+            (scene.window_options.height / PREVIEW_DOWNSCALE).max(1),
+        );
+        let png = preview::render_preview_png(scene, size).expect("failed to render preview");
+        fs::write(&path, png)
+            .unwrap_or_else(|e| panic!("failed to write preview to {}: {e}", path.display()));
+        info!("wrote {}x{} preview to {}", size.0, size.1, path.display());
+        return;
+    }
 
-    let path = Path::new("resources/scenes/").join(&args.scene_file);
-    let file = File::open(path).expect("scene file does not exist");
-    let scene = MeshScene::load_from(file).expect("scene could not be loaded");
-    let mut app: MeshApp<RaytraceRenderer> = MeshApp::new(&event_loop, scene, DEBUG_MODE).unwrap();
+    let mut app_info = AppInfo::default();
+    if let Some(name) = args.app_name {
+        app_info.name = name;
+    }
+    if let Some(version) = args.app_version {
+        app_info.version = version;
+    }
+    app_info.engine_name = args.engine_name;
+    if let Some(version) = args.engine_version {
+        app_info.engine_version = version;
+    }
+
+    if args.ray_query {
+        run::<RayQueryRenderer>(scenes, scene_loader, app_info);
+    } else {
+        run::<RaytraceRenderer>(scenes, scene_loader, app_info);
+    }
+}
+
+fn run<R: Renderer<MeshScene, WindowData>>(
+    scenes: Vec<MeshScene>,
+    scene_loader: impl Fn(usize) -> Result<MeshScene> + 'static,
+    app_info: AppInfo,
+) {
+    let event_loop = EventLoop::new().unwrap();
+    let mut app: MeshApp<R> = MeshAppBuilder::new()
+        .scenes(scenes)
+        .scene_loader(scene_loader)
+        .app_info(app_info)
+        .debug_mode(debug::validation_enabled(DEBUG_MODE))
+        .build(&event_loop)
+        .unwrap();
     event_loop.run_app(&mut app).unwrap();
+
+    if app.device_lost {
+        // clean up before exiting, process::exit doesn't run destructors
+        drop(app);
+        std::process::exit(1);
+    }
 }
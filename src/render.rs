@@ -1,10 +1,17 @@
 use std::{cell::RefCell, ffi::c_char, rc::Rc};
 
 use crate::{features::VkFeatureGuard, scene::Scene, utils::QueueFamilyInfo};
+use aovs::Aovs;
 use ash::{vk, Device, Entry, Instance};
 use gpu_allocator::vulkan::Allocator;
 
+pub mod aovs;
+pub mod denoise;
+pub mod exr;
+#[cfg(test)]
+pub mod golden;
 pub mod renderers;
+pub mod screenshot;
 
 // Device should be initialized outside the renderer, but renderer takes device for construction
 
@@ -13,6 +20,7 @@ where
     S: Scene,
     Self: Sized,
 {
+    /// `debug` is whether the app runs in debug mode, which renderers can use for extra logging
     fn new(
         vk_lib: &Entry,
         instance: &Instance,
@@ -20,14 +28,30 @@ where
         physical_device: vk::PhysicalDevice,
         queue_family_info: &QueueFamilyInfo,
         allocator: Rc<RefCell<Allocator>>,
+        debug: bool,
     ) -> anyhow::Result<Self>;
 
     fn ingest_scene(&mut self, scene: &S) -> anyhow::Result<()>;
+    /// Frees everything created by `ingest_scene`, so another scene can be ingested
+    fn reset(&mut self) -> anyhow::Result<()>;
     fn render_to(&mut self, updates: &[S::Update], target: &mut Target) -> anyhow::Result<()>;
+    /// Copies the last rendered frame back to the host
+    ///
+    /// Returns the image size and its RGBA pixels in linear color, row by row.
+    fn read_image(&self) -> anyhow::Result<((u32, u32), Vec<f32>)>;
+    /// Copies the aovs of the last rendered frame back to the host, if the scene asked for them
+    fn read_aovs(&self) -> anyhow::Result<Option<Aovs>>;
+    /// Waits for everything the renderer submitted (and anything else on the device) to finish
+    ///
+    /// Dropping the renderer waits too, but can't report failure. Call this first to tear things down in a
+    /// known order.
+    fn wait_idle(&self) -> anyhow::Result<()>;
 
     fn required_instance_extensions() -> &'static [*const c_char];
     fn required_device_extensions() -> &'static [*const c_char];
     fn required_features() -> VkFeatureGuard<'static>;
+    /// How the renderer uses the target's images, e.g. TRANSFER_DST to blit into them
+    fn target_image_usage() -> vk::ImageUsageFlags;
 
     fn has_required_queue_families(queue_family_info: &QueueFamilyInfo) -> bool;
     fn get_queue_info(queue_family_info: &QueueFamilyInfo) -> Vec<vk::DeviceQueueCreateInfo<'_>>;
@@ -0,0 +1,95 @@
+use super::exr::{Channel, Samples};
+
+/// What [`Aovs::instance`] is where the camera rays hit nothing, matches NO_INSTANCE in raygen_common.glsl
+pub const NO_INSTANCE: u32 = u32::MAX;
+
+/// Extra outputs from the camera rays' first hits, for compositing and denoising (render.aovs)
+///
+/// Everything but the instance is averaged over the accumulated samples like the image, so edges blend.
+/// Each has one entry per pixel, row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aovs {
+    pub size: (u32, u32),
+    /// The weight of the first hit's brdf sample, which is the color of diffuse surfaces, or the radiance of
+    /// what was hit for emitters and the background
+    pub albedo: Vec<[f32; 3]>,
+    /// World space shading normal facing the camera, zero where nothing was hit
+    pub normal: Vec<[f32; 3]>,
+    /// How far in front of the camera plane the hit is, zero where nothing was hit
+    pub depth: Vec<f32>,
+    /// The hit's index in the tlas (the scene's objects, then its procedural objects) or [`NO_INSTANCE`],
+    /// from the first sample only
+    pub instance: Vec<u32>,
+}
+
+impl Aovs {
+    /// From the two aov images as RGBA pixels, albedo and depth then normal and instance
+    pub fn from_images(size: (u32, u32), albedo_depth: &[f32], normal_instance: &[f32]) -> Self {
+        let rgb = |pixels: &[f32]| -> Vec<[f32; 3]> {
+            pixels.chunks_exact(4).map(|x| [x[0], x[1], x[2]]).collect()
+        };
+        Self {
+            size,
+            albedo: rgb(albedo_depth),
+            normal: rgb(normal_instance),
+            depth: albedo_depth.chunks_exact(4).map(|x| x[3]).collect(),
+            // stored as floats, with -1 for nothing
+            instance: normal_instance
+                .chunks_exact(4)
+                .map(|x| if x[3] < 0.0 { NO_INSTANCE } else { x[3] as u32 })
+                .collect(),
+        }
+    }
+
+    /// Layers albedo (RGB), normal (XYZ) and depth (Z), and an id channel with the instances
+    pub fn exr_channels(&self) -> Vec<Channel> {
+        let flat = |x: &[[f32; 3]]| x.concat();
+        let mut channels =
+            Channel::interleaved(Some("albedo"), &["R", "G", "B"], 3, &flat(&self.albedo));
+        channels.extend(Channel::interleaved(
+            Some("normal"),
+            &["X", "Y", "Z"],
+            3,
+            &flat(&self.normal),
+        ));
+        channels.push(Channel {
+            name: "depth.Z".to_string(),
+            samples: Samples::Float(self.depth.clone()),
+        });
+        channels.push(Channel {
+            name: "id".to_string(),
+            samples: Samples::Uint(self.instance.clone()),
+        });
+        channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aovs_from_images() {
+        let aovs = Aovs::from_images(
+            (2, 1),
+            &[0.1, 0.2, 0.3, 5.0, 1.0, 1.0, 1.0, 0.0],
+            &[0.0, 0.0, 1.0, 3.0, 0.0, 0.0, 0.0, -1.0],
+        );
+        assert_eq!(aovs.albedo, [[0.1, 0.2, 0.3], [1.0, 1.0, 1.0]]);
+        assert_eq!(aovs.normal, [[0.0, 0.0, 1.0], [0.0, 0.0, 0.0]]);
+        assert_eq!(aovs.depth, [5.0, 0.0]);
+        assert_eq!(aovs.instance, [3, NO_INSTANCE]);
+
+        let channels = aovs.exr_channels();
+        let names: Vec<_> = channels.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "albedo.R", "albedo.G", "albedo.B", "normal.X", "normal.Y", "normal.Z", "depth.Z",
+                "id"
+            ]
+        );
+        assert_eq!(channels[2].samples, Samples::Float(vec![0.3, 1.0]));
+        assert_eq!(channels[7].samples, Samples::Uint(vec![3, NO_INSTANCE]));
+    }
+}
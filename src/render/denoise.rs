@@ -0,0 +1,199 @@
+// cleans up the noise of a rendered frame before it's saved, with the aovs as guides
+//
+// the inputs are laid out the way Intel Open Image Denoise's RT filter takes them (color, albedo and normal
+// as rows of float3 pixels). neither OIDN nor a gpu denoiser ships though: OIDN would be a Denoiser impl once
+// it's a dependency, and a gpu one would need its own pass in the renderer. the only denoiser is the built in
+// cpu one, a joint bilateral filter: each pixel averages the neighbours whose albedo and normal are
+// close to its own, which smooths noise across a surface without blurring over edges or texture
+
+use anyhow::{anyhow, bail, Result};
+use glam::Vec3;
+
+use super::{aovs::Aovs, Renderer};
+use crate::scene::Scene;
+
+/// One frame to denoise, each a pixel per entry, row by row
+pub struct DenoiseInput<'a> {
+    pub size: (u32, u32),
+    /// Linear color, unclamped
+    pub color: &'a [[f32; 3]],
+    pub albedo: &'a [[f32; 3]],
+    /// World space, zero where nothing was hit
+    pub normal: &'a [[f32; 3]],
+}
+
+pub trait Denoiser {
+    /// Returns the denoised color, one pixel per input pixel
+    fn denoise(&self, input: &DenoiseInput) -> Result<Vec<[f32; 3]>>;
+}
+
+/// The built in [`Denoiser`], see denoise.rs
+#[derive(Debug, Clone, Copy)]
+pub struct BilateralDenoiser {
+    /// Neighbours up to this many pixels away in x and y get averaged in
+    pub radius: u32,
+    /// Falloff of the weights with distance in pixels
+    pub sigma_spatial: f32,
+    /// Falloff of the weights with the difference in albedo
+    pub sigma_albedo: f32,
+    /// Falloff of the weights with the difference in normal
+    pub sigma_normal: f32,
+}
+
+impl Default for BilateralDenoiser {
+    fn default() -> Self {
+        Self {
+            radius: 3,
+            sigma_spatial: 2.0,
+            sigma_albedo: 0.1,
+            sigma_normal: 0.2,
+        }
+    }
+}
+
+impl Denoiser for BilateralDenoiser {
+    fn denoise(&self, input: &DenoiseInput) -> Result<Vec<[f32; 3]>> {
+        let (width, height) = input.size;
+        let pixel_count = width as usize * height as usize;
+        if [input.color, input.albedo, input.normal]
+            .iter()
+            .any(|x| x.len() != pixel_count)
+        {
+            bail!("denoiser inputs must all have {width}x{height} pixels");
+        }
+
+        let falloff = |sigma: f32| -1.0 / (2.0 * sigma * sigma);
+        let (spatial, albedo, normal) = (
+            falloff(self.sigma_spatial),
+            falloff(self.sigma_albedo),
+            falloff(self.sigma_normal),
+        );
+        let at = |x: u32, y: u32| y as usize * width as usize + x as usize;
+        let r = self.radius as i64;
+
+        let mut output = Vec::with_capacity(pixel_count);
+        for y in 0..height {
+            for x in 0..width {
+                let center = at(x, y);
+                let center_albedo = Vec3::from(input.albedo[center]);
+                let center_normal = Vec3::from(input.normal[center]);
+
+                let mut sum = Vec3::ZERO;
+                let mut weight_sum = 0.0;
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                        if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                            continue;
+                        }
+                        let i = at(nx as u32, ny as u32);
+
+                        let exponent = spatial * (dx * dx + dy * dy) as f32
+                            + albedo * center_albedo.distance_squared(input.albedo[i].into())
+                            + normal * center_normal.distance_squared(input.normal[i].into());
+                        let weight = exponent.exp();
+                        sum += weight * Vec3::from(input.color[i]);
+                        weight_sum += weight;
+                    }
+                }
+                // the center pixel always has weight 1
+                output.push((sum / weight_sum).to_array());
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+// `pixels` (RGBA like Renderer::read_image) run through `denoiser`, with alpha kept as it was
+fn denoise_image(denoiser: &impl Denoiser, pixels: &[f32], aovs: &Aovs) -> Result<Vec<f32>> {
+    let color: Vec<_> = pixels.chunks_exact(4).map(|x| [x[0], x[1], x[2]]).collect();
+    let denoised = denoiser.denoise(&DenoiseInput {
+        size: aovs.size,
+        color: &color,
+        albedo: &aovs.albedo,
+        normal: &aovs.normal,
+    })?;
+
+    Ok(denoised
+        .iter()
+        .zip(pixels.chunks_exact(4))
+        .flat_map(|(&[r, g, b], rgba)| [r, g, b, rgba[3]])
+        .collect())
+}
+
+/// Reads the last frame and its aovs back from `renderer` and denoises it
+///
+/// Returns the image size and the denoised RGBA pixels. The scene has to have render.aovs.
+pub fn denoise<S: Scene, T>(
+    renderer: &impl Renderer<S, T>,
+    denoiser: &impl Denoiser,
+) -> Result<((u32, u32), Vec<f32>)> {
+    let aovs = renderer
+        .read_aovs()?
+        .ok_or(anyhow!("denoising needs render.aovs"))?;
+    let (size, pixels) = renderer.read_image()?;
+    if size != aovs.size {
+        bail!("image is {size:?}, but the aovs are {:?}", aovs.size);
+    }
+
+    Ok((size, denoise_image(denoiser, &pixels, &aovs)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bilateral_denoiser() {
+        // two flat surfaces side by side, with noise on top
+        let (width, height) = (8, 4);
+        let left = |i: usize| i % width < 4;
+        let pixels = width * height;
+        let color: Vec<_> = (0..pixels)
+            .map(|i| {
+                let base = if left(i) { 0.2 } else { 0.8 };
+                let noise = if (i / width + i) % 2 == 0 { 0.1 } else { -0.1 };
+                [base + noise; 3]
+            })
+            .collect();
+        let albedo: Vec<_> = (0..pixels)
+            .map(|i| if left(i) { [0.2; 3] } else { [0.8; 3] })
+            .collect();
+        let normal = vec![[0.0, 0.0, 1.0]; pixels];
+
+        let input = DenoiseInput {
+            size: (width as u32, height as u32),
+            color: &color,
+            albedo: &albedo,
+            normal: &normal,
+        };
+        let denoised = BilateralDenoiser::default().denoise(&input).unwrap();
+
+        let error = |pixels: &[[f32; 3]]| -> f32 {
+            pixels
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (x[0] - if left(i) { 0.2 } else { 0.8 }).abs())
+                .sum()
+        };
+        assert!(error(&denoised) < 0.5 * error(&color));
+        // the edge between the surfaces stays sharp
+        assert!(denoised[3][0] < 0.35 && denoised[4][0] > 0.65);
+
+        let short = DenoiseInput {
+            color: &color[1..],
+            ..input
+        };
+        assert!(BilateralDenoiser::default().denoise(&short).is_err());
+    }
+
+    #[test]
+    fn denoise_keeps_alpha() {
+        let aovs = Aovs::from_images((2, 1), &[0.5; 8], &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        let pixels = [1.0, 1.0, 1.0, 0.5, 1.0, 1.0, 1.0, 1.0];
+        let denoised = denoise_image(&BilateralDenoiser::default(), &pixels, &aovs).unwrap();
+        assert_eq!((denoised[3], denoised[7]), (0.5, 1.0));
+        assert!(denoised.iter().all(|x| (x - 1.0).abs() < 1e-6 || *x == 0.5));
+    }
+}
@@ -0,0 +1,166 @@
+// writes OpenEXR files with the exr crate, for saving renders with their aovs without clamping or 8-bit
+// rounding
+//
+// channels are FLOAT or UINT in a single uncompressed layer. layers are just a prefix on the channel names
+// (albedo.R), which is how multi-layer exr files name them and what compositors show as layers
+
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, WritableImage,
+};
+
+/// The values of a channel, one per pixel row by row from the top left
+#[derive(Debug, Clone, PartialEq)]
+pub enum Samples {
+    Float(Vec<f32>),
+    Uint(Vec<u32>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    /// With the layer in front, like `albedo.R`
+    pub name: String,
+    pub samples: Samples,
+}
+
+impl Channel {
+    /// Splits interleaved pixels with `stride` floats each into channels, the i-th of `names` taking the i-th
+    /// float of every pixel
+    ///
+    /// The names get `layer.` in front of them if there's a layer.
+    pub fn interleaved(
+        layer: Option<&str>,
+        names: &[&str],
+        stride: usize,
+        pixels: &[f32],
+    ) -> Vec<Channel> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Channel {
+                name: match layer {
+                    Some(layer) => format!("{layer}.{name}"),
+                    None => name.to_string(),
+                },
+                samples: Samples::Float(pixels.chunks_exact(stride).map(|x| x[i]).collect()),
+            })
+            .collect()
+    }
+}
+
+fn image(
+    (width, height): (u32, u32),
+    channels: &[Channel],
+) -> Result<Image<Layer<AnyChannels<FlatSamples>>>> {
+    let size = (width as usize, height as usize);
+    if size.0 == 0 || size.1 == 0 {
+        bail!("exr images can't be empty");
+    }
+    if channels.is_empty() {
+        bail!("exr images need at least one channel");
+    }
+    for channel in channels {
+        let len = match &channel.samples {
+            Samples::Float(x) => x.len(),
+            Samples::Uint(x) => x.len(),
+        };
+        if len != size.0 * size.1 {
+            bail!(
+                "expected {width}x{height} samples in channel {}, got {len}",
+                channel.name
+            );
+        }
+    }
+
+    let channels = channels
+        .iter()
+        .map(|channel| {
+            let samples = match &channel.samples {
+                Samples::Float(x) => FlatSamples::F32(x.clone()),
+                Samples::Uint(x) => FlatSamples::U32(x.clone()),
+            };
+            AnyChannel::new(channel.name.as_str(), samples)
+        })
+        .collect();
+    let layer = Layer::new(
+        size,
+        LayerAttributes::default(),
+        Encoding::UNCOMPRESSED,
+        AnyChannels::sort(channels),
+    );
+    Ok(Image::from_layer(layer))
+}
+
+/// Writes an exr file with `channels` to `path`, they need a sample for every pixel and different names
+pub fn write_exr(path: impl AsRef<Path>, size: (u32, u32), channels: &[Channel]) -> Result<()> {
+    image(size, channels)?.write().to_file(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use exr::prelude::{ReadChannels, ReadLayers};
+
+    use super::*;
+
+    #[test]
+    fn exr_round_trip() {
+        let channels = [
+            Channel {
+                name: "id".to_string(),
+                samples: Samples::Uint(vec![7, u32::MAX]),
+            },
+            Channel::interleaved(
+                None,
+                &["R", "G"],
+                4,
+                &[0.5, 1.0, 0.0, 1.0, 2.0, 3.0, 0.0, 1.0],
+            )
+            .remove(1),
+            Channel::interleaved(Some("albedo"), &["R"], 1, &[0.25, 0.75]).remove(0),
+        ];
+        let encode = |size, channels: &[Channel]| -> Result<Cursor<Vec<u8>>> {
+            let mut file = Cursor::new(Vec::new());
+            image(size, channels)?.write().to_buffered(&mut file)?;
+            file.set_position(0);
+            Ok(file)
+        };
+
+        let image = exr::prelude::read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .all_layers()
+            .all_attributes()
+            .from_buffered(encode((2, 1), &channels).unwrap())
+            .unwrap();
+        let [layer] = image.layer_data.as_slice() else {
+            panic!("expected one layer");
+        };
+        assert_eq!(layer.size.0, 2);
+        assert_eq!(layer.size.1, 1);
+        // sorted by name, with the sample types kept
+        let read: Vec<_> = layer
+            .channel_data
+            .list
+            .iter()
+            .map(|x| (x.name.to_string(), x.sample_data.clone()))
+            .collect();
+        assert_eq!(
+            read,
+            [
+                ("G".to_string(), FlatSamples::F32(vec![1.0, 3.0])),
+                ("albedo.R".to_string(), FlatSamples::F32(vec![0.25, 0.75])),
+                ("id".to_string(), FlatSamples::U32(vec![7, u32::MAX])),
+            ]
+        );
+
+        assert!(encode((3, 1), &channels).is_err());
+        assert!(encode((2, 1), &[channels[0].clone(), channels[0].clone()]).is_err());
+        assert!(encode((2, 1), &[]).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+// compares rendered images against references committed under resources/golden/, for the golden image tests
+//
+// after an intentional change to the rendered output, regenerate the references by running the tests with
+// KUBGRUPP_BLESS=1
+
+use std::{fs, fs::File, path::Path};
+
+use anyhow::{bail, Result};
+
+use super::screenshot::write_png;
+
+pub const BLESS_VAR: &str = "KUBGRUPP_BLESS";
+
+/// Checks 8-bit RGB `image` from [`super::screenshot::encode_image`] against the PNG at `reference_path`
+///
+/// Panics if the sizes differ, or if the mean absolute error over all channels (in 8-bit units) is above
+/// `max_mean_abs_error`. With [`BLESS_VAR`] set the reference is overwritten instead.
+pub fn assert_matches_reference(
+    reference_path: &str,
+    size: (u32, u32),
+    image: &[u8],
+    max_mean_abs_error: f64,
+) {
+    if std::env::var(BLESS_VAR).is_ok_and(|x| x != "0") {
+        // git doesn't keep the empty directory around before the first bless
+        fs::create_dir_all(Path::new(reference_path).parent().unwrap())
+            .expect("failed to create the reference directory");
+        write_png(reference_path, size, image).expect("failed to write reference image");
+        return;
+    }
+
+    let (reference_size, reference) = read_png(reference_path).unwrap_or_else(|e| {
+        panic!("failed to read {reference_path} ({e}) - rerun with {BLESS_VAR}=1 to create it")
+    });
+    assert_eq!(size, reference_size, "reference image size differs");
+
+    let error = mean_abs_error(image, &reference);
+    assert!(
+        error <= max_mean_abs_error,
+        "rendered image differs from {reference_path}: mean abs error {error:.3} > {max_mean_abs_error}"
+    );
+}
+
+fn read_png(path: &str) -> Result<((u32, u32), Vec<u8>)> {
+    let decoder = png::Decoder::new(File::open(path)?);
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+
+    if info.color_type != png::ColorType::Rgb || info.bit_depth != png::BitDepth::Eight {
+        bail!("reference image must be 8-bit rgb");
+    }
+
+    data.truncate(info.buffer_size());
+    Ok(((info.width, info.height), data))
+}
+
+fn mean_abs_error(a: &[u8], b: &[u8]) -> f64 {
+    let total: u64 = a.iter().zip(b).map(|(&x, &y)| x.abs_diff(y) as u64).sum();
+    total as f64 / a.len() as f64
+}
+
+#[test]
+fn image_error() {
+    assert_eq!(mean_abs_error(&[0, 10, 255], &[2, 10, 251]), 2.0);
+}
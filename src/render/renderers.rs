@@ -1,3 +1,4 @@
+pub mod preview;
 mod raytrace;
 
-pub use raytrace::RaytraceRenderer;
+pub use raytrace::{RayQueryRenderer, RaytraceRenderer};
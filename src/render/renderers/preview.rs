@@ -0,0 +1,172 @@
+// cpu fallback that draws a rough preview of a MeshScene, for machines without a ray tracing gpu (ci runners,
+// vms and the like)
+//
+// just primary rays against the meshes (see scene::intersect), hitting triangles from either side. every hit is
+// shaded by how directly it faces the camera, N·V rather than N·L, on purpose: there are no lights, brdfs or
+// procedural geometry, so this only shows the shape of the meshes and where they are
+
+use anyhow::Result;
+use glam::{Mat4, Vec3};
+
+use crate::{
+    render::screenshot::{encode_image, encode_png},
+    scene::{
+        intersect::{camera_ray, Intersector},
+        scenes::mesh::MeshScene,
+    },
+};
+
+// faces seen edge-on still get a little light, so they don't vanish into a black background
+const AMBIENT: f32 = 0.1;
+
+/// Renders `scene` from its camera at `size`, as linear RGBA row by row like [`super::super::Renderer::read_image`]
+///
+/// Hits are gray, brighter the more they face the camera, and rays that miss everything get `render.clear_color`.
+pub fn render_preview(scene: &MeshScene, (width, height): (u32, u32)) -> Vec<f32> {
+    let camera = &scene.camera;
+    render(
+        &Intersector::new(scene),
+        camera.view().inverse(),
+        camera.projection_at(width, height).inverse(),
+        scene.render_options.clear_color,
+        (width, height),
+    )
+}
+
+/// [`render_preview`] as an 8-bit PNG file, encoded like screenshots are
+pub fn render_preview_png(scene: &MeshScene, size: (u32, u32)) -> Result<Vec<u8>> {
+    encode_png(size, &encode_image(&render_preview(scene, size)))
+}
+
+fn render(
+    scene: &Intersector,
+    view_inverse: Mat4,
+    projection_inverse: Mat4,
+    background: Vec3,
+    (width, height): (u32, u32),
+) -> Vec<f32> {
+    let mut pixels = Vec::with_capacity(4 * (width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            // through the middle of the pixel
+            let ndc = (
+                (2 * x + 1) as f32 / width as f32 - 1.0,
+                (2 * y + 1) as f32 / height as f32 - 1.0,
+            );
+            let (origin, direction) = camera_ray(view_inverse, projection_inverse, ndc);
+            let color = match scene.intersect(origin, direction) {
+                Some(hit) => Vec3::splat(AMBIENT + (1.0 - AMBIENT) * hit.normal.dot(-direction)),
+                None => background,
+            };
+            pixels.extend_from_slice(&color.extend(1.0).to_array());
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        camera::{Camera, Lens, Projection},
+        render::golden::assert_matches_reference,
+        scene::transform::parse_transform,
+    };
+
+    const REFERENCE_PATH: &str = "resources/golden/preview.png";
+    const IMAGE_SIZE: (u32, u32) = (128, 128);
+    // there's no sampling noise, this only has to absorb float differences between platforms
+    const MAX_MEAN_ABS_ERROR: f64 = 0.5;
+
+    fn obj_triangles(file_name: &str) -> Vec<[Vec3; 3]> {
+        let (models, _) = tobj::load_obj(
+            format!("resources/meshes/{file_name}"),
+            &tobj::GPU_LOAD_OPTIONS,
+        )
+        .unwrap();
+        models
+            .iter()
+            .flat_map(|model| {
+                let mesh = &model.mesh;
+                mesh.indices.chunks_exact(3).map(|triangle| {
+                    [0, 1, 2].map(|corner| {
+                        let i = 3 * triangle[corner] as usize;
+                        Vec3::from_slice(&mesh.positions[i..i + 3])
+                    })
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn preview_quad() {
+        // a 2x2 quad facing the camera, a third of the view across at fov 90
+        let corner = |y, z| Vec3::new(3.0, y, z);
+        let scene = Intersector::from_objects(vec![(
+            vec![
+                [corner(-1.0, -1.0), corner(1.0, -1.0), corner(1.0, 1.0)],
+                [corner(-1.0, -1.0), corner(1.0, 1.0), corner(-1.0, 1.0)],
+            ],
+            Mat4::IDENTITY,
+        )]);
+        let camera = Camera::new(
+            Mat4::look_at_lh(Vec3::ZERO, Vec3::X, Vec3::Z),
+            Projection::Perspective { fov: 90.0 },
+            Lens::default(),
+        );
+        let background = Vec3::new(0.0, 0.0, 0.5);
+        let pixels = render(
+            &scene,
+            camera.view().inverse(),
+            camera.projection_at(9, 9).inverse(),
+            background,
+            (9, 9),
+        );
+        assert_eq!(pixels.len(), 4 * 9 * 9);
+
+        let pixel = |x: usize, y: usize| Vec3::from_slice(&pixels[4 * (9 * y + x)..]);
+        // head on in the middle, a little darker towards the edges of the quad
+        assert!((pixel(4, 4) - Vec3::ONE).length() < 1e-5);
+        assert!(pixel(3, 3).x < 1.0 && pixel(3, 3).x > AMBIENT);
+        assert_eq!(pixel(0, 0), background);
+        assert_eq!(pixel(4, 1), background);
+        assert!(pixels.chunks_exact(4).all(|rgba| rgba[3] == 1.0));
+    }
+
+    // the meshes and camera of resources/scenes/golden.toml, which can't be loaded without compiled shaders
+    #[test]
+    fn preview_golden_image() {
+        let object = |file_name, transform| {
+            (
+                obj_triangles(file_name),
+                parse_transform(transform).unwrap(),
+            )
+        };
+        let scene = Intersector::from_objects(vec![
+            object("cube.obj", "translate 0 -2 0.1"),
+            object("cube.obj", "rotate 45 0 0 1\ntranslate 1 1 0.1"),
+            object("sphere.obj", "scale .5 .5 .5\ntranslate 3 -1 0.6"),
+            object("square.obj", "translate -.5 -.5 -0.05\nscale 10 10 1"),
+        ]);
+        let camera = Camera::new(
+            parse_transform("lookat 4 0 2   0 0 0    0 0 1").unwrap(),
+            Projection::Perspective { fov: 70.0 },
+            Lens::default(),
+        );
+
+        let pixels = render(
+            &scene,
+            camera.view().inverse(),
+            camera.projection_at(IMAGE_SIZE.0, IMAGE_SIZE.1).inverse(),
+            Vec3::new(0.1, 0.1, 0.2),
+            IMAGE_SIZE,
+        );
+        assert_matches_reference(
+            REFERENCE_PATH,
+            IMAGE_SIZE,
+            &encode_image(&pixels),
+            MAX_MEAN_ABS_ERROR,
+        );
+    }
+}
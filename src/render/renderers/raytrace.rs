@@ -1,33 +1,177 @@
-use std::{cell::RefCell, ffi::c_char, rc::Rc, sync::LazyLock};
+use std::{
+    cell::RefCell,
+    ffi::c_char,
+    ops::Range,
+    rc::Rc,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 use ash::{khr, vk, Device, Entry, Instance};
+use glam::{Mat4, Vec3};
 use gpu_allocator::{vulkan::*, MemoryLocation};
+use log::{debug, error, info, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use tobj::Model;
 
 use crate::{
     features::{vk_features, VkFeatureGuard, VkFeatures},
-    render::Renderer,
+    render::{aovs::Aovs, Renderer},
     scene::{
+        intersect::camera_ray,
+        normal_map::NormalMap,
         scenes::mesh::{
             Light, MeshScene, MeshSceneUpdate, Object, ProceduralGeometry, ProceduralObject,
+            RenderRegion, Shader,
         },
         Scene,
     },
-    utils::{align_up, AllocatedBuffer, AllocatedImage, QueueFamilyInfo},
+    utils::{
+        align_up, cmd_pipeline_barrier, legacy_stages, submit_immediate, use_ray_query,
+        use_synchronization2, wait_idle_before_destroy, AllocatedBuffer, AllocatedImage,
+        ImageTransition, QueueFamilyInfo,
+    },
     window::WindowData,
 };
 
+type MeshGeometries = (
+    Vec<vk::AccelerationStructureGeometryKHR<'static>>,
+    Vec<(AllocatedBuffer, AllocatedBuffer)>,
+    Vec<u32>,
+);
+
+#[cfg(test)]
+mod golden;
+mod pick;
+mod ray_query;
+mod render_scale;
+
+pub use pick::Hit;
+pub use ray_query::RayQueryRenderer;
+use render_scale::RenderScale;
+
+// the push constants are just the random offsets for raygen, the rest is in the frame uniform buffer
+const PUSH_CONSTANT_STAGES: vk::ShaderStageFlags = vk::ShaderStageFlags::RAYGEN_KHR;
+
+// the first compute queue renders, the second takes buffer uploads if the compute family has more than one
+static COMPUTE_QUEUE_PRIORITIES: [f32; 2] = [1.0, 0.5];
+static PRESENT_QUEUE_PRIORITY: f32 = 1.0;
+
+// matches the Lights block in hit_common.glsl: a u32 light count and sample total, then 68 bytes per light
+// with the color right after the u32 light type
+const LIGHTS_OFFSET: u64 = 8;
+const LIGHT_SIZE: u64 = 68;
+const LIGHT_COLOR_OFFSET: u64 = 4;
+
+// must match the Environment block in hit_common.glsl
+const ENVIRONMENT_BINDING: u32 = 7;
+// must match the FrameParams block in frame_params.glsl
+const FRAME_PARAMS_BINDING: u32 = 8;
+
+// the custom index of every instance, see Object::custom_index
+const INSTANCE_IDS_BINDING: u32 = 9;
+// must match the NormalMaps block in hit_common.glsl
+const NORMAL_MAPS_BINDING: u32 = 10;
+// must match aov_images in raygen_common.glsl
+const AOV_IMAGES_BINDING: u32 = 11;
+// must match history_image in taa.comp
+const HISTORY_IMAGE_BINDING: u32 = 12;
+
+// the ray query backend runs this instead of any of the scene's shaders
+const RAY_QUERY_SHADER: &str = "ray_query.comp";
+// has to match local_size_x/y in ray_query.comp
+const RAY_QUERY_WORKGROUP_SIZE: u32 = 8;
+
+// blends each traced frame into the history with render.taa, with either backend
+const TAA_SHADER: &str = "taa.comp";
+// has to match local_size_x/y in taa.comp
+const TAA_WORKGROUP_SIZE: u32 = 8;
+
+// traces the single ray of pick, with either backend as long as the device has ray queries
+const PICK_SHADER: &str = "pick.comp";
+
+// frames in a row without a new view before idle_samples_per_frame kicks in, so a short pause while moving
+// around doesn't make the next frame slow
+const IDLE_FRAMES: u32 = 30;
+
+// how rays get traced: a ray tracing pipeline running the scene's shaders,
+// or ray queries from a single compute shader (see RayQueryRenderer)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TraceBackend {
+    Pipeline,
+    RayQuery,
+}
+
+// what is needed to rebuild the tlas at a new point in the shutter interval
+struct MotionBlur {
+    shutter: f32,
+    objects: Vec<Object>,
+    procedural_objects: Vec<ProceduralObject>,
+}
+
+// wall clock time of each step of a longer operation, for finding out which one is slow
+struct PhaseTimes {
+    start: Instant,
+    last: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl PhaseTimes {
+    fn start() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+            phases: Vec::new(),
+        }
+    }
+
+    // everything since the previous phase ended (or since the start) counts towards `name`
+    fn end(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.phases.push((name, now - self.last));
+        self.last = now;
+    }
+
+    fn log(&self, what: &str) {
+        let phases: Vec<_> = self
+            .phases
+            .iter()
+            .map(|(name, duration)| format!("{name} {duration:.2?}"))
+            .collect();
+        debug!(
+            "{what} took {:.2?}: {}",
+            self.start.elapsed(),
+            phases.join(", ")
+        );
+    }
+}
+
 pub struct RaytraceRenderer {
+    backend: TraceBackend,
+    // the app's debug mode, for logging things that are too noisy otherwise
+    debug: bool,
     allocator: Rc<RefCell<Allocator>>,
     device: Device,
     accel_struct_device: khr::acceleration_structure::Device,
     rt_pipeline_device: khr::ray_tracing_pipeline::Device,
+    // loaded when the device has synchronization2 enabled, see utils::use_synchronization2
+    synchronization2: Option<khr::synchronization2::Device>,
+    // whether the device has ray queries enabled, which pick needs, see utils::use_ray_query
+    ray_query: bool,
     device_properties: vk::PhysicalDeviceProperties,
     rt_pipeline_properties: vk::PhysicalDeviceRayTracingPipelinePropertiesKHR<'static>,
     accel_properties: vk::PhysicalDeviceAccelerationStructurePropertiesKHR<'static>,
+    // the per-frame render command buffers, which get reset and recorded again
     command_pool: vk::CommandPool,
+    // one-time command buffers for uploads, builds and copies, see submit_immediate
+    transient_command_pool: vk::CommandPool,
     compute_queue: vk::Queue,
+    // same as compute_queue unless the compute family has a second queue
+    upload_queue: vk::Queue,
+    // same as compute_queue unless the compute family can't present
+    present_queue: vk::Queue,
     top_as: vk::AccelerationStructureKHR,
     top_as_buffer: Option<AllocatedBuffer>,
     triangle_blas: Vec<vk::AccelerationStructureKHR>,
@@ -47,16 +191,246 @@ pub struct RaytraceRenderer {
     descriptor_set_layout: vk::DescriptorSetLayout,
     storage_image: Option<AllocatedImage>,
     accumulation_image: Option<AllocatedImage>,
+    // albedo and depth, then normal and instance, see Aovs. 1x1 unless the scene asks for aovs
+    aov_images: Vec<AllocatedImage>,
+    // see RenderOptions::aovs
+    aovs: bool,
+    // the previous frame's output for taa.comp, 1x1 unless the scene has render.taa
+    history_image: Option<AllocatedImage>,
+    // see RenderOptions::taa
+    taa: bool,
+    // only created with render.taa, null otherwise
+    taa_pipeline_layout: vk::PipelineLayout,
+    taa_pipeline: vk::Pipeline,
+    // only created when the device has ray queries, null otherwise
+    pick_pipeline_layout: vk::PipelineLayout,
+    pick_pipeline: vk::Pipeline,
+    // view projection of the frame in history_image, None until there is one
+    taa_view_projection: Option<Mat4>,
     vertex_normal_buffer: Option<AllocatedBuffer>,
     light_buffer: Option<AllocatedBuffer>,
     offset_buffer: Option<AllocatedBuffer>,
     brdf_param_buffer: Option<AllocatedBuffer>,
+    instance_id_buffer: Option<AllocatedBuffer>,
+    // always there, see NormalMap::buffer
+    normal_map_buffer: Option<AllocatedBuffer>,
+    // always there, with a width and height of 0 if the scene has no environment map
+    environment_buffer: Option<AllocatedBuffer>,
+    // scene light colors, and whether each [[light]] entry (see MeshScene::light_entries) is switched on
+    light_colors: Vec<Vec3>,
+    light_entries: Vec<Range<usize>>,
+    lights_enabled: Vec<bool>,
     command_buffers: Vec<vk::CommandBuffer>,
-    push_data: [u8; 128 + 8 + 4],
+    // two timestamps around the trace per command buffer, only used to pick the render scale
+    timestamp_pool: vk::QueryPool,
+    // whether each command buffer's timestamps have been submitted and not read back yet
+    timestamps_written: Vec<bool>,
+    render_scale: Option<RenderScale>,
+    // window size, the storage image is this times the render scale
+    target_size: (u32, u32),
+    // matches the Constants block in raygen_common.glsl: 0..8 seed offset, 8..12 frame,
+    // 16..24 region offset, 24..32 region size (uvec2s are 8 byte aligned, so 12..16 is padding)
+    push_data: [u8; 8 + 4 + 4 + 8 + 8],
+    // matches the FrameParams block in frame_params.glsl:
+    // 0..64 view inverse, 64..128 projection inverse, 128..132 aperture, 132..136 focus distance,
+    // 136..140 shutter time, 140..144 debug mode, 144..156 clear color, 156..160 pixel sampler,
+    // 160..164 write aovs, 164..168 taa, 168..172 taa history, 172..236 previous view projection
+    frame_data: [u8; 128 + 4 + 4 + 4 + 4 + 12 + 4 + 4 + 4 + 4 + 64],
+    // one copy of frame_data per command buffer, so frames in flight keep theirs
+    frame_params_buffer: Option<AllocatedBuffer>,
+    frame_params_slots: u32,
     current_frame: u32,
+    // render_to only presents once current_frame gets here, see RenderOptions::max_samples
+    max_samples: Option<u32>,
+    // see RenderOptions::idle_samples_per_frame
+    idle_samples_per_frame: u32,
+    // frames presented since the last NewView or NewSize, up to IDLE_FRAMES
+    idle_frames: u32,
+    // what the storage image shows until the first frame is traced into it, see RenderOptions::initial_color
+    initial_color: Vec3,
+    seed: Option<u64>,
+    motion_blur: Option<MotionBlur>,
+    // in window pixels, see RenderOptions::region
+    region: Option<RenderRegion>,
+    // see RenderOptions::dispatch_rows
+    dispatch_rows: Option<u32>,
 }
 
 impl RaytraceRenderer {
+    fn with_backend(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        queue_family_info: &QueueFamilyInfo,
+        allocator: Rc<RefCell<Allocator>>,
+        debug: bool,
+        backend: TraceBackend,
+    ) -> anyhow::Result<Self> {
+        let accel_struct_device = khr::acceleration_structure::Device::new(instance, device);
+        // without the extension these functions just don't load, which is fine as long as they aren't called
+        let rt_pipeline_device = khr::ray_tracing_pipeline::Device::new(instance, device);
+        let synchronization2 = use_synchronization2(instance, physical_device)?
+            .then(|| khr::synchronization2::Device::new(instance, device));
+        debug!(
+            "Using {} barriers",
+            if synchronization2.is_some() {
+                "synchronization2"
+            } else {
+                "legacy"
+            }
+        );
+
+        let mut rt_pipeline_properties =
+            vk::PhysicalDeviceRayTracingPipelinePropertiesKHR::default();
+        let mut accel_properties = vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
+        let mut physical_device_properties2 =
+            vk::PhysicalDeviceProperties2::default().push_next(&mut accel_properties);
+        // the ray tracing pipeline properties can't even be queried without the extension
+        if backend == TraceBackend::Pipeline {
+            physical_device_properties2 =
+                physical_device_properties2.push_next(&mut rt_pipeline_properties);
+        }
+        unsafe {
+            instance
+                .get_physical_device_properties2(physical_device, &mut physical_device_properties2)
+        };
+
+        let compute_queue_index = queue_family_info
+            .compute_index
+            .ok_or(anyhow!("no compute"))?;
+
+        let create_command_pool = |flags| {
+            let create_info = vk::CommandPoolCreateInfo {
+                queue_family_index: compute_queue_index,
+                flags,
+                ..Default::default()
+            };
+            unsafe { device.create_command_pool(&create_info, None) }
+        };
+        let command_pool = create_command_pool(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)?;
+        // every command buffer from here is freed right after it's done, nothing is ever reset
+        let transient_command_pool =
+            match create_command_pool(vk::CommandPoolCreateFlags::TRANSIENT) {
+                Ok(x) => x,
+                Err(e) => {
+                    unsafe { device.destroy_command_pool(command_pool, None) };
+                    return Err(e.into());
+                }
+            };
+        let compute_queue = unsafe { device.get_device_queue(compute_queue_index, 0) };
+        // get_queue_info only asks for the second queue when there is one
+        let upload_queue = if queue_family_info.compute_queue_count > 1 {
+            unsafe { device.get_device_queue(compute_queue_index, 1) }
+        } else {
+            compute_queue
+        };
+        // headless rendering has no present family, and then nothing is presented anyway
+        let present_queue = match queue_family_info.present_index {
+            Some(index) if index != compute_queue_index => unsafe {
+                device.get_device_queue(index, 0)
+            },
+            _ => compute_queue,
+        };
+
+        Ok(RaytraceRenderer {
+            backend,
+            debug,
+            allocator,
+            device: device.clone(),
+            accel_struct_device,
+            rt_pipeline_device,
+            synchronization2,
+            ray_query: use_ray_query(instance, physical_device)?,
+            device_properties: physical_device_properties2.properties,
+            rt_pipeline_properties,
+            accel_properties,
+            command_pool,
+            transient_command_pool,
+            compute_queue,
+            upload_queue,
+            present_queue,
+            top_as: Default::default(),
+            top_as_buffer: Default::default(),
+            triangle_blas: Default::default(),
+            triangle_blas_buffers: Default::default(),
+            procedural_blas: Default::default(),
+            procedural_blas_buffers: Default::default(),
+            triangle_hit_group_count: 0,
+            pipeline_layout: Default::default(),
+            pipeline: Default::default(),
+            sbt_buffer: Default::default(),
+            raygen_region: Default::default(),
+            miss_region: Default::default(),
+            hit_region: Default::default(),
+            callable_region: Default::default(),
+            descriptor_pool: Default::default(),
+            descriptor_set: Default::default(),
+            descriptor_set_layout: Default::default(),
+            storage_image: Default::default(),
+            accumulation_image: Default::default(),
+            aov_images: Vec::new(),
+            aovs: false,
+            history_image: None,
+            taa: false,
+            taa_pipeline_layout: Default::default(),
+            taa_pipeline: Default::default(),
+            pick_pipeline_layout: Default::default(),
+            pick_pipeline: Default::default(),
+            taa_view_projection: None,
+            vertex_normal_buffer: Default::default(),
+            light_buffer: Default::default(),
+            offset_buffer: Default::default(),
+            instance_id_buffer: Default::default(),
+            normal_map_buffer: Default::default(),
+            brdf_param_buffer: Default::default(),
+            environment_buffer: Default::default(),
+            light_colors: Default::default(),
+            light_entries: Default::default(),
+            lights_enabled: Default::default(),
+            command_buffers: Default::default(),
+            timestamp_pool: Default::default(),
+            timestamps_written: Default::default(),
+            render_scale: None,
+            target_size: (WindowData::DEFAULT_WIDTH, WindowData::DEFAULT_HEIGHT),
+            push_data: [0; 8 + 4 + 4 + 8 + 8],
+            frame_data: [0; 128 + 4 + 4 + 4 + 4 + 12 + 4 + 4 + 4 + 4 + 64],
+            frame_params_buffer: None,
+            frame_params_slots: 0,
+            current_frame: 0,
+            max_samples: None,
+            idle_samples_per_frame: 1,
+            idle_frames: 0,
+            initial_color: Vec3::ZERO,
+            seed: None,
+            motion_blur: None,
+            region: None,
+            dispatch_rows: None,
+        })
+    }
+
+    // stages that read the push constants
+    fn push_constant_stages(&self) -> vk::ShaderStageFlags {
+        match self.backend {
+            TraceBackend::Pipeline => PUSH_CONSTANT_STAGES,
+            TraceBackend::RayQuery => vk::ShaderStageFlags::COMPUTE,
+        }
+    }
+
+    // pipeline stage the storage images are written in
+    fn trace_stage(&self) -> vk::PipelineStageFlags2 {
+        match self.backend {
+            TraceBackend::Pipeline => vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR,
+            TraceBackend::RayQuery => vk::PipelineStageFlags2::COMPUTE_SHADER,
+        }
+    }
+
+    fn bind_point(&self) -> vk::PipelineBindPoint {
+        match self.backend {
+            TraceBackend::Pipeline => vk::PipelineBindPoint::RAY_TRACING_KHR,
+            TraceBackend::RayQuery => vk::PipelineBindPoint::COMPUTE,
+        }
+    }
+
     fn build_accel_structs(
         &self,
         ty: vk::AccelerationStructureTypeKHR,
@@ -148,47 +522,23 @@ impl RaytraceRenderer {
         let unsqueezed_build_range_infos: Vec<_> =
             build_range_infos.iter().map(std::slice::from_ref).collect();
 
-        let build_command_buffer = {
-            let allocate_info = vk::CommandBufferAllocateInfo {
-                command_buffer_count: 1,
-                command_pool: self.command_pool,
-                level: vk::CommandBufferLevel::PRIMARY,
-                ..Default::default()
-            };
-
-            let command_buffers = unsafe { self.device.allocate_command_buffers(&allocate_info) }?;
-            command_buffers[0]
-        };
+        submit_immediate(
+            &self.device,
+            self.transient_command_pool,
+            self.compute_queue,
+            |command_buffer| {
+                unsafe {
+                    self.accel_struct_device.cmd_build_acceleration_structures(
+                        command_buffer,
+                        &build_infos,
+                        &unsqueezed_build_range_infos,
+                    );
+                }
+                Ok(())
+            },
+        )?;
 
         unsafe {
-            self.device.begin_command_buffer(
-                build_command_buffer,
-                &vk::CommandBufferBeginInfo {
-                    flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
-                    ..Default::default()
-                },
-            )?;
-
-            self.accel_struct_device.cmd_build_acceleration_structures(
-                build_command_buffer,
-                &build_infos,
-                &unsqueezed_build_range_infos,
-            );
-            self.device.end_command_buffer(build_command_buffer)?;
-            self.device.queue_submit(
-                self.compute_queue,
-                &[vk::SubmitInfo {
-                    p_command_buffers: &raw const build_command_buffer,
-                    command_buffer_count: 1,
-                    ..Default::default()
-                }],
-                vk::Fence::null(),
-            )?;
-
-            self.device.queue_wait_idle(self.compute_queue)?;
-            self.device
-                .free_command_buffers(self.command_pool, &[build_command_buffer]);
-
             for scratch_buffer in scratch_buffers {
                 scratch_buffer.destroy(&self.device, &mut self.allocator.borrow_mut());
             }
@@ -197,14 +547,18 @@ impl RaytraceRenderer {
         Ok((accel_structs, buffers))
     }
 
-    fn get_mesh_geometries(
-        &self,
-        meshes: &[Model],
-    ) -> anyhow::Result<(
-        Vec<vk::AccelerationStructureGeometryKHR<'static>>,
-        Vec<(AllocatedBuffer, AllocatedBuffer)>,
-        Vec<u32>,
-    )> {
+    /// Derives the random offsets for a frame deterministically from a fixed seed
+    ///
+    /// The same `(seed, frame)` pair always produces the same offsets, so renders are reproducible.
+    fn frame_seed(seed: u64, frame: u32) -> (u32, u32) {
+        let mut key = [0u8; 32];
+        key[0..8].copy_from_slice(&seed.to_le_bytes());
+        key[8..12].copy_from_slice(&frame.to_le_bytes());
+
+        StdRng::from_seed(key).gen()
+    }
+
+    fn get_mesh_geometries(&self, meshes: &[Model]) -> anyhow::Result<MeshGeometries> {
         let mut geometries = Vec::new();
         let mut buffers = Vec::new();
         let mut primitive_counts = Vec::new();
@@ -212,32 +566,25 @@ impl RaytraceRenderer {
             let vertex_count = mesh.mesh.positions.len() / 3;
             let vertex_stride = std::mem::size_of_val(&mesh.mesh.positions[0]) * 3;
 
-            let mut vertex_buffer = AllocatedBuffer::new(
-                &self.device,
-                &mut self.allocator.borrow_mut(),
-                (vertex_stride * vertex_count) as vk::DeviceSize,
-                vk::BufferUsageFlags::VERTEX_BUFFER
-                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                    | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
-                MemoryLocation::CpuToGpu,
-                self.device_properties.limits,
-            )?;
-            vertex_buffer.store(&mesh.mesh.positions)?;
+            // builds read these a lot, so they're staged into device local memory like the other scene buffers
+            let vertex_buffer = unsafe {
+                self.create_device_buffer(
+                    &mesh.mesh.positions,
+                    vk::BufferUsageFlags::VERTEX_BUFFER
+                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                        | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+                )?
+            };
 
             let index_count = mesh.mesh.indices.len();
-            let index_stride = std::mem::size_of_val(&mesh.mesh.indices[0]);
-
-            let mut index_buffer = AllocatedBuffer::new(
-                &self.device,
-                &mut self.allocator.borrow_mut(),
-                (index_stride * index_count) as vk::DeviceSize,
-                vk::BufferUsageFlags::INDEX_BUFFER
-                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                    | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
-                MemoryLocation::CpuToGpu,
-                self.device_properties.limits,
-            )?;
-            index_buffer.store(&mesh.mesh.indices)?;
+            let index_buffer = unsafe {
+                self.create_device_buffer(
+                    &mesh.mesh.indices,
+                    vk::BufferUsageFlags::INDEX_BUFFER
+                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                        | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+                )?
+            };
 
             let geometry = vk::AccelerationStructureGeometryKHR {
                 geometry_type: vk::GeometryTypeKHR::TRIANGLES,
@@ -293,17 +640,13 @@ impl RaytraceRenderer {
                 })
                 .collect();
 
-            let aabb_buffer_size = std::mem::size_of::<f32>() * aabb_data.len();
-            let mut aabb_buffer = AllocatedBuffer::new(
-                &self.device,
-                &mut self.allocator.borrow_mut(),
-                aabb_buffer_size as vk::DeviceSize,
-                vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                    | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
-                MemoryLocation::CpuToGpu,
-                self.device_properties.limits,
-            )?;
-            aabb_buffer.store(&aabb_data)?;
+            let aabb_buffer = unsafe {
+                self.create_device_buffer(
+                    &aabb_data,
+                    vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                        | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+                )?
+            };
 
             let geometry = vk::AccelerationStructureGeometryKHR {
                 geometry_type: vk::GeometryTypeKHR::AABBS,
@@ -380,10 +723,13 @@ impl RaytraceRenderer {
 
             instances.push(vk::AccelerationStructureInstanceKHR {
                 transform: vk::TransformMatrixKHR { matrix: matrix_3_4 },
-                instance_custom_index_and_mask: vk::Packed24_8::new(object.vertex_index, 0xff),
+                instance_custom_index_and_mask: vk::Packed24_8::new(
+                    object.vertex_index,
+                    object.visibility_mask,
+                ),
                 instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(
                     object.brdf_i as u32,
-                    vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as u8,
+                    object.instance_flags().as_raw() as u8,
                 ),
                 acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
                     device_handle: triangle_handles[object.mesh_i],
@@ -400,11 +746,14 @@ impl RaytraceRenderer {
             let mut matrix_3_4 = [0f32; 12];
             matrix_3_4.copy_from_slice(&matrix[0..12]);
 
-            let sbt_offset = triangle_hit_group_count + proc_obj.geometry_index;
+            let sbt_offset = proc_obj.hit_record_index(triangle_hit_group_count);
 
             instances.push(vk::AccelerationStructureInstanceKHR {
                 transform: vk::TransformMatrixKHR { matrix: matrix_3_4 },
-                instance_custom_index_and_mask: vk::Packed24_8::new(proc_obj.custom_index, 0xff),
+                instance_custom_index_and_mask: vk::Packed24_8::new(
+                    proc_obj.custom_index,
+                    proc_obj.visibility_mask,
+                ),
                 instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(
                     sbt_offset as u32,
                     vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as u8,
@@ -451,30 +800,33 @@ impl RaytraceRenderer {
     fn get_descriptor_set_layout(
         &self,
     ) -> anyhow::Result<(vk::DescriptorSetLayout, Vec<vk::DescriptorPoolSize>)> {
-        let bindings = [
+        // taa.comp runs with either backend, so what it reads and writes is in the compute stage too
+        let mut bindings = [
             vk::DescriptorSetLayoutBinding {
                 descriptor_count: 1,
                 descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
-                stage_flags: vk::ShaderStageFlags::RAYGEN_KHR,
+                stage_flags: vk::ShaderStageFlags::RAYGEN_KHR | vk::ShaderStageFlags::COMPUTE,
                 binding: 0,
                 ..Default::default()
             },
             vk::DescriptorSetLayoutBinding {
                 descriptor_count: 1,
                 descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
-                stage_flags: vk::ShaderStageFlags::RAYGEN_KHR,
+                stage_flags: vk::ShaderStageFlags::RAYGEN_KHR | vk::ShaderStageFlags::COMPUTE,
                 binding: 1,
                 ..Default::default()
             },
             vk::DescriptorSetLayoutBinding {
                 descriptor_count: 1,
                 descriptor_type: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                // pick.comp traces against it with either backend
                 stage_flags: vk::ShaderStageFlags::RAYGEN_KHR
-                    | vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+                    | vk::ShaderStageFlags::CLOSEST_HIT_KHR
+                    | vk::ShaderStageFlags::COMPUTE,
                 binding: 2,
                 ..Default::default()
             },
-            // vertices and normals
+            // vertices: positions, normals, texture coordinates and tangents
             vk::DescriptorSetLayoutBinding {
                 descriptor_count: 1,
                 descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
@@ -483,11 +835,11 @@ impl RaytraceRenderer {
                 binding: 3,
                 ..Default::default()
             },
-            // lights
+            // lights, the environment miss shader needs the count
             vk::DescriptorSetLayoutBinding {
                 descriptor_count: 1,
                 descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
-                stage_flags: vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+                stage_flags: vk::ShaderStageFlags::CLOSEST_HIT_KHR | vk::ShaderStageFlags::MISS_KHR,
                 binding: 4,
                 ..Default::default()
             },
@@ -507,8 +859,65 @@ impl RaytraceRenderer {
                 binding: 6,
                 ..Default::default()
             },
+            // environment map and its sampling distribution
+            vk::DescriptorSetLayoutBinding {
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                stage_flags: vk::ShaderStageFlags::CLOSEST_HIT_KHR | vk::ShaderStageFlags::MISS_KHR,
+                binding: ENVIRONMENT_BINDING,
+                ..Default::default()
+            },
+            // per-frame parameters, offset to the slot of the frame being recorded
+            vk::DescriptorSetLayoutBinding {
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                stage_flags: vk::ShaderStageFlags::RAYGEN_KHR
+                    | vk::ShaderStageFlags::MISS_KHR
+                    | vk::ShaderStageFlags::COMPUTE,
+                binding: FRAME_PARAMS_BINDING,
+                ..Default::default()
+            },
+            // instance ids
+            vk::DescriptorSetLayoutBinding {
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                stage_flags: vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+                binding: INSTANCE_IDS_BINDING,
+                ..Default::default()
+            },
+            // normal maps of every brdf
+            vk::DescriptorSetLayoutBinding {
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                stage_flags: vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+                binding: NORMAL_MAPS_BINDING,
+                ..Default::default()
+            },
+            // aov images
+            vk::DescriptorSetLayoutBinding {
+                descriptor_count: 2,
+                descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                stage_flags: vk::ShaderStageFlags::RAYGEN_KHR,
+                binding: AOV_IMAGES_BINDING,
+                ..Default::default()
+            },
+            // taa history
+            vk::DescriptorSetLayoutBinding {
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                binding: HISTORY_IMAGE_BINDING,
+                ..Default::default()
+            },
         ];
 
+        // the one compute shader does everything the raygen and hit shaders would
+        if self.backend == TraceBackend::RayQuery {
+            for binding in &mut bindings {
+                binding.stage_flags = vk::ShaderStageFlags::COMPUTE;
+            }
+        }
+
         let create_info = vk::DescriptorSetLayoutCreateInfo {
             p_bindings: bindings.as_ptr(),
             binding_count: bindings.len() as u32,
@@ -535,9 +944,9 @@ impl RaytraceRenderer {
         &self,
         scene: &MeshScene,
         descriptor_set_layouts: &[vk::DescriptorSetLayout],
-    ) -> anyhow::Result<(vk::PipelineLayout, vk::Pipeline, usize, usize)> {
+    ) -> anyhow::Result<(vk::PipelineLayout, vk::Pipeline)> {
         let push_constant_range = vk::PushConstantRange {
-            stage_flags: vk::ShaderStageFlags::RAYGEN_KHR,
+            stage_flags: PUSH_CONSTANT_STAGES,
             offset: 0,
             size: std::mem::size_of_val(&self.push_data) as u32,
         };
@@ -611,8 +1020,7 @@ impl RaytraceRenderer {
             });
         }
 
-        let triangle_hit_group_count = scene.hit_shaders.len();
-
+        // procedural hit groups go right after the triangle ones, see ProceduralObject::hit_record_index
         for proc_geom in scene.procedural_geometries.iter() {
             let int_module = proc_geom
                 .intersection_shader
@@ -677,98 +1085,139 @@ impl RaytraceRenderer {
             }
         }
 
-        Ok((
-            pipeline_layout,
-            pipeline,
+        // the sbt is sized from the scene's requirements, so these have to agree
+        debug_assert_eq!(
             shader_groups.len(),
-            triangle_hit_group_count,
-        ))
+            scene.shader_requirements().shader_group_count()
+        );
+
+        Ok((pipeline_layout, pipeline))
     }
 
-    unsafe fn copy_buffer(
+    fn create_compute_pipeline(
         &self,
-        src: vk::Buffer,
-        dst: vk::Buffer,
-        size: u64,
-    ) -> anyhow::Result<()> {
-        let command_buffer_allocate_info = vk::CommandBufferAllocateInfo {
-            command_buffer_count: 1,
-            command_pool: self.command_pool,
-            level: vk::CommandBufferLevel::PRIMARY,
-            ..Default::default()
+        shader: &str,
+        descriptor_set_layouts: &[vk::DescriptorSetLayout],
+    ) -> anyhow::Result<(vk::PipelineLayout, vk::Pipeline)> {
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: std::mem::size_of_val(&self.push_data) as u32,
         };
-
-        let command_buffer = self
-            .device
-            .allocate_command_buffers(&command_buffer_allocate_info)?[0];
-
-        let command_buffer_begin_info = vk::CommandBufferBeginInfo {
-            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+        let layout_create_info = vk::PipelineLayoutCreateInfo {
+            p_set_layouts: descriptor_set_layouts.as_ptr(),
+            set_layout_count: descriptor_set_layouts.len() as u32,
+            push_constant_range_count: 1,
+            p_push_constant_ranges: &raw const push_constant_range,
             ..Default::default()
         };
+        let pipeline_layout = unsafe {
+            self.device
+                .create_pipeline_layout(&layout_create_info, None)?
+        };
 
-        self.device
-            .begin_command_buffer(command_buffer, &command_buffer_begin_info)?;
-
-        self.device.cmd_copy_buffer(
-            command_buffer,
-            src,
-            dst,
-            &[vk::BufferCopy {
-                src_offset: 0,
-                dst_offset: 0,
-                size,
-            }],
-        );
+        let module = Shader::load(shader, shader)?
+            .compile(&self.device)?
+            .module();
 
-        self.device.end_command_buffer(command_buffer)?;
+        let pipeline = unsafe {
+            let out = self.device.create_compute_pipelines(
+                vk::PipelineCache::null(),
+                &[vk::ComputePipelineCreateInfo {
+                    stage: vk::PipelineShaderStageCreateInfo {
+                        stage: vk::ShaderStageFlags::COMPUTE,
+                        module,
+                        p_name: c"main".as_ptr(),
+                        ..Default::default()
+                    },
+                    layout: pipeline_layout,
+                    ..Default::default()
+                }],
+                None,
+            );
+            self.device.destroy_shader_module(module, None);
 
-        let submit_info = vk::SubmitInfo {
-            command_buffer_count: 1,
-            p_command_buffers: &raw const command_buffer,
-            ..Default::default()
+            match out {
+                Ok(x) => x[0],
+                Err((x, y)) => *x
+                    .first()
+                    .ok_or(anyhow!("failed to construct compute pipeline: {y}"))?,
+            }
         };
 
-        self.device
-            .queue_submit(self.compute_queue, &[submit_info], vk::Fence::null())?;
-
-        self.device.queue_wait_idle(self.compute_queue)?;
-        self.device
-            .free_command_buffers(self.command_pool, &[command_buffer]);
-
-        Ok(())
+        Ok((pipeline_layout, pipeline))
     }
 
-    unsafe fn create_device_buffer<T: Copy>(
+    unsafe fn copy_buffer(
         &self,
-        data: &[T],
-        usage: vk::BufferUsageFlags,
+        src: vk::Buffer,
+        dst: vk::Buffer,
+        dst_offset: u64,
+        size: u64,
+    ) -> anyhow::Result<()> {
+        submit_immediate(
+            &self.device,
+            self.transient_command_pool,
+            self.upload_queue,
+            |command_buffer| {
+                self.device.cmd_copy_buffer(
+                    command_buffer,
+                    src,
+                    dst,
+                    &[vk::BufferCopy {
+                        src_offset: 0,
+                        dst_offset,
+                        size,
+                    }],
+                );
+                Ok(())
+            },
+        )
+    }
+
+    unsafe fn create_device_buffer<T: Copy>(
+        &self,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
     ) -> anyhow::Result<AllocatedBuffer> {
-        let size = std::mem::size_of_val(data) as u64;
-        let mut staging_buffer = AllocatedBuffer::new(
+        let buffer = AllocatedBuffer::new(
             &self.device,
             &mut self.allocator.borrow_mut(),
-            size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            MemoryLocation::CpuToGpu,
+            std::mem::size_of_val(data) as u64,
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuOnly,
             self.device_properties.limits,
         )?;
-        staging_buffer.store(data)?;
 
-        let buffer = AllocatedBuffer::new(
+        self.write_device_buffer(&buffer, 0, data)?;
+
+        Ok(buffer)
+    }
+
+    // overwrites part of a gpu-only buffer created with create_device_buffer through a staging buffer
+    // the upload waits for the queue, but not for frames in flight that might still read the buffer
+    unsafe fn write_device_buffer<T: Copy>(
+        &self,
+        buffer: &AllocatedBuffer,
+        offset: u64,
+        data: &[T],
+    ) -> anyhow::Result<()> {
+        let size = std::mem::size_of_val(data) as u64;
+        let mut staging_buffer = AllocatedBuffer::new(
             &self.device,
             &mut self.allocator.borrow_mut(),
             size,
-            usage | vk::BufferUsageFlags::TRANSFER_DST,
-            MemoryLocation::GpuOnly,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
             self.device_properties.limits,
         )?;
+        staging_buffer.store(data)?;
 
-        self.copy_buffer(staging_buffer.buffer, buffer.buffer, size)?;
+        let result = self.copy_buffer(staging_buffer.buffer, buffer.buffer, offset, size);
 
         staging_buffer.destroy(&self.device, &mut self.allocator.borrow_mut());
 
-        Ok(buffer)
+        result
     }
 
     fn create_sbt(
@@ -810,7 +1259,7 @@ impl RaytraceRenderer {
         // miss
         table_data[base_stride..base_stride + handle_size]
             .copy_from_slice(&unaligned_table_data[handle_size..2 * handle_size]);
-        // closest hit
+        // hit groups, triangle and procedural alike share the same stride
         for i in 0..shader_group_count - 2 {
             let aligned_base = 2 * base_stride + i * handle_stride;
             table_data[aligned_base..aligned_base + handle_size].copy_from_slice(
@@ -853,6 +1302,36 @@ impl RaytraceRenderer {
         ))
     }
 
+    // a misaligned sbt doesn't fail anywhere, rays just run the wrong shaders (or the device gets lost)
+    fn log_sbt_layout(&self) {
+        let properties = &self.rt_pipeline_properties;
+        debug!(
+            "SBT layout: handle size {}, handle alignment {}, base alignment {}",
+            properties.shader_group_handle_size,
+            properties.shader_group_handle_alignment,
+            properties.shader_group_base_alignment
+        );
+
+        for (name, region) in [
+            ("raygen", &self.raygen_region),
+            ("miss", &self.miss_region),
+            ("hit", &self.hit_region),
+            ("callable", &self.callable_region),
+        ] {
+            debug!(
+                "SBT {name} region: address {:#x}, stride {}, size {}",
+                region.device_address, region.stride, region.size
+            );
+        }
+
+        let hit_records = (self.hit_region.size / self.hit_region.stride.max(1)) as usize;
+        debug!(
+            "SBT hit records: {hit_records} ({} triangle, {} procedural)",
+            self.triangle_hit_group_count,
+            hit_records.saturating_sub(self.triangle_hit_group_count)
+        );
+    }
+
     fn create_descriptor_pool_and_set(
         &self,
         layout: vk::DescriptorSetLayout,
@@ -882,6 +1361,62 @@ impl RaytraceRenderer {
         Ok((pool, set))
     }
 
+    /// Creates an image the raygen or compute shader can write to, already in GENERAL layout
+    // cleared to `clear`, otherwise whatever was in the memory shows up until the first trace lands
+    fn create_storage_image(
+        &self,
+        size: (u32, u32),
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        clear: Vec3,
+    ) -> anyhow::Result<AllocatedImage> {
+        let mut image = AllocatedImage::new(
+            &self.device,
+            &mut self.allocator.borrow_mut(),
+            size,
+            format,
+            usage | vk::ImageUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuOnly,
+        )?;
+        submit_immediate(
+            &self.device,
+            self.transient_command_pool,
+            self.compute_queue,
+            |command_buffer| unsafe {
+                image.record_transition(
+                    &self.device,
+                    self.synchronization2.as_ref(),
+                    command_buffer,
+                    ImageTransition::undefined_to_clear(),
+                );
+                self.device.cmd_clear_color_image(
+                    command_buffer,
+                    image.image,
+                    image.layout(),
+                    &vk::ClearColorValue {
+                        float32: clear.extend(1f32).to_array(),
+                    },
+                    &[vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    }],
+                );
+                image.record_transition(
+                    &self.device,
+                    self.synchronization2.as_ref(),
+                    command_buffer,
+                    ImageTransition::cleared_to_storage(self.trace_stage()),
+                );
+                Ok(())
+            },
+        )?;
+
+        Ok(image)
+    }
+
     fn create_command_buffer(&self) -> anyhow::Result<vk::CommandBuffer> {
         let allocate_info = vk::CommandBufferAllocateInfo {
             command_buffer_count: 1,
@@ -893,11 +1428,157 @@ impl RaytraceRenderer {
         Ok(unsafe { self.device.allocate_command_buffers(&allocate_info)?[0] })
     }
 
+    // frame_slot picks the copy of the frame parameters to use, see upload_frame_params
+    // pass 0 traces current_frame, later passes the samples after it (see frame_passes)
+    unsafe fn record_trace(&self, command_buffer: vk::CommandBuffer, frame_slot: u32, pass: u32) {
+        self.device
+            .cmd_bind_pipeline(command_buffer, self.bind_point(), self.pipeline);
+        self.device.cmd_bind_descriptor_sets(
+            command_buffer,
+            self.bind_point(),
+            self.pipeline_layout,
+            0,
+            &[self.descriptor_set],
+            &[frame_slot * self.frame_params_slot_size() as u32],
+        );
+
+        // the shaders add the region offset from the push constants to the launch ids
+        // so each strip is traced as a region of its own, with the rest of the push constants unchanged
+        let ((x, y), (width, height)) = self.trace_region();
+        let strip_rows = self.dispatch_rows.map_or(height, |rows| rows.min(height));
+        let pass_push_data = match pass {
+            0 => self.push_data,
+            _ => self.frame_push_data(self.current_frame + pass),
+        };
+        for strip_y in (0..height).step_by(strip_rows as usize) {
+            let strip_height = strip_rows.min(height - strip_y);
+            let mut push_data = pass_push_data;
+            push_data[16..32].copy_from_slice(bytemuck::cast_slice(&[
+                x,
+                y + strip_y,
+                width,
+                strip_height,
+            ]));
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                self.push_constant_stages(),
+                0,
+                &push_data,
+            );
+
+            match self.backend {
+                TraceBackend::Pipeline => self.rt_pipeline_device.cmd_trace_rays(
+                    command_buffer,
+                    &self.raygen_region,
+                    &self.miss_region,
+                    &self.hit_region,
+                    &self.callable_region,
+                    width,
+                    strip_height,
+                    1,
+                ),
+                // the shader skips the invocations that end up outside the region
+                TraceBackend::RayQuery => self.device.cmd_dispatch(
+                    command_buffer,
+                    width.div_ceil(RAY_QUERY_WORKGROUP_SIZE),
+                    strip_height.div_ceil(RAY_QUERY_WORKGROUP_SIZE),
+                    1,
+                ),
+            }
+        }
+    }
+
+    // blends the frame that was just traced into the history and writes the storage image, see taa.comp
+    unsafe fn record_taa(&self, command_buffer: vk::CommandBuffer, frame_slot: u32) {
+        self.device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.taa_pipeline,
+        );
+        self.device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.taa_pipeline_layout,
+            0,
+            &[self.descriptor_set],
+            &[frame_slot * self.frame_params_slot_size() as u32],
+        );
+        // the push constants of the first pass already cover the whole region
+        self.device.cmd_push_constants(
+            command_buffer,
+            self.taa_pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &self.push_data,
+        );
+
+        let (_, (width, height)) = self.trace_region();
+        self.device.cmd_dispatch(
+            command_buffer,
+            width.div_ceil(TAA_WORKGROUP_SIZE),
+            height.div_ceil(TAA_WORKGROUP_SIZE),
+            1,
+        );
+    }
+
+    // keeps this frame's output as the next frame's taa history
+    unsafe fn record_history_copy(&self, command_buffer: vk::CommandBuffer) {
+        let storage_image = self.storage_image.as_ref().unwrap();
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        self.device.cmd_copy_image(
+            command_buffer,
+            storage_image.image,
+            vk::ImageLayout::GENERAL,
+            self.history_image.as_ref().unwrap().image,
+            vk::ImageLayout::GENERAL,
+            &[vk::ImageCopy {
+                src_subresource: subresource,
+                dst_subresource: subresource,
+                extent: vk::Extent3D {
+                    width: storage_image.width,
+                    height: storage_image.height,
+                    depth: 1,
+                },
+                ..Default::default()
+            }],
+        );
+
+        // the next frame traces into the images this one's taa pass and blit read, and reads the history
+        cmd_pipeline_barrier(
+            &self.device,
+            self.synchronization2.as_ref(),
+            command_buffer,
+            &[vk::MemoryBarrier2 {
+                src_stage_mask: vk::PipelineStageFlags2::COPY
+                    | vk::PipelineStageFlags2::BLIT
+                    | vk::PipelineStageFlags2::COMPUTE_SHADER,
+                src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+                dst_stage_mask: self.trace_stage() | vk::PipelineStageFlags2::COMPUTE_SHADER,
+                dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ
+                    | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                ..Default::default()
+            }],
+            &[],
+        );
+    }
+
+    // without a frame_slot nothing is traced and the storage image is just presented again, otherwise it's
+    // traced `passes` times
     fn record_command_buffer(
         &self,
         command_buffer: vk::CommandBuffer,
-        target_image: vk::Image,
+        // and the layout it was left in, see WindowData::previous_layout
+        (target_image, target_layout): (vk::Image, vk::ImageLayout),
         (target_width, target_height): (u32, u32),
+        frame_slot: Option<u32>,
+        passes: u32,
+        first_query: Option<u32>,
     ) -> anyhow::Result<()> {
         let command_buffer_begin_info = vk::CommandBufferBeginInfo::default();
 
@@ -908,55 +1589,104 @@ impl RaytraceRenderer {
             self.device
                 .begin_command_buffer(command_buffer, &command_buffer_begin_info)?;
 
-            self.device.cmd_bind_pipeline(
-                command_buffer,
-                vk::PipelineBindPoint::RAY_TRACING_KHR,
-                self.pipeline,
-            );
-            self.device.cmd_bind_descriptor_sets(
-                command_buffer,
-                vk::PipelineBindPoint::RAY_TRACING_KHR,
-                self.pipeline_layout,
-                0,
-                &[self.descriptor_set],
-                &[],
-            );
+            if let Some(query) = first_query {
+                self.device
+                    .cmd_reset_query_pool(command_buffer, self.timestamp_pool, query, 2);
+                self.device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    self.timestamp_pool,
+                    query,
+                );
+            }
 
-            self.device.cmd_push_constants(
-                command_buffer,
-                self.pipeline_layout,
-                vk::ShaderStageFlags::RAYGEN_KHR,
-                0,
-                &self.push_data,
-            );
+            if let Some(frame_slot) = frame_slot {
+                self.record_trace(command_buffer, frame_slot, 0);
+            }
 
-            self.rt_pipeline_device.cmd_trace_rays(
-                command_buffer,
-                &self.raygen_region,
-                &self.miss_region,
-                &self.hit_region,
-                &self.callable_region,
-                self.storage_image.as_ref().unwrap().width,
-                self.storage_image.as_ref().unwrap().height,
-                1,
-            );
+            // only the first pass is timed, extra idle passes shouldn't lower the render scale
+            if let Some(query) = first_query {
+                self.device.cmd_write_timestamp(
+                    command_buffer,
+                    legacy_stages(self.trace_stage()),
+                    self.timestamp_pool,
+                    query + 1,
+                );
+            }
 
-            self.device.cmd_pipeline_barrier(
+            if let Some(frame_slot) = frame_slot {
+                for pass in 1..passes {
+                    // each pass accumulates onto what the one before it wrote
+                    cmd_pipeline_barrier(
+                        &self.device,
+                        self.synchronization2.as_ref(),
+                        command_buffer,
+                        &[vk::MemoryBarrier2 {
+                            src_stage_mask: self.trace_stage(),
+                            src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                            dst_stage_mask: self.trace_stage(),
+                            dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ
+                                | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                            ..Default::default()
+                        }],
+                        &[],
+                    );
+                    self.record_trace(command_buffer, frame_slot, pass);
+                }
+            }
+
+            // with taa the storage image is written by taa.comp rather than the trace
+            let taa_slot = frame_slot.filter(|_| self.taa);
+            if let Some(frame_slot) = taa_slot {
+                cmd_pipeline_barrier(
+                    &self.device,
+                    self.synchronization2.as_ref(),
+                    command_buffer,
+                    &[vk::MemoryBarrier2 {
+                        src_stage_mask: self.trace_stage(),
+                        src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                        dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                        dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ
+                            | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                        ..Default::default()
+                    }],
+                    &[],
+                );
+                self.record_taa(command_buffer, frame_slot);
+            }
+            let (output_stage, read_stages) = match taa_slot {
+                Some(_) => (
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::PipelineStageFlags2::BLIT | vk::PipelineStageFlags2::COPY,
+                ),
+                None => (self.trace_stage(), vk::PipelineStageFlags2::BLIT),
+            };
+
+            // no queue family ownership transfers are needed here, even with separate compute and present
+            // families: the storage image never leaves the compute queue (the blit runs on it too), and
+            // swapchain images are created with CONCURRENT sharing between the two families in that case
+            // concurrent images require QUEUE_FAMILY_IGNORED though, which is not what Default gives us (0)
+            // the swapchain image transition waits on the acquire semaphore, which is waited for at the blit
+            cmd_pipeline_barrier(
+                &self.device,
+                self.synchronization2.as_ref(),
                 command_buffer,
-                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR | vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::empty(),
-                &[vk::MemoryBarrier {
-                    src_access_mask: vk::AccessFlags::SHADER_WRITE,
-                    dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                &[vk::MemoryBarrier2 {
+                    src_stage_mask: output_stage,
+                    src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    dst_stage_mask: read_stages,
+                    dst_access_mask: vk::AccessFlags2::TRANSFER_READ,
                     ..Default::default()
                 }],
-                &[],
-                &[vk::ImageMemoryBarrier {
-                    src_access_mask: vk::AccessFlags::NONE,
-                    dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-                    old_layout: vk::ImageLayout::UNDEFINED,
+                &[vk::ImageMemoryBarrier2 {
+                    src_stage_mask: vk::PipelineStageFlags2::BLIT,
+                    src_access_mask: vk::AccessFlags2::NONE,
+                    dst_stage_mask: vk::PipelineStageFlags2::BLIT,
+                    dst_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+                    old_layout: target_layout,
                     new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
                     image: target_image,
                     subresource_range: vk::ImageSubresourceRange {
                         aspect_mask: vk::ImageAspectFlags::COLOR,
@@ -969,9 +1699,36 @@ impl RaytraceRenderer {
                 }],
             );
 
+            // with a region only that part of the target is updated, the rest keeps what was presented there
+            // before. images that haven't been presented yet have nothing there, so they get the whole image
+            let storage_image = self.storage_image.as_ref().unwrap();
+            let (src, dst) = match self.region {
+                Some(region) if target_layout != vk::ImageLayout::UNDEFINED => (
+                    self.trace_region(),
+                    region.scaled(self.target_size, (target_width, target_height)),
+                ),
+                _ => (
+                    ((0, 0), (storage_image.width, storage_image.height)),
+                    ((0, 0), (target_width, target_height)),
+                ),
+            };
+            let offsets = |((x, y), (width, height)): ((u32, u32), (u32, u32))| {
+                [
+                    vk::Offset3D {
+                        x: x as i32,
+                        y: y as i32,
+                        z: 0,
+                    },
+                    vk::Offset3D {
+                        x: (x + width) as i32,
+                        y: (y + height) as i32,
+                        z: 1,
+                    },
+                ]
+            };
             self.device.cmd_blit_image(
                 command_buffer,
-                self.storage_image.as_ref().unwrap().image,
+                storage_image.image,
                 vk::ImageLayout::GENERAL,
                 target_image,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
@@ -982,44 +1739,38 @@ impl RaytraceRenderer {
                         base_array_layer: 0,
                         layer_count: 1,
                     },
-                    src_offsets: [
-                        vk::Offset3D { x: 0, y: 0, z: 0 },
-                        vk::Offset3D {
-                            x: self.storage_image.as_ref().unwrap().width as i32,
-                            y: self.storage_image.as_ref().unwrap().height as i32,
-                            z: 1,
-                        },
-                    ],
+                    src_offsets: offsets(src),
                     dst_subresource: vk::ImageSubresourceLayers {
                         aspect_mask: vk::ImageAspectFlags::COLOR,
                         mip_level: 0,
                         base_array_layer: 0,
                         layer_count: 1,
                     },
-                    dst_offsets: [
-                        vk::Offset3D { x: 0, y: 0, z: 0 },
-                        vk::Offset3D {
-                            x: target_width as i32,
-                            y: target_height as i32,
-                            z: 1,
-                        },
-                    ],
+                    dst_offsets: offsets(dst),
                 }],
+                // a blit only takes a filter, not a sampler. anisotropic filtering (render.anisotropy) is
+                // deferred until the shaders sample images, normal maps and the environment are storage buffers
                 vk::Filter::LINEAR,
             );
+            if taa_slot.is_some() {
+                self.record_history_copy(command_buffer);
+            }
 
-            self.device.cmd_pipeline_barrier(
+            // presentation doesn't need to be waited for by any stage, the render semaphore covers it
+            cmd_pipeline_barrier(
+                &self.device,
+                self.synchronization2.as_ref(),
                 command_buffer,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                vk::DependencyFlags::empty(),
                 &[],
-                &[],
-                &[vk::ImageMemoryBarrier {
-                    src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-                    dst_access_mask: vk::AccessFlags::NONE,
+                &[vk::ImageMemoryBarrier2 {
+                    src_stage_mask: vk::PipelineStageFlags2::BLIT,
+                    src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+                    dst_stage_mask: vk::PipelineStageFlags2::NONE,
+                    dst_access_mask: vk::AccessFlags2::NONE,
                     old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                     new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
                     image: target_image,
                     subresource_range: vk::ImageSubresourceRange {
                         aspect_mask: vk::ImageAspectFlags::COLOR,
@@ -1037,114 +1788,840 @@ impl RaytraceRenderer {
 
         Ok(())
     }
-}
 
-impl Renderer<MeshScene, WindowData> for RaytraceRenderer {
-    fn new(
-        _vk_lib: &Entry,
-        instance: &Instance,
-        device: &Device,
-        physical_device: vk::PhysicalDevice,
-        queue_family_info: &QueueFamilyInfo,
-        allocator: Rc<RefCell<Allocator>>,
-    ) -> anyhow::Result<Self> {
-        let accel_struct_device = khr::acceleration_structure::Device::new(instance, device);
-        let rt_pipeline_device = khr::ray_tracing_pipeline::Device::new(instance, device);
+    // recreates the storage and accumulation images, which restarts accumulation
+    fn resize_storage_images(&mut self, size: (u32, u32)) -> anyhow::Result<()> {
+        unsafe {
+            self.device.device_wait_idle()?;
+
+            let old_images = [
+                self.storage_image.take().unwrap(),
+                self.accumulation_image.take().unwrap(),
+            ];
+            let mut new_images = Vec::new();
+            for (old_image, clear) in old_images.into_iter().zip([self.initial_color, Vec3::ZERO]) {
+                new_images.push(self.create_storage_image(
+                    size,
+                    old_image.format,
+                    old_image.usage,
+                    clear,
+                )?);
+                old_image.destroy(&self.device, &mut self.allocator.borrow_mut());
+            }
 
-        let mut rt_pipeline_properties =
-            vk::PhysicalDeviceRayTracingPipelinePropertiesKHR::default();
-        let mut accel_properties = vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
-        let mut physical_device_properties2 = vk::PhysicalDeviceProperties2::default()
-            .push_next(&mut rt_pipeline_properties)
-            .push_next(&mut accel_properties);
+            // storage image is binding 0, accumulation image is binding 1
+            let infos: Vec<_> = new_images
+                .iter()
+                .map(|image| vk::DescriptorImageInfo {
+                    image_layout: image.layout(),
+                    image_view: image.image_view,
+                    sampler: vk::Sampler::null(),
+                })
+                .collect();
+            let writes: Vec<_> = infos
+                .iter()
+                .enumerate()
+                .map(|(binding, info)| vk::WriteDescriptorSet {
+                    dst_set: self.descriptor_set,
+                    dst_binding: binding as u32,
+                    dst_array_element: 0,
+                    descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: 1,
+                    p_image_info: info,
+                    ..Default::default()
+                })
+                .collect();
+
+            let mut new_images = new_images.into_iter();
+            self.storage_image = new_images.next();
+            self.accumulation_image = new_images.next();
+
+            self.device.update_descriptor_sets(&writes, &[]);
+
+            self.create_aov_images(size)?;
+            self.create_history_image(size)?;
+        }
+
+        self.current_frame = 0;
+        self.taa_view_projection = None;
+
+        Ok(())
+    }
+
+    // (re)creates the taa history at `size`, or at 1x1 without render.taa. nothing in flight can be using the
+    // old one
+    fn create_history_image(&mut self, size: (u32, u32)) -> anyhow::Result<()> {
+        if let Some(image) = self.history_image.take() {
+            unsafe { image.destroy(&self.device, &mut self.allocator.borrow_mut()) };
+        }
+
+        let size = if self.taa { size } else { (1, 1) };
+        let image = self.create_storage_image(
+            size,
+            vk::Format::R32G32B32A32_SFLOAT,
+            vk::ImageUsageFlags::STORAGE,
+            Vec3::ZERO,
+        )?;
+
+        let info = vk::DescriptorImageInfo {
+            image_layout: image.layout(),
+            image_view: image.image_view,
+            sampler: vk::Sampler::null(),
+        };
+        let write = vk::WriteDescriptorSet {
+            dst_set: self.descriptor_set,
+            dst_binding: HISTORY_IMAGE_BINDING,
+            dst_array_element: 0,
+            descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: 1,
+            p_image_info: &raw const info,
+            ..Default::default()
+        };
         unsafe {
-            instance
-                .get_physical_device_properties2(physical_device, &mut physical_device_properties2)
+            self.device.update_descriptor_sets(&[write], &[]);
+        }
+        self.history_image = Some(image);
+        self.taa_view_projection = None;
+
+        Ok(())
+    }
+
+    // (re)creates the aov images at `size`, or at 1x1 when the scene doesn't want aovs since the binding
+    // needs images either way. nothing in flight can be using the old ones
+    fn create_aov_images(&mut self, size: (u32, u32)) -> anyhow::Result<()> {
+        for image in self.aov_images.drain(..) {
+            unsafe { image.destroy(&self.device, &mut self.allocator.borrow_mut()) };
+        }
+
+        let size = if self.aovs { size } else { (1, 1) };
+        for _ in 0..2 {
+            let image = self.create_storage_image(
+                size,
+                vk::Format::R32G32B32A32_SFLOAT,
+                vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
+                Vec3::ZERO,
+            )?;
+            self.aov_images.push(image);
+        }
+
+        let infos: Vec<_> = self
+            .aov_images
+            .iter()
+            .map(|image| vk::DescriptorImageInfo {
+                image_layout: image.layout(),
+                image_view: image.image_view,
+                sampler: vk::Sampler::null(),
+            })
+            .collect();
+        let write = vk::WriteDescriptorSet {
+            dst_set: self.descriptor_set,
+            dst_binding: AOV_IMAGES_BINDING,
+            dst_array_element: 0,
+            descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: infos.len() as u32,
+            p_image_info: infos.as_ptr(),
+            ..Default::default()
         };
+        unsafe {
+            self.device.update_descriptor_sets(&[write], &[]);
+        }
 
-        let compute_queue_index = queue_family_info
-            .compute_index
-            .ok_or(anyhow!("no compute"))?;
+        Ok(())
+    }
 
-        let command_pool = {
-            let create_info = vk::CommandPoolCreateInfo {
-                queue_family_index: compute_queue_index,
-                flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+    // storage image size for the current target size and render scale
+    fn scaled_size(&self) -> (u32, u32) {
+        let scale = self.render_scale.as_ref().map_or(1.0, RenderScale::scale);
+        let (width, height) = self.target_size;
+        (
+            ((width as f32 * scale).round() as u32).max(1),
+            ((height as f32 * scale).round() as u32).max(1),
+        )
+    }
+
+    // feeds the frame times of every frame that has finished since the last call to the render scale,
+    // and resizes the storage images when it changes
+    fn update_render_scale(&mut self) -> anyhow::Result<()> {
+        let Some(render_scale) = self.render_scale.as_mut() else {
+            return Ok(());
+        };
+
+        let mut new_scale = None;
+        for (i, written) in self.timestamps_written.iter_mut().enumerate() {
+            if !*written {
+                continue;
+            }
+
+            let mut timestamps = [0u64; 2];
+            let result = unsafe {
+                self.device.get_query_pool_results(
+                    self.timestamp_pool,
+                    2 * i as u32,
+                    &mut timestamps,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+            };
+            match result {
+                Ok(()) => {}
+                // still in flight, check again next frame
+                Err(vk::Result::NOT_READY) => continue,
+                Err(e) => return Err(e.into()),
+            }
+            *written = false;
+
+            let ticks = timestamps[1].wrapping_sub(timestamps[0]);
+            let frame_time =
+                ticks as f64 * self.device_properties.limits.timestamp_period as f64 / 1e6;
+            new_scale = render_scale
+                .record_frame_time(frame_time as f32)
+                .or(new_scale);
+        }
+
+        if let Some(scale) = new_scale {
+            let size = self.scaled_size();
+            info!(
+                "Render scale is now {:.0}% ({}x{})",
+                scale * 100.0,
+                size.0,
+                size.1
+            );
+            self.resize_storage_images(size)?;
+            // anything still in flight was rendered at the old size
+            self.timestamps_written.fill(false);
+        }
+
+        Ok(())
+    }
+
+    // makes room for two timestamps per command buffer
+    fn ensure_timestamp_pool(&mut self) -> anyhow::Result<()> {
+        if self.timestamps_written.len() == self.command_buffers.len() {
+            return Ok(());
+        }
+
+        unsafe {
+            // command buffers in flight could still write to the old pool
+            self.device.device_wait_idle()?;
+            self.device.destroy_query_pool(self.timestamp_pool, None);
+
+            let create_info = vk::QueryPoolCreateInfo {
+                query_type: vk::QueryType::TIMESTAMP,
+                query_count: 2 * self.command_buffers.len() as u32,
                 ..Default::default()
             };
-            unsafe { device.create_command_pool(&create_info, None) }?
+            self.timestamp_pool = self.device.create_query_pool(&create_info, None)?;
+        }
+        self.timestamps_written = vec![false; self.command_buffers.len()];
+
+        Ok(())
+    }
+
+    fn apply_updates(&mut self, updates: &[MeshSceneUpdate]) -> anyhow::Result<()> {
+        for update in updates {
+            match update {
+                MeshSceneUpdate::NewView(view) => {
+                    let view_inverse_cols = view.inverse().to_cols_array();
+                    let view_bytes: &[u8] = bytemuck::cast_slice(&view_inverse_cols);
+                    self.frame_data[0..64].copy_from_slice(view_bytes);
+
+                    self.current_frame = 0;
+                    self.idle_frames = 0;
+                }
+                MeshSceneUpdate::NewSize((width, height, projection)) => {
+                    // dragging the window edge is interactive too
+                    self.idle_frames = 0;
+                    self.target_size = (*width, *height);
+                    self.resize_storage_images(self.scaled_size())?;
+
+                    let projection_inverse_cols = projection.inverse().to_cols_array();
+                    let projection_bytes: &[u8] = bytemuck::cast_slice(&projection_inverse_cols);
+                    self.frame_data[64..128].copy_from_slice(projection_bytes);
+                }
+                MeshSceneUpdate::DebugMode(mode) => {
+                    self.frame_data[128 + 4 + 4 + 4..128 + 4 + 4 + 4 + 4]
+                        .copy_from_slice(bytemuck::cast_slice(&[*mode as u32]));
+
+                    // debug output isn't accumulated, so the shaded image has to start over
+                    self.current_frame = 0;
+                    self.taa_view_projection = None;
+                }
+                MeshSceneUpdate::ToggleLight(index) => {
+                    let Some(enabled) = self.lights_enabled.get_mut(*index) else {
+                        warn!("scene has no light {index} to toggle");
+                        continue;
+                    };
+                    *enabled = !*enabled;
+                    let enabled = *enabled;
+                    info!(
+                        "Light {index} is now {}",
+                        if enabled { "on" } else { "off" }
+                    );
+
+                    // frames in flight read the light buffer
+                    unsafe { self.device.device_wait_idle()? };
+                    // a light with no color contributes nothing, whether it's sampled or hit
+                    for light in self.light_entries[*index].clone() {
+                        let color = if enabled {
+                            self.light_colors[light]
+                        } else {
+                            Vec3::ZERO
+                        };
+                        unsafe {
+                            self.write_device_buffer(
+                                self.light_buffer.as_ref().unwrap(),
+                                LIGHTS_OFFSET + light as u64 * LIGHT_SIZE + LIGHT_COLOR_OFFSET,
+                                &color.to_array(),
+                            )?;
+                        }
+                    }
+
+                    self.current_frame = 0;
+                }
+                MeshSceneUpdate::Invalidate => {
+                    self.current_frame = 0;
+                    self.taa_view_projection = None;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn build_top_as(
+        &self,
+        objects: &[Object],
+        procedural_objects: &[ProceduralObject],
+    ) -> anyhow::Result<(vk::AccelerationStructureKHR, AllocatedBuffer)> {
+        let (instance_geometry, instance_buffer, instance_count) = self
+            .get_full_instance_geometry(
+                objects,
+                procedural_objects,
+                &self.triangle_blas,
+                &self.procedural_blas,
+                self.triangle_hit_group_count,
+            )?;
+
+        let built = self.build_accel_structs(
+            vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+            &[instance_geometry],
+            &[instance_count],
+        );
+        unsafe {
+            instance_buffer.destroy(&self.device, &mut self.allocator.borrow_mut());
+        }
+
+        let (top_as, mut top_as_buffer) = built?;
+        Ok((top_as[0], top_as_buffer.remove(0)))
+    }
+
+    // time in [0, 1] between previous_transform and transform that this frame is traced at
+    // frames step through the shutter interval with a van der corput sequence, so the accumulated
+    // image converges to an evenly blurred one
+    fn shutter_time(shutter: f32, frame: u32) -> f32 {
+        let u = frame.reverse_bits() as f64 / (1u64 << 32) as f64;
+        1f32 - shutter * u as f32
+    }
+
+    // rebuilds the tlas with every object moved to this frame's shutter time
+    fn update_motion_blur(&mut self) -> anyhow::Result<()> {
+        let Some(motion_blur) = &self.motion_blur else {
+            return Ok(());
         };
-        let compute_queue = unsafe { device.get_device_queue(compute_queue_index, 0) };
 
-        Ok(RaytraceRenderer {
-            allocator,
-            device: device.clone(),
-            accel_struct_device,
-            rt_pipeline_device,
-            device_properties: physical_device_properties2.properties,
-            rt_pipeline_properties,
-            accel_properties,
-            command_pool,
-            compute_queue,
-            top_as: Default::default(),
-            top_as_buffer: Default::default(),
-            triangle_blas: Default::default(),
-            triangle_blas_buffers: Default::default(),
-            procedural_blas: Default::default(),
-            procedural_blas_buffers: Default::default(),
-            triangle_hit_group_count: 0,
-            pipeline_layout: Default::default(),
-            pipeline: Default::default(),
-            sbt_buffer: Default::default(),
-            raygen_region: Default::default(),
-            miss_region: Default::default(),
-            hit_region: Default::default(),
-            callable_region: Default::default(),
-            descriptor_pool: Default::default(),
-            descriptor_set: Default::default(),
-            descriptor_set_layout: Default::default(),
-            storage_image: Default::default(),
-            accumulation_image: Default::default(),
-            vertex_normal_buffer: Default::default(),
-            light_buffer: Default::default(),
-            offset_buffer: Default::default(),
-            brdf_param_buffer: Default::default(),
-            command_buffers: Default::default(),
-            push_data: [0; 128 + 8 + 4],
-            current_frame: 0,
-        })
+        let time = Self::shutter_time(motion_blur.shutter, self.current_frame);
+        let objects: Vec<_> = motion_blur
+            .objects
+            .iter()
+            .map(|x| Object {
+                transform: x.transform_at(time),
+                ..x.clone()
+            })
+            .collect();
+
+        // building waits for the compute queue to go idle, so the old tlas is no longer in use after this
+        let (top_as, top_as_buffer) =
+            self.build_top_as(&objects, &motion_blur.procedural_objects)?;
+        unsafe {
+            self.accel_struct_device
+                .destroy_acceleration_structure(self.top_as, None);
+            if let Some(x) = self.top_as_buffer.take() {
+                x.destroy(&self.device, &mut self.allocator.borrow_mut());
+            }
+        }
+        (self.top_as, self.top_as_buffer) = (top_as, Some(top_as_buffer));
+
+        let accel_info = vk::WriteDescriptorSetAccelerationStructureKHR {
+            acceleration_structure_count: 1,
+            p_acceleration_structures: &raw const self.top_as,
+            ..Default::default()
+        };
+        let write = vk::WriteDescriptorSet {
+            dst_set: self.descriptor_set,
+            dst_binding: 2,
+            dst_array_element: 0,
+            descriptor_type: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            descriptor_count: 1,
+            p_next: &raw const accel_info as *const std::ffi::c_void,
+            ..Default::default()
+        };
+        unsafe {
+            self.device.update_descriptor_sets(&[write], &[]);
+        }
+
+        self.frame_data[128 + 4 + 4..128 + 4 + 4 + 4]
+            .copy_from_slice(bytemuck::cast_slice(&[time]));
+
+        Ok(())
+    }
+
+    fn update_frame_push_data(&mut self) {
+        self.push_data = self.frame_push_data(self.current_frame);
+    }
+
+    // push constants for tracing sample `frame` of the accumulation
+    fn frame_push_data(&self, frame: u32) -> [u8; 8 + 4 + 4 + 8 + 8] {
+        let mut push_data = self.push_data;
+        let r: (u32, u32) = match self.seed {
+            Some(seed) => Self::frame_seed(seed, frame),
+            None => rand::random(),
+        };
+        push_data[0..8].copy_from_slice(bytemuck::cast_slice(&[r.0, r.1]));
+
+        push_data[8..8 + 4].copy_from_slice(bytemuck::cast_slice(&[frame]));
+
+        let ((x, y), (width, height)) = self.trace_region();
+        push_data[16..32].copy_from_slice(bytemuck::cast_slice(&[x, y, width, height]));
+        push_data
+    }
+
+    // tells taa.comp which view projection the history was rendered with (if there is a history), and
+    // remembers this frame's for the next one
+    fn update_taa_params(&mut self) {
+        if !self.taa {
+            return;
+        }
+
+        let matrix =
+            |bytes: &[u8]| Mat4::from_cols_array(&bytemuck::pod_read_unaligned::<[f32; 16]>(bytes));
+        let view_projection =
+            (matrix(&self.frame_data[0..64]) * matrix(&self.frame_data[64..128])).inverse();
+        let previous = self.taa_view_projection.replace(view_projection);
+
+        self.frame_data
+            [128 + 4 + 4 + 4 + 4 + 12 + 4 + 4 + 4..128 + 4 + 4 + 4 + 4 + 12 + 4 + 4 + 4 + 4]
+            .copy_from_slice(bytemuck::cast_slice(&[previous.is_some() as u32]));
+        self.frame_data[128 + 4 + 4 + 4 + 4 + 12 + 4 + 4 + 4 + 4..].copy_from_slice(
+            bytemuck::cast_slice(&previous.unwrap_or(view_projection).to_cols_array()),
+        );
+    }
+
+    // how many samples render_to traces this frame, more than one once the view has been still for IDLE_FRAMES
+    // frames. motion blur rebuilds the tlas for every sample, so it always gets one
+    fn frame_passes(&self) -> u32 {
+        if self.idle_frames < IDLE_FRAMES || self.motion_blur.is_some() {
+            return 1;
+        }
+        // stop right at max_samples
+        let remaining = self
+            .max_samples
+            .map_or(u32::MAX, |max_samples| max_samples - self.current_frame);
+        self.idle_samples_per_frame.min(remaining)
+    }
+
+    /// The object under `(ndc_x, ndc_y)` from the current view, both in [-1, 1] with (-1, -1) the top left
+    /// (unless the camera turned `flip_y` off)
+    ///
+    /// Traces a single ray against the tlas and waits for it. Procedural objects can't be picked, and
+    /// neither can anything when the device has no ray queries.
+    // nothing selects objects yet, this is for click-to-select and focusing the camera
+    #[allow(dead_code)]
+    pub fn pick(&self, ndc_x: f32, ndc_y: f32) -> Option<Hit> {
+        if !self.ray_query {
+            warn!("picking needs ray queries, which the device doesn't have");
+            return None;
+        }
+        if self.pick_pipeline == vk::Pipeline::null() {
+            return None;
+        }
+
+        let matrix =
+            |bytes: &[u8]| Mat4::from_cols_array(&bytemuck::pod_read_unaligned::<[f32; 16]>(bytes));
+        let ray = camera_ray(
+            matrix(&self.frame_data[0..64]),
+            matrix(&self.frame_data[64..128]),
+            (ndc_x, ndc_y),
+        );
+        match self.trace_pick(ray) {
+            Ok(hit) => hit,
+            Err(e) => {
+                warn!("failed to pick: {e}");
+                None
+            }
+        }
+    }
+
+    fn trace_pick(&self, ray: (Vec3, Vec3)) -> anyhow::Result<Option<Hit>> {
+        let result_buffer = AllocatedBuffer::new(
+            &self.device,
+            &mut self.allocator.borrow_mut(),
+            pick::RESULT_SIZE,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            MemoryLocation::GpuToCpu,
+            self.device_properties.limits,
+        )?;
+        let push_data = pick::push_constants(
+            unsafe { result_buffer.get_device_address(&self.device) },
+            ray,
+        );
+
+        let result = submit_immediate(
+            &self.device,
+            self.transient_command_pool,
+            self.compute_queue,
+            |command_buffer| unsafe {
+                // frames before this one can still be rebuilding the tlas
+                cmd_pipeline_barrier(
+                    &self.device,
+                    self.synchronization2.as_ref(),
+                    command_buffer,
+                    &[vk::MemoryBarrier2 {
+                        src_stage_mask: vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR,
+                        src_access_mask: vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR,
+                        dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                        dst_access_mask: vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_KHR,
+                        ..Default::default()
+                    }],
+                    &[],
+                );
+
+                self.device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pick_pipeline,
+                );
+                self.device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pick_pipeline_layout,
+                    0,
+                    &[self.descriptor_set],
+                    &[0],
+                );
+                self.device.cmd_push_constants(
+                    command_buffer,
+                    self.pick_pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    &push_data,
+                );
+                self.device.cmd_dispatch(command_buffer, 1, 1, 1);
+
+                cmd_pipeline_barrier(
+                    &self.device,
+                    self.synchronization2.as_ref(),
+                    command_buffer,
+                    &[vk::MemoryBarrier2 {
+                        src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                        src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                        dst_stage_mask: vk::PipelineStageFlags2::HOST,
+                        dst_access_mask: vk::AccessFlags2::HOST_READ,
+                        ..Default::default()
+                    }],
+                    &[],
+                );
+
+                Ok(())
+            },
+        )
+        .and_then(|()| {
+            result_buffer
+                .mapped_slice()
+                .map(|result| pick::read_hit(result, ray))
+                .ok_or(anyhow!("pick result buffer is not host visible"))
+        });
+
+        unsafe { result_buffer.destroy(&self.device, &mut self.allocator.borrow_mut()) };
+
+        result
+    }
+
+    // offset and size of the part of the storage image that gets traced
+    fn trace_region(&self) -> ((u32, u32), (u32, u32)) {
+        let storage_image = self.storage_image.as_ref().unwrap();
+        let storage_size = (storage_image.width, storage_image.height);
+        match self.region {
+            Some(region) => region.scaled(self.target_size, storage_size),
+            None => ((0, 0), storage_size),
+        }
+    }
+
+    // dynamic uniform buffer offsets have to be aligned
+    fn frame_params_slot_size(&self) -> u64 {
+        let alignment = self
+            .device_properties
+            .limits
+            .min_uniform_buffer_offset_alignment as u32;
+        align_up(self.frame_data.len() as u32, alignment) as u64
+    }
+
+    // makes sure there's a slot of frame parameters for each of `slots` frames in flight
+    fn ensure_frame_params_slots(&mut self, slots: u32) -> anyhow::Result<()> {
+        if slots <= self.frame_params_slots {
+            return Ok(());
+        }
+
+        unsafe {
+            // frames in flight still read the old buffer
+            self.device.device_wait_idle()?;
+            if let Some(x) = self.frame_params_buffer.take() {
+                x.destroy(&self.device, &mut self.allocator.borrow_mut());
+            }
+        }
+
+        let buffer = AllocatedBuffer::new(
+            &self.device,
+            &mut self.allocator.borrow_mut(),
+            self.frame_params_slot_size() * slots as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            MemoryLocation::CpuToGpu,
+            self.device_properties.limits,
+        )?;
+
+        // the range is one slot, record_trace picks which with the dynamic offset
+        let buffer_info = vk::DescriptorBufferInfo {
+            buffer: buffer.buffer,
+            offset: 0,
+            range: self.frame_data.len() as u64,
+        };
+        let write = vk::WriteDescriptorSet {
+            dst_set: self.descriptor_set,
+            dst_binding: FRAME_PARAMS_BINDING,
+            dst_array_element: 0,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: 1,
+            p_buffer_info: &raw const buffer_info,
+            ..Default::default()
+        };
+        unsafe {
+            self.device.update_descriptor_sets(&[write], &[]);
+        }
+
+        self.frame_params_buffer = Some(buffer);
+        self.frame_params_slots = slots;
+
+        Ok(())
+    }
+
+    // copies frame_data to the slot the next frame recorded with `frame_slot` reads
+    fn upload_frame_params(&mut self, frame_slot: u32) -> anyhow::Result<()> {
+        let offset = frame_slot as u64 * self.frame_params_slot_size();
+        self.frame_params_buffer
+            .as_mut()
+            .unwrap()
+            .store_at(offset as usize, &self.frame_data)
+    }
+
+    /// Size of the image being rendered, which is what gets scaled to the swapchain extent when presenting
+    ///
+    /// Returns (0, 0) until a scene is ingested.
+    pub fn render_resolution(&self) -> (u32, u32) {
+        self.storage_image
+            .as_ref()
+            .map_or((0, 0), |x| (x.width, x.height))
+    }
+
+    /// Copies the storage image back to the host
+    ///
+    /// Returns the image size and its RGBA pixels in linear color, row by row.
+    pub fn read_storage_image(&self) -> anyhow::Result<((u32, u32), Vec<f32>)> {
+        let storage_image = self
+            .storage_image
+            .as_ref()
+            .ok_or(anyhow!("no scene has been ingested"))?;
+        self.read_image_pixels(storage_image)
+    }
+
+    /// Copies the aov images back to the host, if the scene asked for them with render.aovs
+    pub fn read_aov_images(&self) -> anyhow::Result<Option<Aovs>> {
+        if !self.aovs {
+            return Ok(None);
+        }
+
+        let (size, albedo_depth) = self.read_image_pixels(&self.aov_images[0])?;
+        let (_, normal_instance) = self.read_image_pixels(&self.aov_images[1])?;
+        Ok(Some(Aovs::from_images(
+            size,
+            &albedo_depth,
+            &normal_instance,
+        )))
+    }
+
+    // one of the R32G32B32A32_SFLOAT storage images, once the trace writing it is done
+    fn read_image_pixels(
+        &self,
+        storage_image: &AllocatedImage,
+    ) -> anyhow::Result<((u32, u32), Vec<f32>)> {
+        let (width, height) = (storage_image.width, storage_image.height);
+        let size = (width * height * 4) as u64 * std::mem::size_of::<f32>() as u64;
+
+        let readback_buffer = AllocatedBuffer::new(
+            &self.device,
+            &mut self.allocator.borrow_mut(),
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+            self.device_properties.limits,
+        )?;
+
+        submit_immediate(
+            &self.device,
+            self.transient_command_pool,
+            self.compute_queue,
+            |command_buffer| unsafe {
+                cmd_pipeline_barrier(
+                    &self.device,
+                    self.synchronization2.as_ref(),
+                    command_buffer,
+                    &[vk::MemoryBarrier2 {
+                        src_stage_mask: self.trace_stage(),
+                        src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                        dst_stage_mask: vk::PipelineStageFlags2::COPY,
+                        dst_access_mask: vk::AccessFlags2::TRANSFER_READ,
+                        ..Default::default()
+                    }],
+                    &[],
+                );
+
+                self.device.cmd_copy_image_to_buffer(
+                    command_buffer,
+                    storage_image.image,
+                    vk::ImageLayout::GENERAL,
+                    readback_buffer.buffer,
+                    &[vk::BufferImageCopy {
+                        buffer_offset: 0,
+                        buffer_row_length: 0,
+                        buffer_image_height: 0,
+                        image_subresource: vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: 0,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        },
+                        image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                        image_extent: vk::Extent3D {
+                            width,
+                            height,
+                            depth: 1,
+                        },
+                    }],
+                );
+
+                cmd_pipeline_barrier(
+                    &self.device,
+                    self.synchronization2.as_ref(),
+                    command_buffer,
+                    &[vk::MemoryBarrier2 {
+                        src_stage_mask: vk::PipelineStageFlags2::COPY,
+                        src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+                        dst_stage_mask: vk::PipelineStageFlags2::HOST,
+                        dst_access_mask: vk::AccessFlags2::HOST_READ,
+                        ..Default::default()
+                    }],
+                    &[],
+                );
+
+                Ok(())
+            },
+        )?;
+
+        let pixels = readback_buffer
+            .mapped_slice()
+            .map(bytemuck::pod_collect_to_vec)
+            .ok_or(anyhow!("readback buffer is not host visible"));
+
+        unsafe { readback_buffer.destroy(&self.device, &mut self.allocator.borrow_mut()) };
+
+        Ok(((width, height), pixels?))
+    }
+}
+
+impl Renderer<MeshScene, WindowData> for RaytraceRenderer {
+    fn new(
+        _vk_lib: &Entry,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        queue_family_info: &QueueFamilyInfo,
+        allocator: Rc<RefCell<Allocator>>,
+        debug: bool,
+    ) -> anyhow::Result<Self> {
+        Self::with_backend(
+            instance,
+            device,
+            physical_device,
+            queue_family_info,
+            allocator,
+            debug,
+            TraceBackend::Pipeline,
+        )
     }
 
     fn ingest_scene(&mut self, scene: &MeshScene) -> anyhow::Result<()> {
-        self.storage_image = Some(AllocatedImage::new(
-            &self.device,
-            &mut self.allocator.borrow_mut(),
+        scene.validate()?;
+
+        // check this before creating anything, there are no any-hit or callable groups in the pipeline
+        let requirements = scene.shader_requirements();
+        if self.backend == TraceBackend::Pipeline && (requirements.any_hit || requirements.callable)
+        {
+            bail!("raytrace renderer doesn't support any-hit or callable shaders");
+        }
+        if self.backend == TraceBackend::RayQuery && !scene.procedural_geometries.is_empty() {
+            warn!("ray query renderer can't intersect procedural geometry, it won't show up");
+        }
+
+        let mut phases = PhaseTimes::start();
+
+        self.seed = scene.render_options.seed;
+        self.max_samples = scene.render_options.max_samples;
+        self.aovs = scene.render_options.aovs;
+        self.taa = scene.render_options.taa;
+        self.taa_view_projection = None;
+        self.idle_samples_per_frame = scene.render_options.idle_samples_per_frame;
+        self.idle_frames = 0;
+        self.region = scene.render_options.region;
+        self.dispatch_rows = scene.render_options.dispatch_rows;
+        self.initial_color = scene
+            .render_options
+            .initial_color
+            .unwrap_or(scene.render_options.clear_color);
+
+        self.target_size = (WindowData::DEFAULT_WIDTH, WindowData::DEFAULT_HEIGHT);
+        self.render_scale = match scene.render_options.target_frame_time {
+            Some(target)
+                if self.device_properties.limits.timestamp_compute_and_graphics == vk::TRUE =>
+            {
+                Some(RenderScale::new(target))
+            }
+            Some(_) => {
+                warn!("device can't write timestamps on every queue, ignoring render.target_frame_time");
+                None
+            }
+            None => None,
+        };
+
+        self.storage_image = Some(self.create_storage_image(
             (WindowData::DEFAULT_WIDTH, WindowData::DEFAULT_HEIGHT),
             vk::Format::R32G32B32A32_SFLOAT,
             vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
-            MemoryLocation::GpuOnly,
+            self.initial_color,
         )?);
-        self.storage_image.as_mut().unwrap().transition(
-            &self.device,
-            self.compute_queue,
-            self.command_pool,
-            vk::ImageLayout::GENERAL,
-        )?;
-
-        self.accumulation_image = Some(AllocatedImage::new(
-            &self.device,
-            &mut self.allocator.borrow_mut(),
+        self.accumulation_image = Some(self.create_storage_image(
             (WindowData::DEFAULT_WIDTH, WindowData::DEFAULT_HEIGHT),
             vk::Format::R32G32B32A32_SFLOAT,
             vk::ImageUsageFlags::STORAGE,
-            MemoryLocation::GpuOnly,
+            Vec3::ZERO,
         )?);
-        self.accumulation_image.as_mut().unwrap().transition(
-            &self.device,
-            self.compute_queue,
-            self.command_pool,
-            vk::ImageLayout::GENERAL,
-        )?;
+        phases.end("storage images");
 
         let (mesh_geometries, mesh_buffers, mesh_primitive_counts) =
             self.get_mesh_geometries(&scene.meshes)?;
@@ -1160,6 +2637,7 @@ impl Renderer<MeshScene, WindowData> for RaytraceRenderer {
                 ibuf.destroy(&self.device, &mut self.allocator.borrow_mut());
             }
         }
+        phases.end("triangle blas");
 
         if !scene.procedural_geometries.is_empty() {
             let (proc_geometries, proc_buffers, proc_primitive_counts) =
@@ -1175,64 +2653,77 @@ impl Renderer<MeshScene, WindowData> for RaytraceRenderer {
                     buf.destroy(&self.device, &mut self.allocator.borrow_mut());
                 }
             }
+            phases.end("procedural blas");
         }
 
         let descriptor_sizes: Vec<vk::DescriptorPoolSize>;
         (self.descriptor_set_layout, descriptor_sizes) = self.get_descriptor_set_layout()?;
 
-        let shader_group_count: usize;
-        (
-            self.pipeline_layout,
-            self.pipeline,
-            shader_group_count,
-            self.triangle_hit_group_count,
-        ) = self.create_pipeline(scene, &[self.descriptor_set_layout])?;
-
-        let (instance_geometry, instance_buffer, instance_count) = self
-            .get_full_instance_geometry(
-                &scene.objects,
-                &scene.procedural_objects,
-                &self.triangle_blas,
-                &self.procedural_blas,
-                self.triangle_hit_group_count,
-            )?;
-
-        (self.top_as, self.top_as_buffer) = {
-            let (top_as, mut top_as_buffer) = self.build_accel_structs(
-                vk::AccelerationStructureTypeKHR::TOP_LEVEL,
-                &[instance_geometry],
-                &[instance_count],
-            )?;
-            (top_as[0], Some(top_as_buffer.remove(0)))
+        (self.pipeline_layout, self.pipeline) = match self.backend {
+            TraceBackend::Pipeline => self.create_pipeline(scene, &[self.descriptor_set_layout])?,
+            TraceBackend::RayQuery => {
+                self.create_compute_pipeline(RAY_QUERY_SHADER, &[self.descriptor_set_layout])?
+            }
         };
-        unsafe {
-            instance_buffer.destroy(&self.device, &mut self.allocator.borrow_mut());
+        if self.taa {
+            (self.taa_pipeline_layout, self.taa_pipeline) =
+                self.create_compute_pipeline(TAA_SHADER, &[self.descriptor_set_layout])?;
         }
+        if self.ray_query {
+            (self.pick_pipeline_layout, self.pick_pipeline) =
+                self.create_compute_pipeline(PICK_SHADER, &[self.descriptor_set_layout])?;
+        }
+        self.triangle_hit_group_count = requirements.triangle_hit_groups;
+        phases.end("pipeline");
+
+        let (top_as, top_as_buffer) =
+            self.build_top_as(&scene.objects, &scene.procedural_objects)?;
+        (self.top_as, self.top_as_buffer) = (top_as, Some(top_as_buffer));
+        phases.end("tlas");
+
+        let shutter = scene.render_options.shutter;
+        self.motion_blur =
+            (shutter > 0f32 && scene.objects.iter().any(Object::is_moving)).then(|| MotionBlur {
+                shutter,
+                objects: scene.objects.clone(),
+                procedural_objects: scene.procedural_objects.clone(),
+            });
 
-        let sbt_buffer: AllocatedBuffer;
-        (
-            sbt_buffer,
-            self.raygen_region,
-            self.miss_region,
-            self.hit_region,
-            self.callable_region,
-        ) = self.create_sbt(shader_group_count)?;
-        self.sbt_buffer = Some(sbt_buffer);
+        if self.backend == TraceBackend::Pipeline {
+            let sbt_buffer: AllocatedBuffer;
+            (
+                sbt_buffer,
+                self.raygen_region,
+                self.miss_region,
+                self.hit_region,
+                self.callable_region,
+            ) = self.create_sbt(requirements.shader_group_count())?;
+            self.sbt_buffer = Some(sbt_buffer);
+
+            if self.debug {
+                self.log_sbt_layout();
+            }
+            phases.end("sbt");
+        }
 
         (self.descriptor_pool, self.descriptor_set) =
             self.create_descriptor_pool_and_set(self.descriptor_set_layout, &descriptor_sizes)?;
 
+        // must match Vertex in hit_common.glsl
         let vertex_normal_data: Vec<f32> = scene
             .meshes
             .iter()
-            .flat_map(|x| {
+            .zip(&scene.mesh_tangents)
+            .flat_map(|(x, tangents)| {
                 let mesh = &x.mesh;
-                mesh.indices.iter().flat_map(|i| {
+                mesh.indices.iter().flat_map(move |i| {
                     let i = *i as usize;
                     let pos = &mesh.positions[3 * i..3 * i + 3];
                     let normal = &mesh.normals[3 * i..3 * i + 3];
+                    // meshes without texture coordinates have zero tangents, so these don't matter
+                    let texcoord = mesh.texcoords.get(2 * i..2 * i + 2).unwrap_or(&[0.0; 2]);
 
-                    pos.iter().chain(normal)
+                    pos.iter().chain(normal).chain(texcoord).chain(&tangents[i])
                 })
             })
             .copied()
@@ -1243,14 +2734,24 @@ impl Renderer<MeshScene, WindowData> for RaytraceRenderer {
         });
 
         let mut light_data = Vec::<u8>::new();
-        light_data.extend_from_slice(bytemuck::cast_slice(&[scene.lights.len() as u32]));
+        let total_samples: u32 = scene.lights.iter().map(Light::samples).sum();
+        light_data.extend_from_slice(bytemuck::cast_slice(&[
+            scene.lights.len() as u32,
+            total_samples,
+        ]));
         for light in scene.lights.iter() {
-            if let Light::Point { color, position } = light {
+            if let Light::Point {
+                color, position, ..
+            } = light
+            {
                 light_data.extend_from_slice(bytemuck::cast_slice(&[0u32]));
                 light_data.extend_from_slice(bytemuck::cast_slice(&color.to_array()));
                 light_data.extend_from_slice(bytemuck::cast_slice(&position.to_array()));
                 light_data.extend_from_slice(bytemuck::cast_slice(&[0f32; 9]));
-            } else if let Light::Triangle { color, vertices } = light {
+            } else if let Light::Triangle {
+                color, vertices, ..
+            } = light
+            {
                 light_data.extend_from_slice(bytemuck::cast_slice(&[1u32]));
                 light_data.extend_from_slice(bytemuck::cast_slice(&color.to_array()));
                 light_data.extend_from_slice(bytemuck::cast_slice(&[0f32, 0f32, 0f32]));
@@ -1272,33 +2773,93 @@ impl Renderer<MeshScene, WindowData> for RaytraceRenderer {
                 light_data.extend_from_slice(&radius.to_ne_bytes());
                 light_data.extend_from_slice(bytemuck::cast_slice(&[0f32; 5]));
             }
+            light_data.extend_from_slice(bytemuck::cast_slice(&[light.samples()]));
         }
 
         self.light_buffer = Some(unsafe {
             self.create_device_buffer(&light_data, vk::BufferUsageFlags::STORAGE_BUFFER)?
         });
+        self.light_colors = scene
+            .lights
+            .iter()
+            .map(|light| match light {
+                Light::Point { color, .. }
+                | Light::Triangle { color, .. }
+                | Light::Directional { color, .. } => *color,
+            })
+            .collect();
+        self.light_entries = scene.light_entries.clone();
+        self.lights_enabled = vec![true; scene.light_entries.len()];
 
         self.offset_buffer = Some(unsafe {
             self.create_device_buffer(&scene.offset_buf, vk::BufferUsageFlags::STORAGE_BUFFER)?
         });
 
+        self.instance_id_buffer = Some(unsafe {
+            self.create_device_buffer(&scene.instance_id_buf, vk::BufferUsageFlags::STORAGE_BUFFER)?
+        });
+
+        self.normal_map_buffer = Some(unsafe {
+            self.create_device_buffer(
+                &NormalMap::buffer(&scene.normal_maps),
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+            )?
+        });
+
         if !scene.brdf_buf.is_empty() {
             self.brdf_param_buffer = Some(unsafe {
                 self.create_device_buffer(&scene.brdf_buf, vk::BufferUsageFlags::STORAGE_BUFFER)?
             });
         }
 
+        // matches the Environment block in hit_common.glsl: width and height, then the rgb pixels,
+        // the marginal cdf over rows and the cdf within each row (see EnvironmentMap::sampling_cdfs)
+        let mut environment_data = Vec::<u8>::new();
+        match &scene.environment {
+            Some(map) => {
+                let (marginal, conditional) = map.sampling_cdfs();
+                environment_data.extend_from_slice(bytemuck::cast_slice(&[map.width, map.height]));
+                for pixel in &map.pixels {
+                    environment_data.extend_from_slice(bytemuck::cast_slice(&pixel.to_array()));
+                }
+                environment_data.extend_from_slice(bytemuck::cast_slice(&marginal));
+                environment_data.extend_from_slice(bytemuck::cast_slice(&conditional));
+            }
+            None => environment_data.extend_from_slice(bytemuck::cast_slice(&[0u32, 0u32])),
+        }
+        self.environment_buffer = Some(unsafe {
+            self.create_device_buffer(&environment_data, vk::BufferUsageFlags::STORAGE_BUFFER)?
+        });
+        phases.end("buffers");
+
         let view_inverse_cols = scene.camera.view().inverse().to_cols_array();
-        let proj_inverse_cols = scene.camera.perspective().inverse().to_cols_array();
+        let proj_inverse_cols = scene.camera.projection().inverse().to_cols_array();
         let view_bytes: &[u8] = bytemuck::cast_slice(&view_inverse_cols);
         let proj_bytes: &[u8] = bytemuck::cast_slice(&proj_inverse_cols);
-        self.push_data[0..64].copy_from_slice(view_bytes);
-        self.push_data[64..128].copy_from_slice(proj_bytes);
+        self.frame_data[0..64].copy_from_slice(view_bytes);
+        self.frame_data[64..128].copy_from_slice(proj_bytes);
+
+        let lens = scene.camera.lens();
+        self.frame_data[128..128 + 4 + 4]
+            .copy_from_slice(bytemuck::cast_slice(&[lens.aperture, lens.focus_distance]));
+        self.frame_data[128 + 4 + 4..128 + 4 + 4 + 4]
+            .copy_from_slice(bytemuck::cast_slice(&[1f32]));
+
+        // scalar layout, so unlike in push constants the vec3 comes right after debug_mode
+        self.frame_data[128 + 4 + 4 + 4 + 4..128 + 4 + 4 + 4 + 4 + 12].copy_from_slice(
+            bytemuck::cast_slice(&scene.render_options.clear_color.to_array()),
+        );
+        self.frame_data[128 + 4 + 4 + 4 + 4 + 12..128 + 4 + 4 + 4 + 4 + 12 + 4]
+            .copy_from_slice(bytemuck::cast_slice(&[scene.render_options.sampler as u32]));
+        self.frame_data[128 + 4 + 4 + 4 + 4 + 12 + 4..128 + 4 + 4 + 4 + 4 + 12 + 4 + 4]
+            .copy_from_slice(bytemuck::cast_slice(&[self.aovs as u32]));
+        self.frame_data[128 + 4 + 4 + 4 + 4 + 12 + 4 + 4..128 + 4 + 4 + 4 + 4 + 12 + 4 + 4 + 4]
+            .copy_from_slice(bytemuck::cast_slice(&[self.taa as u32]));
 
         let mut writes = Vec::new();
 
         let image_info = vk::DescriptorImageInfo {
-            image_layout: vk::ImageLayout::GENERAL,
+            image_layout: self.storage_image.as_ref().unwrap().layout(),
             image_view: self.storage_image.as_ref().unwrap().image_view,
             sampler: vk::Sampler::null(),
         };
@@ -1313,7 +2874,7 @@ impl Renderer<MeshScene, WindowData> for RaytraceRenderer {
         });
 
         let accumulation_info = vk::DescriptorImageInfo {
-            image_layout: vk::ImageLayout::GENERAL,
+            image_layout: self.accumulation_image.as_ref().unwrap().layout(),
             image_view: self.accumulation_image.as_ref().unwrap().image_view,
             sampler: vk::Sampler::null(),
         };
@@ -1342,7 +2903,8 @@ impl Renderer<MeshScene, WindowData> for RaytraceRenderer {
             ..Default::default()
         });
 
-        let mut buffer_infos = Vec::new();
+        // the writes point into this, so it must not reallocate
+        let mut buffer_infos = Vec::with_capacity(4);
 
         for (i, buf) in [
             &self.vertex_normal_buffer,
@@ -1372,121 +2934,286 @@ impl Renderer<MeshScene, WindowData> for RaytraceRenderer {
             });
         }
 
+        let environment_info = vk::DescriptorBufferInfo {
+            buffer: self.environment_buffer.as_ref().unwrap().buffer,
+            range: vk::WHOLE_SIZE,
+            offset: 0,
+        };
+        writes.push(vk::WriteDescriptorSet {
+            dst_set: self.descriptor_set,
+            dst_binding: ENVIRONMENT_BINDING,
+            dst_array_element: 0,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            p_buffer_info: &raw const environment_info,
+            ..Default::default()
+        });
+
+        let instance_id_info = vk::DescriptorBufferInfo {
+            buffer: self.instance_id_buffer.as_ref().unwrap().buffer,
+            range: vk::WHOLE_SIZE,
+            offset: 0,
+        };
+        writes.push(vk::WriteDescriptorSet {
+            dst_set: self.descriptor_set,
+            dst_binding: INSTANCE_IDS_BINDING,
+            dst_array_element: 0,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            p_buffer_info: &raw const instance_id_info,
+            ..Default::default()
+        });
+
+        let normal_map_info = vk::DescriptorBufferInfo {
+            buffer: self.normal_map_buffer.as_ref().unwrap().buffer,
+            range: vk::WHOLE_SIZE,
+            offset: 0,
+        };
+        writes.push(vk::WriteDescriptorSet {
+            dst_set: self.descriptor_set,
+            dst_binding: NORMAL_MAPS_BINDING,
+            dst_array_element: 0,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            p_buffer_info: &raw const normal_map_info,
+            ..Default::default()
+        });
+
         unsafe {
             self.device.update_descriptor_sets(&writes, &[]);
         }
+        self.create_aov_images(self.render_resolution())?;
+        self.create_history_image(self.render_resolution())?;
+
+        self.ensure_frame_params_slots(1)?;
+        phases.end("descriptors");
+
+        phases.log("ingesting the scene");
 
         Ok(())
     }
 
-    fn render_to(
-        &mut self,
-        updates: &[<MeshScene as Scene>::Update],
-        target: &mut WindowData,
-    ) -> anyhow::Result<()> {
-        for update in updates {
-            match update {
-                MeshSceneUpdate::NewView(view) => {
-                    let view_inverse_cols = view.inverse().to_cols_array();
-                    let view_bytes: &[u8] = bytemuck::cast_slice(&view_inverse_cols);
-                    self.push_data[0..64].copy_from_slice(view_bytes);
+    fn reset(&mut self) -> anyhow::Result<()> {
+        unsafe {
+            wait_idle_before_destroy(&self.device)?;
 
-                    self.current_frame = 0;
-                }
-                MeshSceneUpdate::NewSize((width, height, projection)) => unsafe {
-                    self.device.device_wait_idle()?;
-
-                    let mut infos = Vec::new();
-                    let mut writes = Vec::new();
-                    for image in [&mut self.storage_image, &mut self.accumulation_image] {
-                        let old_image = image.take().unwrap();
-
-                        *image = Some(AllocatedImage::new(
-                            &self.device,
-                            &mut self.allocator.borrow_mut(),
-                            (*width, *height),
-                            old_image.format,
-                            old_image.usage,
-                            MemoryLocation::GpuOnly,
-                        )?);
-                        image.as_mut().unwrap().transition(
-                            &self.device,
-                            self.compute_queue,
-                            self.command_pool,
-                            vk::ImageLayout::GENERAL,
-                        )?;
-
-                        old_image.destroy(&self.device, &mut self.allocator.borrow_mut());
-
-                        infos.push(vk::DescriptorImageInfo {
-                            image_layout: vk::ImageLayout::GENERAL,
-                            image_view: image.as_ref().unwrap().image_view,
-                            sampler: vk::Sampler::null(),
-                        });
-                        writes.push(vk::WriteDescriptorSet {
-                            dst_set: self.descriptor_set,
-                            dst_binding: writes.len() as u32,
-                            dst_array_element: 0,
-                            descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
-                            descriptor_count: 1,
-                            p_image_info: infos.as_ptr().add(writes.len()),
-                            ..Default::default()
-                        });
-                    }
+            if !self.command_buffers.is_empty() {
+                self.device
+                    .free_command_buffers(self.command_pool, &self.command_buffers);
+                self.command_buffers.clear();
+            }
+            self.device.destroy_query_pool(self.timestamp_pool, None);
+            self.timestamp_pool = vk::QueryPool::null();
+            self.timestamps_written.clear();
 
-                    self.device.update_descriptor_sets(&writes, &[]);
+            // destroying null handles is fine, so this is safe to call before anything was ingested
+            self.device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.descriptor_pool = vk::DescriptorPool::null();
+            self.descriptor_set = vk::DescriptorSet::null();
+            if let Some(x) = self.sbt_buffer.take() {
+                x.destroy(&self.device, &mut self.allocator.borrow_mut());
+            }
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.pipeline = vk::Pipeline::null();
+            self.device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.descriptor_set_layout = vk::DescriptorSetLayout::null();
 
-                    let projection_inverse_cols = projection.inverse().to_cols_array();
-                    let projection_bytes: &[u8] = bytemuck::cast_slice(&projection_inverse_cols);
-                    self.push_data[64..128].copy_from_slice(projection_bytes);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.pipeline_layout = vk::PipelineLayout::null();
+            self.device.destroy_pipeline(self.taa_pipeline, None);
+            self.taa_pipeline = vk::Pipeline::null();
+            self.device
+                .destroy_pipeline_layout(self.taa_pipeline_layout, None);
+            self.taa_pipeline_layout = vk::PipelineLayout::null();
+            self.device.destroy_pipeline(self.pick_pipeline, None);
+            self.pick_pipeline = vk::Pipeline::null();
+            self.device
+                .destroy_pipeline_layout(self.pick_pipeline_layout, None);
+            self.pick_pipeline_layout = vk::PipelineLayout::null();
 
-                    self.current_frame = 0;
-                },
+            for blas in self.triangle_blas.drain(..) {
+                self.accel_struct_device
+                    .destroy_acceleration_structure(blas, None);
+            }
+            for x in self.triangle_blas_buffers.drain(..) {
+                x.destroy(&self.device, &mut self.allocator.borrow_mut());
+            }
+            for blas in self.procedural_blas.drain(..) {
+                self.accel_struct_device
+                    .destroy_acceleration_structure(blas, None);
+            }
+            for x in self.procedural_blas_buffers.drain(..) {
+                x.destroy(&self.device, &mut self.allocator.borrow_mut());
+            }
+            self.accel_struct_device
+                .destroy_acceleration_structure(self.top_as, None);
+            self.top_as = vk::AccelerationStructureKHR::null();
+            if let Some(x) = self.top_as_buffer.take() {
+                x.destroy(&self.device, &mut self.allocator.borrow_mut());
+            }
+
+            for image in [&mut self.storage_image, &mut self.accumulation_image] {
+                if let Some(x) = image.take() {
+                    x.destroy(&self.device, &mut self.allocator.borrow_mut());
+                }
+            }
+            for image in self.aov_images.drain(..) {
+                image.destroy(&self.device, &mut self.allocator.borrow_mut());
+            }
+            if let Some(x) = self.history_image.take() {
+                x.destroy(&self.device, &mut self.allocator.borrow_mut());
+            }
+
+            for buffer in [
+                &mut self.vertex_normal_buffer,
+                &mut self.light_buffer,
+                &mut self.offset_buffer,
+                &mut self.brdf_param_buffer,
+                &mut self.instance_id_buffer,
+                &mut self.normal_map_buffer,
+                &mut self.environment_buffer,
+                &mut self.frame_params_buffer,
+            ] {
+                if let Some(x) = buffer.take() {
+                    x.destroy(&self.device, &mut self.allocator.borrow_mut());
+                }
             }
         }
 
-        let r: (u32, u32) = rand::random();
-        self.push_data[128..128 + 8].copy_from_slice(bytemuck::cast_slice(&[r.0, r.1]));
+        self.triangle_hit_group_count = 0;
+        self.current_frame = 0;
+        self.max_samples = None;
+        self.aovs = false;
+        self.taa = false;
+        self.taa_view_projection = None;
+        self.idle_samples_per_frame = 1;
+        self.idle_frames = 0;
+        self.region = None;
+        self.dispatch_rows = None;
+        self.initial_color = Vec3::ZERO;
+        self.motion_blur = None;
+        self.render_scale = None;
+        self.light_colors.clear();
+        self.light_entries.clear();
+        self.lights_enabled.clear();
+        self.frame_params_slots = 0;
+
+        Ok(())
+    }
 
-        self.push_data[128 + 8..128 + 8 + 4]
-            .copy_from_slice(bytemuck::cast_slice(&[self.current_frame]));
+    fn render_to(
+        &mut self,
+        updates: &[<MeshScene as Scene>::Update],
+        target: &mut WindowData,
+    ) -> anyhow::Result<()> {
+        self.apply_updates(updates)?;
+        self.update_render_scale()?;
+        // anything that restarts accumulation resets current_frame, so this only holds for an unchanged view
+        let converged = self
+            .max_samples
+            .is_some_and(|max_samples| self.current_frame >= max_samples);
+        let passes = if converged { 0 } else { self.frame_passes() };
+        if !converged {
+            self.update_frame_push_data();
+            self.update_motion_blur()?;
+            self.update_taa_params();
+        }
 
         let (image, image_index) = target.acquire_next_image()?;
 
-        if image_index as usize >= self.command_buffers.len() {
+        // one command buffer per swapchain image, the count can change when the swapchain is recreated
+        while self.command_buffers.len() < target.image_count() {
             self.command_buffers.push(self.create_command_buffer()?);
         }
+        if self.render_scale.is_some() {
+            self.ensure_timestamp_pool()?;
+        }
+        self.ensure_frame_params_slots(self.command_buffers.len() as u32)?;
+        if !converged {
+            self.upload_frame_params(image_index)?;
+        }
 
+        // there's nothing to time when nothing is traced
+        let timed = self.render_scale.is_some() && !converged;
         self.record_command_buffer(
             self.command_buffers[image_index as usize],
-            image,
+            (image, target.previous_layout(image_index)),
             target.get_size(),
+            (!converged).then_some(image_index),
+            passes,
+            timed.then_some(2 * image_index),
         )?;
 
         let (image_semaphore, render_semaphore) = target.get_current_semaphores();
-        let wait_stage = vk::PipelineStageFlags::TRANSFER;
-        let submit_info = vk::SubmitInfo {
-            command_buffer_count: 1,
-            p_command_buffers: &raw const self.command_buffers[image_index as usize],
-            signal_semaphore_count: 1,
-            p_signal_semaphores: &raw const render_semaphore,
-            wait_semaphore_count: 1,
-            p_wait_semaphores: &raw const image_semaphore,
-            p_wait_dst_stage_mask: &raw const wait_stage,
-            ..Default::default()
-        };
-
+        // the swapchain image is first touched by the blit. the signal has to cover the transition to
+        // PRESENT_SRC_KHR after it though, which isn't part of any stage
+        let wait_stage = vk::PipelineStageFlags2::BLIT;
+        let signal_stage = vk::PipelineStageFlags2::ALL_COMMANDS;
+        let command_buffer = self.command_buffers[image_index as usize];
         let flight_fence = target.get_current_flight_fence();
 
         unsafe {
-            self.device
-                .queue_submit(self.compute_queue, &[submit_info], flight_fence)?;
+            if let Some(synchronization2) = &self.synchronization2 {
+                let command_buffer_info =
+                    vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer);
+                let wait_info = vk::SemaphoreSubmitInfo::default()
+                    .semaphore(image_semaphore)
+                    .stage_mask(wait_stage);
+                let signal_info = vk::SemaphoreSubmitInfo::default()
+                    .semaphore(render_semaphore)
+                    .stage_mask(signal_stage);
+                let submit_info = vk::SubmitInfo2::default()
+                    .command_buffer_infos(std::slice::from_ref(&command_buffer_info))
+                    .wait_semaphore_infos(std::slice::from_ref(&wait_info))
+                    .signal_semaphore_infos(std::slice::from_ref(&signal_info));
+                synchronization2.queue_submit2(self.compute_queue, &[submit_info], flight_fence)?;
+            } else {
+                // legacy semaphore signals always wait for every stage
+                let wait_stage = legacy_stages(wait_stage);
+                let submit_info = vk::SubmitInfo {
+                    command_buffer_count: 1,
+                    p_command_buffers: &raw const command_buffer,
+                    signal_semaphore_count: 1,
+                    p_signal_semaphores: &raw const render_semaphore,
+                    wait_semaphore_count: 1,
+                    p_wait_semaphores: &raw const image_semaphore,
+                    p_wait_dst_stage_mask: &raw const wait_stage,
+                    ..Default::default()
+                };
+                self.device
+                    .queue_submit(self.compute_queue, &[submit_info], flight_fence)?;
+            }
+        }
+        if timed {
+            self.timestamps_written[image_index as usize] = true;
+        }
+
+        target.present(self.present_queue)?;
+
+        self.idle_frames = (self.idle_frames + 1).min(IDLE_FRAMES);
+        if !converged {
+            self.current_frame += passes;
+            if self.max_samples == Some(self.current_frame) {
+                info!("Accumulated {} samples, pausing", self.current_frame);
+            }
         }
 
-        target.present(self.compute_queue)?;
+        Ok(())
+    }
+
+    fn read_image(&self) -> anyhow::Result<((u32, u32), Vec<f32>)> {
+        self.read_storage_image()
+    }
 
-        self.current_frame += 1;
+    fn read_aovs(&self) -> anyhow::Result<Option<Aovs>> {
+        self.read_aov_images()
+    }
 
+    fn wait_idle(&self) -> anyhow::Result<()> {
+        unsafe { self.device.device_wait_idle()? };
         Ok(())
     }
 
@@ -1526,90 +3253,52 @@ impl Renderer<MeshScene, WindowData> for RaytraceRenderer {
         FEATURES.get_list()
     }
 
+    fn target_image_usage() -> vk::ImageUsageFlags {
+        // the storage image gets blitted into the swapchain image
+        vk::ImageUsageFlags::TRANSFER_DST
+    }
+
     fn has_required_queue_families(queue_family_info: &QueueFamilyInfo) -> bool {
         queue_family_info.compute_index.is_some() && queue_family_info.present_index.is_some()
     }
 
     fn get_queue_info(queue_family_info: &QueueFamilyInfo) -> Vec<vk::DeviceQueueCreateInfo<'_>> {
-        let create_info = vk::DeviceQueueCreateInfo {
-            queue_family_index: queue_family_info.compute_index.unwrap(),
-            queue_count: 1,
-            p_queue_priorities: &1.0,
+        let compute_index = queue_family_info.compute_index.unwrap();
+        let compute_queue_count = queue_family_info
+            .compute_queue_count
+            .clamp(1, COMPUTE_QUEUE_PRIORITIES.len() as u32);
+
+        let mut queue_info = vec![vk::DeviceQueueCreateInfo {
+            queue_family_index: compute_index,
+            queue_count: compute_queue_count,
+            p_queue_priorities: COMPUTE_QUEUE_PRIORITIES.as_ptr(),
             ..Default::default()
-        };
+        }];
+        if let Some(present_index) = queue_family_info.present_index {
+            if present_index != compute_index {
+                queue_info.push(vk::DeviceQueueCreateInfo {
+                    queue_family_index: present_index,
+                    queue_count: 1,
+                    p_queue_priorities: &PRESENT_QUEUE_PRIORITY,
+                    ..Default::default()
+                });
+            }
+        }
 
-        vec![create_info]
+        queue_info
     }
 }
 
 impl Drop for RaytraceRenderer {
     fn drop(&mut self) {
-        unsafe {
-            self.device
-                .device_wait_idle()
-                .expect("failed to wait for device idle");
+        if let Err(e) = self.reset() {
+            error!("failed to free scene resources: {e}");
+        }
 
+        unsafe {
             self.device.destroy_command_pool(self.command_pool, None);
-
-            self.device
-                .destroy_descriptor_pool(self.descriptor_pool, None);
-            if let Some(x) = self.sbt_buffer.take() {
-                x.destroy(&self.device, &mut self.allocator.borrow_mut());
-            }
-            self.device.destroy_pipeline(self.pipeline, None);
-            self.device
-                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
-
             self.device
-                .destroy_pipeline_layout(self.pipeline_layout, None);
-
-            for blas in self.triangle_blas.iter() {
-                self.accel_struct_device
-                    .destroy_acceleration_structure(*blas, None);
-            }
-            while !self.triangle_blas_buffers.is_empty() {
-                self.triangle_blas_buffers
-                    .swap_remove(0)
-                    .destroy(&self.device, &mut self.allocator.borrow_mut());
-            }
-            for blas in self.procedural_blas.iter() {
-                self.accel_struct_device
-                    .destroy_acceleration_structure(*blas, None);
-            }
-            while !self.procedural_blas_buffers.is_empty() {
-                self.procedural_blas_buffers
-                    .swap_remove(0)
-                    .destroy(&self.device, &mut self.allocator.borrow_mut());
-            }
-            self.accel_struct_device
-                .destroy_acceleration_structure(self.top_as, None);
-            if let Some(x) = self.top_as_buffer.take() {
-                x.destroy(&self.device, &mut self.allocator.borrow_mut());
-            }
-
-            if let Some(x) = self.storage_image.take() {
-                x.destroy(&self.device, &mut self.allocator.borrow_mut());
-            }
-
-            if let Some(x) = self.accumulation_image.take() {
-                x.destroy(&self.device, &mut self.allocator.borrow_mut());
-            }
-
-            if let Some(x) = self.vertex_normal_buffer.take() {
-                x.destroy(&self.device, &mut self.allocator.borrow_mut());
-            }
-
-            if let Some(x) = self.light_buffer.take() {
-                x.destroy(&self.device, &mut self.allocator.borrow_mut());
-            }
-
-            if let Some(x) = self.offset_buffer.take() {
-                x.destroy(&self.device, &mut self.allocator.borrow_mut());
-            }
-
-            if let Some(x) = self.brdf_param_buffer.take() {
-                x.destroy(&self.device, &mut self.allocator.borrow_mut());
-            }
+                .destroy_command_pool(self.transient_command_pool, None);
         }
     }
 }
@@ -0,0 +1,258 @@
+// golden image regression test for the raytrace renderer
+//
+// renders the golden scene offscreen with a fixed seed and compares it against a committed reference
+// this needs a ray tracing capable gpu and compiled shaders (./build_shaders.py), so it is ignored by default:
+//     cargo test golden -- --ignored
+// after an intentional change to the rendered output, regenerate the reference with:
+//     KUBGRUPP_BLESS=1 cargo test golden -- --ignored
+
+use std::{cell::RefCell, ffi::CStr, fs::File, rc::Rc};
+
+use anyhow::{anyhow, bail, Result};
+use ash::{vk, Device, Entry, Instance};
+use gpu_allocator::vulkan::{Allocator, AllocatorCreateDesc};
+
+use super::RaytraceRenderer;
+use crate::{
+    render::{golden::assert_matches_reference, screenshot::encode_image, Renderer},
+    scene::scenes::mesh::{MeshScene, MeshSceneUpdate},
+    utils::{create_device, submit_immediate, QueueFamilyInfo, VULKAN_API_VERSION},
+};
+
+const SCENE_PATH: &str = "resources/scenes/golden.toml";
+const REFERENCE_PATH: &str = "resources/golden/golden.png";
+
+const IMAGE_SIZE: (u32, u32) = (256, 256);
+const FRAME_COUNT: u32 = 16;
+
+// mean absolute error over all channels, in 8-bit units
+const MAX_MEAN_ABS_ERROR: f64 = 1.5;
+
+impl RaytraceRenderer {
+    /// Traces a single frame into the storage image without presenting it anywhere
+    ///
+    /// Blocks until the frame has finished rendering. Use [`Self::read_storage_image`] to get the result.
+    fn render_offscreen(&mut self, updates: &[MeshSceneUpdate]) -> Result<()> {
+        self.apply_updates(updates)?;
+        self.update_frame_push_data();
+        self.update_motion_blur()?;
+        // nothing else is in flight once submit_immediate returns, so one slot is enough
+        self.upload_frame_params(0)?;
+
+        submit_immediate(
+            &self.device,
+            self.transient_command_pool,
+            self.compute_queue,
+            |command_buffer| {
+                unsafe { self.record_trace(command_buffer, 0, 0) };
+                Ok(())
+            },
+        )?;
+
+        self.current_frame += 1;
+
+        Ok(())
+    }
+}
+
+struct HeadlessContext {
+    // same drop order rules as MeshApp
+    renderer: Option<RaytraceRenderer>,
+    allocator: Option<Rc<RefCell<Allocator>>>,
+    device: Device,
+    instance: Instance,
+    _vk_lib: Entry,
+}
+
+impl HeadlessContext {
+    fn new() -> Result<Self> {
+        let vk_lib = unsafe { Entry::load()? };
+
+        let extensions = RaytraceRenderer::required_instance_extensions();
+        let app_info = vk::ApplicationInfo {
+            api_version: VULKAN_API_VERSION,
+            ..Default::default()
+        };
+        let create_info = vk::InstanceCreateInfo {
+            p_application_info: &app_info,
+            enabled_extension_count: extensions.len() as u32,
+            pp_enabled_extension_names: extensions.as_ptr(),
+            ..Default::default()
+        };
+        let instance = unsafe { vk_lib.create_instance(&create_info, None)? };
+
+        let (physical_device, queue_family_info) = match Self::pick_physical_device(&instance) {
+            Ok(x) => x,
+            Err(e) => {
+                unsafe { instance.destroy_instance(None) };
+                return Err(e);
+            }
+        };
+
+        let device_extensions = RaytraceRenderer::required_device_extensions();
+        let features = RaytraceRenderer::required_features();
+        let queue_info = RaytraceRenderer::get_queue_info(&queue_family_info);
+        let device = create_device(
+            &instance,
+            physical_device,
+            device_extensions,
+            features.get(),
+            &queue_info,
+        )?;
+
+        let allocator = Rc::new(RefCell::new(Allocator::new(&AllocatorCreateDesc {
+            instance: instance.clone(),
+            device: device.clone(),
+            physical_device,
+            debug_settings: Default::default(),
+            buffer_device_address: true,
+            allocation_sizes: Default::default(),
+        })?));
+
+        let mut context = HeadlessContext {
+            renderer: None,
+            allocator: Some(allocator.clone()),
+            device,
+            instance,
+            _vk_lib: vk_lib,
+        };
+
+        context.renderer = Some(RaytraceRenderer::new(
+            &context._vk_lib,
+            &context.instance,
+            &context.device,
+            physical_device,
+            &queue_family_info,
+            allocator,
+            false,
+        )?);
+
+        Ok(context)
+    }
+
+    fn pick_physical_device(instance: &Instance) -> Result<(vk::PhysicalDevice, QueueFamilyInfo)> {
+        let required_extensions = RaytraceRenderer::required_device_extensions();
+        let required_features = RaytraceRenderer::required_features();
+
+        for device in unsafe { instance.enumerate_physical_devices()? } {
+            let properties = unsafe { instance.get_physical_device_properties(device) };
+            if properties.api_version < VULKAN_API_VERSION {
+                continue;
+            }
+
+            let supported_extensions =
+                unsafe { instance.enumerate_device_extension_properties(device)? };
+            let extensions_supported = required_extensions.iter().all(|&ext| {
+                let ext_name = unsafe { CStr::from_ptr(ext) };
+                supported_extensions
+                    .iter()
+                    .any(|x| x.extension_name_as_c_str().unwrap() == ext_name)
+            });
+
+            if !extensions_supported || !required_features.unsupported(instance, device).is_empty()
+            {
+                continue;
+            }
+
+            // no surface to present to, so all we need is a compute queue
+            let queue_families =
+                unsafe { instance.get_physical_device_queue_family_properties(device) };
+            let Some(compute_index) = queue_families
+                .iter()
+                .position(|x| x.queue_flags.contains(vk::QueueFlags::COMPUTE))
+            else {
+                continue;
+            };
+
+            let queue_family_info = QueueFamilyInfo {
+                compute_index: Some(compute_index as u32),
+                compute_queue_count: queue_families[compute_index].queue_count,
+                ..Default::default()
+            };
+            return Ok((device, queue_family_info));
+        }
+
+        bail!("no ray tracing capable physical device found")
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        if let Some(Err(e)) = self.renderer.as_ref().map(Renderer::wait_idle) {
+            eprintln!("failed to wait for the device: {e}");
+        }
+        drop(self.renderer.take());
+        drop(self.allocator.take());
+        unsafe {
+            self.device.destroy_device(None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+fn render_golden_scene() -> Result<((u32, u32), Vec<u8>)> {
+    let mut scene = MeshScene::load_from(File::open(SCENE_PATH)?)?;
+    if scene.render_options.seed.is_none() {
+        bail!("golden scene must set render.seed to be reproducible");
+    }
+
+    let mut context = HeadlessContext::new()?;
+    let renderer = context.renderer.as_mut().unwrap();
+    renderer.ingest_scene(&scene)?;
+
+    scene.camera.handle_resize(IMAGE_SIZE.0, IMAGE_SIZE.1);
+    let mut updates = vec![MeshSceneUpdate::NewSize((
+        IMAGE_SIZE.0,
+        IMAGE_SIZE.1,
+        scene.camera.projection(),
+    ))];
+    for _ in 0..FRAME_COUNT {
+        renderer.render_offscreen(&updates)?;
+        updates.clear();
+    }
+
+    let resolution = renderer.render_resolution();
+    if resolution != IMAGE_SIZE {
+        return Err(anyhow!(
+            "expected to render at {:?}, got {:?}",
+            IMAGE_SIZE,
+            resolution
+        ));
+    }
+
+    let (size, pixels) = renderer.read_storage_image()?;
+
+    Ok((size, encode_image(&pixels)))
+}
+
+#[test]
+#[ignore = "requires a ray tracing capable gpu and compiled shaders"]
+fn golden_image() {
+    let (size, image) = render_golden_scene().expect("failed to render golden scene");
+
+    assert_matches_reference(REFERENCE_PATH, size, &image, MAX_MEAN_ABS_ERROR);
+}
+
+#[test]
+#[ignore = "requires a ray tracing capable gpu"]
+fn submit_immediate_record_error() {
+    let context = HeadlessContext::new().expect("failed to create headless context");
+    let renderer = context.renderer.as_ref().unwrap();
+    let submit = |fail: bool| {
+        submit_immediate(
+            &context.device,
+            renderer.transient_command_pool,
+            renderer.compute_queue,
+            |_| {
+                if fail {
+                    bail!("recording failed");
+                }
+                Ok(())
+            },
+        )
+    };
+
+    // the error comes straight from the closure, and the half-recorded buffer doesn't get in the way of the next one
+    assert_eq!(submit(true).unwrap_err().to_string(), "recording failed");
+    submit(false).expect("failed to submit after a failed recording");
+}
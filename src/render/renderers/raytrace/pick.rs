@@ -0,0 +1,125 @@
+// finds what's under a point on the screen for RaytraceRenderer::pick
+//
+// the ray is made on the cpu from the matrices the last frame was traced with, pick.comp traces it against
+// the tlas and writes the hit into a small readback buffer, which is decoded here
+
+use glam::Vec3;
+
+// what pick.comp writes where nothing was hit, same as NO_INSTANCE in raygen_common.glsl
+const NO_INSTANCE: u32 = u32::MAX;
+
+/// Size of what pick.comp writes: the instance, the primitive and the distance
+pub const RESULT_SIZE: u64 = 12;
+
+/// What [`super::RaytraceRenderer::pick`] found
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    /// Index of the tlas instance, which is the scene's objects and then its procedural objects
+    pub instance: u32,
+    /// Triangle within the instance's mesh
+    pub primitive: u32,
+    /// Distance from the camera along the ray
+    pub distance: f32,
+    /// World space position of the hit
+    pub position: Vec3,
+}
+
+// matches the Constants block in pick.comp: the result buffer's address, then the ray
+pub fn push_constants(result_address: u64, (origin, direction): (Vec3, Vec3)) -> [u8; 32] {
+    let mut data = [0; 32];
+    data[0..8].copy_from_slice(&result_address.to_ne_bytes());
+    data[8..20].copy_from_slice(bytemuck::cast_slice(&origin.to_array()));
+    data[20..32].copy_from_slice(bytemuck::cast_slice(&direction.to_array()));
+    data
+}
+
+// decodes the PickResult pick.comp wrote for the ray `(origin, direction)`
+pub fn read_hit(result: &[u8], (origin, direction): (Vec3, Vec3)) -> Option<Hit> {
+    let [instance, primitive, distance] =
+        bytemuck::pod_read_unaligned::<[u32; 3]>(&result[..RESULT_SIZE as usize]);
+    if instance == NO_INSTANCE {
+        return None;
+    }
+
+    let distance = f32::from_bits(distance);
+    Some(Hit {
+        instance,
+        primitive,
+        distance,
+        position: origin + direction * distance,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec3, Vec4Swizzles};
+
+    use super::*;
+    use crate::camera::{Camera, Lens, Projection};
+    use crate::scene::intersect::camera_ray;
+
+    #[test]
+    fn pick_ray_through_ndc() {
+        let camera = Camera::new(
+            Mat4::look_at_lh(Vec3::new(4.0, 1.0, 2.0), Vec3::ZERO, Vec3::Z),
+            Projection::Perspective { fov: 70.0 },
+            Lens::default(),
+        );
+        // a wide window, so the aspect ratio isn't 1 by accident
+        let (view, projection) = (camera.view(), camera.projection_at(1600, 900));
+
+        // the ray through where a point ends up on screen goes back through that point
+        for point in [
+            Vec3::ZERO,
+            Vec3::new(1.0, -1.0, 0.5),
+            Vec3::new(-2.0, 1.5, 1.0),
+        ] {
+            let clip = projection * view * point.extend(1.0);
+            let ndc = clip.xy() / clip.w;
+            let (origin, direction) = camera_ray(view.inverse(), projection.inverse(), ndc.into());
+
+            assert!((origin - Vec3::new(4.0, 1.0, 2.0)).length() < 1e-4);
+            assert!((direction.length() - 1.0).abs() < 1e-5);
+            let distance = (point - origin).dot(direction);
+            assert!(
+                (origin + direction * distance - point).length() < 1e-4,
+                "{point}"
+            );
+        }
+
+        // the top left corner of the screen looks up and to the left
+        let (_, direction) = camera_ray(view.inverse(), projection.inverse(), (-1.0, -1.0));
+        let (_, center) = camera_ray(view.inverse(), projection.inverse(), (0.0, 0.0));
+        let right = Vec3::Z.cross(center);
+        assert!(direction.z > center.z);
+        assert!(direction.dot(right) < 0.0);
+    }
+
+    #[test]
+    fn pick_result() {
+        let ray = (Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z);
+        let data = push_constants(0x1234_5678_9abc_def0, ray);
+        assert_eq!(
+            bytemuck::pod_read_unaligned::<u64>(&data[0..8]),
+            0x1234_5678_9abc_def0
+        );
+        assert_eq!(
+            bytemuck::pod_read_unaligned::<[f32; 6]>(&data[8..32]),
+            [0.0, 0.0, 5.0, 0.0, 0.0, -1.0]
+        );
+
+        let result = |instance: u32, primitive: u32, t: f32| {
+            bytemuck::cast::<[u32; 3], [u8; 12]>([instance, primitive, t.to_bits()])
+        };
+        assert_eq!(
+            read_hit(&result(3, 7, 4.5), ray),
+            Some(Hit {
+                instance: 3,
+                primitive: 7,
+                distance: 4.5,
+                position: Vec3::new(0.0, 0.0, 0.5),
+            })
+        );
+        assert_eq!(read_hit(&result(NO_INSTANCE, 0, 0.0), ray), None);
+    }
+}
@@ -0,0 +1,123 @@
+// ray query variant of the raytrace renderer
+//
+// shares the acceleration structures, scene buffers and presentation with RaytraceRenderer, but traces
+// from a compute shader (ray_query.comp) instead of a ray tracing pipeline, so it works on devices that
+// have VK_KHR_ray_query but not VK_KHR_ray_tracing_pipeline
+
+use std::{cell::RefCell, ffi::c_char, rc::Rc, sync::LazyLock};
+
+use ash::{khr, vk, Device, Entry, Instance};
+use gpu_allocator::vulkan::Allocator;
+
+use super::{RaytraceRenderer, TraceBackend};
+use crate::{
+    features::{vk_features, VkFeatureGuard, VkFeatures},
+    render::{aovs::Aovs, Renderer},
+    scene::{scenes::mesh::MeshScene, Scene},
+    utils::QueueFamilyInfo,
+    window::WindowData,
+};
+
+/// Renders scenes with ray queries from a compute shader
+///
+/// There are no hit shaders, so every surface gets the same simple shading and the scene's own shaders
+/// are ignored. Procedural geometry doesn't show up at all. The debug views work the same as with
+/// [`RaytraceRenderer`].
+pub struct RayQueryRenderer(RaytraceRenderer);
+
+impl Renderer<MeshScene, WindowData> for RayQueryRenderer {
+    fn new(
+        _vk_lib: &Entry,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        queue_family_info: &QueueFamilyInfo,
+        allocator: Rc<RefCell<Allocator>>,
+        debug: bool,
+    ) -> anyhow::Result<Self> {
+        RaytraceRenderer::with_backend(
+            instance,
+            device,
+            physical_device,
+            queue_family_info,
+            allocator,
+            debug,
+            TraceBackend::RayQuery,
+        )
+        .map(Self)
+    }
+
+    fn ingest_scene(&mut self, scene: &MeshScene) -> anyhow::Result<()> {
+        self.0.ingest_scene(scene)
+    }
+
+    fn reset(&mut self) -> anyhow::Result<()> {
+        self.0.reset()
+    }
+
+    fn render_to(
+        &mut self,
+        updates: &[<MeshScene as Scene>::Update],
+        target: &mut WindowData,
+    ) -> anyhow::Result<()> {
+        self.0.render_to(updates, target)
+    }
+
+    fn read_image(&self) -> anyhow::Result<((u32, u32), Vec<f32>)> {
+        self.0.read_storage_image()
+    }
+
+    fn read_aovs(&self) -> anyhow::Result<Option<Aovs>> {
+        self.0.read_aov_images()
+    }
+
+    fn wait_idle(&self) -> anyhow::Result<()> {
+        self.0.wait_idle()
+    }
+
+    fn required_instance_extensions() -> &'static [*const c_char] {
+        RaytraceRenderer::required_instance_extensions()
+    }
+
+    fn required_device_extensions() -> &'static [*const c_char] {
+        const EXTENSIONS: &[*const c_char] = &[
+            khr::acceleration_structure::NAME.as_ptr(),
+            khr::deferred_host_operations::NAME.as_ptr(),
+            khr::ray_query::NAME.as_ptr(),
+        ];
+        EXTENSIONS
+    }
+
+    fn required_features() -> VkFeatureGuard<'static> {
+        static FEATURES: LazyLock<VkFeatures> = LazyLock::new(|| {
+            vk_features! {
+                vk::PhysicalDeviceFeatures {},
+                vk::PhysicalDeviceVulkan12Features {
+                    buffer_device_address,
+                    scalar_block_layout,
+                    timeline_semaphore,
+                },
+                vk::PhysicalDeviceAccelerationStructureFeaturesKHR {
+                    acceleration_structure,
+                },
+                vk::PhysicalDeviceRayQueryFeaturesKHR {
+                    ray_query,
+                },
+            }
+        });
+
+        FEATURES.get_list()
+    }
+
+    fn target_image_usage() -> vk::ImageUsageFlags {
+        RaytraceRenderer::target_image_usage()
+    }
+
+    fn has_required_queue_families(queue_family_info: &QueueFamilyInfo) -> bool {
+        RaytraceRenderer::has_required_queue_families(queue_family_info)
+    }
+
+    fn get_queue_info(queue_family_info: &QueueFamilyInfo) -> Vec<vk::DeviceQueueCreateInfo<'_>> {
+        RaytraceRenderer::get_queue_info(queue_family_info)
+    }
+}
@@ -0,0 +1,117 @@
+// picks the storage image resolution from measured gpu frame times
+//
+// the storage image gets blitted to the swapchain image anyway, so rendering it smaller than the window
+// only costs sharpness. this keeps the scale between MIN_RENDER_SCALE and MAX_RENDER_SCALE so that the
+// frame time stays around the target
+
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+pub const MAX_RENDER_SCALE: f32 = 1.0;
+
+// frame times are averaged over this many frames before deciding anything
+// every change also restarts the average, so this doubles as the cooldown between changes
+const SAMPLE_FRAMES: u32 = 16;
+
+// the scale only changes when the average leaves this band around the target,
+// so measurements that are close to the target don't make it flip back and forth
+const SLOW_THRESHOLD: f32 = 1.1;
+const FAST_THRESHOLD: f32 = 0.7;
+
+// aim a bit below the target after a change, so the next measurement lands inside the band
+const TARGET_MARGIN: f32 = 0.9;
+
+// changes smaller than this aren't worth recreating the images and restarting accumulation for
+const MIN_SCALE_STEP: f32 = 0.01;
+
+pub struct RenderScale {
+    target_frame_time: f32,
+    scale: f32,
+    total_time: f32,
+    samples: u32,
+}
+
+impl RenderScale {
+    /// Starts at full resolution, aiming for `target_frame_time` milliseconds per frame
+    pub fn new(target_frame_time: f32) -> Self {
+        Self {
+            target_frame_time,
+            scale: MAX_RENDER_SCALE,
+            total_time: 0.0,
+            samples: 0,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Records the gpu time of one frame in milliseconds
+    ///
+    /// Returns the new scale if it changed.
+    pub fn record_frame_time(&mut self, frame_time: f32) -> Option<f32> {
+        self.total_time += frame_time;
+        self.samples += 1;
+        if self.samples < SAMPLE_FRAMES {
+            return None;
+        }
+
+        let average = self.total_time / self.samples as f32;
+        self.total_time = 0.0;
+        self.samples = 0;
+
+        if average > FAST_THRESHOLD * self.target_frame_time
+            && average < SLOW_THRESHOLD * self.target_frame_time
+        {
+            return None;
+        }
+
+        // frame time goes with the pixel count, which goes with the square of the scale
+        let factor = (TARGET_MARGIN * self.target_frame_time / average.max(f32::EPSILON)).sqrt();
+        let scale = (self.scale * factor).clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+        if (scale - self.scale).abs() < MIN_SCALE_STEP {
+            return None;
+        }
+
+        self.scale = scale;
+        Some(scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(render_scale: &mut RenderScale, frame_time: f32) -> Option<f32> {
+        (0..SAMPLE_FRAMES).fold(None, |changed, _| {
+            render_scale.record_frame_time(frame_time).or(changed)
+        })
+    }
+
+    #[test]
+    fn render_scale_adapts() {
+        let mut render_scale = RenderScale::new(16.0);
+
+        // nothing happens until enough frames are averaged
+        for _ in 0..SAMPLE_FRAMES - 1 {
+            assert_eq!(render_scale.record_frame_time(64.0), None);
+        }
+        let lowered = render_scale.record_frame_time(64.0).unwrap();
+        assert!(lowered < MAX_RENDER_SCALE);
+
+        // inside the band around the target nothing changes
+        assert_eq!(run(&mut render_scale, 15.0), None);
+        assert_eq!(run(&mut render_scale, 12.0), None);
+        assert_eq!(render_scale.scale(), lowered);
+
+        // with headroom it goes back up, but never past full resolution
+        assert!(run(&mut render_scale, 4.0).unwrap() > lowered);
+        run(&mut render_scale, 1.0);
+        assert_eq!(render_scale.scale(), MAX_RENDER_SCALE);
+        assert_eq!(run(&mut render_scale, 1.0), None);
+
+        // and never below the minimum either
+        for _ in 0..10 {
+            run(&mut render_scale, 1000.0);
+        }
+        assert_eq!(render_scale.scale(), MIN_RENDER_SCALE);
+    }
+}
@@ -0,0 +1,159 @@
+// encodes rendered images the same way they end up on screen
+//
+// the storage image holds linear color, and the blit into the swapchain image converts it to the surface
+// format. into the default B8G8R8A8_SRGB that clamps it and applies the srgb transfer function, which is what
+// encode_image does. screenshots go through encode_for_surface, which matches whatever format the window
+// ended up with
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+use ash::vk;
+
+fn linear_to_srgb(x: f32) -> u8 {
+    let x = x.clamp(0.0, 1.0);
+    let encoded = if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    };
+
+    (encoded * 255.0).round() as u8
+}
+
+/// Converts linear RGBA floats to sRGB-encoded RGB bytes, matching what the swapchain blit displays
+pub fn encode_image(pixels: &[f32]) -> Vec<u8> {
+    pixels
+        .chunks_exact(4)
+        .flat_map(|rgba| rgba[..3].iter().map(|&x| linear_to_srgb(x)))
+        .collect()
+}
+
+/// Like [`encode_image`], but matching what the blit into a swapchain image of `format` displays
+///
+/// Unorm formats get the linear values as they are, since the blit doesn't encode them. scRGB (hdr) is clamped
+/// to the sRGB range, a PNG can't hold what's brighter than that. Anything else is refused.
+pub fn encode_for_surface(pixels: &[f32], format: vk::SurfaceFormatKHR) -> Result<Vec<u8>> {
+    use vk::Format as F;
+
+    let srgb = match (format.format, format.color_space) {
+        (
+            F::B8G8R8A8_SRGB | F::R8G8B8A8_SRGB | F::A8B8G8R8_SRGB_PACK32,
+            vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        )
+        | (F::R16G16B16A16_SFLOAT, vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT) => true,
+        (
+            F::B8G8R8A8_UNORM
+            | F::R8G8B8A8_UNORM
+            | F::A8B8G8R8_UNORM_PACK32
+            | F::A2B10G10R10_UNORM_PACK32
+            | F::A2R10G10B10_UNORM_PACK32,
+            vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        ) => false,
+        (format, color_space) => {
+            bail!("can't save screenshots of a {format:?} {color_space:?} surface")
+        }
+    };
+
+    if srgb {
+        return Ok(encode_image(pixels));
+    }
+    Ok(pixels
+        .chunks_exact(4)
+        .flat_map(|rgba| {
+            rgba[..3]
+                .iter()
+                .map(|&x| (x.clamp(0.0, 1.0) * 255.0).round() as u8)
+        })
+        .collect())
+}
+
+/// Writes RGB bytes from [`encode_image`] as an 8-bit PNG
+pub fn write_png(path: impl AsRef<Path>, size: (u32, u32), data: &[u8]) -> Result<()> {
+    write_png_to(BufWriter::new(File::create(path)?), size, data)
+}
+
+/// [`write_png`] into memory
+pub fn encode_png(size: (u32, u32), data: &[u8]) -> Result<Vec<u8>> {
+    let mut file = Vec::new();
+    write_png_to(&mut file, size, data)?;
+    Ok(file)
+}
+
+fn write_png_to(writer: impl Write, (width, height): (u32, u32), data: &[u8]) -> Result<()> {
+    if data.len() != (width * height * 3) as usize {
+        bail!(
+            "expected {width}x{height} rgb pixels, got {} bytes",
+            data.len()
+        );
+    }
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn srgb_encoding() {
+        assert_eq!(encode_image(&[0.0, 1.0, 2.0, 1.0]), vec![0, 255, 255]);
+        assert_eq!(linear_to_srgb(0.5), 188);
+    }
+
+    #[test]
+    fn surface_encoding() {
+        let surface = |format, color_space| vk::SurfaceFormatKHR {
+            format,
+            color_space,
+        };
+        let pixels = [0.5, 2.0, -1.0, 1.0];
+
+        let srgb = surface(vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR);
+        assert_eq!(encode_for_surface(&pixels, srgb).unwrap(), [188, 255, 0]);
+        let unorm = surface(
+            vk::Format::R8G8B8A8_UNORM,
+            vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        );
+        assert_eq!(encode_for_surface(&pixels, unorm).unwrap(), [128, 255, 0]);
+        let scrgb = crate::window::HDR_SURFACE_FORMATS[0];
+        assert_eq!(encode_for_surface(&pixels, scrgb).unwrap(), [188, 255, 0]);
+
+        let hdr10 = surface(
+            vk::Format::A2B10G10R10_UNORM_PACK32,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        );
+        assert!(encode_for_surface(&pixels, hdr10).is_err());
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let pixels = [0.0, 0.5, 1.0, 1.0, 0.2, 0.04, 3.0, 0.0];
+        let image = encode_image(&pixels);
+
+        let path = std::env::temp_dir().join(format!("kg-screenshot-{}.png", std::process::id()));
+        write_png(&path, (2, 1), &image).unwrap();
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((info.width, info.height), (2, 1));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        assert_eq!(&data[..info.buffer_size()], image);
+        assert_eq!(image, [0, 188, 255, 124, 56, 255]);
+
+        assert!(write_png(&path, (2, 2), &image).is_err());
+    }
+}
@@ -1,6 +1,36 @@
+pub mod asset_cache;
+pub mod coordinates;
+pub mod environment;
+pub mod intersect;
+pub mod normal_map;
 pub mod scenes;
+pub mod shader_archive;
+pub mod spirv;
+pub mod transform;
 pub mod type_lexer;
 
+/// Shader stages a scene needs, so a renderer can check it can handle a scene before ingesting it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShaderRequirements {
+    /// Hit groups with only a closest hit shader, for triangle geometry
+    pub triangle_hit_groups: usize,
+    /// Hit groups with an intersection and closest hit shader, for procedural geometry
+    pub procedural_hit_groups: usize,
+    /// Whether any of the shaders is an any-hit shader (going by its SPIR-V entry points)
+    pub any_hit: bool,
+    /// Whether any of the shaders is a callable shader
+    pub callable: bool,
+}
+
+impl ShaderRequirements {
+    /// Total number of shader groups, counting the raygen and miss groups
+    pub fn shader_group_count(&self) -> usize {
+        2 + self.triangle_hit_groups + self.procedural_hit_groups
+    }
+}
+
 pub trait Scene {
     type Update: Sized;
+
+    fn shader_requirements(&self) -> ShaderRequirements;
 }
@@ -0,0 +1,113 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use anyhow::Result;
+use tobj::Model;
+
+static CACHE: LazyLock<AssetCache> = LazyLock::new(Default::default);
+
+/// Cache of the files scenes load, so scenes sharing meshes or shaders only read them once
+///
+/// Entries are keyed by canonical path, so different spellings of the same path share an entry. Everything
+/// stays cached until [`AssetCache::clear`]. Scenes load through [`AssetCache::global`], which lives as long
+/// as the process, so reloading a scene only reads its toml again.
+#[derive(Default)]
+pub struct AssetCache {
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    meshes: HashMap<PathBuf, Arc<[Model]>>,
+    spirv: HashMap<PathBuf, Arc<[u32]>>,
+}
+
+impl AssetCache {
+    /// The cache scenes load their meshes and shaders through
+    pub fn global() -> &'static AssetCache {
+        &CACHE
+    }
+
+    /// Every model in an obj file, loaded with tobj's GPU load options
+    pub fn load_obj(&self, path: &Path) -> Result<Arc<[Model]>> {
+        self.get_or_load(
+            |entries| &mut entries.meshes,
+            path,
+            |path| Ok(tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)?.0.into()),
+        )
+    }
+
+    /// SPIR-V code from `path`, which is read with `load` if it isn't cached yet
+    pub fn load_spirv(
+        &self,
+        path: &Path,
+        load: impl FnOnce(&Path) -> Result<Box<[u32]>>,
+    ) -> Result<Arc<[u32]>> {
+        self.get_or_load(
+            |entries| &mut entries.spirv,
+            path,
+            |path| load(path).map(Arc::from),
+        )
+    }
+
+    /// Drops everything cached so far, later loads go back to the filesystem
+    pub fn clear(&self) {
+        *self.entries.lock().unwrap() = Entries::default();
+    }
+
+    fn get_or_load<T: ?Sized>(
+        &self,
+        entries: impl FnOnce(&mut Entries) -> &mut HashMap<PathBuf, Arc<T>>,
+        path: &Path,
+        load: impl FnOnce(&Path) -> Result<Arc<T>>,
+    ) -> Result<Arc<T>> {
+        let path = path.canonicalize()?;
+
+        let mut cache = self.entries.lock().unwrap();
+        let entries = entries(&mut cache);
+        if let Some(x) = entries.get(&path) {
+            return Ok(x.clone());
+        }
+
+        let x = load(&path)?;
+        entries.insert(path, x.clone());
+        Ok(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn asset_cache_loads_once() {
+        let cache = AssetCache::default();
+        let loads = Cell::new(0);
+        let load = |_: &Path| {
+            loads.set(loads.get() + 1);
+            Ok(Box::from([1u32, 2, 3]))
+        };
+
+        // same file, spelled differently, and the same allocation both times
+        let code = cache.load_spirv(Path::new("Cargo.toml"), load).unwrap();
+        let cached = cache
+            .load_spirv(Path::new("./src/../Cargo.toml"), load)
+            .unwrap();
+        assert!(Arc::ptr_eq(&code, &cached));
+        assert_eq!(loads.get(), 1);
+
+        cache.clear();
+        cache.load_spirv(Path::new("Cargo.toml"), load).unwrap();
+        assert_eq!(loads.get(), 2);
+
+        assert!(cache
+            .load_spirv(Path::new("does_not_exist.spv"), load)
+            .is_err());
+        assert_eq!(loads.get(), 2);
+    }
+}
@@ -0,0 +1,145 @@
+// converts scenes authored in another coordinate system into the renderer's, which is left-handed with z up
+//
+// everything in world space is premultiplied with one matrix at load time (see CoordinateSystem::to_world), so
+// meshes stay as they are in their files and just get a different transform. y up is rotated 90 degrees about
+// x onto z up, then right-handed is mirrored along y. together that makes right-handed y up a swap of y and z
+
+use glam::{Mat3, Mat4, Vec3};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Handedness {
+    #[default]
+    Left,
+    Right,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UpAxis {
+    Y,
+    #[default]
+    Z,
+}
+
+/// What a scene file's positions, directions and transforms are in (the `[coordinates]` table)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CoordinateSystem {
+    pub handedness: Handedness,
+    pub up_axis: UpAxis,
+}
+
+impl CoordinateSystem {
+    /// From this coordinate system to the renderer's, identity for the default (left-handed, z up)
+    ///
+    /// Y up goes from +y to +z with +z going to -y, and right-handed then negates y.
+    pub fn to_world(self) -> Mat4 {
+        // columns are where x, y and z end up
+        let up = match self.up_axis {
+            UpAxis::Y => Mat4::from_mat3(Mat3::from_cols(Vec3::X, Vec3::Z, -Vec3::Y)),
+            UpAxis::Z => Mat4::IDENTITY,
+        };
+        match self.handedness {
+            Handedness::Left => up,
+            Handedness::Right => Mat4::from_scale(Vec3::new(1.0, -1.0, 1.0)) * up,
+        }
+    }
+
+    /// The up axis as a vector in this coordinate system
+    pub fn up(self) -> Vec3 {
+        match self.up_axis {
+            UpAxis::Y => Vec3::Y,
+            UpAxis::Z => Vec3::Z,
+        }
+    }
+
+    /// A world to camera matrix in this coordinate system as one in the renderer's
+    ///
+    /// The camera moves along with the world. When that mirrors it, camera space x is negated too so the view
+    /// doesn't end up mirrored: what was on the right of the image in the scene's own coordinate system stays
+    /// there. For a `lookat` view this is the same as looking at the converted points.
+    pub fn convert_view(self, view: Mat4) -> Mat4 {
+        let to_world = self.to_world();
+        let view = view * to_world.inverse();
+        match self.handedness {
+            Handedness::Left => view,
+            Handedness::Right => Mat4::from_scale(Vec3::new(-1.0, 1.0, 1.0)) * view,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_world() {
+        let convert = |handedness, up_axis, v: Vec3| {
+            CoordinateSystem {
+                handedness,
+                up_axis,
+            }
+            .to_world()
+            .transform_point3(v)
+        };
+        let v = Vec3::new(1.0, 2.0, 3.0);
+
+        assert_eq!(convert(Handedness::Left, UpAxis::Z, v), v);
+        assert_eq!(
+            convert(Handedness::Left, UpAxis::Y, v),
+            Vec3::new(1.0, -3.0, 2.0)
+        );
+        assert_eq!(
+            convert(Handedness::Right, UpAxis::Z, v),
+            Vec3::new(1.0, -2.0, 3.0)
+        );
+        assert_eq!(
+            convert(Handedness::Right, UpAxis::Y, v),
+            Vec3::new(1.0, 3.0, 2.0)
+        );
+
+        // only the right-handed ones mirror
+        for (handedness, mirrored) in [(Handedness::Left, false), (Handedness::Right, true)] {
+            for up_axis in [UpAxis::Y, UpAxis::Z] {
+                let system = CoordinateSystem {
+                    handedness,
+                    up_axis,
+                };
+                assert_eq!(system.to_world().determinant() < 0.0, mirrored);
+                assert_eq!(system.to_world().transform_vector3(system.up()), Vec3::Z);
+            }
+        }
+    }
+
+    #[test]
+    fn convert_view() {
+        // an L in a right-handed y up scene, seen from +z looking at the origin the way such a tool would show it:
+        // the long arm goes up the image and the short one to the right
+        let system = CoordinateSystem {
+            handedness: Handedness::Right,
+            up_axis: UpAxis::Y,
+        };
+        let (corner, up_arm, right_arm) = (Vec3::ZERO, Vec3::new(0.0, 2.0, 0.0), Vec3::X);
+        let eye = Vec3::new(0.0, 0.0, 5.0);
+
+        let view = system.convert_view(Mat4::look_at_lh(eye, corner, Vec3::Y));
+        let to_world = system.to_world();
+        let camera_space = |v: Vec3| view.transform_point3(to_world.transform_point3(v));
+
+        // camera space is x right, y up and z forward
+        assert!(camera_space(right_arm).x > 0.0);
+        assert!(camera_space(up_arm).y > 0.0);
+        assert!(camera_space(corner).z > 0.0);
+        assert!(view.determinant() > 0.0);
+
+        // same as looking at the converted points
+        let converted = Mat4::look_at_lh(
+            to_world.transform_point3(eye),
+            to_world.transform_point3(corner),
+            to_world.transform_vector3(Vec3::Y),
+        );
+        assert!(view.abs_diff_eq(converted, 1e-5));
+
+        // nothing changes for the default
+        let view = Mat4::look_at_lh(eye, corner, Vec3::Y);
+        assert_eq!(CoordinateSystem::default().convert_view(view), view);
+    }
+}
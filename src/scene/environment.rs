@@ -0,0 +1,241 @@
+use std::io::BufRead;
+
+use anyhow::{bail, Result};
+use glam::Vec3;
+
+// rec. 709 luminance, which is what the sampling distribution follows
+const LUMINANCE: Vec3 = Vec3::new(0.2126, 0.7152, 0.0722);
+
+/// An equirectangular image of the light arriving from every direction, with z up
+///
+/// The top row is straight up (+z) and the bottom row straight down, and u goes around z starting from -x.
+/// This has to match environment_uv in environment.glsl.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    pub width: u32,
+    pub height: u32,
+    // row by row, starting at the top
+    pub pixels: Vec<Vec3>,
+}
+
+impl EnvironmentMap {
+    /// Reads a Radiance .hdr (RGBE) image, either flat or with the usual per-scanline run length encoding
+    ///
+    /// Only the standard `-Y height +X width` orientation is supported.
+    pub fn from_hdr(mut reader: impl BufRead) -> Result<Self> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("#?") {
+            bail!("not a radiance hdr file");
+        }
+
+        // header lines until an empty one, only the format matters
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                bail!("hdr header ends without a resolution");
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    bail!("unsupported hdr format {format:?}, expected 32-bit_rle_rgbe");
+                }
+            }
+        }
+
+        line.clear();
+        reader.read_line(&mut line)?;
+        let (height, width) = match line.split_ascii_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (height.parse::<u32>()?, width.parse::<u32>()?),
+            _ => bail!("unsupported hdr resolution line {:?}", line.trim_end()),
+        };
+        if width == 0 || height == 0 {
+            bail!("hdr image is empty");
+        }
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        let mut scanline = vec![[0u8; 4]; width as usize];
+        for _ in 0..height {
+            Self::read_scanline(&mut reader, &mut scanline)?;
+            pixels.extend(scanline.iter().map(|&rgbe| Self::rgbe_to_vec3(rgbe)));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    fn read_scanline(reader: &mut impl BufRead, scanline: &mut [[u8; 4]]) -> Result<()> {
+        let mut first = [0u8; 4];
+        reader.read_exact(&mut first)?;
+
+        // run length encoded scanlines start with 2 2 and the width, anything else is a flat pixel
+        let width = scanline.len();
+        let is_rle =
+            (8..0x8000).contains(&width) && first[0] == 2 && first[1] == 2 && first[2] & 0x80 == 0;
+        if !is_rle {
+            scanline[0] = first;
+            for pixel in &mut scanline[1..] {
+                reader.read_exact(pixel)?;
+            }
+            return Ok(());
+        }
+
+        if ((first[2] as usize) << 8 | first[3] as usize) != width {
+            bail!("hdr scanline width doesn't match the image width");
+        }
+
+        // each channel is encoded separately, as runs (count > 128) or literal bytes
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let mut count = [0u8; 1];
+                reader.read_exact(&mut count)?;
+                let (count, is_run) = match count[0] {
+                    c if c > 128 => (c as usize - 128, true),
+                    c => (c as usize, false),
+                };
+                if count == 0 || x + count > width {
+                    bail!("hdr scanline run doesn't fit the image width");
+                }
+
+                // a run repeats one byte, literal bytes each get their own
+                let mut value = [0u8; 1];
+                if is_run {
+                    reader.read_exact(&mut value)?;
+                }
+                for pixel in &mut scanline[x..x + count] {
+                    if !is_run {
+                        reader.read_exact(&mut value)?;
+                    }
+                    pixel[channel] = value[0];
+                }
+                x += count;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Importance sampling distribution over the pixels, proportional to luminance times the solid angle
+    ///
+    /// Returns the marginal cdf over rows (height + 1 entries), then one cdf over the pixels of each row (width + 1
+    /// entries each, row by row). Every cdf starts at 0 and ends at 1. Rows that are completely black get a
+    /// uniform cdf, as does the marginal if the whole image is.
+    pub fn sampling_cdfs(&self) -> (Vec<f32>, Vec<f32>) {
+        let (width, height) = (self.width as usize, self.height as usize);
+
+        let mut conditional = Vec::with_capacity(height * (width + 1));
+        let mut row_weights = Vec::with_capacity(height);
+        for (y, row) in self.pixels.chunks_exact(width).enumerate() {
+            // rows near the poles cover less of the sphere
+            let sin_theta = (std::f32::consts::PI * (y as f32 + 0.5) / height as f32).sin();
+            let weights: Vec<f32> = row
+                .iter()
+                .map(|pixel| pixel.dot(LUMINANCE) * sin_theta)
+                .collect();
+            row_weights.push(weights.iter().sum());
+            conditional.extend(Self::cdf(&weights));
+        }
+
+        (Self::cdf(&row_weights), conditional)
+    }
+
+    fn cdf(weights: &[f32]) -> Vec<f32> {
+        let total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            let n = weights.len() as f32;
+            return (0..=weights.len()).map(|i| i as f32 / n).collect();
+        }
+
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        let mut sum = 0.0;
+        cdf.push(0.0);
+        for weight in weights {
+            sum += weight;
+            cdf.push(sum / total);
+        }
+        // don't leave rounding error at the end, the shader relies on xi < cdf[last]
+        *cdf.last_mut().unwrap() = 1.0;
+
+        cdf
+    }
+
+    fn rgbe_to_vec3([r, g, b, e]: [u8; 4]) -> Vec3 {
+        if e == 0 {
+            return Vec3::ZERO;
+        }
+        let scale = 2f32.powi(e as i32 - (128 + 8));
+        Vec3::new(r as f32 + 0.5, g as f32 + 0.5, b as f32 + 0.5) * scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hdr_file(width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+        let mut file =
+            format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {height} +X {width}\n").into_bytes();
+        file.extend_from_slice(data);
+        file
+    }
+
+    #[test]
+    fn hdr_flat_and_rle() {
+        // 2x1, flat: 1.0 (128 at exponent 129) and black
+        let flat = EnvironmentMap::from_hdr(&hdr_file(2, 1, &[128, 128, 128, 129, 0, 0, 0, 0])[..])
+            .unwrap();
+        assert_eq!((flat.width, flat.height), (2, 1));
+        assert!((flat.pixels[0] - Vec3::splat(1.0)).abs().max_element() < 0.01);
+        assert_eq!(flat.pixels[1], Vec3::ZERO);
+
+        // 8x1, run length encoded: red is a run, green literals, blue and exponent runs
+        let mut data = vec![2, 2, 0, 8];
+        data.extend_from_slice(&[128 + 8, 64]);
+        data.extend_from_slice(&[8, 0, 16, 32, 48, 64, 80, 96, 112]);
+        data.extend_from_slice(&[128 + 8, 0]);
+        data.extend_from_slice(&[128 + 8, 129]);
+        let rle = EnvironmentMap::from_hdr(&hdr_file(8, 1, &data)[..]).unwrap();
+        assert_eq!(rle.pixels.len(), 8);
+        for (x, pixel) in rle.pixels.iter().enumerate() {
+            assert!((pixel.x - 64.5 / 128.0).abs() < 1e-6);
+            assert!((pixel.y - (16 * x) as f32 / 128.0 - 0.5 / 128.0).abs() < 1e-6);
+        }
+
+        assert!(EnvironmentMap::from_hdr(&b"P6\n"[..]).is_err());
+        assert!(EnvironmentMap::from_hdr(&hdr_file(2, 2, &[0; 8])[..]).is_err());
+        // run longer than the scanline
+        let data = [2, 2, 0, 8, 128 + 9, 0];
+        assert!(EnvironmentMap::from_hdr(&hdr_file(8, 1, &data)[..]).is_err());
+    }
+
+    #[test]
+    fn sampling_cdfs() {
+        let map = EnvironmentMap {
+            width: 2,
+            height: 2,
+            pixels: vec![Vec3::ONE, Vec3::ZERO, Vec3::ZERO, Vec3::ZERO],
+        };
+        let (marginal, conditional) = map.sampling_cdfs();
+        assert_eq!(marginal, [0.0, 1.0, 1.0]);
+        // black rows fall back to uniform
+        assert_eq!(conditional, [0.0, 1.0, 1.0, 0.0, 0.5, 1.0]);
+
+        let map = EnvironmentMap {
+            width: 1,
+            height: 3,
+            pixels: vec![Vec3::ONE; 3],
+        };
+        let (marginal, _) = map.sampling_cdfs();
+        // the middle row is on the equator and covers the most solid angle
+        assert!(marginal[2] - marginal[1] > marginal[1] - marginal[0]);
+        assert!((marginal[1] - (1.0 - marginal[2])).abs() < 1e-6);
+        assert_eq!(marginal[3], 1.0);
+    }
+}
@@ -0,0 +1,261 @@
+// casts rays against a MeshScene on the cpu, for when there's no gpu to trace on (see the preview renderer)
+//
+// keeps the triangles of every mesh on the cpu and intersects them directly, skipping instances whose bounds
+// the ray misses. procedural geometry isn't included
+
+use glam::{Mat4, Vec3};
+
+use crate::scene::scenes::mesh::{Aabb, MeshScene};
+
+// matches VISIBILITY_CAMERA in raygen_common.glsl, objects hidden from the camera can't be hit either
+const VISIBILITY_CAMERA: u8 = 0x01;
+
+/// What [`Intersector::intersect`] found
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    /// Index into `MeshScene::objects`
+    pub object: usize,
+    /// World space position of the hit
+    pub position: Vec3,
+    /// World space normal of the triangle that was hit, facing back along the ray
+    pub normal: Vec3,
+}
+
+struct Instance {
+    object: usize,
+    mesh_i: usize,
+    world_to_object: Mat4,
+    bounds: Aabb,
+}
+
+#[derive(Default)]
+pub struct Intersector {
+    // triangles of each mesh in object space
+    meshes: Vec<Vec<[Vec3; 3]>>,
+    instances: Vec<Instance>,
+}
+
+impl Intersector {
+    /// Objects are taken at `transform`, like [`MeshScene::world_triangles`]
+    pub fn new(scene: &MeshScene) -> Self {
+        let meshes = scene
+            .meshes
+            .iter()
+            .map(|model| {
+                let mesh = &model.mesh;
+                mesh.indices
+                    .chunks_exact(3)
+                    .map(|triangle| {
+                        [0, 1, 2].map(|corner| {
+                            let i = 3 * triangle[corner] as usize;
+                            Vec3::from_slice(&mesh.positions[i..i + 3])
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let instances = scene
+            .objects
+            .iter()
+            .enumerate()
+            .filter(|(_, object)| object.visibility_mask & VISIBILITY_CAMERA != 0)
+            .map(|(i, object)| Instance {
+                object: i,
+                mesh_i: object.mesh_i,
+                world_to_object: object.transform.inverse(),
+                bounds: scene.object_aabb(object),
+            })
+            .collect();
+
+        Self { meshes, instances }
+    }
+
+    /// One object per mesh, each made of object space triangles at its transform
+    #[cfg(test)]
+    pub fn from_objects(objects: Vec<(Vec<[Vec3; 3]>, Mat4)>) -> Self {
+        let mut meshes = Vec::with_capacity(objects.len());
+        let mut instances = Vec::with_capacity(objects.len());
+        for (i, (triangles, transform)) in objects.into_iter().enumerate() {
+            let positions: Vec<f32> = triangles
+                .iter()
+                .flatten()
+                .flat_map(|x| x.to_array())
+                .collect();
+            instances.push(Instance {
+                object: i,
+                mesh_i: i,
+                world_to_object: transform.inverse(),
+                bounds: Aabb::from_positions(&positions).transformed(transform),
+            });
+            meshes.push(triangles);
+        }
+        Self { meshes, instances }
+    }
+
+    /// The closest triangle along the ray, whichever way it faces
+    pub fn intersect(&self, origin: Vec3, direction: Vec3) -> Option<Hit> {
+        let mut closest: Option<(f32, &Instance, Vec3)> = None;
+        for instance in &self.instances {
+            let Some(t_bounds) = Self::intersect_aabb(&instance.bounds, origin, direction) else {
+                continue;
+            };
+            if closest.is_some_and(|(t, _, _)| t < t_bounds) {
+                continue;
+            }
+
+            // t is the same along the object space ray, since the direction isn't normalized
+            let object_origin = instance.world_to_object.transform_point3(origin);
+            let object_direction = instance.world_to_object.transform_vector3(direction);
+            for triangle in &self.meshes[instance.mesh_i] {
+                let Some(t) = Self::intersect_triangle(triangle, object_origin, object_direction)
+                else {
+                    continue;
+                };
+                if closest.is_none_or(|(closest_t, _, _)| t < closest_t) {
+                    let [a, b, c] = triangle;
+                    closest = Some((t, instance, (*b - *a).cross(*c - *a)));
+                }
+            }
+        }
+
+        closest.map(|(t, instance, object_normal)| {
+            // normals go through the inverse transpose
+            let normal = instance
+                .world_to_object
+                .transpose()
+                .transform_vector3(object_normal)
+                .normalize();
+            Hit {
+                object: instance.object,
+                position: origin + t * direction,
+                normal: if normal.dot(direction) > 0.0 {
+                    -normal
+                } else {
+                    normal
+                },
+            }
+        })
+    }
+
+    // distance along the ray to where it enters the box (0 if it starts inside)
+    fn intersect_aabb(aabb: &Aabb, origin: Vec3, direction: Vec3) -> Option<f32> {
+        let t0 = (aabb.min - origin) / direction;
+        let t1 = (aabb.max - origin) / direction;
+        let t_near = t0.min(t1).max_element().max(0.0);
+        let t_far = t0.max(t1).min_element();
+
+        (t_near <= t_far).then_some(t_near)
+    }
+
+    // möller-trumbore, two-sided
+    fn intersect_triangle([a, b, c]: &[Vec3; 3], origin: Vec3, direction: Vec3) -> Option<f32> {
+        let ab = *b - *a;
+        let ac = *c - *a;
+        let p = direction.cross(ac);
+        let det = ab.dot(p);
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let ao = origin - *a;
+        let u = ao.dot(p) / det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = ao.cross(ab);
+        let v = direction.dot(q) / det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = ac.dot(q) / det;
+        (t > 0.0).then_some(t)
+    }
+}
+
+/// World space ray through `ndc` (in [-1, 1], y down like the image), the same way camera_ray in
+/// raygen_common.glsl makes it without depth of field
+pub fn camera_ray(
+    view_inverse: Mat4,
+    projection_inverse: Mat4,
+    (x, y): (f32, f32),
+) -> (Vec3, Vec3) {
+    let near = projection_inverse.project_point3(Vec3::new(x, y, 0.0));
+    let far = projection_inverse.project_point3(Vec3::new(x, y, 1.0));
+    let direction = far - near;
+    let origin = near - direction * (near.z / direction.z);
+
+    (
+        view_inverse.transform_point3(origin),
+        view_inverse.transform_vector3(direction).normalize(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Camera, Lens, Projection};
+
+    fn quad_at(x: f32) -> Vec<[Vec3; 3]> {
+        let corner = |y, z| Vec3::new(x, y, z);
+        vec![
+            [corner(-1.0, -1.0), corner(1.0, -1.0), corner(1.0, 1.0)],
+            [corner(-1.0, -1.0), corner(1.0, 1.0), corner(-1.0, 1.0)],
+        ]
+    }
+
+    #[test]
+    fn intersect_closest() {
+        let instance = |object, mesh_i, transform: Mat4| Instance {
+            object,
+            mesh_i,
+            world_to_object: transform.inverse(),
+            bounds: Aabb {
+                min: Vec3::new(0.0, -1.0, -1.0),
+                max: Vec3::new(0.0, 1.0, 1.0),
+            }
+            .transformed(transform),
+        };
+        let scene = Intersector {
+            meshes: vec![quad_at(0.0)],
+            instances: vec![
+                instance(0, 0, Mat4::from_translation(Vec3::new(5.0, 0.0, 0.0))),
+                instance(1, 0, Mat4::from_translation(Vec3::new(3.0, 0.0, 0.0))),
+                instance(2, 0, Mat4::from_translation(Vec3::new(-3.0, 0.0, 0.0))),
+            ],
+        };
+
+        let hit = scene.intersect(Vec3::ZERO, Vec3::X).unwrap();
+        assert_eq!(hit.object, 1);
+        assert!((hit.position - Vec3::new(3.0, 0.0, 0.0)).length() < 1e-5);
+        assert!((hit.normal - Vec3::NEG_X).length() < 1e-5);
+        // from behind works too
+        let hit = scene
+            .intersect(Vec3::new(10.0, 0.5, 0.5), Vec3::NEG_X)
+            .unwrap();
+        assert_eq!(hit.object, 0);
+        assert!((hit.normal - Vec3::X).length() < 1e-5);
+        assert_eq!(scene.intersect(Vec3::ZERO, Vec3::Y), None);
+        assert_eq!(scene.intersect(Vec3::new(0.0, 2.0, 0.0), Vec3::X), None);
+    }
+
+    #[test]
+    fn camera_ray_through_ndc() {
+        let camera = Camera::new(
+            Mat4::look_at_lh(Vec3::ZERO, Vec3::X, Vec3::Z),
+            Projection::Perspective { fov: 90.0 },
+            Lens::default(),
+        );
+        let ray = |ndc| camera_ray(camera.view().inverse(), camera.projection().inverse(), ndc);
+
+        let (origin, direction) = ray((0.0, 0.0));
+        assert!(origin.length() < 1e-5);
+        assert!((direction - Vec3::X).length() < 1e-5);
+
+        // the top of the image is up, and the view is left-handed so the right is +y
+        let (_, direction) = ray((1.0, -1.0));
+        assert!((direction - Vec3::ONE.normalize()).length() < 1e-4);
+    }
+}
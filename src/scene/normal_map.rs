@@ -0,0 +1,136 @@
+use std::io::Read;
+
+use anyhow::{bail, Result};
+
+/// Marks a brdf without a normal map in [`NormalMap::buffer`], matches NO_NORMAL_MAP in hit_common.glsl
+pub const NO_NORMAL_MAP: u32 = u32::MAX;
+
+/// A tangent space normal map, with red along the tangent (u), green along the bitangent (v) and blue out of
+/// the surface, each mapped from [-1, 1] to [0, 255]
+///
+/// Green goes up the image like obj texture coordinates do, which is the OpenGL convention. Shaders read it
+/// at the nearest texel without any filtering or mipmaps, so maps much bigger or smaller on screen than their
+/// resolution alias or look blocky.
+#[derive(Debug, Clone)]
+pub struct NormalMap {
+    pub width: u32,
+    pub height: u32,
+    // row by row, starting at the top
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl NormalMap {
+    /// Reads an rgb or rgba png of any bit depth, alpha is ignored
+    pub fn from_png(reader: impl Read) -> Result<Self> {
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+
+        let channels = match info.color_type {
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            color_type => bail!("normal map must be rgb or rgba, not {color_type:?}"),
+        };
+        let pixels = data[..info.buffer_size()]
+            .chunks_exact(channels)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect();
+
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    /// The normal maps of every brdf as the shaders read them
+    ///
+    /// Starts with one entry per brdf, the offset of its map in the buffer or [`NO_NORMAL_MAP`]. Each map is
+    /// its width, its height and then its pixels packed as rgba8 with alpha 255.
+    pub fn buffer(maps: &[Option<NormalMap>]) -> Vec<u32> {
+        let mut data = vec![NO_NORMAL_MAP; maps.len()];
+        for (i, map) in maps.iter().enumerate() {
+            let Some(map) = map else {
+                continue;
+            };
+
+            data[i] = data.len() as u32;
+            data.extend_from_slice(&[map.width, map.height]);
+            data.extend(
+                map.pixels
+                    .iter()
+                    .map(|&[r, g, b]| u32::from_le_bytes([r, g, b, 255])),
+            );
+        }
+
+        // shaders can't bind an empty buffer
+        if data.is_empty() {
+            data.push(NO_NORMAL_MAP);
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_file(color_type: png::ColorType, (width, height): (u32, u32), data: &[u8]) -> Vec<u8> {
+        let mut file = Vec::new();
+        let mut encoder = png::Encoder::new(&mut file, width, height);
+        encoder.set_color(color_type);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(data)
+            .unwrap();
+        file
+    }
+
+    #[test]
+    fn normal_map_png() {
+        let file = png_file(
+            png::ColorType::Rgba,
+            (2, 1),
+            &[128, 128, 255, 0, 255, 0, 0, 7],
+        );
+        let map = NormalMap::from_png(&file[..]).unwrap();
+        assert_eq!((map.width, map.height), (2, 1));
+        assert_eq!(map.pixels, [[128, 128, 255], [255, 0, 0]]);
+
+        let file = png_file(png::ColorType::Rgb, (1, 2), &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(
+            NormalMap::from_png(&file[..]).unwrap().pixels,
+            [[1, 2, 3], [4, 5, 6]]
+        );
+
+        let file = png_file(png::ColorType::Grayscale, (1, 1), &[128]);
+        assert!(NormalMap::from_png(&file[..]).is_err());
+        assert!(NormalMap::from_png(&b"not a png"[..]).is_err());
+    }
+
+    #[test]
+    fn normal_map_buffer() {
+        let map = NormalMap {
+            width: 2,
+            height: 1,
+            pixels: vec![[128, 128, 255], [1, 2, 3]],
+        };
+        assert_eq!(
+            NormalMap::buffer(&[None, Some(map), None]),
+            [
+                NO_NORMAL_MAP,
+                3,
+                NO_NORMAL_MAP,
+                2,
+                1,
+                0xffff8080,
+                0xff030201
+            ]
+        );
+        assert_eq!(NormalMap::buffer(&[]), [NO_NORMAL_MAP]);
+    }
+}
@@ -1,11 +1,12 @@
 use std::{
     alloc::{self, Layout},
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     f32::consts::PI,
     ffi::{CStr, CString},
     fs::File,
-    io::Read,
+    io::{BufReader, Read},
     iter::{self, Peekable},
+    ops::Range,
     path::Path,
     ptr::NonNull,
 };
@@ -14,63 +15,159 @@ use anyhow::{anyhow, bail, Result};
 use ash::{vk, Device};
 use bytemuck::BoxBytes;
 use glam::{Mat4, Vec2, Vec3, Vec4};
-use log::warn;
-use tobj::Model;
+use log::{debug, warn};
+use rspirv::spirv::ExecutionModel;
+use tobj::{Mesh, Model};
 use toml::{map::Map, Table, Value};
 
 use crate::{
-    camera::Camera,
+    camera::{Camera, Lens, Projection},
     scene::{
+        asset_cache::AssetCache,
+        coordinates::{CoordinateSystem, Handedness, UpAxis},
+        environment::EnvironmentMap,
+        normal_map::NormalMap,
+        shader_archive::ShaderArchive,
+        spirv::{self, Field, Scalar},
+        transform,
         type_lexer::{Token, TokenIter},
-        Scene,
+        Scene, ShaderRequirements,
     },
+    window::WindowData,
 };
 
+mod render_options;
+
+pub use render_options::{RenderOptions, RenderRegion};
+
 const MESHES_DIR: &str = "resources/meshes";
+const ENVIRONMENTS_DIR: &str = "resources/environments";
+const TEXTURES_DIR: &str = "resources/textures";
 const SPIRV_DIR: &str = "resources/shaders/spv/";
 const SPIRV_EXTENSION: &str = ".spv";
 const SPIRV_MAGIC: u32 = 0x07230203;
 
+// must match BRDF_PARAMS_BINDING in hit_common.glsl
+const BRDF_PARAMS_BINDING: u32 = 6;
+
+// returns render.clear_color, used when global_shaders.miss isn't set
+const DEFAULT_MISS_SHADER: &str = "clear_color.rmiss";
+// looks up the environment map, used instead when the scene has one
+const ENVIRONMENT_MISS_SHADER: &str = "environment.rmiss";
+
+// bundled shaders for [[sphere]] entries
+const SPHERE_INTERSECTION_SHADER: &str = "sphere.rint";
+const SPHERE_HIT_SHADER: &str = "procedural_diffuse.rchit";
+const SPHERE_DEFAULT_ALBEDO: Vec3 = Vec3::new(0.8, 0.8, 0.8);
+
+// bundled brdf without fields that objects can use without a [[brdf]] for it, white and lit by the lights
+// only added to the hit shaders when an object uses it
+const FLAT_SHADED_BRDF: &str = "flat_shaded";
+const FLAT_SHADED_SHADER: &str = "flat_shaded.rchit";
+
+// visibility_mask bits are the ray kinds in raygen_common.glsl:
+// 1 = camera rays, 2 = shadow rays, 4 = indirect rays (bounces, reflections, refractions)
+const VISIBILITY_ALL: u8 = 0xff;
+
 #[derive(Debug)]
 pub struct MeshScene {
     pub camera: Camera,
     pub lights: Vec<Light>,
+    // which of lights each [[light]] entry became, in file order. an area light is a light per triangle
+    pub light_entries: Vec<Range<usize>>,
+    // lights the scene from every direction, and is what rays that hit nothing see
+    pub environment: Option<EnvironmentMap>,
     pub objects: Vec<Object>,
     pub meshes: Vec<Model>,
+    // object space bounds of each of meshes, computed once at load
+    pub mesh_aabbs: Vec<Aabb>,
+    // per-vertex tangents of each of meshes, xyz and the sign of the bitangent in w
+    // all zero for meshes without texture coordinates, which can't be normal mapped
+    pub mesh_tangents: Vec<Vec<[f32; 4]>>,
 
     pub raygen_shader: Shader,
     pub miss_shader: Shader,
     pub hit_shaders: Vec<Shader>,
+    // the normal map of each of hit_shaders, None for emitter_hit and brdfs without one
+    pub normal_maps: Vec<Option<NormalMap>>,
 
     pub procedural_geometries: Vec<ProceduralGeometry>,
     pub procedural_objects: Vec<ProceduralObject>,
 
     pub brdf_buf: Vec<u8>,
     pub offset_buf: Vec<u32>,
+    // the custom index of every instance, in tlas order
+    pub instance_id_buf: Vec<u32>,
+
+    pub render_options: RenderOptions,
+    pub window_options: WindowOptions,
 }
 
+#[derive(Debug, Clone)]
+pub struct WindowOptions {
+    // confine and hide the cursor, and use mouse motion to look around
+    pub grab_cursor: bool,
+    // swapchain images to ask for, clamped to what the surface supports
+    pub image_count: Option<u32>,
+    // size the window opens at, only the first scene's is used
+    pub width: u32,
+    pub height: u32,
+    // prefer an hdr surface format (see HDR_SURFACE_FORMATS), only the first scene's is used
+    pub hdr: bool,
+}
+
+impl Default for WindowOptions {
+    fn default() -> Self {
+        WindowOptions {
+            grab_cursor: true,
+            image_count: None,
+            width: WindowData::DEFAULT_WIDTH,
+            height: WindowData::DEFAULT_HEIGHT,
+            hdr: false,
+        }
+    }
+}
+
+// samples is how many times as often sample_light picks the light as one with the default of 1
 #[derive(Debug, Clone)]
 pub enum Light {
     Point {
         color: Vec3,
         position: Vec3,
+        samples: u32,
     },
     Triangle {
         color: Vec3,
         vertices: [Vec3; 3],
+        samples: u32,
     },
     Directional {
         color: Vec3,
         position: Vec3,
         direction: Vec3,
+        samples: u32,
+        // beam radius, used when sampling the light (and so for how soft shadows get)
         radius: f32,
+        // radius of the disk that camera and bounce rays can hit, defaults to radius
+        geometry_scale: f32,
     },
 }
 
+impl Light {
+    pub fn samples(&self) -> u32 {
+        match self {
+            Light::Point { samples, .. }
+            | Light::Triangle { samples, .. }
+            | Light::Directional { samples, .. } => *samples,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Shader {
     Uncompiled(CString, Box<[u32]>),
-    Compiled(CString, vk::ShaderModule),
+    // keeps the code around for reflection after the module is created
+    Compiled(CString, Box<[u32]>, vk::ShaderModule),
 }
 
 #[derive(Debug, Clone)]
@@ -83,15 +180,143 @@ pub struct Object {
     pub brdf_params: Vec<u8>,
 
     // this is pretty much just the base of the mesh in the list of all vertices
+    // EXCEPT for area lights (brdf_i 0, the emitter hit group): there it's the index of the mesh's first
+    // Light::Triangle in MeshScene::lights, and emitter.rchit adds the primitive id to find the hit triangle
+    // it ends up as the instance custom index either way, so shaders have to know which one they got
     pub vertex_index: u32,
+
+    // user-defined id (e.g. for picking or material lookup), unrelated to vertex_index
+    // shaders read it from the instance ids buffer at gl_InstanceID
+    pub custom_index: u32,
+
+    // where the object was when the shutter opened, same as transform if it doesn't move
+    pub previous_transform: Mat4,
+
+    // which kinds of rays can hit this object (instance mask), see VISIBILITY_ALL
+    pub visibility_mask: u8,
+
+    pub cull: Cull,
+    pub front_face: FrontFace,
+}
+
+/// Which side of an object's triangles rays skip, the front being whichever side `front_face` says
+///
+/// Every ray is traced with back face culling, which the instance flags either disable or flip to the front.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Cull {
+    // two-sided
+    #[default]
+    None,
+    // for closed meshes, rays only ever need to hit the outside
+    Back,
+    Front,
+}
+
+/// Which winding of a mesh's triangles is their front, looking at them in the mesh's own space
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FrontFace {
+    // what obj files use
+    #[default]
+    CounterClockwise,
+    Clockwise,
+}
+
+impl Object {
+    /// Flags for the object's instance, which set up its culling
+    ///
+    /// Vulkan decides which way a triangle faces in object space, so the transform doesn't change anything: a
+    /// mirrored transform (negative determinant) winds the triangles the other way around in world space, but
+    /// the front stays the side `front_face` picked in the mesh, and the outside of a closed mesh stays its
+    /// outside. Only meshes exported with the other winding need `front_face`.
+    pub fn instance_flags(&self) -> vk::GeometryInstanceFlagsKHR {
+        // culling the front of clockwise triangles is culling the back of counter-clockwise ones
+        let flip = (self.cull == Cull::Front) != (self.front_face == FrontFace::Clockwise);
+        match self.cull {
+            Cull::None => vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE,
+            _ if flip => vk::GeometryInstanceFlagsKHR::TRIANGLE_FLIP_FACING,
+            _ => vk::GeometryInstanceFlagsKHR::empty(),
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.previous_transform != self.transform
+    }
+
+    /// Blends between `previous_transform` (time 0) and `transform` (time 1)
+    pub fn transform_at(&self, time: f32) -> Mat4 {
+        if !self.is_moving() {
+            return self.transform;
+        }
+
+        let (prev_scale, prev_rotation, prev_translation) =
+            self.previous_transform.to_scale_rotation_translation();
+        let (scale, rotation, translation) = self.transform.to_scale_rotation_translation();
+
+        Mat4::from_scale_rotation_translation(
+            prev_scale.lerp(scale, time),
+            prev_rotation.slerp(rotation, time),
+            prev_translation.lerp(translation, time),
+        )
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Aabb {
     pub min: Vec3,
     pub max: Vec3,
 }
 
+impl Aabb {
+    /// Contains nothing, min is +inf and max is -inf so that it disappears in a union
+    pub const EMPTY: Aabb = Aabb {
+        min: Vec3::INFINITY,
+        max: Vec3::NEG_INFINITY,
+    };
+
+    /// Bounds of flat xyz positions, like tobj's `Mesh::positions`
+    pub fn from_positions(positions: &[f32]) -> Self {
+        positions
+            .chunks_exact(3)
+            .map(Vec3::from_slice)
+            .fold(Self::EMPTY, |aabb, point| Aabb {
+                min: aabb.min.min(point),
+                max: aabb.max.max(point),
+            })
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// The box around all 8 transformed corners, so it can be bigger than the tightest box after rotations
+    pub fn transformed(&self, transform: Mat4) -> Aabb {
+        if self.min.cmpgt(self.max).any() {
+            return Self::EMPTY;
+        }
+
+        (0..8)
+            .map(|corner| {
+                let select =
+                    |bit: u32, min: f32, max: f32| if corner & bit == 0 { min } else { max };
+                Vec3::new(
+                    select(1, self.min.x, self.max.x),
+                    select(2, self.min.y, self.max.y),
+                    select(4, self.min.z, self.max.z),
+                )
+            })
+            .fold(Self::EMPTY, |aabb, corner| {
+                let corner = transform.transform_point3(corner);
+                Aabb {
+                    min: aabb.min.min(corner),
+                    max: aabb.max.max(corner),
+                }
+            })
+    }
+}
+
 #[derive(Debug)]
 pub struct ProceduralGeometry {
     pub aabbs: Vec<Aabb>,
@@ -104,6 +329,19 @@ pub struct ProceduralObject {
     pub transform: Mat4,
     pub geometry_index: usize,
     pub custom_index: u32,
+    // per-object fields for the closest hit shader, packed the same way as object brdf params
+    pub params: Vec<u8>,
+    pub visibility_mask: u8,
+}
+
+impl ProceduralObject {
+    /// Index of this object's hit group in the SBT hit region
+    ///
+    /// Triangle hit groups come first, one per hit shader (so mesh objects use `brdf_i`), followed by one
+    /// procedural hit group per geometry in `procedural_geometries` order.
+    pub fn hit_record_index(&self, triangle_hit_group_count: usize) -> usize {
+        triangle_hit_group_count + self.geometry_index
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -121,21 +359,135 @@ struct Shaders {
     raygen: Shader,
     miss: Shader,
     rchit: Vec<Shader>,
+    // one for each of rchit
+    normal_maps: Vec<Option<NormalMap>>,
+}
+
+/// What the raygen shader outputs in place of the shaded image, for debugging geometry
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum DebugMode {
+    #[default]
+    Off = 0,
+    // world space normal of the first hit, mapped from [-1, 1] to rgb
+    Normals = 1,
+    // triangle edges from the hit barycentrics, procedural geometry shows up without edges
+    Wireframe = 2,
+}
+
+impl DebugMode {
+    pub fn next(self) -> Self {
+        match self {
+            DebugMode::Off => DebugMode::Normals,
+            DebugMode::Normals => DebugMode::Wireframe,
+            DebugMode::Wireframe => DebugMode::Off,
+        }
+    }
 }
 
+/// Changes handed to the renderer along with each frame
+///
+/// The renderer keeps accumulating samples into the same image for as long as it gets no updates
+/// that change the picture, so an empty update list just means "render another sample". Anything
+/// that changes what's on screen (`NewView`, `NewSize`, `DebugMode`, `ToggleLight`) restarts accumulation, and
+/// `Invalidate` restarts it without changing anything else.
+///
+/// Restarting accumulation also restarts the frame counter that random offsets are derived from, so
+/// with a fixed `render.seed` the renderer replays exactly the same sample sequence afterwards.
 #[derive(Debug)]
 pub enum MeshSceneUpdate {
     NewView(Mat4),
     NewSize((u32, u32, Mat4)),
+    DebugMode(DebugMode),
+    // index into MeshScene::light_entries, so all of an area light's triangles go together
+    // every light starts out enabled when the scene is ingested
+    ToggleLight(usize),
+    Invalidate,
 }
 
 impl Scene for MeshScene {
     type Update = MeshSceneUpdate;
+
+    fn shader_requirements(&self) -> ShaderRequirements {
+        let procedural_shaders = self
+            .procedural_geometries
+            .iter()
+            .flat_map(|x| [&x.intersection_shader, &x.closest_hit_shader]);
+        let models: Vec<_> = [&self.raygen_shader, &self.miss_shader]
+            .into_iter()
+            .chain(&self.hit_shaders)
+            .chain(procedural_shaders)
+            // invalid spir-v fails when the shader module is created, that's the better place to report it
+            .flat_map(|shader| spirv::execution_models(shader.code()).unwrap_or_default())
+            .collect();
+
+        // brdfs only have a slot for a closest hit shader, so an any-hit shader in one would need its own group
+        ShaderRequirements {
+            triangle_hit_groups: self.hit_shaders.len(),
+            procedural_hit_groups: self.procedural_geometries.len(),
+            any_hit: models.contains(&ExecutionModel::AnyHitKHR),
+            callable: models.contains(&ExecutionModel::CallableKHR),
+        }
+    }
 }
 
 impl Shader {
+    /// Reads the compiled shader `file_name` from resources/shaders/spv/
+    pub fn load(file_name: &str, shader_name: &str) -> Result<Self> {
+        let mut spv_name = file_name.to_string();
+        spv_name.push_str(SPIRV_EXTENSION);
+
+        let spv_path = Path::new(SPIRV_DIR).join(spv_name);
+        let code = AssetCache::global().load_spirv(&spv_path, Self::read_spirv)?;
+
+        Ok(Shader::Uncompiled(
+            CString::new(shader_name)?,
+            Box::from(&*code),
+        ))
+    }
+
+    fn read_spirv(spv_path: &Path) -> Result<Box<[u32]>> {
+        let mut spv_file = File::open(spv_path)?;
+        let file_info = spv_file.metadata()?;
+
+        let shader_size = file_info.len();
+        if shader_size == 0 || shader_size % 4 != 0 {
+            bail!("invalid shader size: {shader_size} - must be aligned to 4 bytes and greater than 0");
+        }
+
+        // allocate a buffer that is aligned to u32 since that is required for shader code
+        let layout = Layout::array::<u8>(shader_size as usize)?;
+        let layout = layout.align_to(align_of::<u32>()).unwrap();
+
+        let code = unsafe { alloc::alloc(layout) };
+        if code.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        let mut code = unsafe { BoxBytes::from_raw_parts(NonNull::new_unchecked(code), layout) };
+        spv_file.read_exact(&mut code)?;
+
+        // now that the code has been read in, we can cast as u32
+        // this should be guaranteed to succeed because of the alignment stuff above
+        #[allow(unused_mut)]
+        let mut code: Box<[u32]> = bytemuck::from_box_bytes(code);
+
+        // on big endian systems, we need to swap endianness of every u32
+        // this is because the shader is in little-endian
+        #[cfg(target_endian = "big")]
+        for word in &mut code {
+            *word = (*word).swap_bytes();
+        }
+
+        // assert SPIRV magic number: https://registry.khronos.org/SPIR-V/specs/unified1/SPIRV.html#_magic_number
+        if code[0] != SPIRV_MAGIC {
+            bail!("invalid SPIR-V magic number");
+        }
+
+        Ok(code)
+    }
+
     pub fn module(&self) -> vk::ShaderModule {
-        let Shader::Compiled(_, module) = self else {
+        let Shader::Compiled(_, _, module) = self else {
             panic!("shader is not compiled")
         };
 
@@ -145,7 +497,15 @@ impl Shader {
     fn name(&self) -> &CStr {
         match self {
             Shader::Uncompiled(name, _) => name,
-            Shader::Compiled(name, _) => name,
+            Shader::Compiled(name, _, _) => name,
+        }
+    }
+
+    /// The SPIR-V words the shader was loaded from, whether or not it's been compiled yet
+    pub fn code(&self) -> &[u32] {
+        match self {
+            Shader::Uncompiled(_, code) => code,
+            Shader::Compiled(_, code, _) => code,
         }
     }
 
@@ -159,7 +519,7 @@ impl Shader {
                 };
 
                 let module = unsafe { device.create_shader_module(&create_info, None) }?;
-                Ok(Shader::Compiled(name.clone(), module))
+                Ok(Shader::Compiled(name.clone(), code.clone(), module))
             }
             x @ Shader::Compiled(..) => Ok(x.clone()),
         }
@@ -167,43 +527,279 @@ impl Shader {
 }
 
 impl MeshScene {
+    /// Every object along with the mesh it's an instance of
+    pub fn object_meshes(&self) -> impl Iterator<Item = (&Object, &Model)> {
+        self.objects
+            .iter()
+            .map(|object| (object, &self.meshes[object.mesh_i]))
+    }
+
+    /// The triangles of every object, transformed to world space
+    ///
+    /// Moving objects are taken at `transform`, and procedural geometry isn't included.
+    pub fn world_triangles(&self) -> impl Iterator<Item = [Vec3; 3]> + '_ {
+        self.object_meshes().flat_map(|(object, model)| {
+            let mesh = &model.mesh;
+            mesh.indices.chunks_exact(3).map(move |triangle| {
+                [0, 1, 2].map(|corner| {
+                    let i = 3 * triangle[corner] as usize;
+                    object
+                        .transform
+                        .transform_point3(Vec3::from_slice(&mesh.positions[i..i + 3]))
+                })
+            })
+        })
+    }
+
+    /// Bounds of every object's triangles in world space, tighter than the objects' aabbs for rotated objects
+    pub fn world_bounds(&self) -> Aabb {
+        self.world_triangles()
+            .flatten()
+            .fold(Aabb::EMPTY, |aabb, point| Aabb {
+                min: aabb.min.min(point),
+                max: aabb.max.max(point),
+            })
+    }
+
+    /// World space bounds of an object's mesh, at `transform` for moving objects
+    pub fn object_aabb(&self, object: &Object) -> Aabb {
+        self.mesh_aabbs[object.mesh_i].transformed(object.transform)
+    }
+
+    // some editors start utf-8 files with a byte order mark, which toml doesn't allow
+    fn parse_toml_conf(toml_conf: &str) -> Result<Table> {
+        let toml_conf = toml_conf.strip_prefix('\u{feff}').unwrap_or(toml_conf);
+        Ok(toml_conf.parse()?)
+    }
+
     pub fn load_from(mut reader: impl Read) -> Result<Self> {
         let mut toml_conf = String::new();
         reader.read_to_string(&mut toml_conf)?;
 
-        let conf: Table = toml_conf.parse()?;
+        let conf = Self::parse_toml_conf(&toml_conf)?;
 
         let camera = Self::parse_toml_camera(&conf)?;
+        let render_options = render_options::parse_toml_render_options(&conf)?;
+        let window_options = Self::parse_toml_window_options(&conf)?;
+        let environment = Self::parse_toml_environment(&conf)?;
 
         // load the global shaders
-        let (shaders, shader_type_map) = Self::parse_toml_shaders(&conf)?;
-        let (meshes, mesh_map) = Self::parse_toml_meshes(&conf)?;
+        let default_miss = match environment {
+            Some(_) => ENVIRONMENT_MISS_SHADER,
+            None => DEFAULT_MISS_SHADER,
+        };
+        let archive = Self::parse_toml_shader_archive(&conf)?;
+        let (shaders, shader_type_map) =
+            Self::parse_toml_shaders(&conf, default_miss, archive.as_ref())?;
+        let (meshes, mesh_map) = Self::parse_toml_meshes(&conf, render_options.max_triangles)?;
 
         // load objects before lights
         // this is to give them the correct brdf_params_index
         let mut objects =
             Self::parse_toml_objects(&conf, &mesh_map, &meshes, &shaders.rchit, &shader_type_map)?;
-        let lights = Self::parse_toml_lights(&conf, &mesh_map, &meshes, &mut objects)?;
+        let (mut lights, light_entries) =
+            Self::parse_toml_lights(&conf, &mesh_map, &meshes, &mut objects)?;
+
+        let (procedural_geometries, mut procedural_objects) = Self::parse_procedural_geometries(
+            &conf,
+            &lights,
+            render_options.directional_geometry,
+            archive.as_ref(),
+        )?;
 
-        let (procedural_geometries, procedural_objects) =
-            Self::parse_procedural_geometries(&conf, &lights)?;
+        // the camera was already converted by parse_toml_camera
+        let to_world = Self::parse_toml_coordinates(&conf)?.to_world();
+        Self::convert_coordinates(to_world, &mut objects, &mut lights, &mut procedural_objects);
 
         let (brdf_buf, offset_buf) =
-            Self::get_brdf_params_buffer_and_indices(&objects, &shaders.rchit);
+            Self::get_brdf_params_buffer_and_indices(&objects, &procedural_objects, &shaders.rchit);
+        let instance_id_buf = Self::get_instance_ids(&objects, &procedural_objects);
+
+        let mesh_aabbs = meshes
+            .iter()
+            .map(|model| Aabb::from_positions(&model.mesh.positions))
+            .collect();
+        let mesh_tangents = meshes
+            .iter()
+            .map(|model| Self::generate_tangents(&model.mesh))
+            .collect();
 
-        Ok(Self {
+        let scene = Self {
             camera,
             lights,
+            light_entries,
+            environment,
             objects,
             meshes,
+            mesh_aabbs,
+            mesh_tangents,
             raygen_shader: shaders.raygen,
             miss_shader: shaders.miss,
             hit_shaders: shaders.rchit,
+            normal_maps: shaders.normal_maps,
             procedural_geometries,
             procedural_objects,
             brdf_buf,
             offset_buf,
-        })
+            instance_id_buf,
+            render_options,
+            window_options,
+        };
+
+        let bounds = scene.objects.iter().fold(Aabb::EMPTY, |bounds, object| {
+            bounds.union(&scene.object_aabb(object))
+        });
+        debug!("mesh objects are within {bounds:?}");
+
+        scene.validate()?;
+
+        // not an error, the shaders just keep the mesh's own normals
+        for object in &scene.objects {
+            let model = &scene.meshes[object.mesh_i];
+            if scene.normal_maps[object.brdf_i].is_some() && model.mesh.texcoords.is_empty() {
+                warn!(
+                    "mesh {:?} has no texture coordinates, so the normal map of brdf {:?} does nothing on it",
+                    model.name,
+                    scene.hit_shaders[object.brdf_i].name()
+                );
+            }
+        }
+
+        Ok(scene)
+    }
+
+    /// Checks the cross-references the renderer relies on, so a broken scene fails before anything is uploaded
+    pub fn validate(&self) -> Result<()> {
+        // an instance custom index only has 24 bits
+        const MAX_CUSTOM_INDEX: u32 = (1 << 24) - 1;
+
+        Self::check_emitter_hit(&self.lights, &self.hit_shaders)?;
+        if let Some(entry) = self
+            .light_entries
+            .iter()
+            .find(|entry| entry.end > self.lights.len())
+        {
+            bail!(
+                "light entry {entry:?} is past the {} lights",
+                self.lights.len()
+            );
+        }
+        let has_emitter_hit = self
+            .hit_shaders
+            .first()
+            .is_some_and(|shader| shader.name() == c"emitter_hit");
+
+        // same as the vertex buffer: every mesh's triangles one after the other, 3 vertices each
+        let mut start_offsets = Vec::with_capacity(self.meshes.len());
+        let mut offset = 0;
+        for model in &self.meshes {
+            start_offsets.push(offset);
+            offset += model.mesh.indices.len();
+        }
+
+        for (i, object) in self.objects.iter().enumerate() {
+            let Some(model) = self.meshes.get(object.mesh_i) else {
+                bail!(
+                    "object {i} uses mesh {}, but there are only {} meshes",
+                    object.mesh_i,
+                    self.meshes.len()
+                );
+            };
+            if object.brdf_i >= self.hit_shaders.len() {
+                bail!(
+                    "object {i} uses hit shader {}, but there are only {} hit shaders",
+                    object.brdf_i,
+                    self.hit_shaders.len()
+                );
+            }
+
+            // area lights point at their triangles in the light list instead, see Object::vertex_index
+            if has_emitter_hit && object.brdf_i == 0 {
+                let triangles = object.vertex_index as usize
+                    ..object.vertex_index as usize + model.mesh.indices.len() / 3;
+                let all_triangles = self.lights.get(triangles.clone()).is_some_and(|lights| {
+                    lights
+                        .iter()
+                        .all(|light| matches!(light, Light::Triangle { .. }))
+                });
+                if !all_triangles {
+                    bail!(
+                        "area light object {i} should have its triangles at lights {triangles:?}"
+                    );
+                }
+            } else if object.vertex_index as usize != start_offsets[object.mesh_i] {
+                bail!(
+                    "object {i} has vertex index {}, but mesh {} starts at {}",
+                    object.vertex_index,
+                    object.mesh_i,
+                    start_offsets[object.mesh_i]
+                );
+            }
+            if object.vertex_index > MAX_CUSTOM_INDEX {
+                bail!(
+                    "object {i} has vertex index {}, which doesn't fit in an instance custom index",
+                    object.vertex_index
+                );
+            }
+        }
+
+        for (i, object) in self.procedural_objects.iter().enumerate() {
+            if object.geometry_index >= self.procedural_geometries.len() {
+                bail!(
+                    "procedural object {i} uses geometry {}, but there are only {} procedural geometries",
+                    object.geometry_index,
+                    self.procedural_geometries.len()
+                );
+            }
+            if object.custom_index > MAX_CUSTOM_INDEX {
+                bail!(
+                    "procedural object {i} has custom index {}, which doesn't fit in 24 bits",
+                    object.custom_index
+                );
+            }
+        }
+
+        let instance_count = self.objects.len() + self.procedural_objects.len();
+        for (name, len) in [
+            ("offset", self.offset_buf.len()),
+            ("instance id", self.instance_id_buf.len()),
+        ] {
+            if len != instance_count {
+                bail!("{name} buffer has {len} entries for {instance_count} instances");
+            }
+        }
+        if self.mesh_aabbs.len() != self.meshes.len() {
+            bail!(
+                "there are {} mesh bounds for {} meshes",
+                self.mesh_aabbs.len(),
+                self.meshes.len()
+            );
+        }
+        if self.mesh_tangents.len() != self.meshes.len() {
+            bail!(
+                "there are {} mesh tangent lists for {} meshes",
+                self.mesh_tangents.len(),
+                self.meshes.len()
+            );
+        }
+        for (i, (model, tangents)) in self.meshes.iter().zip(&self.mesh_tangents).enumerate() {
+            if tangents.len() != model.mesh.positions.len() / 3 {
+                bail!(
+                    "mesh {i} has {} tangents for {} vertices",
+                    tangents.len(),
+                    model.mesh.positions.len() / 3
+                );
+            }
+        }
+        if self.normal_maps.len() != self.hit_shaders.len() {
+            bail!(
+                "there are {} normal map slots for {} hit shaders",
+                self.normal_maps.len(),
+                self.hit_shaders.len()
+            );
+        }
+
+        Ok(())
     }
 
     fn get_field<'a>(conf: &'a Table, field: &str) -> Result<&'a Value> {
@@ -225,6 +821,14 @@ impl MeshScene {
         }
     }
 
+    fn get_optional_string<'a>(conf: &'a Table, field: &str) -> Result<Option<&'a str>> {
+        match conf.get(field) {
+            Some(Value::String(str)) => Ok(Some(str)),
+            Some(_) => Err(anyhow!("field {} must be a string", field)),
+            None => Ok(None),
+        }
+    }
+
     fn get_table<'a>(conf: &'a Table, field: &str) -> Result<&'a Map<String, Value>> {
         match Self::get_field(conf, field)? {
             Value::Table(table) => Ok(table),
@@ -234,15 +838,33 @@ impl MeshScene {
 
     fn get_brdf_params_buffer_and_indices(
         objects: &[Object],
+        procedural_objects: &[ProceduralObject],
         hit_shaders: &[Shader],
     ) -> (Vec<u8>, Vec<u32>) {
-        // create vecs for the array of each brdf
-        let mut arrays: Vec<Vec<u8>> = vec![Vec::new(); hit_shaders.len()];
+        // every sbt hit record gets its own array, procedural records come after the triangle ones
+        // this is also the order instances are put in the tlas, so offsets line up with gl_InstanceID
+        let records: Vec<(usize, &[u8])> = objects
+            .iter()
+            .map(|x| (x.brdf_i, &x.brdf_params[..]))
+            .chain(
+                procedural_objects
+                    .iter()
+                    .map(|x| (x.hit_record_index(hit_shaders.len()), &x.params[..])),
+            )
+            .collect();
+        let record_count = hit_shaders.len()
+            + procedural_objects
+                .iter()
+                .map(|x| x.geometry_index + 1)
+                .max()
+                .unwrap_or(0);
+
+        // create vecs for the array of each record
+        let mut arrays: Vec<Vec<u8>> = vec![Vec::new(); record_count];
         let mut param_sizes = vec![0usize; arrays.len()];
-        for object in objects {
-            let brdf_i = object.brdf_i;
-            arrays[brdf_i].extend_from_slice(&object.brdf_params);
-            param_sizes[brdf_i] = object.brdf_params.len();
+        for &(record_i, params) in &records {
+            arrays[record_i].extend_from_slice(params);
+            param_sizes[record_i] = params.len();
         }
 
         // concatenate arrays
@@ -272,8 +894,8 @@ impl MeshScene {
 
         // create offset buffer
         let mut offsets = Vec::new();
-        for object in objects {
-            let index = &mut indices[object.brdf_i];
+        for &(record_i, _) in &records {
+            let index = &mut indices[record_i];
             offsets.push(*index as u32);
             *index += 1;
         }
@@ -281,6 +903,25 @@ impl MeshScene {
         (data, offsets)
     }
 
+    // same order as the instances in the tlas, so shaders can index this with gl_InstanceID
+    // procedural objects already have their custom index as the instance custom index, it's here too for uniformity
+    fn get_instance_ids(objects: &[Object], procedural_objects: &[ProceduralObject]) -> Vec<u32> {
+        objects
+            .iter()
+            .map(|x| x.custom_index)
+            .chain(procedural_objects.iter().map(|x| x.custom_index))
+            .collect()
+    }
+
+    fn parse_toml_custom_index(table: &Table) -> Result<u32> {
+        match table.get("custom_index") {
+            Some(&Value::Integer(i)) => u32::try_from(i)
+                .map_err(|_| anyhow!("custom_index must be between 0 and {}, got {i}", u32::MAX)),
+            Some(_) => bail!("custom_index must be an integer"),
+            None => Ok(0),
+        }
+    }
+
     fn parse_toml_objects(
         conf: &Table,
         mesh_map: &HashMap<String, u32>,
@@ -302,19 +943,31 @@ impl MeshScene {
             .collect();
 
         let mut objects = Vec::new();
+        // transforms are relative to the parent until all objects are parsed, parents can come later in the file
+        let mut names = Vec::new();
+        let mut parents = Vec::new();
 
         let object_confs = Self::get_array(conf, "object")?;
-        for object in object_confs {
+        for (object_i, object) in object_confs.iter().enumerate() {
             let Value::Table(object) = object else {
                 bail!("object should be a table");
             };
 
             let mesh_name = Self::get_string(object, "mesh")?;
             let transform = Self::parse_toml_transform(Self::get_field(object, "transform")?)?;
+            let previous_transform = match object.get("previous_transform") {
+                Some(x) => Self::parse_toml_transform(x)?,
+                None => transform,
+            };
 
             let brdf_info = Self::get_table(object, "brdf")?;
             let brdf_name = Self::get_string(brdf_info, "name")?;
-            let brdf_fields = Self::get_array(brdf_info, "fields")?;
+            // brdfs without fields don't need the empty list
+            let brdf_fields = match brdf_info.get("fields") {
+                Some(Value::Array(fields)) => &fields[..],
+                Some(_) => bail!("field fields must be an array"),
+                None => &[],
+            };
             let field_types = type_map
                 .get(brdf_name)
                 .ok_or(anyhow!("undefined brdf name: {}", brdf_name))?;
@@ -340,7 +993,9 @@ impl MeshScene {
                 .iter()
                 .position(|x| x.name() == &brdf_name[..])
                 .ok_or(anyhow!("undefined brdf: {:?}", brdf_name))?;
-            let mesh_i = *mesh_map.get(mesh_name).ok_or(anyhow!("asd"))? as usize;
+            let mesh_i = *mesh_map.get(mesh_name).ok_or_else(|| {
+                anyhow!("object {object_i} references mesh {mesh_name:?}, which was not loaded")
+            })? as usize;
             let vertex_index = start_offsets[mesh_i] as u32;
 
             objects.push(Object {
@@ -349,12 +1004,76 @@ impl MeshScene {
                 brdf_i,
                 brdf_params: datas,
                 vertex_index,
-            })
+                custom_index: Self::parse_toml_custom_index(object)?,
+                previous_transform,
+                visibility_mask: Self::parse_toml_visibility_mask(object)?,
+                cull: Self::parse_toml_cull(object)?,
+                front_face: Self::parse_toml_front_face(object)?,
+            });
+            names.push(Self::get_optional_string(object, "name")?);
+            parents.push(Self::get_optional_string(object, "parent")?);
         }
 
+        Self::resolve_parent_transforms(&mut objects, &names, &parents)?;
         Ok(objects)
     }
 
+    // premultiplies every object's transform with its parent's, all the way up to an object without a parent
+    // previous_transform goes through the parents' previous transforms the same way, so children move with them
+    fn resolve_parent_transforms(
+        objects: &mut [Object],
+        names: &[Option<&str>],
+        parents: &[Option<&str>],
+    ) -> Result<()> {
+        let mut name_map = HashMap::new();
+        for (i, name) in names.iter().enumerate() {
+            if let Some(name) = name {
+                if name_map.insert(*name, i).is_some() {
+                    bail!("object name {name:?} is used more than once");
+                }
+            }
+        }
+        let parent_is = parents
+            .iter()
+            .enumerate()
+            .map(|(i, parent)| {
+                parent
+                    .map(|parent| {
+                        name_map.get(parent).copied().ok_or_else(|| {
+                            anyhow!("object {i} has parent {parent:?}, but no object is named that")
+                        })
+                    })
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let local: Vec<_> = objects
+            .iter()
+            .map(|x| (x.transform, x.previous_transform))
+            .collect();
+        for (i, object) in objects.iter_mut().enumerate() {
+            let (mut transform, mut previous_transform) = local[i];
+            let mut ancestors = vec![i];
+            let mut parent = parent_is[i];
+            while let Some(parent_i) = parent {
+                if ancestors.contains(&parent_i) {
+                    bail!(
+                        "object {i}'s parents go around in a cycle through {:?}",
+                        names[parent_i].unwrap_or_default()
+                    );
+                }
+                ancestors.push(parent_i);
+                transform = local[parent_i].0 * transform;
+                previous_transform = local[parent_i].1 * previous_transform;
+                parent = parent_is[parent_i];
+            }
+            object.transform = transform;
+            object.previous_transform = previous_transform;
+        }
+
+        Ok(())
+    }
+
     fn parse_toml_field(field: &Value, type_info: &ShaderType) -> Result<Vec<u8>> {
         match type_info {
             ShaderType::Float => {
@@ -427,29 +1146,50 @@ impl MeshScene {
         }
     }
 
-    fn parse_toml_shaders(conf: &Table) -> Result<(Shaders, HashMap<String, Vec<ShaderType>>)> {
+    fn parse_toml_shaders(
+        conf: &Table,
+        default_miss: &str,
+        archive: Option<&ShaderArchive>,
+    ) -> Result<(Shaders, HashMap<String, Vec<ShaderType>>)> {
         let Value::Table(_global_shaders) = Self::get_field(conf, "global_shaders")? else {
             bail!("global_shaders must be a table");
         };
         let global_shaders = Self::get_table(conf, "global_shaders")?;
 
-        let raygen = Self::parse_toml_shader(Self::get_field(global_shaders, "raygen")?, "raygen")?;
-        let miss = Self::parse_toml_shader(Self::get_field(global_shaders, "miss")?, "miss")?;
+        let raygen = Self::parse_toml_shader(
+            Self::get_field(global_shaders, "raygen")?,
+            "raygen",
+            archive,
+        )?;
+        let miss = Self::parse_toml_shader(
+            global_shaders
+                .get("miss")
+                .unwrap_or(&Value::String(default_miss.to_string())),
+            "miss",
+            archive,
+        )?;
 
         let mut chit_shaders = Vec::new();
+        let mut normal_maps = Vec::new();
         if global_shaders.get("emitter_hit").is_some() {
             let emitter_hit = Self::parse_toml_shader(
                 Self::get_field(global_shaders, "emitter_hit")?,
                 "emitter_hit",
+                archive,
             )?;
             chit_shaders.push(emitter_hit);
+            normal_maps.push(None);
         }
 
         // parse shaders in brdfs
         // these also include types
-        let Value::Array(brdfs) = Self::get_field(conf, "brdf")? else {
-            bail!("brdf must be a list")
+        // a scene that only uses built in brdfs doesn't need any
+        let brdfs = match conf.get("brdf") {
+            Some(Value::Array(brdfs)) => &brdfs[..],
+            Some(_) => bail!("brdf must be a list"),
+            None => &[],
         };
+        Self::check_brdf_names(brdfs)?;
 
         let mut type_map = HashMap::new();
 
@@ -459,7 +1199,8 @@ impl MeshScene {
             };
 
             let name = Self::get_string(brdf, "name")?;
-            let chit_shader = Self::parse_toml_shader(Self::get_field(brdf, "chit_shader")?, name)?;
+            let chit_shader =
+                Self::parse_toml_shader(Self::get_field(brdf, "chit_shader")?, name, archive)?;
 
             let fields = Self::get_array(brdf, "field")?;
             let mut shader_types = Vec::new();
@@ -472,8 +1213,21 @@ impl MeshScene {
                 shader_types.push(shader_type);
             }
 
+            Self::check_params_layout(&chit_shader, &shader_types, &format!("brdf {name}"))?;
+
             type_map.insert(name.clone(), shader_types);
             chit_shaders.push(chit_shader);
+            normal_maps.push(Self::parse_toml_normal_map(brdf)?);
+        }
+
+        if Self::uses_brdf(conf, FLAT_SHADED_BRDF) {
+            chit_shaders.push(Self::parse_toml_shader(
+                &Value::String(FLAT_SHADED_SHADER.to_string()),
+                FLAT_SHADED_BRDF,
+                archive,
+            )?);
+            normal_maps.push(None);
+            type_map.insert(FLAT_SHADED_BRDF.to_string(), Vec::new());
         }
 
         Ok((
@@ -481,60 +1235,131 @@ impl MeshScene {
                 raygen,
                 miss,
                 rchit: chit_shaders,
+                normal_maps,
             },
             type_map,
         ))
     }
 
-    fn parse_toml_shader(name: &Value, shader_name: &str) -> Result<Shader> {
-        let Value::String(name) = name else {
-            bail!("shader path must be a string");
+    // optional, a png from resources/textures that perturbs the shading normal of every mesh using the brdf
+    fn parse_toml_normal_map(brdf: &Table) -> Result<Option<NormalMap>> {
+        let Some(file_name) = brdf.get("normal_map") else {
+            return Ok(None);
+        };
+        let Value::String(file_name) = file_name else {
+            bail!("normal_map must be a string");
         };
 
-        let mut spv_name = name.clone();
-        spv_name.push_str(SPIRV_EXTENSION);
-
-        let spv_path = Path::new(SPIRV_DIR).join(spv_name);
-        let mut spv_file = File::open(spv_path)?;
-        let file_info = spv_file.metadata()?;
+        let path = Path::new(TEXTURES_DIR).join(file_name);
+        let file = File::open(&path)
+            .map_err(|e| anyhow!("failed to open normal map {}: {e}", path.display()))?;
+        let map = NormalMap::from_png(BufReader::new(file))
+            .map_err(|e| anyhow!("failed to load normal map {}: {e}", path.display()))?;
 
-        let shader_size = file_info.len();
-        if shader_size == 0 || shader_size % 4 != 0 {
-            bail!("invalid shader size: {shader_size} - must be aligned to 4 bytes and greater than 0");
-        }
+        Ok(Some(map))
+    }
 
-        // allocate a buffer that is aligned to u32 since that is required for shader code
-        let layout = Layout::array::<u8>(shader_size as usize)?;
-        let layout = layout.align_to(align_of::<u32>()).unwrap();
+    // whether any object's brdf is `name`, malformed objects are left to parse_toml_objects
+    fn uses_brdf(conf: &Table, name: &str) -> bool {
+        let Some(Value::Array(objects)) = conf.get("object") else {
+            return false;
+        };
+        objects.iter().any(|object| {
+            object
+                .get("brdf")
+                .and_then(|brdf| brdf.get("name"))
+                .and_then(Value::as_str)
+                == Some(name)
+        })
+    }
 
-        let code = unsafe { alloc::alloc(layout) };
-        if code.is_null() {
-            alloc::handle_alloc_error(layout);
+    // objects find their brdf by name, so a repeated name would quietly pick the first one
+    // emitter_hit is taken by global_shaders.emitter_hit, which sits in the same list, and the built in brdfs
+    // can't be redefined either
+    fn check_brdf_names(brdfs: &[Value]) -> Result<()> {
+        let mut names = HashSet::new();
+        for brdf in brdfs {
+            let Value::Table(brdf) = brdf else {
+                bail!("brdf entry must be tables");
+            };
+
+            let name = Self::get_string(brdf, "name")?;
+            if name == "emitter_hit" {
+                bail!("brdf name emitter_hit is reserved for global_shaders.emitter_hit");
+            }
+            if name == FLAT_SHADED_BRDF {
+                bail!("brdf name {FLAT_SHADED_BRDF} is reserved for the built in one");
+            }
+            if !names.insert(name) {
+                bail!("brdf name {name:?} is defined more than once");
+            }
         }
-        let mut code = unsafe { BoxBytes::from_raw_parts(NonNull::new_unchecked(code), layout) };
-        spv_file.read_exact(&mut code)?;
 
-        // now that the code has been read in, we can cast as u32
-        // this should be guaranteed to succeed because of the alignment stuff above
-        #[allow(unused_mut)]
-        let mut code: Box<[u32]> = bytemuck::from_box_bytes(code);
+        Ok(())
+    }
 
-        // on big endian systems, we need to swap endianness of every u32
-        // this is because the shader is in little-endian
-        #[cfg(target_endian = "big")]
-        for word in &mut code {
-            *word = (*word).swap_bytes();
+    // shaders in the scene's archive win over the ones in resources/shaders/spv/
+    fn parse_toml_shader(
+        name: &Value,
+        shader_name: &str,
+        archive: Option<&ShaderArchive>,
+    ) -> Result<Shader> {
+        let Value::String(name) = name else {
+            bail!("shader path must be a string");
+        };
+
+        match archive.and_then(|archive| archive.get(name)) {
+            Some(code) => Ok(Shader::Uncompiled(CString::new(shader_name)?, code)),
+            None => Shader::load(name, shader_name),
         }
+    }
 
-        // assert SPIRV magic number: https://registry.khronos.org/SPIR-V/specs/unified1/SPIRV.html#_magic_number
-        if code[0] != SPIRV_MAGIC {
-            bail!("invalid SPIR-V magic number");
+    // optional, a file in resources/shaders/spv/ like the shaders themselves
+    fn parse_toml_shader_archive(conf: &Table) -> Result<Option<ShaderArchive>> {
+        let global_shaders = Self::get_table(conf, "global_shaders")?;
+        match global_shaders.get("archive") {
+            None => Ok(None),
+            Some(Value::String(file_name)) => Ok(Some(ShaderArchive::open(
+                &Path::new(SPIRV_DIR).join(file_name),
+            )?)),
+            Some(_) => bail!("global_shaders.archive must be a string"),
+        }
+    }
+
+    // optional, an equirectangular .hdr from resources/environments, scaled by strength
+    fn parse_toml_environment(conf: &Table) -> Result<Option<EnvironmentMap>> {
+        let Some(environment) = conf.get("environment") else {
+            return Ok(None);
+        };
+        let Value::Table(environment) = environment else {
+            bail!("environment must be a table");
+        };
+
+        let file_name = Self::get_string(environment, "file")?;
+        let strength = match environment.get("strength") {
+            Some(strength) => Self::parse_toml_f32(strength)?,
+            None => 1.0,
+        };
+        if strength < 0.0 {
+            bail!("environment.strength must not be negative");
         }
 
-        Ok(Shader::Uncompiled(CString::new(shader_name)?, code))
+        let path = Path::new(ENVIRONMENTS_DIR).join(file_name);
+        let file = File::open(&path)
+            .map_err(|e| anyhow!("failed to open environment map {}: {e}", path.display()))?;
+        let mut map = EnvironmentMap::from_hdr(BufReader::new(file))
+            .map_err(|e| anyhow!("failed to load environment map {}: {e}", path.display()))?;
+        for pixel in &mut map.pixels {
+            *pixel *= strength;
+        }
+
+        Ok(Some(map))
     }
 
-    fn parse_toml_meshes(conf: &Table) -> Result<(Vec<Model>, HashMap<String, u32>)> {
+    fn parse_toml_meshes(
+        conf: &Table,
+        max_triangles: u64,
+    ) -> Result<(Vec<Model>, HashMap<String, u32>)> {
         let Value::Array(obj_confs) = Self::get_field(conf, "object")? else {
             bail!("objects field must be an array of objects");
         };
@@ -557,6 +1382,7 @@ impl MeshScene {
 
         let mut meshes = Vec::new();
         let mut mesh_map = HashMap::new();
+        let mut triangles = 0;
 
         for obj in obj_confs.iter().chain(area_lights) {
             let Value::Table(obj) = obj else {
@@ -572,8 +1398,17 @@ impl MeshScene {
                 continue;
             }
 
-            let mesh_path = Path::new(MESHES_DIR).join(Self::get_string(obj, "mesh")?);
-            let (mesh, _) = tobj::load_obj(mesh_path, &tobj::GPU_LOAD_OPTIONS)?;
+            // check up front, tobj's error for this is just a bare io error
+            let mesh_path = Path::new(MESHES_DIR).join(mesh_name);
+            if !mesh_path.is_file() {
+                bail!(
+                    "mesh file not found: {} (referenced as mesh = {mesh_name:?})",
+                    mesh_path.display()
+                );
+            }
+            let mesh = AssetCache::global()
+                .load_obj(&mesh_path)
+                .map_err(|e| anyhow!("failed to load mesh file {}: {e}", mesh_path.display()))?;
 
             // only take the first model
             if mesh.len() > 1 {
@@ -584,21 +1419,153 @@ impl MeshScene {
                 );
             }
 
-            if let Some(mesh) = mesh.into_iter().next() {
-                mesh_map.insert(mesh_name.clone(), meshes.len() as u32);
-                meshes.push(mesh);
+            let Some(mut mesh) = mesh.first().cloned() else {
+                bail!("mesh file {} contains no meshes", mesh_path.display());
+            };
+            Self::check_mesh_normals(&mut mesh.mesh, mesh_name)?;
+            Self::check_mesh_texcoords(&mesh.mesh, mesh_name)?;
+
+            // the mesh file is already in memory by now, but the vertex buffer (and bvh) would be a lot bigger
+            triangles += mesh.mesh.indices.len() as u64 / 3;
+            if triangles > max_triangles {
+                bail!(
+                    "meshes have more than {max_triangles} triangles in total once {mesh_name:?} is loaded, \
+                     raise render.max_triangles if that's intended"
+                );
             }
+            mesh_map.insert(mesh_name.clone(), meshes.len() as u32);
+            meshes.push(mesh);
         }
 
         Ok((meshes, mesh_map))
     }
 
+    // the vertex buffer needs a normal for every position
+    // meshes without any get smooth ones generated, a different count is an error instead of a panic later
+    fn check_mesh_normals(mesh: &mut Mesh, mesh_name: &str) -> Result<()> {
+        if mesh.normals.is_empty() {
+            warn!("mesh {mesh_name:?} has no normals, generating them");
+            mesh.normals = Self::generate_normals(&mesh.positions, &mesh.indices);
+        }
+
+        if mesh.normals.len() != mesh.positions.len() {
+            bail!(
+                "mesh {mesh_name:?} has {} normals but {} positions",
+                mesh.normals.len() / 3,
+                mesh.positions.len() / 3
+            );
+        }
+
+        Ok(())
+    }
+
+    // texture coordinates are optional, but without them on every vertex there's nothing to normal map with
+    fn check_mesh_texcoords(mesh: &Mesh, mesh_name: &str) -> Result<()> {
+        if !mesh.texcoords.is_empty() && mesh.texcoords.len() / 2 != mesh.positions.len() / 3 {
+            bail!(
+                "mesh {mesh_name:?} has {} texture coordinates but {} positions",
+                mesh.texcoords.len() / 2,
+                mesh.positions.len() / 3
+            );
+        }
+
+        Ok(())
+    }
+
+    // sums the tangents and bitangents of the faces around each vertex, then makes the tangent perpendicular
+    // to the normal. the bitangent is left for shaders to recompute from the normal, tangent and w
+    // (w is in the mesh's own space, shaders flip it again for mirroring transforms, see transform_handedness)
+    fn generate_tangents(mesh: &Mesh) -> Vec<[f32; 4]> {
+        let vertex_count = mesh.positions.len() / 3;
+        if mesh.texcoords.is_empty() {
+            return vec![[0.0; 4]; vertex_count];
+        }
+
+        let position = |i: u32| Vec3::from_slice(&mesh.positions[3 * i as usize..]);
+        let texcoord = |i: u32| Vec2::from_slice(&mesh.texcoords[2 * i as usize..]);
+
+        let mut tangents = vec![Vec3::ZERO; vertex_count];
+        let mut bitangents = vec![Vec3::ZERO; vertex_count];
+        for face in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [face[0], face[1], face[2]].map(position);
+            let [uv_a, uv_b, uv_c] = [face[0], face[1], face[2]].map(texcoord);
+            let (edge1, edge2) = (b - a, c - a);
+            let (duv1, duv2) = (uv_b - uv_a, uv_c - uv_a);
+
+            // faces with degenerate texture coordinates don't have a tangent
+            let det = duv1.perp_dot(duv2);
+            if det.abs() < f32::EPSILON {
+                continue;
+            }
+            let tangent = (edge1 * duv2.y - edge2 * duv1.y) / det;
+            let bitangent = (edge2 * duv1.x - edge1 * duv2.x) / det;
+            for &i in face {
+                tangents[i as usize] += tangent;
+                bitangents[i as usize] += bitangent;
+            }
+        }
+
+        tangents
+            .iter()
+            .zip(&bitangents)
+            .enumerate()
+            .map(|(i, (tangent, bitangent))| {
+                let normal = Vec3::from_slice(&mesh.normals[3 * i..]);
+                let tangent = (*tangent - normal * normal.dot(*tangent)).normalize_or_zero();
+                let sign = if normal.cross(tangent).dot(*bitangent) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                if tangent == Vec3::ZERO {
+                    [0.0; 4]
+                } else {
+                    tangent.extend(sign).to_array()
+                }
+            })
+            .collect()
+    }
+
+    // sums the (area weighted) face normals around each vertex
+    fn generate_normals(positions: &[f32], indices: &[u32]) -> Vec<f32> {
+        let position = |i: u32| Vec3::from_slice(&positions[3 * i as usize..]);
+
+        let mut normals = vec![Vec3::ZERO; positions.len() / 3];
+        for face in indices.chunks_exact(3) {
+            let [a, b, c] = [face[0], face[1], face[2]].map(position);
+            let normal = (b - a).cross(c - a);
+            for &i in face {
+                normals[i as usize] += normal;
+            }
+        }
+
+        normals
+            .iter()
+            .flat_map(|n| n.normalize_or_zero().to_array())
+            .collect()
+    }
+
+    // area light objects always use brdf 0, which is only emitter_hit if the scene has one
+    // without it they'd silently get shaded by the first brdf instead
+    fn check_emitter_hit(lights: &[Light], hit_shaders: &[Shader]) -> Result<()> {
+        let has_area_lights = lights
+            .iter()
+            .any(|light| matches!(light, Light::Triangle { .. }));
+        let has_emitter_hit = hit_shaders
+            .first()
+            .is_some_and(|shader| shader.name() == c"emitter_hit");
+        if has_area_lights && !has_emitter_hit {
+            bail!("area lights need a global_shaders.emitter_hit shader");
+        }
+        Ok(())
+    }
+
     fn parse_toml_lights(
         conf: &Table,
         mesh_map: &HashMap<String, u32>,
         meshes: &[Model],
         objects: &mut Vec<Object>,
-    ) -> Result<Vec<Light>> {
+    ) -> Result<(Vec<Light>, Vec<Range<usize>>)> {
         let Value::Array(light_confs) = conf
             .get("light")
             .ok_or(anyhow!("no lights field provided"))?
@@ -607,11 +1574,13 @@ impl MeshScene {
         };
 
         let mut lights = Vec::new();
+        let mut entries = Vec::new();
 
         for light_conf in light_confs {
             let Value::Table(light_conf) = light_conf else {
                 bail!("light must be a table");
             };
+            let first_light = lights.len();
             let Value::String(light_type) = light_conf
                 .get("type")
                 .ok_or(anyhow!("no type field found for light"))?
@@ -624,6 +1593,8 @@ impl MeshScene {
                     .get("color")
                     .ok_or(anyhow!("no color field found for light"))?,
             )?;
+            let power = Self::parse_toml_light_power(light_conf, color)?;
+            let samples = Self::parse_toml_light_samples(light_conf)?;
 
             match light_type.as_str() {
                 "point" => {
@@ -632,7 +1603,13 @@ impl MeshScene {
                             .get("position")
                             .ok_or(anyhow!("no top_left field found for light"))?,
                     )?;
-                    lights.push(Light::Point { color, position });
+                    // radiant intensity, spread evenly over the whole sphere
+                    let color = power.map_or(color, |power| power / (4.0 * PI));
+                    lights.push(Light::Point {
+                        color,
+                        position,
+                        samples,
+                    });
                 }
                 "area" => {
                     let transform = Self::parse_toml_transform(
@@ -642,10 +1619,9 @@ impl MeshScene {
                     )?;
 
                     let mesh_name = Self::get_string(light_conf, "mesh")?;
-                    let mesh_i = *mesh_map
-                        .get(mesh_name)
-                        .ok_or(anyhow!("no such mesh: {}", mesh_name))?
-                        as usize;
+                    let mesh_i = *mesh_map.get(mesh_name).ok_or_else(|| {
+                        anyhow!("area light references mesh {mesh_name:?}, which was not loaded")
+                    })? as usize;
                     let mesh = &meshes[mesh_i].mesh;
 
                     let start_idx = lights.len();
@@ -655,6 +1631,7 @@ impl MeshScene {
                     if !triangles.remainder().is_empty() {
                         bail!("obj face list was not a multiple of 3 in length")
                     }
+                    let mut triangle_vertices = Vec::new();
                     for triangle in triangles {
                         let vertices: Vec<_> = triangle
                             .iter()
@@ -671,18 +1648,46 @@ impl MeshScene {
                             })
                             .collect();
 
-                        lights.push(Light::Triangle {
-                            color,
-                            vertices: vertices.try_into().unwrap(),
-                        })
+                        triangle_vertices.push(<[Vec3; 3]>::try_from(vertices).unwrap());
                     }
 
+                    // radiance, the front faces emit like a lambertian surface
+                    let color = match power {
+                        Some(power) => {
+                            let area: f32 = triangle_vertices
+                                .iter()
+                                .map(|[a, b, c]| 0.5 * (*b - *a).cross(*c - *a).length())
+                                .sum();
+                            if area <= 0.0 {
+                                bail!("area light mesh {mesh_name:?} has no area to spread its power over");
+                            }
+                            power / (PI * area)
+                        }
+                        None => color,
+                    };
+                    lights.extend(
+                        triangle_vertices
+                            .into_iter()
+                            .map(|vertices| Light::Triangle {
+                                color,
+                                vertices,
+                                samples,
+                            }),
+                    );
+
                     objects.push(Object {
                         transform,
                         mesh_i,
                         brdf_i: 0, // emitter hit brdf is always 0
                         brdf_params: Vec::new(),
-                        vertex_index: start_idx as u32, // vertex index is actually light index
+                        // NOT a vertex index, see Object::vertex_index
+                        vertex_index: start_idx as u32,
+                        custom_index: 0,
+                        // lights are baked into the light buffer, so they can't move
+                        previous_transform: transform,
+                        visibility_mask: VISIBILITY_ALL,
+                        cull: Cull::None,
+                        front_face: FrontFace::default(),
                     });
                 }
                 "directional" => {
@@ -701,18 +1706,85 @@ impl MeshScene {
                             .get("radius")
                             .ok_or(anyhow!("no radius field found for light"))?,
                     )?;
+                    let geometry_scale = match light_conf.get("geometry_scale") {
+                        Some(x) => Self::parse_toml_f32(x)?,
+                        None => radius,
+                    };
+                    if geometry_scale <= 0.0 {
+                        bail!("directional light geometry_scale must be positive");
+                    }
+                    // irradiance across the beam, it stays the same all the way along it
+                    let color = match power {
+                        Some(_) if radius <= 0.0 => {
+                            bail!("directional light needs a positive radius to be given in watts")
+                        }
+                        Some(power) => power / (PI * radius * radius),
+                        None => color,
+                    };
                     lights.push(Light::Directional {
                         color,
                         position,
                         direction,
+                        samples,
                         radius,
+                        geometry_scale,
                     });
                 }
                 _ => bail!("unknown light type"),
             };
+            entries.push(first_light..lights.len());
+        }
+
+        Ok((lights, entries))
+    }
+
+    // optional, a light with samples = 4 gets picked 4 times as often as the others when sampling lights
+    // shadow rays are traced from the raygen shader one per bounce, so this is as close as a hit gets to
+    // sampling the light several times. area lights apply it to each of their triangles
+    fn parse_toml_light_samples(light_conf: &Table) -> Result<u32> {
+        let Some(samples) = light_conf.get("samples") else {
+            return Ok(1);
+        };
+        let &Value::Integer(samples) = samples else {
+            bail!("light samples must be an integer");
+        };
+        if samples < 1 {
+            bail!("light samples must be at least 1");
         }
 
-        Ok(lights)
+        Ok(samples.try_into()?)
+    }
+
+    // unit = "watts" gives the light's total emitted power as color times intensity, which the light types
+    // then turn into what their shaders use as color (see parse_toml_lights)
+    // the default unit = "raw" uses color as is
+    fn parse_toml_light_power(light_conf: &Table, color: Vec3) -> Result<Option<Vec3>> {
+        let unit = match light_conf.get("unit") {
+            Some(Value::String(unit)) => unit.as_str(),
+            Some(_) => bail!("light unit must be a string"),
+            None => "raw",
+        };
+
+        match unit {
+            "raw" => {
+                if light_conf.contains_key("intensity") {
+                    bail!("light intensity is only used with unit = \"watts\"");
+                }
+                Ok(None)
+            }
+            "watts" => {
+                // without an intensity the color itself is the power
+                let intensity = match light_conf.get("intensity") {
+                    Some(intensity) => Self::parse_toml_f32(intensity)?,
+                    None => 1.0,
+                };
+                if intensity < 0.0 {
+                    bail!("light intensity can't be negative");
+                }
+                Ok(Some(color * intensity))
+            }
+            _ => bail!("unknown light unit {unit:?}, expected \"raw\" or \"watts\""),
+        }
     }
 
     fn parse_toml_vec3(conf: &Value) -> Result<Vec3> {
@@ -753,108 +1825,119 @@ impl MeshScene {
         })
     }
 
-    fn parse_toml_transform(value: &Value) -> Result<Mat4> {
-        let Value::String(transform_str) = value else {
-            bail!("transform must be a string");
-        };
-
-        Self::parse_transform(transform_str)
+    // optional, objects are visible to every kind of ray by default
+    fn parse_toml_visibility_mask(conf: &Table) -> Result<u8> {
+        match conf.get("visibility_mask") {
+            None => Ok(VISIBILITY_ALL),
+            Some(&Value::Integer(mask)) => u8::try_from(mask)
+                .map_err(|_| anyhow!("visibility_mask must be between 0 and 255, got {mask}")),
+            Some(_) => bail!("visibility_mask must be an integer"),
+        }
     }
 
-    fn parse_transform(transform_str: &str) -> Result<Mat4> {
-        let mut transform = Mat4::IDENTITY;
+    // optional, objects are two-sided by default
+    fn parse_toml_cull(conf: &Table) -> Result<Cull> {
+        let Some(cull) = conf.get("cull") else {
+            return Ok(Cull::None);
+        };
+        let Value::String(cull) = cull else {
+            bail!("cull must be a string");
+        };
 
-        for line in transform_str.lines() {
-            let mut tokens = line.trim().split_ascii_whitespace();
+        match cull.as_str() {
+            "none" => Ok(Cull::None),
+            "back" => Ok(Cull::Back),
+            "front" => Ok(Cull::Front),
+            _ => bail!("unknown cull {cull:?}, expected \"none\", \"back\" or \"front\""),
+        }
+    }
 
-            let Some(action) = tokens.next() else {
-                // empty means we ignore
-                continue;
-            };
+    // optional, counter-clockwise by default
+    fn parse_toml_front_face(conf: &Table) -> Result<FrontFace> {
+        let Some(front_face) = conf.get("front_face") else {
+            return Ok(FrontFace::default());
+        };
+        let Value::String(front_face) = front_face else {
+            bail!("front_face must be a string");
+        };
 
-            // ignore comments
-            if action.starts_with('#') {
-                continue;
-            }
+        match front_face.as_str() {
+            "ccw" => Ok(FrontFace::CounterClockwise),
+            "cw" => Ok(FrontFace::Clockwise),
+            _ => bail!("unknown front_face {front_face:?}, expected \"ccw\" or \"cw\""),
+        }
+    }
 
-            // match on action (omg thats a cinema term)
-            match action {
-                "identity" => transform = Mat4::IDENTITY,
-                "translate" => {
-                    let x = Self::parse_f32(&mut tokens)?;
-                    let y = Self::parse_f32(&mut tokens)?;
-                    let z = Self::parse_f32(&mut tokens)?;
+    fn parse_toml_transform(value: &Value) -> Result<Mat4> {
+        let Value::String(transform_str) = value else {
+            bail!("transform must be a string");
+        };
 
-                    if tokens.next().is_some() {
-                        bail!("transform requires only x y z, but extra info was provided");
-                    }
+        transform::parse_transform(transform_str)
+    }
 
-                    let translation = Mat4::from_translation(Vec3::new(x, y, z));
-                    transform = translation * transform;
+    // lays out field types the way parse_toml_field packs them, i.e. tightly like glsl's scalar layout
+    fn flatten_shader_type(shader_type: &ShaderType, offset: &mut u32, fields: &mut Vec<Field>) {
+        let (scalar, components) = match shader_type {
+            ShaderType::Float => (Scalar::Float, 1),
+            ShaderType::Vec2 => (Scalar::Float, 2),
+            ShaderType::Vec3 => (Scalar::Float, 3),
+            ShaderType::UInt => (Scalar::UInt, 1),
+            ShaderType::Int => (Scalar::Int, 1),
+            ShaderType::Array(element, len) => {
+                for _ in 0..*len {
+                    Self::flatten_shader_type(element, offset, fields);
                 }
-                "rotate" => {
-                    let angle = Self::parse_f32(&mut tokens)? * PI / 180f32;
-                    let x = Self::parse_f32(&mut tokens)?;
-                    let y = Self::parse_f32(&mut tokens)?;
-                    let z = Self::parse_f32(&mut tokens)?;
-                    let axis = Vec3::new(x, y, z);
-
-                    if tokens.next().is_some() {
-                        bail!("rotate requires only angle x y z, but extra info was provided");
-                    }
+                return;
+            }
+        };
 
-                    let rotation = Mat4::from_axis_angle(axis, angle);
-                    transform = rotation * transform;
-                }
-                "scale" => {
-                    let x = Self::parse_f32(&mut tokens)?;
-                    let y = Self::parse_f32(&mut tokens)?;
-                    let z = Self::parse_f32(&mut tokens)?;
-                    let scale = Vec3::new(x, y, z);
-
-                    if tokens.next().is_some() {
-                        bail!("scale requires only x y z, but extra info was provided");
-                    }
+        fields.push(Field {
+            offset: *offset,
+            scalar,
+            components,
+        });
+        *offset += 4 * components;
+    }
 
-                    let scale = Mat4::from_scale(scale);
-                    transform = scale * transform;
-                }
-                "lookat" => {
-                    let eye_x = Self::parse_f32(&mut tokens)?;
-                    let eye_y = Self::parse_f32(&mut tokens)?;
-                    let eye_z = Self::parse_f32(&mut tokens)?;
-                    let eye = Vec3::new(eye_x, eye_y, eye_z);
-
-                    let center_x = Self::parse_f32(&mut tokens)?;
-                    let center_y = Self::parse_f32(&mut tokens)?;
-                    let center_z = Self::parse_f32(&mut tokens)?;
-                    let center = Vec3::new(center_x, center_y, center_z);
-
-                    let up_x = Self::parse_f32(&mut tokens)?;
-                    let up_y = Self::parse_f32(&mut tokens)?;
-                    let up_z = Self::parse_f32(&mut tokens)?;
-                    let up = Vec3::new(up_x, up_y, up_z);
-
-                    if tokens.next().is_some() {
-                        bail!("lookat requires only eye_x eye_y eye_z center_x center_y center_z up_x up_y up_z, but extra info was provided");
-                    }
+    // cross-checks declared field types against the params struct the hit shader actually reads
+    // a mismatch would otherwise just shade with garbage
+    fn check_params_layout(
+        shader: &Shader,
+        shader_types: &[ShaderType],
+        owner: &str,
+    ) -> Result<()> {
+        let mut size = 0;
+        let mut expected = Vec::new();
+        for shader_type in shader_types {
+            Self::flatten_shader_type(shader_type, &mut size, &mut expected);
+        }
+        debug!("{owner}: params struct is {size} bytes");
 
-                    let lookat = Mat4::look_at_lh(eye, center, up);
-                    transform = lookat;
-                }
-                _ if action.starts_with("#") => (),
-                x => bail!("invalid transform action: {x}"),
+        let name = shader.name().to_string_lossy();
+        let Some(layout) = spirv::runtime_array_layout(shader.code(), 0, BRDF_PARAMS_BINDING)
+            .map_err(|e| anyhow!("failed to reflect {name}: {e}"))?
+        else {
+            if !expected.is_empty() {
+                warn!("{owner} declares fields, but {name} never reads them");
+            }
+            return Ok(());
+        };
+
+        if layout.fields != expected || layout.stride != size {
+            let describe = |fields: &[Field]| {
+                let fields: Vec<_> = fields.iter().map(|x| x.to_string()).collect();
+                fields.join(", ")
             };
+            bail!(
+                "{owner}: declared fields ({}, {size} bytes) don't match what {name} reads ({}, {} bytes)",
+                describe(&expected),
+                describe(&layout.fields),
+                layout.stride
+            );
         }
 
-        Ok(transform)
-    }
-
-    fn parse_f32<'a>(mut tokens: impl Iterator<Item = &'a str>) -> Result<f32> {
-        let num = tokens
-            .next()
-            .ok_or(anyhow!("float expected but not found"))?;
-        Ok(num.parse()?)
+        Ok(())
     }
 
     fn parse_type_str(type_str: &str) -> Result<ShaderType> {
@@ -932,12 +2015,16 @@ impl MeshScene {
         })
     }
 
+    // directional lights only get their geometry (and need its shaders) with directional_geometry
     fn parse_procedural_geometries(
         conf: &Table,
         lights: &[Light],
+        directional_geometry: bool,
+        archive: Option<&ShaderArchive>,
     ) -> Result<(Vec<ProceduralGeometry>, Vec<ProceduralObject>)> {
         let mut geometries = Vec::new();
         let mut geometry_map = HashMap::new();
+        let mut geometry_types = Vec::new();
         let mut objects = Vec::new();
 
         if let Some(Value::Array(geom_confs)) = conf.get("procedural_geometry") {
@@ -953,10 +2040,12 @@ impl MeshScene {
                 let int_shader = Self::parse_toml_shader(
                     &Value::String(int_shader_name.clone()),
                     &format!("{}_int", name),
+                    archive,
                 )?;
                 let hit_shader = Self::parse_toml_shader(
                     &Value::String(hit_shader_name.clone()),
                     &format!("{}_hit", name),
+                    archive,
                 )?;
 
                 let aabbs_conf = Self::get_array(geom_conf, "aabbs")?;
@@ -974,13 +2063,44 @@ impl MeshScene {
                     let max_x = Self::parse_toml_f32(&coords[3])?;
                     let max_y = Self::parse_toml_f32(&coords[4])?;
                     let max_z = Self::parse_toml_f32(&coords[5])?;
-                    aabbs.push(Aabb {
+                    let aabb = Aabb {
                         min: Vec3::new(min_x, min_y, min_z),
                         max: Vec3::new(max_x, max_y, max_z),
-                    });
+                    };
+                    if aabb.min.cmpgt(aabb.max).any() {
+                        bail!("aabb min must not be greater than max");
+                    }
+                    aabbs.push(aabb);
+                }
+
+                // an aabb blas with no primitives would need an empty buffer, which vulkan doesn't allow
+                if aabbs.is_empty() {
+                    bail!("procedural_geometry {name} must have at least one aabb");
+                }
+
+                // optional fields work just like brdf fields
+                let mut shader_types = Vec::new();
+                if let Some(fields) = geom_conf.get("field") {
+                    let Value::Array(fields) = fields else {
+                        bail!("procedural_geometry field must be an array");
+                    };
+                    for field in fields {
+                        let Value::Table(field) = field else {
+                            bail!("field must be a table");
+                        };
+
+                        shader_types.push(Self::parse_type_str(Self::get_string(field, "type")?)?);
+                    }
                 }
 
+                Self::check_params_layout(
+                    &hit_shader,
+                    &shader_types,
+                    &format!("procedural_geometry {name}"),
+                )?;
+
                 geometry_map.insert(name.clone(), geometries.len());
+                geometry_types.push(shader_types);
                 geometries.push(ProceduralGeometry {
                     aabbs,
                     intersection_shader: int_shader,
@@ -1003,19 +2123,89 @@ impl MeshScene {
                 let transform =
                     Self::parse_toml_transform(Self::get_field(obj_conf, "transform")?)?;
 
-                let custom_index = obj_conf
-                    .get("custom_index")
-                    .map(|v| match v {
-                        Value::Integer(i) => Ok(*i as u32),
-                        _ => bail!("custom_index must be an integer"),
-                    })
-                    .transpose()?
-                    .unwrap_or(0);
+                let custom_index = Self::parse_toml_custom_index(obj_conf)?;
+
+                let field_types = &geometry_types[geometry_index];
+                let fields = match obj_conf.get("fields") {
+                    Some(Value::Array(fields)) => &fields[..],
+                    Some(_) => bail!("procedural_object fields must be an array"),
+                    None => &[],
+                };
+                if field_types.len() != fields.len() {
+                    bail!(
+                        "expected number of fields ({}) doesn't match up with provided fields ({})",
+                        field_types.len(),
+                        fields.len()
+                    );
+                }
+
+                let mut params = Vec::new();
+                for (field, type_info) in fields.iter().zip(field_types) {
+                    params.extend_from_slice(&Self::parse_toml_field(field, type_info)?);
+                }
 
                 objects.push(ProceduralObject {
                     transform,
                     geometry_index,
                     custom_index,
+                    params,
+                    visibility_mask: Self::parse_toml_visibility_mask(obj_conf)?,
+                });
+            }
+        }
+
+        // spheres all share one unit sphere geometry, scaled and moved into place by their transform
+        if let Some(sphere_confs) = conf.get("sphere") {
+            let Value::Array(sphere_confs) = sphere_confs else {
+                bail!("sphere must be an array of tables");
+            };
+
+            let geometry_index = geometries.len();
+            if !sphere_confs.is_empty() {
+                geometries.push(ProceduralGeometry {
+                    aabbs: vec![Aabb {
+                        min: Vec3::splat(-1.0),
+                        max: Vec3::splat(1.0),
+                    }],
+                    intersection_shader: Self::parse_toml_shader(
+                        &Value::String(SPHERE_INTERSECTION_SHADER.to_string()),
+                        "sphere_int",
+                        archive,
+                    )?,
+                    closest_hit_shader: Self::parse_toml_shader(
+                        &Value::String(SPHERE_HIT_SHADER.to_string()),
+                        "sphere_hit",
+                        archive,
+                    )?,
+                });
+            }
+
+            for sphere_conf in sphere_confs {
+                let Value::Table(sphere_conf) = sphere_conf else {
+                    bail!("sphere must be a table");
+                };
+
+                let center = Self::parse_toml_vec3(Self::get_field(sphere_conf, "center")?)?;
+                let radius = Self::parse_toml_f32(Self::get_field(sphere_conf, "radius")?)?;
+                if radius <= 0.0 {
+                    bail!("sphere radius must be positive");
+                }
+                let albedo = match sphere_conf.get("albedo") {
+                    Some(x) => Self::parse_toml_vec3(x)?,
+                    None => SPHERE_DEFAULT_ALBEDO,
+                };
+
+                let albedo: [u8; 12] = bytemuck::cast(albedo);
+                objects.push(ProceduralObject {
+                    transform: Mat4::from_scale_rotation_translation(
+                        Vec3::splat(radius),
+                        Default::default(),
+                        center,
+                    ),
+                    geometry_index,
+                    custom_index: 0,
+                    params: albedo.to_vec(),
+                    visibility_mask: Self::parse_toml_visibility_mask(sphere_conf)?,
                 });
             }
         }
@@ -1027,14 +2217,14 @@ impl MeshScene {
                 Light::Directional {
                     position,
                     direction,
-                    radius,
+                    geometry_scale,
                     ..
-                } => Some((i, *position, direction.normalize(), *radius)),
+                } => Some((i, *position, direction.normalize(), *geometry_scale)),
                 _ => None,
             })
             .collect();
 
-        if !directional_lights.is_empty() {
+        if directional_geometry && !directional_lights.is_empty() {
             let global_shaders = conf
                 .get("global_shaders")
                 .and_then(|v| v.as_table())
@@ -1060,10 +2250,12 @@ impl MeshScene {
             let int_shader = Self::parse_toml_shader(
                 &Value::String(int_shader_name.to_string()),
                 "directional_emitter_int",
+                archive,
             )?;
             let hit_shader = Self::parse_toml_shader(
                 &Value::String(hit_shader_name.to_string()),
                 "directional_emitter_hit",
+                archive,
             )?;
 
             let geometry_index = geometries.len();
@@ -1076,12 +2268,15 @@ impl MeshScene {
                 closest_hit_shader: hit_shader,
             });
 
-            for (light_index, position, direction, radius) in directional_lights {
-                let transform = Self::compute_light_geometry_transform(position, direction, radius);
+            for (light_index, position, direction, geometry_scale) in directional_lights {
+                let transform =
+                    Self::compute_light_geometry_transform(position, direction, geometry_scale);
                 objects.push(ProceduralObject {
                     transform,
                     geometry_index,
                     custom_index: light_index as u32,
+                    params: Vec::new(),
+                    visibility_mask: VISIBILITY_ALL,
                 });
             }
         }
@@ -1089,7 +2284,11 @@ impl MeshScene {
         Ok((geometries, objects))
     }
 
-    fn compute_light_geometry_transform(position: Vec3, direction: Vec3, radius: f32) -> Mat4 {
+    fn compute_light_geometry_transform(
+        position: Vec3,
+        direction: Vec3,
+        geometry_scale: f32,
+    ) -> Mat4 {
         let target_normal = direction.normalize();
         let object_normal = Vec3::Z;
 
@@ -1103,35 +2302,1626 @@ impl MeshScene {
             glam::Quat::from_rotation_arc(object_normal, target_normal)
         };
 
-        let scale = Mat4::from_scale(Vec3::new(radius, radius, 1.0));
+        let scale = Mat4::from_scale(Vec3::new(geometry_scale, geometry_scale, 1.0));
         let rotation_mat = Mat4::from_quat(rotation);
         let translation = Mat4::from_translation(position);
 
         translation * rotation_mat * scale
     }
 
+    fn parse_toml_window_options(conf: &Table) -> Result<WindowOptions> {
+        let mut options = WindowOptions::default();
+
+        let Some(window_table) = conf.get("window") else {
+            return Ok(options);
+        };
+        let Value::Table(window_table) = window_table else {
+            bail!("window must be a table")
+        };
+
+        if let Some(grab_cursor) = window_table.get("grab_cursor") {
+            let &Value::Boolean(grab_cursor) = grab_cursor else {
+                bail!("window.grab_cursor must be a boolean")
+            };
+            options.grab_cursor = grab_cursor;
+        }
+
+        if let Some(hdr) = window_table.get("hdr") {
+            let &Value::Boolean(hdr) = hdr else {
+                bail!("window.hdr must be a boolean")
+            };
+            options.hdr = hdr;
+        }
+
+        if let Some(image_count) = window_table.get("image_count") {
+            let &Value::Integer(image_count) = image_count else {
+                bail!("window.image_count must be an integer")
+            };
+            if image_count < 1 {
+                bail!("window.image_count must be at least 1");
+            }
+            options.image_count = Some(image_count.try_into()?);
+        }
+
+        for (field, size) in [
+            ("width", &mut options.width),
+            ("height", &mut options.height),
+        ] {
+            if let Some(value) = window_table.get(field) {
+                let &Value::Integer(value) = value else {
+                    bail!("window.{field} must be an integer")
+                };
+                if value < 1 {
+                    bail!("window.{field} must be at least 1");
+                }
+                *size = value.try_into()?;
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn parse_toml_coordinates(conf: &Table) -> Result<CoordinateSystem> {
+        let mut coordinates = CoordinateSystem::default();
+
+        let Some(coordinates_table) = conf.get("coordinates") else {
+            return Ok(coordinates);
+        };
+        let Value::Table(coordinates_table) = coordinates_table else {
+            bail!("coordinates must be a table")
+        };
+
+        if let Some(handedness) = Self::get_optional_string(coordinates_table, "handedness")? {
+            coordinates.handedness = match handedness {
+                "left" => Handedness::Left,
+                "right" => Handedness::Right,
+                x => bail!("invalid coordinates.handedness: {x} - must be left or right"),
+            };
+        }
+        if let Some(up_axis) = Self::get_optional_string(coordinates_table, "up_axis")? {
+            coordinates.up_axis = match up_axis {
+                "y" => UpAxis::Y,
+                "z" => UpAxis::Z,
+                x => bail!("invalid coordinates.up_axis: {x} - must be y or z"),
+            };
+        }
+
+        Ok(coordinates)
+    }
+
+    // premultiplies everything in world space with to_world, see CoordinateSystem::to_world
+    // directional light geometry was made from the unconverted lights, but converting its transform the same
+    // way puts the disk at the converted position facing the converted direction, so it can just go along
+    fn convert_coordinates(
+        to_world: Mat4,
+        objects: &mut [Object],
+        lights: &mut [Light],
+        procedural_objects: &mut [ProceduralObject],
+    ) {
+        if to_world == Mat4::IDENTITY {
+            return;
+        }
+        // area lights emit from the side their world space winding says, which mirroring turns around
+        let mirrored = to_world.determinant() < 0.0;
+
+        for object in objects {
+            object.transform = to_world * object.transform;
+            object.previous_transform = to_world * object.previous_transform;
+        }
+        for object in procedural_objects {
+            object.transform = to_world * object.transform;
+        }
+        for light in lights {
+            match light {
+                Light::Point { position, .. } => *position = to_world.transform_point3(*position),
+                Light::Triangle { vertices, .. } => {
+                    *vertices = vertices.map(|v| to_world.transform_point3(v));
+                    if mirrored {
+                        vertices.swap(1, 2);
+                    }
+                }
+                Light::Directional {
+                    position,
+                    direction,
+                    ..
+                } => {
+                    *position = to_world.transform_point3(*position);
+                    *direction = to_world.transform_vector3(*direction);
+                }
+            }
+        }
+    }
+
     fn parse_toml_camera(conf: &Table) -> Result<Camera> {
         let Some(Value::Table(camera_table)) = conf.get("camera") else {
             bail!("camera must be a table")
         };
 
-        let fov = camera_table
-            .get("fov")
-            .ok_or(anyhow!("camera.fov must be set"))?;
-        let fov = match fov {
-            Value::Integer(x) => *x as f32,
-            Value::Float(x) => *x as f32,
-            _ => bail!("camera.fov must be an integer or float"),
+        let projection = match camera_table.get("projection") {
+            None => "perspective",
+            Some(Value::String(x)) => x,
+            Some(_) => bail!("camera.projection must be a string"),
+        };
+        let projection = match projection {
+            "perspective" => Projection::Perspective {
+                fov: Self::parse_toml_camera_f32(camera_table, "fov")?,
+            },
+            "orthographic" => Projection::Orthographic {
+                height: Self::parse_toml_camera_f32(camera_table, "ortho_height")?,
+            },
+            x => bail!("invalid camera.projection: {x} - must be perspective or orthographic"),
+        };
+
+        let coordinates = Self::parse_toml_coordinates(conf)?;
+        // in the scene's coordinates like everything else
+        let up = match camera_table.get("up") {
+            Some(up) => Self::parse_toml_vec3(up)?,
+            None => coordinates.up(),
+        };
+        if up.length_squared() < f32::EPSILON {
+            bail!("camera.up can't be zero");
+        }
+        let to_world = coordinates.to_world();
+        let up = to_world.transform_vector3(up);
+
+        let mut lens = Lens::default();
+        if camera_table.contains_key("aperture") {
+            lens.aperture = Self::parse_toml_camera_f32(camera_table, "aperture")?;
+            lens.focus_distance = Self::parse_toml_camera_f32(camera_table, "focus_distance")?;
+        }
+        if lens.aperture < 0f32 || lens.focus_distance <= 0f32 {
+            bail!(
+                "camera.aperture must not be negative and camera.focus_distance must be positive"
+            );
+        }
+
+        // either a view transform, or where the camera is and what it looks at
+        let mut camera = match (camera_table.get("view"), camera_table.get("position")) {
+            (Some(Value::String(view)), None) => {
+                let view = coordinates.convert_view(transform::parse_transform(view)?);
+                let mut camera = Camera::new(view, projection, lens);
+                camera.set_up(up);
+                camera
+            }
+            (Some(_), None) => bail!("camera.view must be a transform string"),
+            (None, Some(position)) => {
+                let target = camera_table
+                    .get("target")
+                    .ok_or(anyhow!("camera.position needs a camera.target"))?;
+                let position = to_world.transform_point3(Self::parse_toml_vec3(position)?);
+                let target = to_world.transform_point3(Self::parse_toml_vec3(target)?);
+                if (target - position).cross(up).length_squared() < f32::EPSILON {
+                    bail!("camera.target must be away from camera.position, and not straight along camera.up");
+                }
+                Camera::look_at(position, target, up, projection, lens)
+            }
+            (Some(_), Some(_)) => bail!("camera.view and camera.position can't both be set"),
+            (None, None) => bail!("camera.view or camera.position and camera.target must be set"),
+        };
+        if camera_table.contains_key("speed") {
+            let speed = Self::parse_toml_camera_f32(camera_table, "speed")?;
+            if speed <= 0f32 {
+                bail!("camera.speed must be greater than 0");
+            }
+            camera.set_speed(speed);
+        }
+        match camera_table.get("flip_y") {
+            None => (),
+            Some(&Value::Boolean(flip_y)) => camera.set_flip_y(flip_y),
+            Some(_) => bail!("camera.flip_y must be a boolean"),
+        }
+
+        Ok(camera)
+    }
+
+    fn parse_toml_camera_f32(camera_table: &Table, field: &str) -> Result<f32> {
+        let value = camera_table
+            .get(field)
+            .ok_or(anyhow!("camera.{field} must be set"))?;
+        match value {
+            Value::Integer(x) => Ok(*x as f32),
+            Value::Float(x) => Ok(*x as f32),
+            _ => bail!("camera.{field} must be an integer or float"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        f32::consts::PI,
+        ffi::{CStr, CString},
+        fs,
+    };
+
+    use ash::vk;
+    use glam::{Mat4, Vec3};
+    use rspirv::spirv::ExecutionModel;
+    use tobj::Mesh;
+    use toml::{Table, Value};
+    use winit::keyboard::KeyCode;
+
+    use super::{
+        render_options::DEFAULT_MAX_TRIANGLES, Aabb, Cull, DebugMode, FrontFace, Light, MeshScene,
+        Object, ProceduralObject, RenderOptions, Shader, WindowOptions, VISIBILITY_ALL,
+    };
+    use crate::{
+        camera::{Camera, Lens, Projection},
+        scene::{
+            coordinates::{CoordinateSystem, Handedness, UpAxis},
+            shader_archive::ShaderArchive,
+            spirv, Scene, ShaderRequirements,
+        },
+        window::WindowData,
+    };
+
+    const MIRROR_SCENE_PATH: &str = "resources/scenes/mirror.toml";
+
+    fn shaders(count: usize) -> Vec<Shader> {
+        (0..count)
+            .map(|i| Shader::Uncompiled(CString::new(format!("{i}.rchit")).unwrap(), Box::new([])))
+            .collect()
+    }
+
+    fn object(brdf_i: usize, brdf_params: &[u8]) -> Object {
+        Object {
+            transform: Mat4::IDENTITY,
+            mesh_i: 0,
+            brdf_i,
+            brdf_params: brdf_params.to_vec(),
+            vertex_index: 0,
+            custom_index: 0,
+            previous_transform: Mat4::IDENTITY,
+            visibility_mask: VISIBILITY_ALL,
+            cull: Cull::None,
+            front_face: FrontFace::default(),
+        }
+    }
+
+    #[test]
+    fn parent_transforms() {
+        let translate = |x: f32| Mat4::from_translation(Vec3::new(x, 0.0, 0.0));
+        let resolve = |names: &[Option<&str>], parents: &[Option<&str>]| {
+            let mut objects: Vec<_> = (0..names.len())
+                .map(|i| Object {
+                    transform: translate(10f32.powi(i as i32)),
+                    previous_transform: translate(10f32.powi(i as i32)),
+                    ..object(0, &[])
+                })
+                .collect();
+            objects[0].previous_transform = translate(5.0);
+            MeshScene::resolve_parent_transforms(&mut objects, names, parents).map(|()| objects)
+        };
+
+        // a chain where children come before their parents, and an unrelated object
+        let objects = resolve(
+            &[Some("hand"), Some("arm"), Some("body"), None],
+            &[Some("arm"), Some("body"), None, None],
+        )
+        .unwrap();
+        let x = |transform: Mat4| transform.w_axis.x;
+        let xs: Vec<_> = objects.iter().map(|o| x(o.transform)).collect();
+        assert_eq!(xs, [111.0, 110.0, 100.0, 1000.0]);
+        // only the hand moves, its parents don't have a previous transform of their own
+        assert_eq!(x(objects[0].previous_transform), 115.0);
+        assert!(!objects[1].is_moving());
+
+        assert!(resolve(&[Some("a"), Some("a")], &[None, None]).is_err());
+        assert!(resolve(&[Some("a")], &[Some("b")]).is_err());
+        let err = resolve(
+            &[Some("a"), Some("b"), None],
+            &[Some("b"), Some("a"), Some("a")],
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("cycle"), "{err}");
+        assert!(resolve(&[Some("a")], &[Some("a")]).is_err());
+    }
+
+    #[test]
+    fn brdf_params_interleaved_objects() {
+        let objects = [
+            object(0, &[1; 12]),
+            object(1, &[2; 8]),
+            object(0, &[3; 12]),
+            object(1, &[4; 8]),
+        ];
+        let (data, offsets) =
+            MeshScene::get_brdf_params_buffer_and_indices(&objects, &[], &shaders(2));
+
+        // brdf 0 fills bytes 0..24, which is already a multiple of brdf 1's size so there is no padding
+        let expected: Vec<u8> = [[1; 12], [3; 12]]
+            .concat()
+            .into_iter()
+            .chain([[2; 8], [4; 8]].concat())
+            .collect();
+        assert_eq!(data, expected);
+        assert_eq!(offsets, [0, 3, 1, 4]);
+    }
+
+    #[test]
+    fn brdf_params_padding() {
+        let objects = [object(0, &[1; 12]), object(1, &[2; 8]), object(1, &[3; 8])];
+        let (data, offsets) =
+            MeshScene::get_brdf_params_buffer_and_indices(&objects, &[], &shaders(2));
+
+        // 12 bytes of brdf 0 get padded to 16 so brdf 1 starts at index 2 of its own stride
+        let mut expected = vec![1; 12];
+        expected.extend([0; 4]);
+        expected.extend([2; 8]);
+        expected.extend([3; 8]);
+        assert_eq!(data, expected);
+        assert_eq!(offsets, [0, 2, 3]);
+    }
+
+    #[test]
+    fn brdf_params_empty_and_unused() {
+        // brdf 0 has no params (like the emitter), brdf 1 is never used, brdf 2 has a single float
+        let objects = [
+            object(0, &[]),
+            object(2, &[5; 4]),
+            object(0, &[]),
+            object(2, &[6; 4]),
+        ];
+        let (data, offsets) =
+            MeshScene::get_brdf_params_buffer_and_indices(&objects, &[], &shaders(3));
+
+        assert_eq!(data, [[5; 4], [6; 4]].concat());
+        assert_eq!(offsets, [0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn brdf_params_no_objects() {
+        let (data, offsets) = MeshScene::get_brdf_params_buffer_and_indices(&[], &[], &shaders(2));
+
+        assert!(data.is_empty());
+        assert!(offsets.is_empty());
+    }
+
+    fn mirror_scene_expected_params() -> (Vec<u8>, Vec<u32>) {
+        let diffuse: [f32; 9] = [0.2, 0.2, 0.8, 0.8, 0.2, 0.2, 0.6, 0.6, 0.6];
+        let mirror: [f32; 6] = [0.95, 0.95, 0.95, 0.9, 0.9, 0.7];
+        let data = bytemuck::cast_slice(&[&diffuse[..], &mirror[..]].concat()).to_vec();
+
+        // the area light comes last and uses the emitter, which has no params
+        (data, vec![0, 1, 3, 4, 2, 0])
+    }
+
+    #[test]
+    fn perfect_mirror_params() {
+        let conf: Table = fs::read_to_string(MIRROR_SCENE_PATH)
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        // same hit shader order and type map parse_toml_shaders would produce, minus loading the spir-v
+        let shaders: Vec<_> = ["emitter_hit", "diffuse", "perfect_mirror"]
+            .into_iter()
+            .map(|name| Shader::Uncompiled(CString::new(name).unwrap(), Box::new([])))
+            .collect();
+        let type_map = HashMap::from(["diffuse", "perfect_mirror"].map(|name| {
+            let types = vec![MeshScene::parse_type_str("vec3").unwrap()];
+            (name.to_string(), types)
+        }));
+
+        let (meshes, mesh_map) =
+            MeshScene::parse_toml_meshes(&conf, DEFAULT_MAX_TRIANGLES).unwrap();
+        let mut objects =
+            MeshScene::parse_toml_objects(&conf, &mesh_map, &meshes, &shaders, &type_map).unwrap();
+        MeshScene::parse_toml_lights(&conf, &mesh_map, &meshes, &mut objects).unwrap();
+
+        let brdf_is: Vec<_> = objects.iter().map(|x| x.brdf_i).collect();
+        assert_eq!(brdf_is, [1, 1, 2, 2, 1, 0]);
+
+        let params = MeshScene::get_brdf_params_buffer_and_indices(&objects, &[], &shaders);
+        assert_eq!(params, mirror_scene_expected_params());
+    }
+
+    #[test]
+    #[ignore = "requires compiled shaders"]
+    fn perfect_mirror_scene() {
+        let scene = MeshScene::load_from(fs::File::open(MIRROR_SCENE_PATH).unwrap()).unwrap();
+        assert_eq!(scene.mesh_aabbs.len(), scene.meshes.len());
+
+        let names: Vec<_> = scene.hit_shaders.iter().map(|x| x.name()).collect();
+        assert_eq!(names, [c"emitter_hit", c"diffuse", c"perfect_mirror"]);
+        assert_eq!(
+            (scene.brdf_buf, scene.offset_buf),
+            mirror_scene_expected_params()
+        );
+    }
+
+    #[test]
+    fn bom_and_crlf_scene() {
+        let mirror = fs::read_to_string(MIRROR_SCENE_PATH).unwrap();
+        let expected = MeshScene::parse_toml_conf(&mirror).unwrap();
+
+        let windows = format!("\u{feff}{}", mirror.replace('\n', "\r\n"));
+        let conf = MeshScene::parse_toml_conf(&windows).unwrap();
+        assert_eq!(conf, expected);
+        // multiline transforms now have \r\n line endings
+        let transform =
+            |conf: &Table| MeshScene::parse_toml_transform(&conf["light"][0]["transform"]).unwrap();
+        assert_eq!(transform(&conf), transform(&expected));
+    }
+
+    #[test]
+    fn duplicate_brdf_names() {
+        let check = |src: &str| {
+            let conf: Table = src.parse().unwrap();
+            MeshScene::check_brdf_names(conf["brdf"].as_array().unwrap())
+        };
+
+        assert!(check("[[brdf]]\nname = \"a\"\n[[brdf]]\nname = \"b\"").is_ok());
+        let err = check("[[brdf]]\nname = \"a\"\n[[brdf]]\nname = \"a\"")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("more than once"), "{err}");
+        assert!(check("[[brdf]]\nname = \"emitter_hit\"").is_err());
+        assert!(check("[[brdf]]\nname = \"flat_shaded\"").is_err());
+    }
+
+    #[test]
+    fn flat_shaded_brdf() {
+        let conf: Table = [
+            "[[object]]",
+            "mesh = \"cube.obj\"",
+            "transform = \"\"",
+            "brdf = {name = \"flat_shaded\"}",
+            "[[light]]",
+            "type = \"point\"",
+            "position = [0, 0, 5]",
+            "color = [1, 1, 1]",
+        ]
+        .join("\n")
+        .parse()
+        .unwrap();
+        assert!(MeshScene::uses_brdf(&conf, "flat_shaded"));
+        assert!(!MeshScene::uses_brdf(&conf, "diffuse"));
+
+        // what parse_toml_shaders adds for it
+        let shaders = [Shader::Uncompiled(
+            CString::new("flat_shaded").unwrap(),
+            Box::new([]),
+        )];
+        let type_map = HashMap::from([("flat_shaded".to_string(), Vec::new())]);
+
+        let (meshes, mesh_map) =
+            MeshScene::parse_toml_meshes(&conf, DEFAULT_MAX_TRIANGLES).unwrap();
+        let objects =
+            MeshScene::parse_toml_objects(&conf, &mesh_map, &meshes, &shaders, &type_map).unwrap();
+        assert_eq!(objects[0].brdf_i, 0);
+        assert!(objects[0].brdf_params.is_empty());
+
+        let (data, offsets) =
+            MeshScene::get_brdf_params_buffer_and_indices(&objects, &[], &shaders);
+        assert!(data.is_empty());
+        assert_eq!(offsets, [0]);
+    }
+
+    #[test]
+    fn mesh_aabbs() {
+        let conf: Table = fs::read_to_string(MIRROR_SCENE_PATH)
+            .unwrap()
+            .parse()
+            .unwrap();
+        let (meshes, _) = MeshScene::parse_toml_meshes(&conf, DEFAULT_MAX_TRIANGLES).unwrap();
+        for model in &meshes {
+            let aabb = Aabb::from_positions(&model.mesh.positions);
+            assert!(aabb.min.cmple(aabb.max).all());
+            for point in model.mesh.positions.chunks_exact(3).map(Vec3::from_slice) {
+                assert!(point.cmpge(aabb.min).all() && point.cmple(aabb.max).all());
+            }
+        }
+
+        let unit = Aabb::from_positions(&[0.0, 0.0, 0.0, 1.0, 2.0, 0.5, -1.0, 0.0, 0.0]);
+        assert_eq!(
+            unit,
+            Aabb {
+                min: Vec3::new(-1.0, 0.0, 0.0),
+                max: Vec3::new(1.0, 2.0, 0.5),
+            }
+        );
+        assert_eq!(Aabb::from_positions(&[]), Aabb::EMPTY);
+
+        // rotating a quarter turn around z swaps x and y, then it's moved up
+        let transform = Mat4::from_translation(Vec3::Z) * Mat4::from_rotation_z(PI / 2.0);
+        let world = unit.transformed(transform);
+        assert!(world.min.abs_diff_eq(Vec3::new(-2.0, -1.0, 1.0), 1e-5));
+        assert!(world.max.abs_diff_eq(Vec3::new(0.0, 1.0, 1.5), 1e-5));
+        assert_eq!(Aabb::EMPTY.transformed(transform), Aabb::EMPTY);
+        assert_eq!(unit.union(&Aabb::EMPTY), unit);
+    }
+
+    fn quad() -> tobj::Model {
+        let mesh = tobj::Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0],
+            indices: vec![0, 1, 2, 2, 1, 3],
+            ..Default::default()
+        };
+        tobj::Model::new(mesh, "quad".to_string())
+    }
+
+    // a scene with just these objects and meshes, the buffers aren't filled in
+    fn scene(objects: Vec<Object>, meshes: Vec<tobj::Model>) -> MeshScene {
+        let shader = || Shader::Uncompiled(CString::new("unused").unwrap(), Box::new([]));
+        MeshScene {
+            camera: Camera::new(
+                Mat4::IDENTITY,
+                Projection::Perspective { fov: 60.0 },
+                Lens::default(),
+            ),
+            lights: Vec::new(),
+            light_entries: Vec::new(),
+            environment: None,
+            objects,
+            meshes,
+            mesh_aabbs: Vec::new(),
+            mesh_tangents: Vec::new(),
+            raygen_shader: shader(),
+            miss_shader: shader(),
+            hit_shaders: Vec::new(),
+            normal_maps: Vec::new(),
+            procedural_geometries: Vec::new(),
+            procedural_objects: Vec::new(),
+            brdf_buf: Vec::new(),
+            offset_buf: Vec::new(),
+            instance_id_buf: Vec::new(),
+            render_options: RenderOptions::default(),
+            window_options: WindowOptions::default(),
+        }
+    }
+
+    #[test]
+    fn shader_requirements_any_hit() {
+        let mut scene = scene(Vec::new(), Vec::new());
+        assert_eq!(
+            scene.shader_requirements(),
+            ShaderRequirements {
+                triangle_hit_groups: 0,
+                procedural_hit_groups: 0,
+                any_hit: false,
+                callable: false
+            }
+        );
+
+        let shader = |model| {
+            Shader::Uncompiled(
+                CString::new("test").unwrap(),
+                spirv::entry_point_module(model).into(),
+            )
+        };
+        scene.hit_shaders = vec![
+            shader(ExecutionModel::ClosestHitKHR),
+            shader(ExecutionModel::AnyHitKHR),
+        ];
+        let requirements = scene.shader_requirements();
+        assert_eq!(requirements.triangle_hit_groups, 2);
+        assert!(requirements.any_hit && !requirements.callable);
+    }
+
+    #[test]
+    fn world_triangles() {
+        let mut moved = object(0, &[]);
+        moved.transform = Mat4::from_translation(Vec3::new(0.0, 0.0, 5.0));
+        let scene = scene(vec![object(0, &[]), moved], vec![quad()]);
+
+        let triangles: Vec<_> = scene.world_triangles().collect();
+        assert_eq!(triangles.len(), 4);
+        assert_eq!(triangles[1], [Vec3::Y, Vec3::X, Vec3::new(1.0, 1.0, 0.0)]);
+        assert_eq!(
+            triangles[2],
+            [
+                Vec3::new(0.0, 0.0, 5.0),
+                Vec3::new(1.0, 0.0, 5.0),
+                Vec3::new(0.0, 1.0, 5.0)
+            ]
+        );
+        assert!(scene.object_meshes().all(|(_, model)| model.name == "quad"));
+        assert_eq!(
+            scene.world_bounds(),
+            Aabb {
+                min: Vec3::ZERO,
+                max: Vec3::new(1.0, 1.0, 5.0)
+            }
+        );
+    }
+
+    #[test]
+    fn validate_scene() {
+        let valid = || {
+            let second = Object {
+                mesh_i: 1,
+                vertex_index: 6,
+                ..object(1, &[])
+            };
+            let mut scene = scene(vec![object(0, &[]), second], vec![quad(), quad()]);
+            scene.hit_shaders = shaders(2);
+            scene.mesh_aabbs = vec![Aabb::EMPTY; 2];
+            scene.mesh_tangents = vec![vec![[0.0; 4]; 4]; 2];
+            scene.normal_maps = vec![None; 2];
+            scene.offset_buf = vec![0; 2];
+            scene.instance_id_buf = vec![0; 2];
+            scene
+        };
+        valid().validate().unwrap();
+
+        let mut scene = valid();
+        scene.objects[1].mesh_i = 2;
+        assert!(scene.validate().is_err());
+        let mut scene = valid();
+        scene.objects[1].brdf_i = 2;
+        assert!(scene.validate().is_err());
+        let mut scene = valid();
+        scene.objects[1].vertex_index = 0;
+        let err = scene.validate().unwrap_err();
+        assert!(err.to_string().contains("mesh 1 starts at 6"), "{err}");
+        let mut scene = valid();
+        scene.instance_id_buf.pop();
+        assert!(scene.validate().is_err());
+        let mut scene = valid();
+        scene.mesh_tangents[1].pop();
+        assert!(scene.validate().is_err());
+
+        // area lights index the light list, which needs one triangle light per triangle
+        let mut scene = valid();
+        scene.hit_shaders[0] = Shader::Uncompiled(c"emitter_hit".into(), Box::new([]));
+        let triangle = Light::Triangle {
+            color: Vec3::ONE,
+            vertices: [Vec3::ZERO; 3],
+            samples: 1,
+        };
+        scene.lights = vec![triangle.clone()];
+        assert!(scene.validate().is_err());
+        scene.lights.push(triangle);
+        scene.validate().unwrap();
+    }
+
+    #[test]
+    fn area_light_indices() {
+        let triangle = tobj::Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        };
+        let quad = tobj::Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0],
+            indices: vec![0, 1, 2, 2, 1, 3],
+            ..Default::default()
+        };
+        let meshes = [
+            tobj::Model::new(triangle, "triangle".to_string()),
+            tobj::Model::new(quad, "quad".to_string()),
+        ];
+        let mesh_map =
+            HashMap::from([("triangle.obj".to_string(), 0), ("quad.obj".to_string(), 1)]);
+        let conf: Table = r#"
+            [[light]]
+            type = "area"
+            color = [1, 0, 0]
+            mesh = "quad.obj"
+            transform = "translate 0 0 2"
+
+            [[light]]
+            type = "point"
+            color = [0, 1, 0]
+            position = [0, 0, 0]
+
+            [[light]]
+            type = "area"
+            color = [0, 0, 1]
+            mesh = "triangle.obj"
+            transform = "translate 0 0 3"
+        "#
+        .parse()
+        .unwrap();
+
+        let mut objects = vec![object(1, &[])];
+        let (lights, entries) =
+            MeshScene::parse_toml_lights(&conf, &mesh_map, &meshes, &mut objects).unwrap();
+
+        // one light per triangle, the point light in between
+        let triangles: Vec<_> = lights
+            .iter()
+            .enumerate()
+            .filter_map(|(i, light)| match light {
+                Light::Triangle {
+                    color, vertices, ..
+                } => Some((i, *color, vertices[0].z)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            triangles,
+            [(0, Vec3::X, 2.0), (1, Vec3::X, 2.0), (3, Vec3::Z, 3.0)]
+        );
+        // and each [[light]] remembers which lights it became
+        assert_eq!(entries, [0..2, 2..3, 3..4]);
+
+        // the emitter objects point at their first triangle, which is what the custom index gets
+        let emitters: Vec<_> = objects[1..]
+            .iter()
+            .map(|x| (x.brdf_i, x.mesh_i, x.vertex_index))
+            .collect();
+        assert_eq!(emitters, [(0, 1, 0), (0, 0, 3)]);
+        // and shaders find the triangle they hit by adding the primitive id
+        let Light::Triangle { vertices, .. } = &lights[objects[1].vertex_index as usize + 1] else {
+            panic!("expected a triangle light");
+        };
+        assert_eq!(vertices[2], Vec3::new(1.0, 1.0, 2.0));
+    }
+
+    #[test]
+    fn shader_from_archive() {
+        let mut file = b"KGSA".to_vec();
+        for x in [1, 12] {
+            file.extend_from_slice(&u32::to_le_bytes(x));
+        }
+        file.extend_from_slice(b"packed.rchit");
+        file.extend_from_slice(&u32::to_le_bytes(4));
+        file.extend_from_slice(&u32::to_le_bytes(0x07230203));
+        let archive = ShaderArchive::from_reader(&file[..]).unwrap();
+        let parse = |name: &str| {
+            MeshScene::parse_toml_shader(&Value::String(name.to_string()), "hit", Some(&archive))
+        };
+
+        let shader = parse("packed.rchit").unwrap();
+        assert_eq!(shader.name(), c"hit");
+        assert!(matches!(shader, Shader::Uncompiled(..)));
+        assert_eq!(shader.code(), [0x07230203]);
+        // and the code stays around once there's a module
+        let compiled = Shader::Compiled(
+            c"hit".into(),
+            [0x07230203].into(),
+            ash::vk::ShaderModule::null(),
+        );
+        assert_eq!(compiled.code(), shader.code());
+
+        // anything else still comes from resources/shaders/spv/
+        assert!(parse("not_packed.rchit").is_err());
+    }
+
+    #[test]
+    fn emitter_hit_required() {
+        let shader = |name: &CStr| Shader::Uncompiled(name.into(), Box::new([]));
+        let lights = [
+            Light::Point {
+                color: Vec3::ONE,
+                position: Vec3::ZERO,
+                samples: 1,
+            },
+            Light::Triangle {
+                color: Vec3::ONE,
+                vertices: [Vec3::ZERO, Vec3::X, Vec3::Y],
+                samples: 1,
+            },
+        ];
+
+        let with_emitter = [shader(c"emitter_hit"), shader(c"diffuse")];
+        let without_emitter = [shader(c"diffuse")];
+        assert!(MeshScene::check_emitter_hit(&lights, &with_emitter).is_ok());
+        // only area lights use it
+        assert!(MeshScene::check_emitter_hit(&lights[..1], &without_emitter).is_ok());
+        let err = MeshScene::check_emitter_hit(&lights, &without_emitter).unwrap_err();
+        assert!(err.to_string().contains("emitter_hit"), "{err}");
+    }
+
+    #[test]
+    fn light_watts() {
+        let square = tobj::Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0],
+            indices: vec![0, 1, 2, 2, 1, 3],
+            ..Default::default()
+        };
+        let meshes = [tobj::Model::new(square, "square".to_string())];
+        let mesh_map = HashMap::from([("square.obj".to_string(), 0)]);
+        let parse = |src: &str| {
+            MeshScene::parse_toml_lights(&src.parse().unwrap(), &mesh_map, &meshes, &mut Vec::new())
+                .map(|(lights, _)| lights)
+        };
+        let color = |light: &Light| match light {
+            Light::Point { color, .. }
+            | Light::Triangle { color, .. }
+            | Light::Directional { color, .. } => *color,
+        };
+        let close = |a: Vec3, b: Vec3| (a - b).abs().max_element() < 1e-5;
+
+        let lights = parse(
+            r#"
+            [[light]]
+            type = "point"
+            color = [1, 0.5, 0]
+            position = [0, 0, 0]
+            unit = "watts"
+            intensity = 100
+
+            [[light]]
+            type = "area"
+            color = [1, 1, 1]
+            mesh = "square.obj"
+            transform = "scale 2 2 1"
+            unit = "watts"
+            intensity = 100
+
+            [[light]]
+            type = "directional"
+            color = [1, 1, 1]
+            position = [0, 0, 0]
+            direction = [0, 0, -1]
+            radius = 2
+            unit = "watts"
+            intensity = 100
+
+            [[light]]
+            type = "point"
+            color = [3, 3, 3]
+            position = [0, 0, 0]
+        "#,
+        )
+        .unwrap();
+
+        // 100 W over 4 pi sr, over pi times the 4 square units of the (two triangle) area light,
+        // and over the beam's pi r^2 cross section, which all come out the same here
+        let expected = 100.0 / (4.0 * PI);
+        assert!(close(
+            color(&lights[0]),
+            Vec3::new(1.0, 0.5, 0.0) * expected
+        ));
+        assert!(close(color(&lights[1]), Vec3::splat(expected)));
+        assert!(close(color(&lights[2]), Vec3::splat(expected)));
+        assert!(close(color(&lights[3]), Vec3::splat(expected)));
+        assert_eq!(color(&lights[4]), Vec3::splat(3.0));
+
+        let point = "[[light]]\ntype = \"point\"\ncolor = [1, 1, 1]\nposition = [0, 0, 0]\n";
+        assert!(close(
+            color(&parse(&format!("{point}unit = \"watts\"")).unwrap()[0]),
+            Vec3::splat(1.0 / (4.0 * PI))
+        ));
+        assert!(parse(&format!("{point}intensity = 10")).is_err());
+        assert!(parse(&format!("{point}unit = \"lumens\"\nintensity = 10")).is_err());
+        assert!(parse(&format!("{point}unit = \"watts\"\nintensity = -1")).is_err());
+    }
+
+    #[test]
+    fn area_light_power_subdivided() {
+        // the same unit square as two triangles and as four around its center
+        let halves = tobj::Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0],
+            indices: vec![0, 1, 2, 2, 1, 3],
+            ..Default::default()
+        };
+        let quarters = tobj::Mesh {
+            positions: vec![
+                0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.5, 0.5, 0.0,
+            ],
+            indices: vec![0, 1, 4, 1, 3, 4, 3, 2, 4, 2, 0, 4],
+            ..Default::default()
+        };
+        let meshes = [
+            tobj::Model::new(halves, "halves".to_string()),
+            tobj::Model::new(quarters, "quarters".to_string()),
+        ];
+        let mesh_map = HashMap::from([
+            ("halves.obj".to_string(), 0),
+            ("quarters.obj".to_string(), 1),
+        ]);
+        let parse = |mesh: &str, unit: &str| {
+            let src =
+                format!("[[light]]\ntype = \"area\"\ncolor = [5, 5, 5]\nmesh = \"{mesh}\"\ntransform = \"scale 1 1 1\"\n{unit}");
+            MeshScene::parse_toml_lights(&src.parse().unwrap(), &mesh_map, &meshes, &mut Vec::new())
+                .unwrap()
+                .0
+        };
+        // total power of an area light, every triangle emits pi times its area times its radiance
+        let power = |lights: &[Light]| -> Vec3 {
+            lights
+                .iter()
+                .map(|light| match light {
+                    Light::Triangle {
+                        vertices: [a, b, c],
+                        color,
+                        ..
+                    } => *color * PI * 0.5 * (*b - *a).cross(*c - *a).length(),
+                    _ => unreachable!(),
+                })
+                .sum()
+        };
+        let close = |a: Vec3, b: Vec3| (a - b).abs().max_element() < 1e-4;
+
+        // by default every triangle gets the color as its radiance, so the power goes with the area
+        let (halves, quarters) = (parse("halves.obj", ""), parse("quarters.obj", ""));
+        assert_eq!((halves.len(), quarters.len()), (2, 4));
+        assert!(close(power(&halves), power(&quarters)));
+
+        // in watts the color is the total power, however the mesh is split up
+        let unit = "unit = \"watts\"";
+        let (halves, quarters) = (parse("halves.obj", unit), parse("quarters.obj", unit));
+        assert!(close(power(&halves), Vec3::splat(5.0)));
+        assert!(close(power(&quarters), Vec3::splat(5.0)));
+    }
+
+    #[test]
+    fn object_transform_at() {
+        let mut moving = object(0, &[]);
+        moving.previous_transform = Mat4::from_translation(Vec3::new(-2.0, 0.0, 0.0));
+        moving.transform = Mat4::from_translation(Vec3::new(2.0, 4.0, 0.0));
+
+        assert!(moving.is_moving());
+        assert!(moving
+            .transform_at(0.0)
+            .abs_diff_eq(moving.previous_transform, 1e-5));
+        assert!(moving.transform_at(1.0).abs_diff_eq(moving.transform, 1e-5));
+        assert!(moving
+            .transform_at(0.5)
+            .abs_diff_eq(Mat4::from_translation(Vec3::new(0.0, 2.0, 0.0)), 1e-5));
+
+        let still = object(0, &[]);
+        assert!(!still.is_moving());
+        assert_eq!(still.transform_at(0.3), still.transform);
+    }
+
+    #[test]
+    fn brdf_params_procedural_records() {
+        let objects = [object(0, &[]), object(1, &[1; 4])];
+        let procedural = |geometry_index, params: &[u8]| ProceduralObject {
+            transform: Mat4::IDENTITY,
+            geometry_index,
+            custom_index: 0,
+            params: params.to_vec(),
+            visibility_mask: VISIBILITY_ALL,
+        };
+        let procedural_objects = [
+            procedural(1, &[2; 12]),
+            procedural(0, &[]),
+            procedural(1, &[3; 12]),
+        ];
+        let (data, offsets) = MeshScene::get_brdf_params_buffer_and_indices(
+            &objects,
+            &procedural_objects,
+            &shaders(2),
+        );
+
+        // procedural geometry 1 is record 3, after the 2 triangle records and procedural geometry 0
+        let mut expected = vec![1; 4];
+        expected.extend([0; 8]);
+        expected.extend([2; 12]);
+        expected.extend([3; 12]);
+        assert_eq!(data, expected);
+        assert_eq!(offsets, [0, 0, 1, 0, 2]);
+    }
+
+    #[test]
+    fn instance_ids() {
+        let parse = |src: &str| MeshScene::parse_toml_custom_index(&src.parse().unwrap());
+        assert_eq!(parse("").unwrap(), 0);
+        assert_eq!(parse("custom_index = 42").unwrap(), 42);
+        assert!(parse("custom_index = \"42\"").is_err());
+        // out of range instead of wrapping around
+        let err = parse("custom_index = -1").unwrap_err().to_string();
+        assert!(err.contains("custom_index"), "{err}");
+        assert!(parse("custom_index = 4294967296").is_err());
+
+        let objects = [
+            Object {
+                custom_index: 7,
+                ..object(0, &[])
+            },
+            object(1, &[]),
+        ];
+        let procedural = ProceduralObject {
+            transform: Mat4::IDENTITY,
+            geometry_index: 0,
+            custom_index: 3,
+            params: Vec::new(),
+            visibility_mask: VISIBILITY_ALL,
+        };
+        // triangle instances come first in the tlas, then procedural ones
+        assert_eq!(
+            MeshScene::get_instance_ids(&objects, &[procedural]),
+            [7, 0, 3]
+        );
+    }
+
+    #[test]
+    fn debug_mode_cycles() {
+        let mut mode = DebugMode::default();
+        assert_eq!(mode, DebugMode::Off);
+
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            mode = mode.next();
+            seen.push(mode as u32);
+        }
+        assert_eq!(seen, [1, 2, 0]);
+    }
+
+    #[test]
+    fn visibility_mask_parsing() {
+        let parse =
+            |src: &str| MeshScene::parse_toml_visibility_mask(&src.parse::<Table>().unwrap());
+
+        assert_eq!(parse("").unwrap(), VISIBILITY_ALL);
+        assert_eq!(parse("visibility_mask = 0b101").unwrap(), 5);
+        assert_eq!(parse("visibility_mask = 0").unwrap(), 0);
+        assert!(parse("visibility_mask = 256").is_err());
+        assert!(parse("visibility_mask = -1").is_err());
+        assert!(parse("visibility_mask = \"camera\"").is_err());
+    }
+
+    #[test]
+    fn cull_parsing() {
+        let parse = |src: &str| MeshScene::parse_toml_cull(&src.parse::<Table>().unwrap());
+
+        assert_eq!(parse("").unwrap(), Cull::None);
+        assert_eq!(parse("cull = \"none\"").unwrap(), Cull::None);
+        assert_eq!(parse("cull = \"back\"").unwrap(), Cull::Back);
+        assert_eq!(parse("cull = \"front\"").unwrap(), Cull::Front);
+        assert!(parse("cull = \"both\"").is_err());
+        assert!(parse("cull = true").is_err());
+    }
+
+    #[test]
+    fn front_face_instance_flags() {
+        let parse = |src: &str| MeshScene::parse_toml_front_face(&src.parse::<Table>().unwrap());
+        assert_eq!(parse("").unwrap(), FrontFace::CounterClockwise);
+        assert_eq!(parse("front_face = \"cw\"").unwrap(), FrontFace::Clockwise);
+        assert!(parse("front_face = \"left\"").is_err());
+        assert!(parse("front_face = 1").is_err());
+
+        let flags = |cull, front_face| {
+            Object {
+                cull,
+                front_face,
+                ..object(0, &[])
+            }
+            .instance_flags()
+        };
+        let none = vk::GeometryInstanceFlagsKHR::empty();
+        let flip = vk::GeometryInstanceFlagsKHR::TRIANGLE_FLIP_FACING;
+        let ccw = FrontFace::CounterClockwise;
+        let cw = FrontFace::Clockwise;
+        assert_eq!(flags(Cull::Back, ccw), none);
+        assert_eq!(flags(Cull::Front, ccw), flip);
+        assert_eq!(flags(Cull::Back, cw), flip);
+        assert_eq!(flags(Cull::Front, cw), none);
+        assert_eq!(
+            flags(Cull::None, cw),
+            vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE
+        );
+
+        // a right-handed scene mirrors everything on load. the clockwise object stays flipped since facing is
+        // decided in object space, but the area light's world space winding has to be turned back around
+        let parse = |coordinates: &str| {
+            let conf: Table = [
+                coordinates,
+                "[[object]]",
+                "mesh = \"cube.obj\"",
+                "transform = \"translate 0 1 0\"",
+                "cull = \"back\"",
+                "front_face = \"cw\"",
+                "brdf = {name = \"flat_shaded\"}",
+                "[[light]]",
+                "type = \"area\"",
+                "color = [1, 1, 1]",
+                "mesh = \"square.obj\"",
+                "transform = \"rotate 30 1 0 0\\ntranslate 0 0 3\"",
+            ]
+            .join("\n")
+            .parse()
+            .unwrap();
+            let shaders = [Shader::Uncompiled(
+                CString::new("flat_shaded").unwrap(),
+                Box::new([]),
+            )];
+            let type_map = HashMap::from([("flat_shaded".to_string(), Vec::new())]);
+            let (meshes, mesh_map) =
+                MeshScene::parse_toml_meshes(&conf, DEFAULT_MAX_TRIANGLES).unwrap();
+            let mut objects =
+                MeshScene::parse_toml_objects(&conf, &mesh_map, &meshes, &shaders, &type_map)
+                    .unwrap();
+            let (mut lights, _) =
+                MeshScene::parse_toml_lights(&conf, &mesh_map, &meshes, &mut objects).unwrap();
+            let to_world = MeshScene::parse_toml_coordinates(&conf).unwrap().to_world();
+            MeshScene::convert_coordinates(to_world, &mut objects, &mut lights, &mut []);
+            (to_world, objects, lights)
+        };
+        let (_, authored_objects, authored_lights) = parse("");
+        let (to_world, objects, lights) = parse("[coordinates]\nhandedness = \"right\"");
+        assert!(to_world.determinant() < 0.0);
+        assert!(objects[0].transform.determinant() < 0.0);
+        assert_eq!(objects[0].instance_flags(), flip);
+        assert_eq!(
+            objects[0].instance_flags(),
+            authored_objects[0].instance_flags()
+        );
+
+        let normal = |light: &Light| {
+            let Light::Triangle {
+                vertices: [a, b, c],
+                ..
+            } = *light
+            else {
+                panic!("expected a triangle light");
+            };
+            (b - a).cross(c - a).normalize()
+        };
+        assert!(!lights.is_empty());
+        for (light, authored) in lights.iter().zip(&authored_lights) {
+            let expected = to_world.transform_vector3(normal(authored));
+            assert!(normal(light).abs_diff_eq(expected, 1e-5));
+        }
+    }
+
+    #[test]
+    fn missing_mesh_file_error() {
+        let conf: Table = r#"
+            light = []
+            [[object]]
+            mesh = "does_not_exist.obj"
+        "#
+        .parse()
+        .unwrap();
+
+        let err = MeshScene::parse_toml_meshes(&conf, DEFAULT_MAX_TRIANGLES)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("mesh file not found"), "{err}");
+        assert!(err.contains("does_not_exist.obj"), "{err}");
+    }
+
+    #[test]
+    fn max_triangles() {
+        // square.obj is two triangles, cube.obj twelve
+        let conf: Table = r#"
+            [[object]]
+            mesh = "square.obj"
+            [[light]]
+            type = "area"
+            mesh = "cube.obj"
+        "#
+        .parse()
+        .unwrap();
+
+        assert!(MeshScene::parse_toml_meshes(&conf, 14).is_ok());
+        let err = MeshScene::parse_toml_meshes(&conf, 13)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("\"cube.obj\""), "{err}");
+    }
+
+    #[test]
+    fn mesh_normals_checked() {
+        let mut mesh = Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        };
+        MeshScene::check_mesh_normals(&mut mesh, "triangle.obj").unwrap();
+        assert_eq!(mesh.normals, [0.0, 0.0, 1.0].repeat(3));
+
+        mesh.normals.truncate(6);
+        let err = MeshScene::check_mesh_normals(&mut mesh, "triangle.obj")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("triangle.obj"), "{err}");
+        assert!(err.contains("2 normals but 3 positions"), "{err}");
+    }
+
+    #[test]
+    fn mesh_tangents() {
+        let mut mesh = quad().mesh;
+        mesh.normals = [0.0, 0.0, 1.0].repeat(4);
+        assert_eq!(MeshScene::generate_tangents(&mesh), [[0.0; 4]; 4]);
+
+        // u along x and v along y, so the bitangent is +y = cross(normal, tangent)
+        mesh.texcoords = vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        MeshScene::check_mesh_texcoords(&mesh, "quad.obj").unwrap();
+        assert_eq!(
+            MeshScene::generate_tangents(&mesh),
+            [[1.0, 0.0, 0.0, 1.0]; 4]
+        );
+
+        // v mirrored, so the bitangent points the other way
+        mesh.texcoords = vec![0.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0];
+        assert_eq!(
+            MeshScene::generate_tangents(&mesh),
+            [[1.0, 0.0, 0.0, -1.0]; 4]
+        );
+
+        // every vertex on a line in uv space
+        mesh.texcoords = vec![0.0, 0.0, 1.0, 1.0, 2.0, 2.0, 3.0, 3.0];
+        assert_eq!(MeshScene::generate_tangents(&mesh), [[0.0; 4]; 4]);
+
+        mesh.texcoords.truncate(6);
+        let err = MeshScene::check_mesh_texcoords(&mesh, "quad.obj").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("3 texture coordinates but 4 positions"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn normal_map_option() {
+        let parse = |src: &str| MeshScene::parse_toml_normal_map(&src.parse().unwrap());
+        assert!(parse("").unwrap().is_none());
+        assert!(parse("normal_map = 1").is_err());
+
+        let err = parse("normal_map = \"does_not_exist.png\"").unwrap_err();
+        assert!(err.to_string().contains("does_not_exist.png"), "{err}");
+    }
+
+    #[test]
+    fn environment_options() {
+        let parse = |src: &str| MeshScene::parse_toml_environment(&src.parse().unwrap());
+
+        assert!(parse("").unwrap().is_none());
+        assert!(parse("environment = 1").is_err());
+        assert!(parse("[environment]\nstrength = 2").is_err());
+        assert!(parse("[environment]\nfile = \"sky.hdr\"\nstrength = -1").is_err());
+
+        let err = parse("[environment]\nfile = \"does_not_exist.hdr\"")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("does_not_exist.hdr"), "{err}");
+    }
+
+    #[test]
+    fn directional_light_geometry_scale() {
+        let geometry_scales = |src: &str| -> Vec<(f32, f32)> {
+            let conf: Table = src.parse().unwrap();
+            let (lights, _) =
+                MeshScene::parse_toml_lights(&conf, &HashMap::new(), &[], &mut Vec::new()).unwrap();
+            lights
+                .iter()
+                .map(|x| match x {
+                    Light::Directional {
+                        radius,
+                        geometry_scale,
+                        ..
+                    } => (*radius, *geometry_scale),
+                    _ => panic!("expected a directional light"),
+                })
+                .collect()
         };
 
-        let Value::String(view_str) = camera_table
-            .get("view")
-            .ok_or(anyhow!("camera.view must be set"))?
+        let light = r#"
+            [[light]]
+            type = "directional"
+            color = [1, 1, 1]
+            position = [0, 0, 10]
+            direction = [0, 0, -1]
+            radius = 0.5
+        "#;
+        assert_eq!(geometry_scales(light), [(0.5, 0.5)]);
+        assert_eq!(
+            geometry_scales(&format!("{light}geometry_scale = 2")),
+            [(0.5, 2.0)]
+        );
+
+        let conf: Table = format!("{light}geometry_scale = 0").parse().unwrap();
+        assert!(
+            MeshScene::parse_toml_lights(&conf, &HashMap::new(), &[], &mut Vec::new()).is_err()
+        );
+    }
+
+    #[test]
+    fn light_samples() {
+        let samples = |src: &str| -> anyhow::Result<Vec<u32>> {
+            let conf: Table = format!(
+                "[[light]]\ntype = \"point\"\ncolor = [1, 1, 1]\nposition = [0, 0, 0]\n{src}"
+            )
+            .parse()
+            .unwrap();
+            let (lights, _) =
+                MeshScene::parse_toml_lights(&conf, &HashMap::new(), &[], &mut Vec::new())?;
+            Ok(lights.iter().map(Light::samples).collect())
+        };
+
+        assert_eq!(samples("").unwrap(), [1]);
+        assert_eq!(samples("samples = 8").unwrap(), [8]);
+        assert!(samples("samples = 0").is_err());
+        assert!(samples("samples = 1.5").is_err());
+    }
+
+    #[test]
+    fn directional_geometry_optional() {
+        let conf: Table = r#"
+            [[light]]
+            type = "directional"
+            color = [1, 1, 1]
+            position = [0, 0, 10]
+            direction = [0, 0, -1]
+            radius = 0.5
+        "#
+        .parse()
+        .unwrap();
+        let (lights, _) =
+            MeshScene::parse_toml_lights(&conf, &HashMap::new(), &[], &mut Vec::new()).unwrap();
+
+        // the disc needs global_shaders.directional_emitter_*, which this scene doesn't have
+        let err = MeshScene::parse_procedural_geometries(&conf, &lights, true, None).unwrap_err();
+        assert!(err.to_string().contains("directional"), "{err}");
+        let (geometries, objects) =
+            MeshScene::parse_procedural_geometries(&conf, &lights, false, None).unwrap();
+        assert!(geometries.is_empty() && objects.is_empty());
+    }
+
+    #[test]
+    fn window_options_size() {
+        let parse = |src: &str| MeshScene::parse_toml_window_options(&src.parse().unwrap());
+
+        let options = parse("").unwrap();
+        assert_eq!(
+            (options.width, options.height),
+            (WindowData::DEFAULT_WIDTH, WindowData::DEFAULT_HEIGHT)
+        );
+        let options = parse("[window]\nwidth = 1280\nheight = 720").unwrap();
+        assert_eq!((options.width, options.height), (1280, 720));
+        let options = parse("[window]\nwidth = 640").unwrap();
+        assert_eq!(
+            (options.width, options.height),
+            (640, WindowData::DEFAULT_HEIGHT)
+        );
+        assert!(parse("[window]\nheight = 0").is_err());
+        assert!(parse("[window]\nwidth = 1.5").is_err());
+    }
+
+    #[test]
+    fn window_options_hdr() {
+        let parse = |src: &str| MeshScene::parse_toml_window_options(&src.parse().unwrap());
+
+        assert!(!parse("").unwrap().hdr);
+        assert!(parse("[window]\nhdr = true").unwrap().hdr);
+        assert!(parse("[window]\nhdr = 1").is_err());
+    }
+
+    #[test]
+    fn camera_speed() {
+        let parse = |src: &str| {
+            let conf = format!("[camera]\nview = \"lookat 0 0 0  1 0 0  0 0 1\"\nfov = 60\n{src}");
+            MeshScene::parse_toml_camera(&conf.parse().unwrap())
+        };
+        // holding w for a second moves the camera speed units along its view direction
+        let moved = |mut camera: Camera| {
+            camera.handle_key_input(KeyCode::KeyW, true);
+            camera.handle_movement(1.0);
+            camera
+                .update_view()
+                .unwrap()
+                .inverse()
+                .col(3)
+                .truncate()
+                .length()
+        };
+
+        assert!((moved(parse("").unwrap()) - Camera::SPEED).abs() < 1e-5);
+        assert!((moved(parse("speed = 50").unwrap()) - 50.0).abs() < 1e-4);
+        assert!((moved(parse("speed = 0.25").unwrap()) - 0.25).abs() < 1e-5);
+        assert!(parse("speed = 0").is_err());
+        assert!(parse("speed = \"fast\"").is_err());
+    }
+
+    #[test]
+    fn camera_up() {
+        let parse = |src: &str| {
+            let conf = format!("[camera]\nfov = 60\n{src}");
+            MeshScene::parse_toml_camera(&conf.parse().unwrap())
+        };
+        let rise = |mut camera: Camera| {
+            camera.handle_key_input(KeyCode::Space, true);
+            camera.handle_movement(1.0);
+            camera.update_view().unwrap().inverse().col(3).truncate() / Camera::SPEED
+        };
+        let close = |a: Vec3, b: Vec3| (a - b).abs().max_element() < 1e-5;
+
+        // z up by default, y up when the scene says so
+        let z_up = parse("view = \"lookat 0 0 0  1 0 0  0 0 1\"").unwrap();
+        assert!(close(rise(z_up), Vec3::Z));
+        let y_up = parse("view = \"lookat 0 0 0  1 0 0  0 1 0\"\nup = [0, 2, 0]").unwrap();
+        assert!(close(rise(y_up), Vec3::Y));
+
+        // and the view stays level, with camera space +y pointing along the up vector
+        let mut y_up = parse("view = \"lookat 0 0 0  1 0 0  0 1 0\"\nup = [0, 1, 0]").unwrap();
+        y_up.handle_mouse_input(0.3, 0.2);
+        let view = y_up.update_view().unwrap();
+        assert!(view.transform_vector3(Vec3::Y).y > 0.9 * view.transform_vector3(Vec3::Y).length());
+        assert!(view.inverse().transform_vector3(Vec3::X).y.abs() < 1e-5);
+
+        assert!(parse("view = \"lookat 0 0 0  1 0 0  0 0 1\"\nup = [0, 0, 0]").is_err());
+    }
+
+    #[test]
+    fn camera_position_and_target() {
+        let parse = |coordinates: &str, src: &str| {
+            let conf = format!("[coordinates]\n{coordinates}\n[camera]\nfov = 60\n{src}");
+            MeshScene::parse_toml_camera(&conf.parse().unwrap())
+        };
+        let close = |a: Mat4, b: Mat4| a.abs_diff_eq(b, 1e-5);
+
+        // the same camera as the lookat view, also in other coordinate systems
+        for coordinates in ["", "handedness = \"right\"\nup_axis = \"y\""] {
+            let view = parse(
+                coordinates,
+                "view = \"lookat 4 1 2  0 0 0  0 0 1\"\nup = [0, 0, 1]",
+            )
+            .unwrap()
+            .view();
+            let mut camera = parse(
+                coordinates,
+                "position = [4, 1, 2]\ntarget = [0, 0, 0]\nup = [0, 0, 1]",
+            )
+            .unwrap();
+            assert!(close(camera.update_view().unwrap(), view), "{coordinates}");
+        }
+
+        assert!(parse("", "position = [4, 1, 2]").is_err());
+        assert!(parse("", "position = [0, 0, 2]\ntarget = [0, 0, 0]").is_err());
+        assert!(parse(
+            "",
+            "view = \"lookat 4 1 2  0 0 0  0 0 1\"\nposition = [4, 1, 2]\ntarget = [0, 0, 0]"
+        )
+        .is_err());
+        assert!(parse("", "").is_err());
+    }
+
+    #[test]
+    fn camera_flip_y() {
+        let parse = |src: &str| {
+            let conf = format!("[camera]\nfov = 90\nview = \"lookat 0 0 0  1 0 0  0 0 1\"\n{src}");
+            MeshScene::parse_toml_camera(&conf.parse().unwrap())
+        };
+        // up in the scene ends up at the top of the framebuffer unless the flip is off
+        let up = |camera: Camera| {
+            let view = camera.view().transform_point3(Vec3::new(1.0, 0.0, 1.0));
+            camera.projection().project_point3(view).y
+        };
+
+        assert!(up(parse("").unwrap()) < 0.0);
+        assert!(up(parse("flip_y = true").unwrap()) < 0.0);
+        assert!(up(parse("flip_y = false").unwrap()) > 0.0);
+        assert!(parse("flip_y = 0").is_err());
+    }
+
+    #[test]
+    fn coordinates() {
+        let parse = |src: &str| MeshScene::parse_toml_coordinates(&src.parse().unwrap());
+        let right_y_up = CoordinateSystem {
+            handedness: Handedness::Right,
+            up_axis: UpAxis::Y,
+        };
+
+        assert_eq!(parse("").unwrap(), CoordinateSystem::default());
+        assert_eq!(
+            parse("[coordinates]\nhandedness = \"right\"\nup_axis = \"y\"").unwrap(),
+            right_y_up
+        );
+        assert_eq!(
+            parse("[coordinates]\nup_axis = \"y\"").unwrap().handedness,
+            Handedness::Left
+        );
+        assert!(parse("coordinates = \"right\"").is_err());
+        assert!(parse("[coordinates]\nhandedness = \"up\"").is_err());
+        assert!(parse("[coordinates]\nup_axis = \"x\"").is_err());
+        assert!(parse("[coordinates]\nup_axis = 1").is_err());
+
+        // an L lying in a right-handed y up scene: the long arm along +x, the short one along -z, with an area
+        // light above it facing down (counterclockwise seen from below) and a sun shining down onto it
+        // right-handed y up is a swap of y and z
+        let swap = |v: Vec3| Vec3::new(v.x, v.z, v.y);
+        let l_shape = Mat4::from_translation(Vec3::new(1.0, 0.0, -0.5));
+        let mut objects = vec![Object {
+            transform: l_shape,
+            previous_transform: Mat4::from_translation(Vec3::X),
+            ..object(1, &[])
+        }];
+        let triangle = [
+            Vec3::new(0.0, 3.0, 0.0),
+            Vec3::new(0.0, 3.0, -1.0),
+            Vec3::new(2.0, 3.0, 0.0),
+        ];
+        let mut lights = vec![
+            Light::Point {
+                color: Vec3::ONE,
+                position: Vec3::new(2.0, 1.0, -1.0),
+                samples: 1,
+            },
+            Light::Triangle {
+                color: Vec3::ONE,
+                vertices: triangle,
+                samples: 1,
+            },
+            Light::Directional {
+                color: Vec3::ONE,
+                position: Vec3::new(0.0, 5.0, 0.0),
+                direction: Vec3::new(0.0, -1.0, 0.5),
+                samples: 1,
+                radius: 1.0,
+                geometry_scale: 1.0,
+            },
+        ];
+        let mut procedural_objects = vec![ProceduralObject {
+            transform: Mat4::from_translation(Vec3::new(0.0, 1.0, -2.0)),
+            geometry_index: 0,
+            custom_index: 0,
+            params: Vec::new(),
+            visibility_mask: VISIBILITY_ALL,
+        }];
+        let normal = |[a, b, c]: [Vec3; 3]| (b - a).cross(c - a).normalize();
+        assert!(normal(triangle).abs_diff_eq(-Vec3::Y, 1e-6));
+
+        MeshScene::convert_coordinates(
+            right_y_up.to_world(),
+            &mut objects,
+            &mut lights,
+            &mut procedural_objects,
+        );
+
+        // the L's long arm still goes along +x, and the short one now along -y, it's lying on the z = 0 floor
+        let point = |transform: Mat4, v: Vec3| transform.transform_point3(v);
+        let corner = point(objects[0].transform, Vec3::ZERO);
+        assert!(corner.abs_diff_eq(Vec3::new(1.0, -0.5, 0.0), 1e-6));
+        assert!(point(objects[0].transform, Vec3::X).abs_diff_eq(corner + Vec3::X, 1e-6));
+        assert!(point(objects[0].transform, -Vec3::Z).abs_diff_eq(corner - Vec3::Y, 1e-6));
+        assert!(point(objects[0].previous_transform, Vec3::ZERO).abs_diff_eq(Vec3::X, 1e-6));
+        assert!(point(procedural_objects[0].transform, Vec3::ZERO)
+            .abs_diff_eq(Vec3::new(0.0, -2.0, 1.0), 1e-6));
+
+        let Light::Point { position, .. } = lights[0] else {
+            panic!("expected a point light");
+        };
+        assert_eq!(position, Vec3::new(2.0, -1.0, 1.0));
+        // the area light is now above the floor and still emits down towards it
+        let Light::Triangle { vertices, .. } = lights[1] else {
+            panic!("expected a triangle light");
+        };
+        let mut expected = triangle.map(swap);
+        expected.swap(1, 2);
+        assert_eq!(vertices, expected);
+        assert!(normal(vertices).abs_diff_eq(-Vec3::Z, 1e-6));
+        let Light::Directional {
+            position,
+            direction,
+            ..
+        } = lights[2]
         else {
-            bail!("camera.view must be a transform string")
+            panic!("expected a directional light");
         };
-        let view = Self::parse_transform(view_str)?;
+        assert_eq!(
+            (position, direction),
+            (Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.5, -1.0))
+        );
+    }
 
-        Ok(Camera::new(view, fov))
+    #[test]
+    fn camera_coordinates() {
+        let parse = |coordinates: &str, camera: &str| {
+            let conf = format!(
+                "[coordinates]\n{coordinates}\n[camera]\nview = \"lookat 0 1 5  0 1 0  0 1 0\"\nfov = 60\n{camera}"
+            );
+            MeshScene::parse_toml_camera(&conf.parse().unwrap())
+        };
+        let rise = |mut camera: Camera| {
+            let start = camera.view().inverse().col(3).truncate();
+            camera.handle_key_input(KeyCode::Space, true);
+            camera.handle_movement(1.0);
+            (camera.update_view().unwrap().inverse().col(3).truncate() - start) / Camera::SPEED
+        };
+        let right_y_up = "handedness = \"right\"\nup_axis = \"y\"";
+
+        // seen from +z in a right-handed y up scene, which is from +y at z = 1 here
+        let camera = parse(right_y_up, "").unwrap();
+        let expected =
+            Mat4::look_at_lh(Vec3::new(0.0, 5.0, 1.0), Vec3::new(0.0, 0.0, 1.0), Vec3::Z);
+        assert!(camera.view().abs_diff_eq(expected, 1e-5));
+        // moving up goes along z, the scene's y
+        assert!(rise(camera).abs_diff_eq(Vec3::Z, 1e-5));
+
+        // an explicit up is in the scene's coordinates too
+        let camera = parse(right_y_up, "up = [1, 0, 0]").unwrap();
+        assert!(rise(camera).abs_diff_eq(Vec3::X, 1e-5));
+        assert!(parse(right_y_up, "up = [0, 0, 0]").is_err());
+        assert!(parse("up_axis = \"w\"", "").is_err());
     }
 }
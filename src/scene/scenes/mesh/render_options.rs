@@ -0,0 +1,462 @@
+// the [render] table of a scene file, how it gets traced rather than what's in it
+
+use anyhow::{anyhow, bail, Result};
+use glam::Vec3;
+use toml::{Table, Value};
+
+use super::MeshScene;
+
+// way more than any of the bundled scenes, but still only about a gigabyte of vertex data
+pub(super) const DEFAULT_MAX_TRIANGLES: u64 = 1 << 24;
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    // fixed seed for the per-frame random offsets
+    // if None, the seed is pulled from the thread rng every frame
+    pub seed: Option<u64>,
+    // fraction of the motion between previous_transform and transform that the shutter is open for
+    // 0 disables motion blur
+    pub shutter: f32,
+    // what the default miss shader (clear_color.rmiss) returns for rays that hit nothing
+    pub clear_color: Vec3,
+    // what the storage image is cleared to when it's (re)created, until the first frame is traced
+    // if None, it starts out as clear_color
+    pub initial_color: Option<Vec3>,
+    pub sampler: PixelSampler,
+    // gpu time per frame in milliseconds to aim for by lowering the render resolution
+    // if None, the renderer always renders at the window size
+    pub target_frame_time: Option<f32>,
+    // stop tracing once this many samples are accumulated, until something restarts accumulation
+    // if None, it keeps accumulating forever
+    pub max_samples: Option<u32>,
+    // samples to accumulate per displayed frame once the camera has been still for a moment, to converge faster
+    // while looking at a still. one (the default) is the same as while moving, ignored with motion blur
+    pub idle_samples_per_frame: u32,
+    // loading fails if the meshes have more triangles than this in total, before anything is uploaded
+    pub max_triangles: u64,
+    // only this part of the window gets traced and blitted, the rest keeps whatever was presented there before
+    // if None, the whole window is traced
+    pub region: Option<RenderRegion>,
+    // the window's event loop sleeps between frames so it doesn't present more often than this
+    // if None, frames go out as fast as the present mode allows
+    pub max_fps: Option<f32>,
+    // trace the region in horizontal strips this many rows tall, one dispatch each, to tune the dispatch shape
+    // if None, a single dispatch covers the whole region
+    pub dispatch_rows: Option<u32>,
+    // give every directional light a disc of procedural geometry that camera and bounce rays can hit, which needs
+    // the global_shaders.directional_emitter_* shaders. without it directional lights are only sampled directly
+    pub directional_geometry: bool,
+    // fill the aov images (albedo, normal, depth, instance) that screenshots save in an exr next to the png
+    pub aovs: bool,
+    // screenshots also save a denoised png, guided by the aovs (so this needs aovs too)
+    pub denoise: bool,
+    // temporal anti-aliasing instead of accumulation: one jittered position per pixel and frame, blended into a
+    // history that follows the camera, so edges stay smooth while moving. can't be combined with the options
+    // that only make sense for accumulation (max_samples, idle_samples_per_frame)
+    pub taa: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            seed: None,
+            shutter: 0f32,
+            clear_color: Vec3::ZERO,
+            initial_color: None,
+            sampler: PixelSampler::default(),
+            target_frame_time: None,
+            max_samples: None,
+            idle_samples_per_frame: 1,
+            max_triangles: DEFAULT_MAX_TRIANGLES,
+            region: None,
+            max_fps: None,
+            dispatch_rows: None,
+            directional_geometry: true,
+            aovs: false,
+            denoise: false,
+            taa: false,
+        }
+    }
+}
+
+/// How the raygen shader jitters samples within a pixel, see pixel_sampler in raygen_common.glsl
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PixelSampler {
+    #[default]
+    Random = 0,
+    // stratifies samples across frames, so accumulation converges faster
+    Halton = 1,
+}
+
+/// A rectangle in window pixels, with the origin at the top left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl RenderRegion {
+    /// The region in an image of size `to`, when it was given for one of size `from`
+    ///
+    /// Returns the offset and size, which are grown to whole pixels and clamped to the image. Regions that
+    /// end up entirely outside still cover the pixel closest to them, so there's always something to trace.
+    pub fn scaled(&self, from: (u32, u32), to: (u32, u32)) -> ((u32, u32), (u32, u32)) {
+        let scale = |start: u32, len: u32, from: u32, to: u32| {
+            let ratio = to as f64 / from.max(1) as f64;
+            let end = (((start + len) as f64 * ratio).ceil() as u32).min(to);
+            let start = ((start as f64 * ratio).floor() as u32).min(to - 1);
+            (start, end.max(start + 1) - start)
+        };
+        let (x, width) = scale(self.x, self.width, from.0, to.0);
+        let (y, height) = scale(self.y, self.height, from.1, to.1);
+        ((x, y), (width, height))
+    }
+}
+
+// typed getters for the options in the render table, None for the ones that aren't there
+struct RenderTable<'a>(&'a Table);
+
+impl RenderTable<'_> {
+    fn bool(&self, name: &str) -> Result<Option<bool>> {
+        match self.0.get(name) {
+            None => Ok(None),
+            Some(&Value::Boolean(x)) => Ok(Some(x)),
+            Some(_) => bail!("render.{name} must be a boolean"),
+        }
+    }
+
+    // integers of at least `min` that fit in T
+    fn integer<T: TryFrom<i64>>(&self, name: &str, min: i64) -> Result<Option<T>> {
+        let x = match self.0.get(name) {
+            None => return Ok(None),
+            Some(&Value::Integer(x)) => x,
+            Some(_) => bail!("render.{name} must be an integer"),
+        };
+        if x < min {
+            match min {
+                0 => bail!("render.{name} can't be negative"),
+                _ => bail!("render.{name} must be at least {min}"),
+            }
+        }
+        let x = x
+            .try_into()
+            .map_err(|_| anyhow!("render.{name} is too big"))?;
+        Ok(Some(x))
+    }
+
+    fn f32(&self, name: &str) -> Result<Option<f32>> {
+        self.0
+            .get(name)
+            .map(|x| MeshScene::parse_toml_f32(x).map_err(|e| anyhow!("render.{name}: {e}")))
+            .transpose()
+    }
+
+    fn positive_f32(&self, name: &str) -> Result<Option<f32>> {
+        let x = self.f32(name)?;
+        if x.is_some_and(|x| x <= 0f32) {
+            bail!("render.{name} must be greater than 0");
+        }
+        Ok(x)
+    }
+
+    fn vec3(&self, name: &str) -> Result<Option<Vec3>> {
+        self.0
+            .get(name)
+            .map(|x| MeshScene::parse_toml_vec3(x).map_err(|e| anyhow!("render.{name}: {e}")))
+            .transpose()
+    }
+
+    fn string(&self, name: &str) -> Result<Option<&str>> {
+        match self.0.get(name) {
+            None => Ok(None),
+            Some(Value::String(x)) => Ok(Some(x)),
+            Some(_) => bail!("render.{name} must be a string"),
+        }
+    }
+}
+
+pub(super) fn parse_toml_render_options(conf: &Table) -> Result<RenderOptions> {
+    let mut options = RenderOptions::default();
+
+    let Some(render_table) = conf.get("render") else {
+        return Ok(options);
+    };
+    let Value::Table(render_table) = render_table else {
+        bail!("render must be a table")
+    };
+    let render = RenderTable(render_table);
+
+    options.seed = render.integer("seed", 0)?;
+
+    options.shutter = render.f32("shutter")?.unwrap_or(options.shutter);
+    if !(0f32..=1f32).contains(&options.shutter) {
+        bail!("render.shutter must be between 0 and 1");
+    }
+
+    options.clear_color = render.vec3("clear_color")?.unwrap_or(options.clear_color);
+    options.initial_color = render.vec3("initial_color")?;
+
+    if let Some(sampler) = render.string("sampler")? {
+        options.sampler = match sampler {
+            "random" => PixelSampler::Random,
+            "halton" => PixelSampler::Halton,
+            _ => bail!("unknown render.sampler {sampler:?}, expected \"random\" or \"halton\""),
+        };
+    }
+
+    options.target_frame_time = render.positive_f32("target_frame_time")?;
+    options.max_samples = render.integer("max_samples", 1)?;
+    options.idle_samples_per_frame = render
+        .integer("idle_samples_per_frame", 1)?
+        .unwrap_or(options.idle_samples_per_frame);
+    options.max_triangles = render
+        .integer("max_triangles", 0)?
+        .unwrap_or(options.max_triangles);
+
+    if let Some(region) = render_table.get("region") {
+        let Value::Array(values) = region else {
+            bail!("render.region must be an array [x, y, width, height]");
+        };
+        let values = values
+            .iter()
+            .map(|value| match value {
+                &Value::Integer(x) => u32::try_from(x).ok(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>();
+        let Some(&[x, y, width, height]) = values.as_deref() else {
+            bail!("render.region must be 4 non-negative integers [x, y, width, height]");
+        };
+        if width == 0 || height == 0 {
+            bail!("render.region must not be empty");
+        }
+        options.region = Some(RenderRegion {
+            x,
+            y,
+            width,
+            height,
+        });
+    }
+
+    options.max_fps = render.positive_f32("max_fps")?;
+    options.dispatch_rows = render.integer("dispatch_rows", 1)?;
+
+    options.directional_geometry = render
+        .bool("directional_geometry")?
+        .unwrap_or(options.directional_geometry);
+    options.aovs = render.bool("aovs")?.unwrap_or(options.aovs);
+
+    options.denoise = render.bool("denoise")?.unwrap_or(options.denoise);
+    if options.denoise && !options.aovs {
+        bail!("render.denoise needs render.aovs = true");
+    }
+
+    options.taa = render.bool("taa")?.unwrap_or(options.taa);
+    if options.taa && options.max_samples.is_some() {
+        bail!("render.taa doesn't accumulate, so it can't have render.max_samples");
+    }
+    if options.taa && options.idle_samples_per_frame > 1 {
+        bail!("render.taa doesn't accumulate, so it can't have render.idle_samples_per_frame");
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> Result<RenderOptions> {
+        parse_toml_render_options(&src.parse().unwrap())
+    }
+
+    #[test]
+    fn render_options_max_triangles() {
+        assert_eq!(parse("").unwrap().max_triangles, DEFAULT_MAX_TRIANGLES);
+        assert_eq!(
+            parse("[render]\nmax_triangles = 100")
+                .unwrap()
+                .max_triangles,
+            100
+        );
+        assert!(parse("[render]\nmax_triangles = -1").is_err());
+    }
+
+    #[test]
+    fn render_options_directional_geometry() {
+        assert!(parse("").unwrap().directional_geometry);
+        assert!(
+            !parse("[render]\ndirectional_geometry = false")
+                .unwrap()
+                .directional_geometry
+        );
+        assert!(parse("[render]\ndirectional_geometry = 0").is_err());
+    }
+
+    #[test]
+    fn render_options_clear_color() {
+        assert_eq!(parse("").unwrap().clear_color, Vec3::ZERO);
+        assert_eq!(
+            parse("[render]\nclear_color = [0.5, 0.6, 1]")
+                .unwrap()
+                .clear_color,
+            Vec3::new(0.5, 0.6, 1.0)
+        );
+        assert!(parse("[render]\nclear_color = 1").is_err());
+    }
+
+    #[test]
+    fn render_options_sampler() {
+        assert_eq!(parse("").unwrap().sampler, PixelSampler::Random);
+        assert_eq!(
+            parse("[render]\nsampler = \"halton\"").unwrap().sampler,
+            PixelSampler::Halton
+        );
+        assert!(parse("[render]\nsampler = \"blue_noise\"").is_err());
+        assert!(parse("[render]\nsampler = 1").is_err());
+    }
+
+    #[test]
+    fn render_options_target_frame_time() {
+        assert_eq!(parse("").unwrap().target_frame_time, None);
+        assert_eq!(
+            parse("[render]\ntarget_frame_time = 16")
+                .unwrap()
+                .target_frame_time,
+            Some(16.0)
+        );
+        assert!(parse("[render]\ntarget_frame_time = 0").is_err());
+    }
+
+    #[test]
+    fn render_options_max_samples() {
+        assert_eq!(parse("").unwrap().max_samples, None);
+        assert_eq!(
+            parse("[render]\nmax_samples = 1024").unwrap().max_samples,
+            Some(1024)
+        );
+        assert!(parse("[render]\nmax_samples = 0").is_err());
+        assert!(parse("[render]\nmax_samples = 1e3").is_err());
+    }
+
+    #[test]
+    fn render_options_initial_color() {
+        assert_eq!(parse("").unwrap().initial_color, None);
+        assert_eq!(
+            parse("[render]\ninitial_color = [0.5, 0.5, 1]")
+                .unwrap()
+                .initial_color,
+            Some(Vec3::new(0.5, 0.5, 1.0))
+        );
+        assert!(parse("[render]\ninitial_color = 1").is_err());
+    }
+
+    #[test]
+    fn render_region() {
+        assert_eq!(parse("").unwrap().region, None);
+        let region = parse("[render]\nregion = [100, 50, 200, 100]")
+            .unwrap()
+            .region
+            .unwrap();
+        assert_eq!(
+            region,
+            RenderRegion {
+                x: 100,
+                y: 50,
+                width: 200,
+                height: 100
+            }
+        );
+        assert!(parse("[render]\nregion = [0, 0, 0, 10]").is_err());
+        assert!(parse("[render]\nregion = [0, 0, 10]").is_err());
+        assert!(parse("[render]\nregion = [-1, 0, 10, 10]").is_err());
+        assert!(parse("[render]\nregion = [0.5, 0, 10, 10]").is_err());
+
+        // same size, half the resolution, and grown to whole pixels
+        assert_eq!(
+            region.scaled((800, 600), (800, 600)),
+            ((100, 50), (200, 100))
+        );
+        assert_eq!(region.scaled((800, 600), (400, 300)), ((50, 25), (100, 50)));
+        assert_eq!(region.scaled((800, 600), (3, 3)), ((0, 0), (2, 1)));
+
+        // clamped to the image, or to its last pixel when it's entirely outside
+        assert_eq!(
+            region.scaled((200, 100), (200, 100)),
+            ((100, 50), (100, 50))
+        );
+        assert_eq!(region.scaled((50, 50), (50, 50)), ((49, 49), (1, 1)));
+    }
+
+    #[test]
+    fn render_options_max_fps() {
+        assert_eq!(parse("").unwrap().max_fps, None);
+        assert_eq!(parse("[render]\nmax_fps = 60").unwrap().max_fps, Some(60.0));
+        assert_eq!(
+            parse("[render]\nmax_fps = 29.97").unwrap().max_fps,
+            Some(29.97)
+        );
+        assert!(parse("[render]\nmax_fps = 0").is_err());
+        assert!(parse("[render]\nmax_fps = -30").is_err());
+    }
+
+    #[test]
+    fn render_options_dispatch_rows() {
+        assert_eq!(parse("").unwrap().dispatch_rows, None);
+        assert_eq!(
+            parse("[render]\ndispatch_rows = 64").unwrap().dispatch_rows,
+            Some(64)
+        );
+        assert!(parse("[render]\ndispatch_rows = 0").is_err());
+        assert!(parse("[render]\ndispatch_rows = 1.5").is_err());
+    }
+
+    #[test]
+    fn render_options_aovs() {
+        assert!(!parse("").unwrap().aovs);
+        assert!(parse("[render]\naovs = true").unwrap().aovs);
+        assert!(parse("[render]\naovs = 1").is_err());
+    }
+
+    #[test]
+    fn render_options_denoise() {
+        assert!(!parse("").unwrap().denoise);
+        assert!(
+            parse("[render]\naovs = true\ndenoise = true")
+                .unwrap()
+                .denoise
+        );
+        assert!(parse("[render]\ndenoise = true").is_err());
+        assert!(parse("[render]\naovs = true\ndenoise = \"oidn\"").is_err());
+    }
+
+    #[test]
+    fn render_options_taa() {
+        assert!(!parse("").unwrap().taa);
+        assert!(parse("[render]\ntaa = true").unwrap().taa);
+        assert!(
+            !parse("[render]\ntaa = false\nmax_samples = 64")
+                .unwrap()
+                .taa
+        );
+        assert!(parse("[render]\ntaa = true\nmax_samples = 64").is_err());
+        assert!(parse("[render]\ntaa = true\nidle_samples_per_frame = 4").is_err());
+        assert!(parse("[render]\ntaa = 1").is_err());
+    }
+
+    #[test]
+    fn render_options_idle_samples_per_frame() {
+        assert_eq!(parse("").unwrap().idle_samples_per_frame, 1);
+        assert_eq!(
+            parse("[render]\nidle_samples_per_frame = 8")
+                .unwrap()
+                .idle_samples_per_frame,
+            8
+        );
+        assert!(parse("[render]\nidle_samples_per_frame = 0").is_err());
+        assert!(parse("[render]\nidle_samples_per_frame = true").is_err());
+    }
+}
@@ -1,5 +1,9 @@
-use crate::scene::Scene;
+use crate::scene::{Scene, ShaderRequirements};
 
 impl Scene for () {
     type Update = ();
+
+    fn shader_requirements(&self) -> ShaderRequirements {
+        ShaderRequirements::default()
+    }
 }
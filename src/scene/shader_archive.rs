@@ -0,0 +1,110 @@
+use std::{collections::HashMap, fs::File, io::Read, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+
+const MAGIC: &[u8; 4] = b"KGSA";
+
+/// Compiled shaders packed into one file, looked up by the same names as the files in resources/shaders/spv/
+///
+/// `build_shaders.py --archive` writes these. Everything is little endian: the magic `KGSA`, the number of
+/// shaders as a u32, then for each shader the length of its name as a u32, the utf-8 name, the length of
+/// its SPIR-V code in bytes as a u32 and the code.
+#[derive(Debug, Default)]
+pub struct ShaderArchive {
+    shaders: HashMap<String, Box<[u32]>>,
+}
+
+impl ShaderArchive {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .map_err(|e| anyhow!("failed to open shader archive {}: {e}", path.display()))?;
+        Self::from_reader(file)
+            .map_err(|e| anyhow!("failed to read shader archive {}: {e}", path.display()))
+    }
+
+    pub fn from_reader(mut reader: impl Read) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("not a shader archive");
+        }
+
+        let count = Self::read_u32(&mut reader)?;
+        let mut shaders = HashMap::new();
+        for _ in 0..count {
+            let name = String::from_utf8(Self::read_bytes(&mut reader)?)?;
+            let code = Self::read_bytes(&mut reader)?;
+            if code.is_empty() || code.len() % 4 != 0 {
+                bail!("shader {name:?} has an invalid size: {} - must be aligned to 4 bytes and greater than 0", code.len());
+            }
+
+            // SPIR-V files are little endian, same as the rest of the archive
+            let code = code
+                .chunks_exact(4)
+                .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+                .collect();
+            if shaders.insert(name.clone(), code).is_some() {
+                bail!("shader {name:?} is in the archive more than once");
+            }
+        }
+
+        Ok(Self { shaders })
+    }
+
+    /// The code of the shader `name` (e.g. `diffuse.rchit`), if it's in the archive
+    pub fn get(&self, name: &str) -> Option<Box<[u32]>> {
+        self.shaders.get(name).cloned()
+    }
+
+    fn read_u32(reader: &mut impl Read) -> Result<u32> {
+        let mut bytes = [0u8; 4];
+        reader.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    // a u32 length, then that many bytes
+    fn read_bytes(reader: &mut impl Read) -> Result<Vec<u8>> {
+        let len = Self::read_u32(reader)? as usize;
+        let mut bytes = Vec::new();
+        reader.take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            bail!("shader archive ends early");
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(shaders: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut file = MAGIC.to_vec();
+        file.extend_from_slice(&(shaders.len() as u32).to_le_bytes());
+        for (name, code) in shaders {
+            file.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            file.extend_from_slice(name.as_bytes());
+            file.extend_from_slice(&(code.len() as u32).to_le_bytes());
+            file.extend_from_slice(code);
+        }
+        file
+    }
+
+    #[test]
+    fn shader_archive() {
+        let file = archive(&[
+            ("diffuse.rchit", &[0x03, 0x02, 0x23, 0x07, 1, 0, 0, 0]),
+            ("raygen.rgen", &[2, 0, 0, 0]),
+        ]);
+        let shaders = ShaderArchive::from_reader(&file[..]).unwrap();
+        assert_eq!(shaders.get("diffuse.rchit").unwrap()[..], [0x07230203, 1]);
+        assert_eq!(shaders.get("raygen.rgen").unwrap()[..], [2]);
+        assert!(shaders.get("missing.rmiss").is_none());
+
+        assert!(ShaderArchive::from_reader(&b"SPV\0"[..]).is_err());
+        assert!(ShaderArchive::from_reader(&file[..file.len() - 1]).is_err());
+        assert!(ShaderArchive::from_reader(&archive(&[("odd.rgen", &[1, 2, 3])])[..]).is_err());
+        let twice = archive(&[("twice.rgen", &[1, 0, 0, 0]), ("twice.rgen", &[2, 0, 0, 0])]);
+        assert!(ShaderArchive::from_reader(&twice[..]).is_err());
+    }
+}
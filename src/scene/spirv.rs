@@ -0,0 +1,327 @@
+// spir-v reflection on top of rspirv, just enough to find out what a shader expects a storage buffer element
+// to look like
+
+use std::{collections::HashMap, fmt};
+
+use anyhow::{anyhow, bail, Result};
+use rspirv::{
+    dr::{
+        self, Instruction,
+        Operand::{self, IdRef, LiteralBit32},
+    },
+    spirv::{Decoration, ExecutionModel, Op, Word},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scalar {
+    Float,
+    Int,
+    UInt,
+}
+
+/// A scalar or vector the shader reads at some offset into the buffer element
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub offset: u32,
+    pub scalar: Scalar,
+    pub components: u32,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (scalar, prefix) = match self.scalar {
+            Scalar::Float => ("float", ""),
+            Scalar::Int => ("int", "i"),
+            Scalar::UInt => ("uint", "u"),
+        };
+        match self.components {
+            1 => write!(f, "{scalar} at {}", self.offset),
+            n => write!(f, "{prefix}vec{n} at {}", self.offset),
+        }
+    }
+}
+
+/// Layout of one element of a runtime array storage buffer, flattened down to scalars and vectors
+#[derive(Debug, PartialEq, Eq)]
+pub struct ElementLayout {
+    pub stride: u32,
+    pub fields: Vec<Field>,
+}
+
+fn load(code: &[u32]) -> Result<dr::Module> {
+    dr::load_words(code).map_err(|e| anyhow!("invalid spir-v: {e}"))
+}
+
+// a decoration's target and member, None for decorations of the target itself
+type DecorationKey = (Word, Option<u32>, Decoration);
+
+// OpDecorate or OpMemberDecorate with a single literal, which is all the layout needs
+fn literal_decoration(inst: &Instruction) -> Option<(DecorationKey, u32)> {
+    let (target, member, rest) = match (inst.class.opcode, inst.operands.as_slice()) {
+        (Op::Decorate, &[IdRef(target), ref rest @ ..]) => (target, None, rest),
+        (Op::MemberDecorate, &[IdRef(target), LiteralBit32(member), ref rest @ ..]) => {
+            (target, Some(member), rest)
+        }
+        _ => return None,
+    };
+    let &[Operand::Decoration(decoration), LiteralBit32(value)] = rest else {
+        return None;
+    };
+    Some(((target, member, decoration), value))
+}
+
+// the types, constants and decorations of a module, looked up by id
+struct Reflection<'a> {
+    definitions: HashMap<Word, &'a Instruction>,
+    decorations: HashMap<DecorationKey, u32>,
+}
+
+impl<'a> Reflection<'a> {
+    fn new(module: &'a dr::Module) -> Self {
+        Self {
+            definitions: module
+                .types_global_values
+                .iter()
+                .filter_map(|inst| Some((inst.result_id?, inst)))
+                .collect(),
+            decorations: module
+                .annotations
+                .iter()
+                .filter_map(literal_decoration)
+                .collect(),
+        }
+    }
+
+    fn decoration(&self, id: Word, member: Option<u32>, decoration: Decoration) -> Option<u32> {
+        self.decorations.get(&(id, member, decoration)).copied()
+    }
+
+    fn definition(&self, id: Word) -> Result<&'a Instruction> {
+        self.definitions
+            .get(&id)
+            .copied()
+            .ok_or_else(|| anyhow!("spir-v id %{id} is not defined"))
+    }
+
+    fn scalar(&self, id: Word) -> Result<Scalar> {
+        let inst = self.definition(id)?;
+        match (inst.class.opcode, inst.operands.as_slice()) {
+            (Op::TypeFloat, [LiteralBit32(32), ..]) => Ok(Scalar::Float),
+            (Op::TypeInt, [LiteralBit32(32), LiteralBit32(0)]) => Ok(Scalar::UInt),
+            (Op::TypeInt, [LiteralBit32(32), LiteralBit32(_)]) => Ok(Scalar::Int),
+            _ => bail!("unsupported scalar type in buffer: {}", inst.class.opname),
+        }
+    }
+
+    // only 32 bit constants are used as array lengths
+    fn constant(&self, id: Word) -> Result<u32> {
+        let inst = self.definition(id)?;
+        match (inst.class.opcode, inst.operands.as_slice()) {
+            (Op::Constant, &[LiteralBit32(value)]) => Ok(value),
+            _ => bail!("array length %{id} is not a constant"),
+        }
+    }
+
+    fn array_stride(&self, id: Word) -> Result<u32> {
+        self.decoration(id, None, Decoration::ArrayStride)
+            .ok_or_else(|| anyhow!("array %{id} has no stride"))
+    }
+
+    fn flatten(&self, id: Word, offset: u32, fields: &mut Vec<Field>) -> Result<()> {
+        let inst = self.definition(id)?;
+        match (inst.class.opcode, inst.operands.as_slice()) {
+            (Op::TypeVector, &[IdRef(component), LiteralBit32(count)]) => fields.push(Field {
+                offset,
+                scalar: self.scalar(component)?,
+                components: count,
+            }),
+            (Op::TypeArray, &[IdRef(element), IdRef(length)]) => {
+                let stride = self.array_stride(id)?;
+                for i in 0..self.constant(length)? {
+                    self.flatten(element, offset + i * stride, fields)?;
+                }
+            }
+            (Op::TypeStruct, members) => {
+                for (i, member) in members.iter().enumerate() {
+                    let &IdRef(member) = member else {
+                        bail!("struct %{id} member {i} is not a type");
+                    };
+                    let member_offset = self
+                        .decoration(id, Some(i as u32), Decoration::Offset)
+                        .ok_or_else(|| anyhow!("struct %{id} member {i} has no offset"))?;
+                    self.flatten(member, offset + member_offset, fields)?;
+                }
+            }
+            _ => fields.push(Field {
+                offset,
+                scalar: self.scalar(id)?,
+                components: 1,
+            }),
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds the storage buffer at `set`/`binding` and returns the layout of its runtime array elements
+///
+/// Returns `None` if the shader doesn't declare anything at that binding. The buffer block is expected to look
+/// like the ones in the hit shaders, i.e. a single runtime array member.
+pub fn runtime_array_layout(code: &[u32], set: u32, binding: u32) -> Result<Option<ElementLayout>> {
+    let module = load(code)?;
+    let reflection = Reflection::new(&module);
+
+    let Some(variable) = module.types_global_values.iter().find(|inst| {
+        inst.class.opcode == Op::Variable
+            && inst.result_id.is_some_and(|id| {
+                reflection.decoration(id, None, Decoration::DescriptorSet) == Some(set)
+                    && reflection.decoration(id, None, Decoration::Binding) == Some(binding)
+            })
+    }) else {
+        return Ok(None);
+    };
+
+    let pointer = reflection.definition(variable.result_type.unwrap_or_default())?;
+    let (Op::TypePointer, &[_, IdRef(block)]) = (pointer.class.opcode, pointer.operands.as_slice())
+    else {
+        bail!("variable at set {set} binding {binding} is not a pointer");
+    };
+    let block = reflection.definition(block)?;
+    let (Op::TypeStruct, &[IdRef(array)]) = (block.class.opcode, block.operands.as_slice()) else {
+        bail!(
+            "buffer block at set {set} binding {binding} must be a struct with exactly one member"
+        );
+    };
+    let array_type = reflection.definition(array)?;
+    let (Op::TypeRuntimeArray, &[IdRef(element)]) =
+        (array_type.class.opcode, array_type.operands.as_slice())
+    else {
+        bail!("buffer block at set {set} binding {binding} must be a runtime array");
+    };
+
+    let stride = reflection.array_stride(array)?;
+    let mut fields = Vec::new();
+    reflection.flatten(element, 0, &mut fields)?;
+
+    Ok(Some(ElementLayout { stride, fields }))
+}
+
+/// The stage of every entry point in the module, so a shader used as a closest hit shader can be checked to be one
+pub fn execution_models(code: &[u32]) -> Result<Vec<ExecutionModel>> {
+    Ok(load(code)?
+        .entry_points
+        .iter()
+        .filter_map(|inst| match inst.operands.first() {
+            Some(&Operand::ExecutionModel(model)) => Some(model),
+            _ => None,
+        })
+        .collect())
+}
+
+/// An empty shader with a single `main` entry point of `model`
+#[cfg(test)]
+pub fn entry_point_module(model: ExecutionModel) -> Vec<u32> {
+    use rspirv::{binary::Assemble, dr::Builder, spirv::FunctionControl};
+
+    let mut b = Builder::new();
+    let void = b.type_void();
+    let function_type = b.type_function(void, []);
+    let main = b
+        .begin_function(void, None, FunctionControl::NONE, function_type)
+        .unwrap();
+    b.begin_block(None).unwrap();
+    b.ret().unwrap();
+    b.end_function().unwrap();
+    b.entry_point(model, main, "main", []);
+
+    b.module().assemble()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rspirv::{
+        binary::Assemble,
+        dr::Builder,
+        spirv::{Decoration, StorageClass},
+    };
+
+    // what glslc emits (give or take) for
+    //     struct Params { vec3 albedo; uint flags; float weights[2]; };
+    //     layout(scalar, set = 0, binding = 6) readonly buffer Fields { Params params[]; };
+    fn module() -> Vec<u32> {
+        let mut b = Builder::new();
+        let stride = |x| [LiteralBit32(x)];
+
+        let float = b.type_float(32, None);
+        let vec3 = b.type_vector(float, 3);
+        let uint = b.type_int(32, 0);
+        let two = b.constant_bit32(uint, 2);
+        let weights = b.type_array(float, two);
+        b.decorate(weights, Decoration::ArrayStride, stride(4));
+        let params = b.type_struct([vec3, uint, weights]);
+        for (member, offset) in [(0, 0), (1, 12), (2, 16)] {
+            b.member_decorate(params, member, Decoration::Offset, stride(offset));
+        }
+        let array = b.type_runtime_array(params);
+        b.decorate(array, Decoration::ArrayStride, stride(24));
+        let block = b.type_struct([array]);
+        b.member_decorate(block, 0, Decoration::Offset, stride(0));
+        let pointer = b.type_pointer(None, StorageClass::StorageBuffer, block);
+        let variable = b.variable(pointer, None, StorageClass::StorageBuffer, None);
+        b.decorate(variable, Decoration::DescriptorSet, stride(0));
+        b.decorate(variable, Decoration::Binding, stride(6));
+
+        b.module().assemble()
+    }
+
+    #[test]
+    fn reflect_runtime_array_layout() {
+        let layout = runtime_array_layout(&module(), 0, 6).unwrap().unwrap();
+
+        let field = |offset, scalar, components| Field {
+            offset,
+            scalar,
+            components,
+        };
+        assert_eq!(
+            layout,
+            ElementLayout {
+                stride: 24,
+                fields: vec![
+                    field(0, Scalar::Float, 3),
+                    field(12, Scalar::UInt, 1),
+                    field(16, Scalar::Float, 1),
+                    field(20, Scalar::Float, 1),
+                ],
+            }
+        );
+        assert_eq!(layout.fields[0].to_string(), "vec3 at 0");
+    }
+
+    #[test]
+    fn reflect_missing_binding() {
+        assert_eq!(runtime_array_layout(&module(), 0, 5).unwrap(), None);
+        assert_eq!(runtime_array_layout(&module(), 1, 6).unwrap(), None);
+    }
+
+    #[test]
+    fn reflect_execution_models() {
+        assert_eq!(
+            execution_models(&entry_point_module(ExecutionModel::AnyHitKHR)).unwrap(),
+            [ExecutionModel::AnyHitKHR]
+        );
+        assert_eq!(execution_models(&module()).unwrap(), []);
+    }
+
+    #[test]
+    fn reflect_malformed() {
+        assert!(runtime_array_layout(&[0x07230203], 0, 6).is_err());
+
+        // an instruction claiming more words than are left
+        let mut code = module();
+        code.push(10 << 16 | Op::TypeFloat as u32);
+        assert!(runtime_array_layout(&code, 0, 6).is_err());
+    }
+}
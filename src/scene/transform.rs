@@ -0,0 +1,203 @@
+use std::f32::consts::PI;
+
+use anyhow::{anyhow, bail, Result};
+use glam::{Mat4, Vec3};
+
+/// Parses a transform written in the scene transform language
+///
+/// Each line is one action (`identity`, `translate x y z`, `rotate angle x y z`, `scale x y z` or
+/// `lookat eye center up`), applied on top of the lines before it. Blank lines and `#` comments are skipped.
+pub fn parse_transform(transform_str: &str) -> Result<Mat4> {
+    let mut transform = Mat4::IDENTITY;
+
+    for line in transform_str.lines() {
+        let mut tokens = line.trim().split_ascii_whitespace();
+
+        let Some(action) = tokens.next() else {
+            // empty means we ignore
+            continue;
+        };
+
+        // ignore comments
+        if action.starts_with('#') {
+            continue;
+        }
+
+        // match on action (omg thats a cinema term)
+        match action {
+            "identity" => {
+                if tokens.next().is_some() {
+                    bail!("identity takes no arguments, but extra info was provided");
+                }
+
+                transform = Mat4::IDENTITY;
+            }
+            "translate" => {
+                let x = parse_f32(&mut tokens)?;
+                let y = parse_f32(&mut tokens)?;
+                let z = parse_f32(&mut tokens)?;
+
+                if tokens.next().is_some() {
+                    bail!("transform requires only x y z, but extra info was provided");
+                }
+
+                let translation = Mat4::from_translation(Vec3::new(x, y, z));
+                transform = translation * transform;
+            }
+            "rotate" => {
+                let angle = parse_f32(&mut tokens)? * PI / 180f32;
+                let x = parse_f32(&mut tokens)?;
+                let y = parse_f32(&mut tokens)?;
+                let z = parse_f32(&mut tokens)?;
+                let axis = Vec3::new(x, y, z);
+
+                if tokens.next().is_some() {
+                    bail!("rotate requires only angle x y z, but extra info was provided");
+                }
+
+                let rotation = Mat4::from_axis_angle(axis, angle);
+                transform = rotation * transform;
+            }
+            "scale" => {
+                let x = parse_f32(&mut tokens)?;
+                let y = parse_f32(&mut tokens)?;
+                let z = parse_f32(&mut tokens)?;
+                let scale = Vec3::new(x, y, z);
+
+                if tokens.next().is_some() {
+                    bail!("scale requires only x y z, but extra info was provided");
+                }
+
+                let scale = Mat4::from_scale(scale);
+                transform = scale * transform;
+            }
+            "lookat" => {
+                let eye_x = parse_f32(&mut tokens)?;
+                let eye_y = parse_f32(&mut tokens)?;
+                let eye_z = parse_f32(&mut tokens)?;
+                let eye = Vec3::new(eye_x, eye_y, eye_z);
+
+                let center_x = parse_f32(&mut tokens)?;
+                let center_y = parse_f32(&mut tokens)?;
+                let center_z = parse_f32(&mut tokens)?;
+                let center = Vec3::new(center_x, center_y, center_z);
+
+                let up_x = parse_f32(&mut tokens)?;
+                let up_y = parse_f32(&mut tokens)?;
+                let up_z = parse_f32(&mut tokens)?;
+                let up = Vec3::new(up_x, up_y, up_z);
+
+                if tokens.next().is_some() {
+                    bail!("lookat requires only eye_x eye_y eye_z center_x center_y center_z up_x up_y up_z, but extra info was provided");
+                }
+
+                let lookat = Mat4::look_at_lh(eye, center, up);
+                transform = lookat;
+            }
+            _ if action.starts_with("#") => (),
+            x => bail!("invalid transform action: {x}"),
+        };
+    }
+
+    Ok(transform)
+}
+
+fn parse_f32<'a>(mut tokens: impl Iterator<Item = &'a str>) -> Result<f32> {
+    let num = tokens
+        .next()
+        .ok_or(anyhow!("float expected but not found"))?;
+    Ok(num.parse()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use glam::{Mat4, Vec3};
+
+    use super::parse_transform;
+
+    fn assert_mat_eq(a: Mat4, b: Mat4) {
+        assert!(a.abs_diff_eq(b, 1e-5), "{a} != {b}");
+    }
+
+    #[test]
+    fn transform_composition_order() {
+        let translation = Mat4::from_translation(Vec3::new(1.0, 0.0, 0.0));
+        let rotation = Mat4::from_rotation_z(FRAC_PI_2);
+
+        // each line is applied after the ones before it
+        let transform = parse_transform("translate 1 0 0\nrotate 90 0 0 1").unwrap();
+        assert_mat_eq(transform, rotation * translation);
+        assert!(transform
+            .transform_point3(Vec3::ZERO)
+            .abs_diff_eq(Vec3::new(0.0, 1.0, 0.0), 1e-5));
+
+        let transform = parse_transform("rotate 90 0 0 1\ntranslate 1 0 0").unwrap();
+        assert_mat_eq(transform, translation * rotation);
+
+        let transform = parse_transform("scale 2 3 4\ntranslate 1 2 3").unwrap();
+        assert_mat_eq(
+            transform,
+            Mat4::from_scale_rotation_translation(
+                Vec3::new(2.0, 3.0, 4.0),
+                Default::default(),
+                Vec3::new(1.0, 2.0, 3.0),
+            ),
+        );
+    }
+
+    #[test]
+    fn transform_identity_and_lookat_reset() {
+        let transform = parse_transform("translate 1 2 3\nidentity").unwrap();
+        assert_mat_eq(transform, Mat4::IDENTITY);
+
+        // lookat replaces everything before it, but later lines still apply on top
+        let eye = Vec3::new(4.0, 0.0, 2.0);
+        let lookat = Mat4::look_at_lh(eye, Vec3::ZERO, Vec3::Z);
+        let transform = parse_transform("scale 5 5 5\nlookat 4 0 2  0 0 0  0 0 1").unwrap();
+        assert_mat_eq(transform, lookat);
+
+        let transform = parse_transform("lookat 4 0 2  0 0 0  0 0 1\ntranslate 0 0 1").unwrap();
+        assert_mat_eq(
+            transform,
+            Mat4::from_translation(Vec3::new(0.0, 0.0, 1.0)) * lookat,
+        );
+    }
+
+    #[test]
+    fn transform_skips_comments_and_blank_lines() {
+        let transform = parse_transform(
+            "
+            # move it over
+            translate 1 2 3
+
+               #rotate 90 0 0 1
+            ",
+        )
+        .unwrap();
+        assert_mat_eq(transform, Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0)));
+
+        assert_mat_eq(parse_transform("").unwrap(), Mat4::IDENTITY);
+    }
+
+    #[test]
+    fn transform_errors() {
+        for transform_str in [
+            "translate 1 2 3 4",
+            "rotate 90 0 0 1 0",
+            "scale 1 1 1 1",
+            "lookat 0 0 0  1 0 0  0 0 1  1",
+            "translate 1 2",
+            "rotate 90",
+            "scale 1 x 1",
+            "shear 1 0 0",
+            "translate 1 2 3\nidentity 1",
+        ] {
+            assert!(
+                parse_transform(transform_str).is_err(),
+                "{transform_str:?} should not parse"
+            );
+        }
+    }
+}
@@ -1,13 +1,33 @@
+use std::ffi::{c_char, c_void, CStr};
+
 use anyhow::Result;
 use ash::{khr, vk, Device, Entry, Instance};
 use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
+use log::warn;
+
+/// The Vulkan version we create the instance with and require from devices
+///
+/// Nothing past the 1.2 core features (buffer device address, scalar layouts, timeline semaphores)
+/// is used, so there's no point locking out 1.2-only drivers.
+pub const VULKAN_API_VERSION: u32 = vk::API_VERSION_1_2;
+
+pub fn format_api_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
 
 #[derive(Default, Clone)]
 pub struct QueueFamilyInfo {
     pub graphics_index: Option<u32>,
     pub present_index: Option<u32>,
     pub compute_index: Option<u32>,
+    /// How many queues the compute family exposes
+    pub compute_queue_count: u32,
     pub transfer_index: Option<u32>,
 }
 
@@ -24,12 +44,14 @@ pub fn query_queue_families(
 
     // this currently just chooses the first available queue family for each thing
     // possibly suboptimal idk, but oh well
+    let mut compute_presents = false;
     for (i, family) in queue_families.iter().enumerate() {
         if info.graphics_index.is_none() && family.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
             info.graphics_index = Some(i as u32);
         }
         if info.compute_index.is_none() && family.queue_flags.contains(vk::QueueFlags::COMPUTE) {
             info.compute_index = Some(i as u32);
+            info.compute_queue_count = family.queue_count;
         }
         if info.transfer_index.is_none() && family.queue_flags.contains(vk::QueueFlags::TRANSFER) {
             info.transfer_index = Some(i as u32);
@@ -41,11 +63,294 @@ pub fn query_queue_families(
         if info.present_index.is_none() && present_support {
             info.present_index = Some(i as u32);
         }
+        if info.compute_index == Some(i as u32) {
+            compute_presents = present_support;
+        }
+    }
+
+    // but present from the compute family when it can, so no ownership transfer between queues is needed
+    if compute_presents {
+        info.present_index = info.compute_index;
     }
 
     Ok(info)
 }
 
+/// Whether `err` is Vulkan reporting the device as lost, which no retry is going to fix
+pub fn is_device_lost(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|e| e.downcast_ref::<vk::Result>() == Some(&vk::Result::ERROR_DEVICE_LOST))
+}
+
+/// Waits for the device to go idle before destroying the objects it uses
+///
+/// A lost device has nothing running anymore and its objects can still be destroyed, so that isn't an error here.
+pub fn wait_idle_before_destroy(device: &Device) -> Result<()> {
+    match unsafe { device.device_wait_idle() } {
+        Err(vk::Result::ERROR_DEVICE_LOST) => {
+            warn!("device was lost, destroying its objects without waiting");
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Records a single-use command buffer with `record`, submits it to `queue` and waits for it to finish
+///
+/// The command buffer is freed again whether or not recording and submission succeeded.
+pub fn submit_immediate<T>(
+    device: &Device,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    record: impl FnOnce(vk::CommandBuffer) -> Result<T>,
+) -> Result<T> {
+    let allocate_info = vk::CommandBufferAllocateInfo {
+        command_buffer_count: 1,
+        command_pool,
+        level: vk::CommandBufferLevel::PRIMARY,
+        ..Default::default()
+    };
+    let command_buffer = unsafe { device.allocate_command_buffers(&allocate_info)?[0] };
+
+    let result = (|| {
+        unsafe {
+            device.begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo {
+                    flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                    ..Default::default()
+                },
+            )?;
+        }
+
+        let value = record(command_buffer)?;
+
+        unsafe {
+            device.end_command_buffer(command_buffer)?;
+            device.queue_submit(
+                queue,
+                &[vk::SubmitInfo {
+                    command_buffer_count: 1,
+                    p_command_buffers: &raw const command_buffer,
+                    ..Default::default()
+                }],
+                vk::Fence::null(),
+            )?;
+
+            // waiting on the whole queue is heavy handed, a fence would do once anything cares
+            device.queue_wait_idle(queue)?;
+        }
+
+        Ok(value)
+    })();
+
+    // if the wait failed the device is lost anyways, so it doesn't matter that the buffer might be pending
+    unsafe { device.free_command_buffers(command_pool, &[command_buffer]) };
+
+    result
+}
+
+/// Set to `0` to stick to the legacy barriers and submits, even on devices with synchronization2
+const SYNCHRONIZATION2_VAR: &str = "KUBGRUPP_SYNC2";
+
+/// Whether to use VK_KHR_synchronization2 on `device`: whenever it's supported, unless `KUBGRUPP_SYNC2=0`
+///
+/// [`create_device`] enables it exactly when this is true, so whoever gets one of its devices can ask
+/// again to find out whether the synchronization2 commands are available.
+pub fn use_synchronization2(instance: &Instance, device: vk::PhysicalDevice) -> Result<bool> {
+    if std::env::var(SYNCHRONIZATION2_VAR).is_ok_and(|x| x == "0") {
+        return Ok(false);
+    }
+
+    let extensions = unsafe { instance.enumerate_device_extension_properties(device)? };
+    if !extensions
+        .iter()
+        .any(|x| x.extension_name_as_c_str() == Ok(khr::synchronization2::NAME))
+    {
+        return Ok(false);
+    }
+
+    let mut synchronization2 = vk::PhysicalDeviceSynchronization2Features::default();
+    let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut synchronization2);
+    unsafe { instance.get_physical_device_features2(device, &mut features) };
+
+    Ok(synchronization2.synchronization2 == vk::TRUE)
+}
+
+/// Whether `device` gets ray queries: [`create_device`] enables them whenever they're supported
+///
+/// The ray query renderer can't do without them, the pipeline one only needs them for
+/// `RaytraceRenderer::pick`.
+pub fn use_ray_query(instance: &Instance, device: vk::PhysicalDevice) -> Result<bool> {
+    let extensions = unsafe { instance.enumerate_device_extension_properties(device)? };
+    if !extensions
+        .iter()
+        .any(|x| x.extension_name_as_c_str() == Ok(khr::ray_query::NAME))
+    {
+        return Ok(false);
+    }
+
+    let mut ray_query = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
+    let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut ray_query);
+    unsafe { instance.get_physical_device_features2(device, &mut features) };
+
+    Ok(ray_query.ray_query == vk::TRUE)
+}
+
+/// Creates a logical device with `extensions` and the feature chain in `features`, plus synchronization2
+/// if [`use_synchronization2`] allows it and ray queries if [`use_ray_query`] does
+pub fn create_device(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    extensions: &[*const c_char],
+    features: &vk::PhysicalDeviceFeatures2,
+    queue_info: &[vk::DeviceQueueCreateInfo],
+) -> Result<Device> {
+    // a renderer that asks for ray queries itself has them in its feature chain already
+    let has_ray_query = extensions
+        .iter()
+        .any(|&x| unsafe { CStr::from_ptr(x) } == khr::ray_query::NAME);
+    let mut extensions = extensions.to_vec();
+
+    // prepended to the chain so the renderer's features stay untouched
+    let mut p_next = features as *const _ as *const c_void;
+    let mut ray_query = vk::PhysicalDeviceRayQueryFeaturesKHR {
+        p_next: p_next as *mut c_void,
+        ray_query: vk::TRUE,
+        ..Default::default()
+    };
+    if !has_ray_query && use_ray_query(instance, physical_device)? {
+        extensions.push(khr::ray_query::NAME.as_ptr());
+        p_next = &raw mut ray_query as *const c_void;
+    }
+    let mut synchronization2 = vk::PhysicalDeviceSynchronization2Features {
+        p_next: p_next as *mut c_void,
+        synchronization2: vk::TRUE,
+        ..Default::default()
+    };
+    if use_synchronization2(instance, physical_device)? {
+        extensions.push(khr::synchronization2::NAME.as_ptr());
+        p_next = &raw mut synchronization2 as *const c_void;
+    }
+
+    let create_info = vk::DeviceCreateInfo {
+        p_next,
+        queue_create_info_count: queue_info.len() as u32,
+        p_queue_create_infos: queue_info.as_ptr(),
+        enabled_extension_count: extensions.len() as u32,
+        pp_enabled_extension_names: extensions.as_ptr(),
+        p_enabled_features: std::ptr::null(),
+        ..Default::default()
+    };
+
+    Ok(unsafe { instance.create_device(physical_device, &create_info, None)? })
+}
+
+/// Legacy version of synchronization2 stages, for devices without it
+///
+/// The legacy flags are the low 32 bits of the synchronization2 ones. The rest only exist in
+/// synchronization2 and are split out of legacy stages, so they map back onto those.
+pub fn legacy_stages(stages: vk::PipelineStageFlags2) -> vk::PipelineStageFlags {
+    let mut legacy = vk::PipelineStageFlags::from_raw(stages.as_raw() as u32);
+    if stages.intersects(
+        vk::PipelineStageFlags2::COPY
+            | vk::PipelineStageFlags2::BLIT
+            | vk::PipelineStageFlags2::RESOLVE
+            | vk::PipelineStageFlags2::CLEAR,
+    ) {
+        legacy |= vk::PipelineStageFlags::TRANSFER;
+    }
+    legacy
+}
+
+/// Legacy version of synchronization2 accesses, same idea as [`legacy_stages`]
+pub fn legacy_access(access: vk::AccessFlags2) -> vk::AccessFlags {
+    let mut legacy = vk::AccessFlags::from_raw(access.as_raw() as u32);
+    if access
+        .intersects(vk::AccessFlags2::SHADER_SAMPLED_READ | vk::AccessFlags2::SHADER_STORAGE_READ)
+    {
+        legacy |= vk::AccessFlags::SHADER_READ;
+    }
+    if access.intersects(vk::AccessFlags2::SHADER_STORAGE_WRITE) {
+        legacy |= vk::AccessFlags::SHADER_WRITE;
+    }
+    legacy
+}
+
+/// Records `memory_barriers` and `image_barriers` with `cmd_pipeline_barrier2` if `synchronization2` is
+/// loaded, and with the legacy `cmd_pipeline_barrier` otherwise
+///
+/// The legacy barrier only has one pair of stage masks, so it waits on the stages of every barrier at
+/// once. No stages at all become TOP_OF_PIPE and BOTTOM_OF_PIPE there.
+pub unsafe fn cmd_pipeline_barrier(
+    device: &Device,
+    synchronization2: Option<&khr::synchronization2::Device>,
+    command_buffer: vk::CommandBuffer,
+    memory_barriers: &[vk::MemoryBarrier2],
+    image_barriers: &[vk::ImageMemoryBarrier2],
+) {
+    if let Some(synchronization2) = synchronization2 {
+        let dependency_info = vk::DependencyInfo::default()
+            .memory_barriers(memory_barriers)
+            .image_memory_barriers(image_barriers);
+        synchronization2.cmd_pipeline_barrier2(command_buffer, &dependency_info);
+        return;
+    }
+
+    let mut src_stages = vk::PipelineStageFlags2::empty();
+    let mut dst_stages = vk::PipelineStageFlags2::empty();
+    let legacy_memory_barriers: Vec<_> = memory_barriers
+        .iter()
+        .map(|barrier| {
+            src_stages |= barrier.src_stage_mask;
+            dst_stages |= barrier.dst_stage_mask;
+            vk::MemoryBarrier {
+                src_access_mask: legacy_access(barrier.src_access_mask),
+                dst_access_mask: legacy_access(barrier.dst_access_mask),
+                ..Default::default()
+            }
+        })
+        .collect();
+    let legacy_image_barriers: Vec<_> = image_barriers
+        .iter()
+        .map(|barrier| {
+            src_stages |= barrier.src_stage_mask;
+            dst_stages |= barrier.dst_stage_mask;
+            vk::ImageMemoryBarrier {
+                src_access_mask: legacy_access(barrier.src_access_mask),
+                dst_access_mask: legacy_access(barrier.dst_access_mask),
+                old_layout: barrier.old_layout,
+                new_layout: barrier.new_layout,
+                src_queue_family_index: barrier.src_queue_family_index,
+                dst_queue_family_index: barrier.dst_queue_family_index,
+                image: barrier.image,
+                subresource_range: barrier.subresource_range,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    // NONE is only allowed for the legacy barrier with synchronization2 enabled
+    let src_stages = match legacy_stages(src_stages) {
+        x if x.is_empty() => vk::PipelineStageFlags::TOP_OF_PIPE,
+        x => x,
+    };
+    let dst_stages = match legacy_stages(dst_stages) {
+        x if x.is_empty() => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        x => x,
+    };
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src_stages,
+        dst_stages,
+        vk::DependencyFlags::empty(),
+        &legacy_memory_barriers,
+        &[],
+        &legacy_image_barriers,
+    );
+}
+
 pub fn align_up(value: u32, alignment: u32) -> u32 {
     (value + alignment - 1) & !(alignment - 1)
 }
@@ -118,15 +423,25 @@ impl AllocatedBuffer {
     }
 
     pub fn store<T: Copy>(&mut self, data: &[T]) -> Result<()> {
+        self.store_at(0, data)
+    }
+
+    /// Like [`Self::store`], but starting `offset` bytes into the buffer
+    pub fn store_at<T: Copy>(&mut self, offset: usize, data: &[T]) -> Result<()> {
         presser::copy_from_slice_to_offset_with_align(
             data,
             &mut self.allocation,
-            0,
+            offset,
             self.offset_alignment,
         )?;
         Ok(())
     }
 
+    /// Returns the buffer contents if the buffer lives in host-visible memory
+    pub fn mapped_slice(&self) -> Option<&[u8]> {
+        self.allocation.mapped_slice()
+    }
+
     pub unsafe fn get_device_address(&self, device: &Device) -> u64 {
         let buffer_device_address_info = vk::BufferDeviceAddressInfo {
             buffer: self.buffer,
@@ -137,7 +452,55 @@ impl AllocatedBuffer {
 
     pub unsafe fn destroy(self, device: &Device, allocator: &mut Allocator) {
         device.destroy_buffer(self.buffer, None);
-        allocator.free(self.allocation).unwrap();
+        if let Err(e) = allocator.free(self.allocation) {
+            warn!("failed to free buffer memory: {e}");
+        }
+    }
+}
+
+/// A layout change for an [`AllocatedImage`] along with the stages and accesses it synchronizes
+#[derive(Clone, Copy, Debug)]
+pub struct ImageTransition {
+    pub layout: vk::ImageLayout,
+    pub src_stage: vk::PipelineStageFlags2,
+    pub src_access: vk::AccessFlags2,
+    pub dst_stage: vk::PipelineStageFlags2,
+    pub dst_access: vk::AccessFlags2,
+}
+
+impl ImageTransition {
+    /// Freshly created image to a storage image that shaders in `stage` read and write
+    pub const fn undefined_to_storage(stage: vk::PipelineStageFlags2) -> Self {
+        Self {
+            layout: vk::ImageLayout::GENERAL,
+            src_stage: vk::PipelineStageFlags2::NONE,
+            src_access: vk::AccessFlags2::NONE,
+            dst_stage: stage,
+            dst_access: vk::AccessFlags2::from_raw(
+                vk::AccessFlags2::SHADER_STORAGE_READ.as_raw()
+                    | vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw(),
+            ),
+        }
+    }
+
+    /// Freshly created image to one that `cmd_clear_color_image` can clear in the general layout
+    pub const fn undefined_to_clear() -> Self {
+        Self {
+            layout: vk::ImageLayout::GENERAL,
+            src_stage: vk::PipelineStageFlags2::NONE,
+            src_access: vk::AccessFlags2::NONE,
+            dst_stage: vk::PipelineStageFlags2::CLEAR,
+            dst_access: vk::AccessFlags2::TRANSFER_WRITE,
+        }
+    }
+
+    /// Cleared image to a storage image, so that shaders in `stage` wait for the clear
+    pub const fn cleared_to_storage(stage: vk::PipelineStageFlags2) -> Self {
+        Self {
+            src_stage: vk::PipelineStageFlags2::CLEAR,
+            src_access: vk::AccessFlags2::TRANSFER_WRITE,
+            ..Self::undefined_to_storage(stage)
+        }
     }
 }
 
@@ -223,29 +586,31 @@ impl AllocatedImage {
         })
     }
 
-    pub fn transition(
+    pub fn layout(&self) -> vk::ImageLayout {
+        self.layout
+    }
+
+    /// Records a barrier moving the image into `transition.layout`
+    ///
+    /// The image is assumed to be in that layout from here on, so the command buffer has to actually
+    /// get submitted before the image is used again. See [`cmd_pipeline_barrier`] for `synchronization2`.
+    pub unsafe fn record_transition(
         &mut self,
         device: &Device,
-        queue: vk::Queue,
-        command_pool: vk::CommandPool,
-        layout: vk::ImageLayout,
-    ) -> Result<()> {
-        let command_buffer = {
-            let allocate_info = vk::CommandBufferAllocateInfo {
-                command_buffer_count: 1,
-                command_pool,
-                level: vk::CommandBufferLevel::PRIMARY,
-                ..Default::default()
-            };
-
-            unsafe { device.allocate_command_buffers(&allocate_info)?[0] }
-        };
-
-        let image_barrier = vk::ImageMemoryBarrier {
-            src_access_mask: vk::AccessFlags::empty(),
-            dst_access_mask: vk::AccessFlags::empty(),
+        synchronization2: Option<&khr::synchronization2::Device>,
+        command_buffer: vk::CommandBuffer,
+        transition: ImageTransition,
+    ) {
+        let image_barrier = vk::ImageMemoryBarrier2 {
+            src_stage_mask: transition.src_stage,
+            src_access_mask: transition.src_access,
+            dst_stage_mask: transition.dst_stage,
+            dst_access_mask: transition.dst_access,
             old_layout: self.layout,
-            new_layout: layout,
+            new_layout: transition.layout,
+            // images only ever get used on the queue they were created for, so no ownership transfer
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
             image: self.image,
             subresource_range: vk::ImageSubresourceRange {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
@@ -257,47 +622,69 @@ impl AllocatedImage {
             ..Default::default()
         };
 
-        let command_buffer_begin_info = vk::CommandBufferBeginInfo {
-            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
-            ..Default::default()
-        };
-
-        unsafe {
-            device.begin_command_buffer(command_buffer, &command_buffer_begin_info)?;
-            device.cmd_pipeline_barrier(
-                command_buffer,
-                vk::PipelineStageFlags::TOP_OF_PIPE,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[image_barrier],
-            );
-
-            device.end_command_buffer(command_buffer)?;
-        }
-
-        let submit_info = vk::SubmitInfo {
-            command_buffer_count: 1,
-            p_command_buffers: &raw const command_buffer,
-            ..Default::default()
-        };
+        cmd_pipeline_barrier(
+            device,
+            synchronization2,
+            command_buffer,
+            &[],
+            &[image_barrier],
+        );
 
-        unsafe {
-            device.queue_submit(queue, &[submit_info], vk::Fence::null())?;
-
-            device.queue_wait_idle(queue)?;
-            device.free_command_buffers(command_pool, &[command_buffer]);
-        }
-
-        self.layout = layout;
-
-        Ok(())
+        self.layout = transition.layout;
     }
 
     pub unsafe fn destroy(self, device: &Device, allocator: &mut Allocator) {
         device.destroy_image_view(self.image_view, None);
         device.destroy_image(self.image, None);
-        allocator.free(self.allocation).unwrap();
+        if let Err(e) = allocator.free(self.allocation) {
+            warn!("failed to free image memory: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    #[test]
+    fn legacy_synchronization() {
+        assert_eq!(
+            legacy_stages(
+                vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR | vk::PipelineStageFlags2::BLIT
+            ),
+            vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR | vk::PipelineStageFlags::TRANSFER
+        );
+        assert_eq!(
+            legacy_stages(vk::PipelineStageFlags2::COMPUTE_SHADER),
+            vk::PipelineStageFlags::COMPUTE_SHADER
+        );
+        assert_eq!(
+            legacy_access(
+                vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE
+            ),
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE
+        );
+        assert_eq!(
+            legacy_access(vk::AccessFlags2::TRANSFER_READ | vk::AccessFlags2::HOST_READ),
+            vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::HOST_READ
+        );
+    }
+
+    #[test]
+    fn device_lost_errors() {
+        let lost = anyhow::Error::from(vk::Result::ERROR_DEVICE_LOST);
+        assert!(is_device_lost(&lost));
+        assert!(is_device_lost(&lost.context("failed to submit")));
+        assert!(!is_device_lost(&anyhow::Error::from(
+            vk::Result::ERROR_OUT_OF_DEVICE_MEMORY
+        )));
+        assert!(!is_device_lost(&anyhow!("device lost")));
+
+        let result: Result<(), vk::Result> = Err(vk::Result::ERROR_DEVICE_LOST);
+        assert!(is_device_lost(
+            &result.context("failed to render").unwrap_err()
+        ));
     }
 }
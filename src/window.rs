@@ -1,13 +1,27 @@
 use std::{ffi::c_char, ptr};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use ash::{khr, vk, Device, Entry, Instance};
-use winit::window::Window;
+use log::{error, warn};
+use winit::{
+    raw_window_handle::{HasDisplayHandle, HasWindowHandle},
+    window::{CursorGrabMode, Window},
+};
 
 use crate::{defer::Defer, utils};
 
 const MAX_FRAMES_IN_FLIGHT: usize = 2;
 
+/// Surface formats to prefer for HDR output, most preferred first
+///
+/// The renderers blit linear color into the swapchain image, so only extended linear sRGB (scRGB) displays
+/// correctly. HDR10 (A2B10G10R10 with ST2084) would need PQ encoding first. These color spaces only show up
+/// when the instance has VK_EXT_swapchain_colorspace.
+pub const HDR_SURFACE_FORMATS: &[vk::SurfaceFormatKHR] = &[vk::SurfaceFormatKHR {
+    format: vk::Format::R16G16B16A16_SFLOAT,
+    color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+}];
+
 pub struct WindowData {
     swapchain: vk::SwapchainKHR,
     surface: vk::SurfaceKHR,
@@ -22,7 +36,14 @@ pub struct WindowData {
 
     image_extent: vk::Extent2D,
     images: Vec<vk::Image>,
+    // whether each of images has been presented since the swapchain was (re)created, see previous_layout
+    presented: Vec<bool>,
     current_image: u32,
+    swapchain_options: SwapchainOptions,
+    // what the surface supported when the swapchain was last (re)created
+    present_modes: Vec<vk::PresentModeKHR>,
+    surface_formats: Vec<vk::SurfaceFormatKHR>,
+    surface_format: vk::SurfaceFormatKHR,
 
     image_semaphores: Vec<vk::Semaphore>,
     frame_fences: Vec<vk::Fence>,
@@ -30,6 +51,20 @@ pub struct WindowData {
     current_frame: usize,
 }
 
+/// What to ask for when (re)creating the swapchain
+#[derive(Clone, Debug)]
+pub struct SwapchainOptions {
+    /// None means min_image_count + 1
+    pub image_count: Option<u32>,
+    /// How the renderer uses the swapchain images, the surface has to support all of it
+    pub image_usage: vk::ImageUsageFlags,
+    /// Surface formats to use if supported, most preferred first
+    ///
+    /// If none of them are supported (or there are none), B8G8R8A8_SRGB is used if it can be, or else whatever
+    /// the surface lists first.
+    pub surface_formats: Vec<vk::SurfaceFormatKHR>,
+}
+
 pub struct SwapchainSupportDetails {
     pub capabilities: vk::SurfaceCapabilitiesKHR,
     pub formats: Vec<vk::SurfaceFormatKHR>,
@@ -47,13 +82,22 @@ impl WindowData {
         physical_device: vk::PhysicalDevice,
         surface: vk::SurfaceKHR,
         window: Window,
+        swapchain_options: SwapchainOptions,
     ) -> Result<WindowData> {
         let swapchain_loader = khr::swapchain::Device::new(instance, device);
         let surface_loader = khr::surface::Instance::new(vk_lib, instance);
         let surface = surface.defer(|x| unsafe { surface_loader.destroy_surface(*x, None) });
 
-        let (swapchain, image_extent, images) =
-            Self::create_swapchain(vk_lib, instance, device, physical_device, *surface, &window)?;
+        let (swapchain, image_extent, images, support_details, surface_format) =
+            Self::create_swapchain(
+                vk_lib,
+                instance,
+                device,
+                physical_device,
+                *surface,
+                &window,
+                &swapchain_options,
+            )?;
 
         let image_count = images.len();
         let (image_semaphores, frame_fences, render_semaphores) =
@@ -71,8 +115,13 @@ impl WindowData {
             instance: instance.clone(),
             physical_device,
             image_extent,
+            presented: vec![false; images.len()],
             images,
             current_image: 0,
+            swapchain_options,
+            present_modes: support_details.present_modes,
+            surface_formats: support_details.formats,
+            surface_format,
             image_semaphores,
             frame_fences,
             render_semaphores,
@@ -91,15 +140,14 @@ impl WindowData {
             ..Default::default()
         };
 
-        let needs_recreate =
-            match unsafe { self.swapchain_loader.queue_present(queue, &present_info) } {
-                Ok(suboptimal) => suboptimal,
-                Err(vk::Result::ERROR_OUT_OF_DATE_KHR | vk::Result::SUBOPTIMAL_KHR) => true,
-                Err(e) => return Err(e.into()),
-            };
-
-        if needs_recreate {
-            self.recreate_swapchain()?;
+        self.presented[self.current_image as usize] = true;
+        match unsafe { self.swapchain_loader.queue_present(queue, &present_info) } {
+            Ok(false) => (),
+            Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR | vk::Result::SUBOPTIMAL_KHR) => {
+                self.recreate_swapchain()?
+            }
+            Err(vk::Result::ERROR_SURFACE_LOST_KHR) => self.recreate_surface()?,
+            Err(e) => return Err(e.into()),
         }
 
         self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
@@ -113,14 +161,16 @@ impl WindowData {
                 .destroy_swapchain(self.swapchain, None)
         };
 
-        let (swapchain, image_extent, images) = Self::create_swapchain(
-            &self.vk_lib,
-            &self.instance,
-            &self.device,
-            self.physical_device,
-            self.surface,
-            &self.window,
-        )?;
+        let (swapchain, image_extent, images, support_details, surface_format) =
+            Self::create_swapchain(
+                &self.vk_lib,
+                &self.instance,
+                &self.device,
+                self.physical_device,
+                self.surface,
+                &self.window,
+                &self.swapchain_options,
+            )?;
 
         if images.len() != self.images.len() {
             self.recreate_render_semaphores(images.len())?;
@@ -128,10 +178,40 @@ impl WindowData {
 
         self.swapchain = swapchain;
         self.image_extent = image_extent;
+        self.presented = vec![false; images.len()];
         self.images = images;
+        self.present_modes = support_details.present_modes;
+        self.surface_formats = support_details.formats;
+        self.surface_format = surface_format;
         Ok(())
     }
 
+    // the surface can go away under us (e.g. display hotplug), so make a new one for the same window
+    fn recreate_surface(&mut self) -> Result<()> {
+        warn!("surface lost, recreating surface and swapchain");
+
+        unsafe {
+            self.device.device_wait_idle()?;
+
+            // the swapchain has to go before the surface it belongs to
+            self.swapchain_loader
+                .destroy_swapchain(self.swapchain, None);
+            self.swapchain = vk::SwapchainKHR::null();
+            self.surface_loader.destroy_surface(self.surface, None);
+            self.surface = vk::SurfaceKHR::null();
+
+            self.surface = ash_window::create_surface(
+                &self.vk_lib,
+                &self.instance,
+                self.window.display_handle()?.as_raw(),
+                self.window.window_handle()?.as_raw(),
+                None,
+            )?;
+        }
+
+        self.recreate_swapchain()
+    }
+
     fn recreate_render_semaphores(&mut self, count: usize) -> Result<()> {
         unsafe {
             for semaphore in &self.render_semaphores {
@@ -174,6 +254,10 @@ impl WindowData {
                 self.recreate_swapchain()?;
                 self.do_acquire(image_semaphore)?.0
             }
+            Err(vk::Result::ERROR_SURFACE_LOST_KHR) => {
+                self.recreate_surface()?;
+                self.do_acquire(image_semaphore)?.0
+            }
             Err(e) => return Err(e.into()),
         };
 
@@ -195,10 +279,63 @@ impl WindowData {
         self.window.request_redraw();
     }
 
+    /// Confines (or failing that locks) and hides the cursor, or gives it back
+    pub fn grab_cursor(&self, grab: bool) -> Result<()> {
+        if grab {
+            self.window
+                .set_cursor_grab(CursorGrabMode::Confined)
+                .or_else(|_e| self.window.set_cursor_grab(CursorGrabMode::Locked))?;
+        } else {
+            self.window.set_cursor_grab(CursorGrabMode::None)?;
+        }
+        self.window.set_cursor_visible(!grab);
+        Ok(())
+    }
+
+    /// Current swapchain extent, i.e. the size of what gets presented
     pub fn get_size(&self) -> (u32, u32) {
         (self.image_extent.width, self.image_extent.height)
     }
 
+    /// The layout swapchain image `index` was left in, so a renderer can keep what was presented there
+    ///
+    /// That's PRESENT_SRC_KHR once it has been presented, and UNDEFINED (with nothing worth keeping in it) for
+    /// images of a new swapchain.
+    pub fn previous_layout(&self, index: u32) -> vk::ImageLayout {
+        match self.presented[index as usize] {
+            true => vk::ImageLayout::PRESENT_SRC_KHR,
+            false => vk::ImageLayout::UNDEFINED,
+        }
+    }
+
+    /// Number of images the swapchain actually has, which can be more than was requested
+    pub fn image_count(&self) -> usize {
+        self.images.len()
+    }
+
+    /// Present modes the surface supports, FIFO is always among them
+    pub fn supported_present_modes(&self) -> &[vk::PresentModeKHR] {
+        &self.present_modes
+    }
+
+    /// Surface formats the surface supports
+    pub fn supported_surface_formats(&self) -> &[vk::SurfaceFormatKHR] {
+        &self.surface_formats
+    }
+
+    /// Waits for all work on the device to finish, including presentation
+    ///
+    /// The window counterpart of `Renderer::wait_idle`, for whoever owns the window.
+    pub fn wait_idle(&self) -> Result<()> {
+        unsafe { self.device.device_wait_idle()? };
+        Ok(())
+    }
+
+    /// The format and color space the swapchain images actually have
+    pub fn surface_format(&self) -> vk::SurfaceFormatKHR {
+        self.surface_format
+    }
+
     fn create_sync_objects(
         device: &Device,
         swapchain_image_count: usize,
@@ -243,14 +380,35 @@ impl WindowData {
         physical_device: vk::PhysicalDevice,
         surface: vk::SurfaceKHR,
         window: &Window,
-    ) -> Result<(vk::SwapchainKHR, vk::Extent2D, Vec<vk::Image>)> {
+        options: &SwapchainOptions,
+    ) -> Result<(
+        vk::SwapchainKHR,
+        vk::Extent2D,
+        Vec<vk::Image>,
+        SwapchainSupportDetails,
+        vk::SurfaceFormatKHR,
+    )> {
         let swapchain_loader = khr::swapchain::Device::new(instance, device);
 
         let support_details =
             Self::query_swapchain_support_details(vk_lib, instance, physical_device, surface)?;
-        let surface_format = Self::choose_surface_format(&support_details.formats);
+        Self::check_image_usage(
+            support_details.capabilities.supported_usage_flags,
+            options.image_usage,
+        )?;
+        let surface_format =
+            Self::choose_surface_format(&support_details.formats, &options.surface_formats);
+        if !options.surface_formats.is_empty() && !options.surface_formats.contains(&surface_format)
+        {
+            warn!(
+                "none of the preferred surface formats are supported, using {:?}",
+                surface_format
+            );
+        }
         let present_mode = Self::choose_present_mode(&support_details.present_modes);
         let image_extent = Self::choose_extent(window, &support_details.capabilities);
+        let composite_alpha =
+            Self::choose_composite_alpha(support_details.capabilities.supported_composite_alpha);
 
         let queue_info = utils::query_queue_families(vk_lib, instance, physical_device, surface)?;
         let queue_indices = [
@@ -269,12 +427,8 @@ impl WindowData {
                 (vk::SharingMode::CONCURRENT, 2, queue_indices.as_ptr())
             };
 
-        let image_count = if support_details.capabilities.max_image_count > 0 {
-            (support_details.capabilities.min_image_count + 1)
-                .min(support_details.capabilities.max_image_count)
-        } else {
-            support_details.capabilities.min_image_count + 1
-        };
+        let image_count =
+            Self::choose_image_count(&support_details.capabilities, options.image_count);
 
         let create_info = vk::SwapchainCreateInfoKHR {
             surface,
@@ -283,12 +437,12 @@ impl WindowData {
             image_color_space: surface_format.color_space,
             image_extent,
             image_array_layers: 1,
-            image_usage: vk::ImageUsageFlags::TRANSFER_DST,
+            image_usage: options.image_usage,
             image_sharing_mode,
             queue_family_index_count: queue_family_count,
             p_queue_family_indices: queue_family_indices,
             pre_transform: support_details.capabilities.current_transform,
-            composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
+            composite_alpha,
             present_mode,
             clipped: vk::TRUE,
             old_swapchain: vk::SwapchainKHR::null(),
@@ -298,10 +452,60 @@ impl WindowData {
 
         let images = unsafe { swapchain_loader.get_swapchain_images(swapchain) }?;
 
-        Ok((swapchain, image_extent, images))
+        Ok((
+            swapchain,
+            image_extent,
+            images,
+            support_details,
+            surface_format,
+        ))
+    }
+
+    fn check_image_usage(
+        supported: vk::ImageUsageFlags,
+        requested: vk::ImageUsageFlags,
+    ) -> Result<()> {
+        if !supported.contains(requested) {
+            bail!(
+                "surface doesn't support swapchain image usage {:?} (supported: {:?})",
+                requested & !supported,
+                supported
+            );
+        }
+
+        Ok(())
     }
 
-    fn choose_surface_format(formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
+    fn choose_image_count(
+        capabilities: &vk::SurfaceCapabilitiesKHR,
+        requested_image_count: Option<u32>,
+    ) -> u32 {
+        let requested = requested_image_count.unwrap_or(capabilities.min_image_count + 1);
+
+        // max_image_count of 0 means there is no maximum
+        let mut image_count = requested.max(capabilities.min_image_count);
+        if capabilities.max_image_count > 0 {
+            image_count = image_count.min(capabilities.max_image_count);
+        }
+
+        if requested_image_count.is_some() && image_count != requested {
+            warn!(
+                "requested {requested} swapchain images, but the surface supports {}..={} - using {image_count}",
+                capabilities.min_image_count, capabilities.max_image_count
+            );
+        }
+
+        image_count
+    }
+
+    fn choose_surface_format(
+        formats: &[vk::SurfaceFormatKHR],
+        preferred: &[vk::SurfaceFormatKHR],
+    ) -> vk::SurfaceFormatKHR {
+        if let Some(format) = preferred.iter().find(|x| formats.contains(x)) {
+            return *format;
+        }
+
         for format in formats {
             if format.format == vk::Format::B8G8R8A8_SRGB
                 && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
@@ -313,6 +517,20 @@ impl WindowData {
         formats[0]
     }
 
+    fn choose_composite_alpha(supported: vk::CompositeAlphaFlagsKHR) -> vk::CompositeAlphaFlagsKHR {
+        // we always write alpha = 1 anyways, so any of these end up looking opaque
+        // (some surfaces, e.g. on wayland and android, don't offer OPAQUE at all)
+        [
+            vk::CompositeAlphaFlagsKHR::OPAQUE,
+            vk::CompositeAlphaFlagsKHR::INHERIT,
+            vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+            vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        ]
+        .into_iter()
+        .find(|&x| supported.contains(x))
+        .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE)
+    }
+
     fn choose_present_mode(modes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
         if modes.contains(&vk::PresentModeKHR::MAILBOX) {
             vk::PresentModeKHR::MAILBOX
@@ -377,9 +595,10 @@ impl WindowData {
 impl Drop for WindowData {
     fn drop(&mut self) {
         unsafe {
-            self.device
-                .device_wait_idle()
-                .expect("failed to wait for device idle");
+            // panicking here would only hide whatever went wrong with the device in the first place
+            if let Err(e) = utils::wait_idle_before_destroy(&self.device) {
+                error!("failed to wait for the device before destroying the swapchain: {e}");
+            }
 
             for semaphore in &self.image_semaphores {
                 self.device.destroy_semaphore(*semaphore, None);
@@ -397,3 +616,111 @@ impl Drop for WindowData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ash::vk;
+
+    use super::{WindowData, HDR_SURFACE_FORMATS};
+
+    #[test]
+    fn swapchain_image_count() {
+        let capabilities = |min_image_count, max_image_count| vk::SurfaceCapabilitiesKHR {
+            min_image_count,
+            max_image_count,
+            ..Default::default()
+        };
+
+        assert_eq!(WindowData::choose_image_count(&capabilities(2, 8), None), 3);
+        assert_eq!(WindowData::choose_image_count(&capabilities(2, 2), None), 2);
+        assert_eq!(
+            WindowData::choose_image_count(&capabilities(2, 8), Some(4)),
+            4
+        );
+        assert_eq!(
+            WindowData::choose_image_count(&capabilities(2, 8), Some(1)),
+            2
+        );
+        assert_eq!(
+            WindowData::choose_image_count(&capabilities(2, 3), Some(5)),
+            3
+        );
+        // no maximum
+        assert_eq!(
+            WindowData::choose_image_count(&capabilities(2, 0), Some(16)),
+            16
+        );
+    }
+
+    #[test]
+    fn swapchain_image_usage() {
+        let supported = vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::COLOR_ATTACHMENT;
+
+        assert!(
+            WindowData::check_image_usage(supported, vk::ImageUsageFlags::TRANSFER_DST).is_ok()
+        );
+        let err = WindowData::check_image_usage(
+            supported,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::STORAGE,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("STORAGE"), "{err}");
+    }
+
+    #[test]
+    fn composite_alpha() {
+        assert_eq!(
+            WindowData::choose_composite_alpha(
+                vk::CompositeAlphaFlagsKHR::OPAQUE | vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED
+            ),
+            vk::CompositeAlphaFlagsKHR::OPAQUE
+        );
+        assert_eq!(
+            WindowData::choose_composite_alpha(
+                vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED | vk::CompositeAlphaFlagsKHR::INHERIT
+            ),
+            vk::CompositeAlphaFlagsKHR::INHERIT
+        );
+        assert_eq!(
+            WindowData::choose_composite_alpha(vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED),
+            vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED
+        );
+    }
+
+    #[test]
+    fn surface_format_preference() {
+        let format = |format, color_space| vk::SurfaceFormatKHR {
+            format,
+            color_space,
+        };
+        let unorm = format(
+            vk::Format::B8G8R8A8_UNORM,
+            vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        );
+        let srgb = format(vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR);
+        let formats = [unorm, srgb, HDR_SURFACE_FORMATS[0]];
+
+        assert_eq!(WindowData::choose_surface_format(&formats, &[]), srgb);
+        assert_eq!(
+            WindowData::choose_surface_format(&formats, HDR_SURFACE_FORMATS),
+            HDR_SURFACE_FORMATS[0]
+        );
+        assert_eq!(
+            WindowData::choose_surface_format(&formats[..1], HDR_SURFACE_FORMATS),
+            unorm
+        );
+        // the color space has to match too
+        let hdr10 = format(
+            vk::Format::A2B10G10R10_UNORM_PACK32,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        );
+        let sdr10 = format(
+            vk::Format::A2B10G10R10_UNORM_PACK32,
+            vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        );
+        assert_eq!(
+            WindowData::choose_surface_format(&[sdr10, hdr10], &[unorm, hdr10]),
+            hdr10
+        );
+    }
+}